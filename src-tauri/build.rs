@@ -1,3 +1,27 @@
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
 fn main() {
+    // For `get_app_info`: the commit built and when, in seconds since the
+    // Unix epoch (`SOURCE_DATE_EPOCH` wins, for reproducible builds)
+    let commit = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|out| out.status.success())
+        .map(|out| String::from_utf8_lossy(&out.stdout).trim().to_string());
+    if let Some(commit) = commit {
+        println!("cargo:rustc-env=XML_READER_COMMIT={}", commit);
+    }
+    let built = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .or_else(|| Some(SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs().to_string()));
+    if let Some(built) = built {
+        println!("cargo:rustc-env=XML_READER_BUILT={}", built);
+    }
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    println!("cargo:rerun-if-changed=../.git/HEAD");
+    println!("cargo:rerun-if-changed=../.git/refs/heads");
+
     tauri_build::build()
 }
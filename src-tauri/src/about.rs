@@ -0,0 +1,45 @@
+use crate::error::XmlReaderError;
+use crate::io;
+use tauri::{AppHandle, Manager};
+
+#[derive(serde::Serialize)]
+pub struct AppInfo {
+    version: String,
+    /// Short hash of the commit built, if it was built from a git checkout.
+    commit: Option<&'static str>,
+    /// RFC 3339.
+    build_date: Option<String>,
+    /// Optional capabilities of this build and run, e.g. `"binary-xml"`
+    /// (EXI, Fast Infoset and WBXML input) and `"mmap"` (memory-mapped
+    /// reads, while switched on). Compressed input and XSD validation aren't
+    /// supported yet and never appear.
+    features: Vec<&'static str>,
+    os: &'static str,
+    arch: &'static str,
+    tauri: &'static str,
+}
+
+/// What this backend is and can do, for the About dialog and bug reports.
+#[tauri::command]
+pub async fn get_app_info(app: AppHandle) -> Result<AppInfo, XmlReaderError> {
+    let build_date = option_env!("XML_READER_BUILT")
+        .and_then(|secs| secs.parse().ok())
+        .and_then(|secs| chrono::DateTime::from_timestamp(secs, 0))
+        .map(|date| date.to_rfc3339());
+    let mut features = Vec::new();
+    if cfg!(feature = "binary-xml") {
+        features.push("binary-xml");
+    }
+    if io::mmap_enabled() {
+        features.push("mmap");
+    }
+    Ok(AppInfo {
+        version: app.package_info().version.to_string(),
+        commit: option_env!("XML_READER_COMMIT"),
+        build_date,
+        features,
+        os: std::env::consts::OS,
+        arch: std::env::consts::ARCH,
+        tauri: tauri::VERSION,
+    })
+}
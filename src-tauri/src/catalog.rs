@@ -0,0 +1,224 @@
+use crate::error::XmlReaderError;
+use anyhow::Result;
+use quick_xml::events::Event;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// One mapping from an OASIS XML Catalog, in document order.
+enum Entry {
+    Public { id: String, uri: String },
+    System { id: String, uri: String },
+    RewriteSystem { prefix: String, rewrite: String },
+    SystemSuffix { suffix: String, uri: String },
+    Uri { name: String, uri: String },
+    RewriteUri { prefix: String, rewrite: String },
+    UriSuffix { suffix: String, uri: String },
+}
+
+/// Entries of the configured catalogs and the ones they chain to with
+/// `nextCatalog`, in lookup order.
+static CATALOGS: Mutex<Vec<Entry>> = Mutex::new(Vec::new());
+
+/// Load the catalog files used to map public and system identifiers (DTDs,
+/// schemas, external entities) to local copies, replacing the previous ones.
+/// Returns how many mappings were loaded.
+#[tauri::command]
+pub async fn set_catalogs(paths: Vec<String>) -> Result<usize, XmlReaderError> {
+    let mut entries = Vec::new();
+    let mut loaded = Vec::new();
+    for path in &paths {
+        load(Path::new(path), &mut entries, &mut loaded)?;
+    }
+    let count = entries.len();
+    *CATALOGS.lock().unwrap() = entries;
+    Ok(count)
+}
+
+/// Where the catalogs map an external identifier, if anywhere.
+#[tauri::command]
+pub async fn resolve_external_id(
+    public_id: Option<String>,
+    system_id: Option<String>,
+) -> Result<Option<String>, XmlReaderError> {
+    Ok(resolve(public_id.as_deref(), system_id.as_deref()))
+}
+
+/// Map an external identifier to a local file the way catalog resolution
+/// does: an exact `system` match, then the longest `rewriteSystem` prefix,
+/// then the longest `systemSuffix`, then an exact `public` match. `file:`
+/// URIs come back as plain paths.
+pub fn resolve(public_id: Option<&str>, system_id: Option<&str>) -> Option<String> {
+    let entries = CATALOGS.lock().unwrap();
+    let by_system = system_id.and_then(|system| {
+        entries
+            .iter()
+            .find_map(|entry| match entry {
+                Entry::System { id, uri } => (id == system).then(|| uri.clone()),
+                _ => None,
+            })
+            .or_else(|| rewrite(&entries, system, true))
+            .or_else(|| longest_suffix(&entries, system, true))
+    });
+    let resolved = by_system.or_else(|| {
+        let public = normalize_public(public_id?);
+        entries.iter().find_map(|entry| match entry {
+            Entry::Public { id, uri } => (*id == public).then(|| uri.clone()),
+            _ => None,
+        })
+    })?;
+    Some(to_path(&resolved))
+}
+
+/// Map a namespace or schema location URI to a local file with the `uri`,
+/// `rewriteURI` and `uriSuffix` entries.
+pub fn resolve_uri(name: &str) -> Option<String> {
+    let entries = CATALOGS.lock().unwrap();
+    let resolved = entries
+        .iter()
+        .find_map(|entry| match entry {
+            Entry::Uri { name: n, uri } => (n == name).then(|| uri.clone()),
+            _ => None,
+        })
+        .or_else(|| rewrite(&entries, name, false))
+        .or_else(|| longest_suffix(&entries, name, false))?;
+    Some(to_path(&resolved))
+}
+
+/// Apply the `rewriteSystem` (or `rewriteURI`) entry with the longest
+/// matching prefix.
+fn rewrite(entries: &[Entry], id: &str, system: bool) -> Option<String> {
+    entries
+        .iter()
+        .filter_map(|entry| match entry {
+            Entry::RewriteSystem { prefix, rewrite } if system => Some((prefix, rewrite)),
+            Entry::RewriteUri { prefix, rewrite } if !system => Some((prefix, rewrite)),
+            _ => None,
+        })
+        .filter(|(prefix, _)| id.starts_with(prefix.as_str()))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map(|(prefix, rewrite)| format!("{}{}", rewrite, &id[prefix.len()..]))
+}
+
+fn longest_suffix(entries: &[Entry], id: &str, system: bool) -> Option<String> {
+    entries
+        .iter()
+        .filter_map(|entry| match entry {
+            Entry::SystemSuffix { suffix, uri } if system => Some((suffix, uri)),
+            Entry::UriSuffix { suffix, uri } if !system => Some((suffix, uri)),
+            _ => None,
+        })
+        .filter(|(suffix, _)| id.ends_with(suffix.as_str()))
+        .max_by_key(|(suffix, _)| suffix.len())
+        .map(|(_, uri)| uri.clone())
+}
+
+/// Public identifiers compare with runs of whitespace collapsed.
+fn normalize_public(id: &str) -> String {
+    id.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn to_path(uri: &str) -> String {
+    match uri.strip_prefix("file://") {
+        // `file:///C:/...` on Windows
+        Some(path) if path.len() > 3 && path.as_bytes()[2] == b':' => path[1..].to_string(),
+        Some(path) => path.to_string(),
+        None => uri.strip_prefix("file:").unwrap_or(uri).to_string(),
+    }
+}
+
+/// Read one catalog file into `entries`, following `nextCatalog` once per
+/// file. Relative URIs are resolved against the catalog's directory or the
+/// nearest `xml:base`.
+fn load(path: &Path, entries: &mut Vec<Entry>, loaded: &mut Vec<PathBuf>) -> Result<()> {
+    let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    if loaded.contains(&canonical) {
+        return Ok(());
+    }
+    loaded.push(canonical);
+
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::Error::new(e).context(format!("Could not read catalog {}", path.display())))?;
+    let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    let mut reader = quick_xml::Reader::from_str(&text);
+    // Base of each open element, the catalog's directory at the top
+    let mut bases = vec![dir.to_string_lossy().into_owned()];
+    let mut next = Vec::new();
+
+    loop {
+        let event = reader.read_event()?;
+        let empty = matches!(event, Event::Empty(_));
+        match event {
+            Event::Start(e) | Event::Empty(e) => {
+                let mut attrs = std::collections::HashMap::new();
+                for attr in e.attributes().flatten() {
+                    let key = String::from_utf8_lossy(attr.key.as_ref()).into_owned();
+                    attrs.insert(key, attr.unescape_value()?.into_owned());
+                }
+                let base = bases.last().cloned().unwrap_or_default();
+                let base = attrs.get("xml:base").map_or(base.clone(), |b| absolute(&base, b));
+                let get = |name: &str| attrs.get(name).cloned().unwrap_or_default();
+                let uri = || absolute(&base, &get("uri"));
+
+                let local = e.local_name();
+                let entry = match local.as_ref() {
+                    b"public" => Some(Entry::Public {
+                        id: normalize_public(&get("publicId")),
+                        uri: uri(),
+                    }),
+                    b"system" => Some(Entry::System { id: get("systemId"), uri: uri() }),
+                    b"rewriteSystem" => Some(Entry::RewriteSystem {
+                        prefix: get("systemIdStartString"),
+                        rewrite: absolute(&base, &get("rewritePrefix")),
+                    }),
+                    b"systemSuffix" => Some(Entry::SystemSuffix {
+                        suffix: get("systemIdSuffix"),
+                        uri: uri(),
+                    }),
+                    b"uri" => Some(Entry::Uri { name: get("name"), uri: uri() }),
+                    b"rewriteURI" => Some(Entry::RewriteUri {
+                        prefix: get("uriStartString"),
+                        rewrite: absolute(&base, &get("rewritePrefix")),
+                    }),
+                    b"uriSuffix" => Some(Entry::UriSuffix {
+                        suffix: get("uriSuffix"),
+                        uri: uri(),
+                    }),
+                    b"nextCatalog" => {
+                        next.push(to_path(&absolute(&base, &get("catalog"))));
+                        None
+                    }
+                    _ => None,
+                };
+                entries.extend(entry);
+                if !empty {
+                    bases.push(base);
+                }
+            }
+            Event::End(_) => {
+                bases.pop();
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    // Chained catalogs are only consulted after every entry of this one
+    for path in next {
+        load(Path::new(&path), entries, loaded)?;
+    }
+    Ok(())
+}
+
+/// `uri` made absolute against `base`, a directory path or URI.
+fn absolute(base: &str, uri: &str) -> String {
+    let has_scheme = uri
+        .split_once(':')
+        .is_some_and(|(scheme, _)| scheme.len() > 1 && scheme.chars().all(|c| c.is_ascii_alphanumeric()));
+    if uri.is_empty() || has_scheme || Path::new(uri).is_absolute() {
+        return uri.to_string();
+    }
+    if base.contains("://") {
+        return format!("{}/{}", base.trim_end_matches('/'), uri);
+    }
+    Path::new(&to_path(base)).join(uri).to_string_lossy().into_owned()
+}
@@ -0,0 +1,90 @@
+use crate::error::XmlReaderError;
+use std::path::Path;
+use std::sync::Mutex;
+use tauri::{AppHandle, Emitter, RunEvent, WebviewWindow};
+
+/// A file to open from the command line or the OS ("Open with…"), and where
+/// to jump in it.
+#[derive(serde::Serialize, Clone)]
+pub struct OpenRequest {
+    path: String,
+    offset: Option<u64>,
+    /// 1-based.
+    line: Option<u64>,
+}
+
+/// Requests windows haven't taken yet, by window label, see
+/// `take_open_requests`.
+static PENDING: Mutex<Vec<(String, OpenRequest)>> = Mutex::new(Vec::new());
+
+/// Parse one argument: `big.xml`, `big.xml:123456` (byte offset) or
+/// `big.xml:L120` (line). An argument naming an existing file is taken
+/// whole, so paths that contain `:` still open. Relative paths are resolved
+/// against `cwd`.
+pub fn parse(arg: &str, cwd: &Path) -> OpenRequest {
+    let resolve = |path: &str| cwd.join(path).to_string_lossy().into_owned();
+    let whole = OpenRequest {
+        path: resolve(arg),
+        offset: None,
+        line: None,
+    };
+    if Path::new(&whole.path).exists() {
+        return whole;
+    }
+    let Some((path, at)) = arg.rsplit_once(':').filter(|(path, _)| !path.is_empty()) else {
+        return whole;
+    };
+    let line = at.strip_prefix(['L', 'l']).and_then(|line| line.parse().ok());
+    let offset = at.parse().ok();
+    if line.is_none() && offset.is_none() {
+        return whole;
+    }
+    OpenRequest {
+        path: resolve(path),
+        offset,
+        line,
+    }
+}
+
+/// The files named in `args`, the arguments after the program name of this
+/// process or of another instance. `-` (stdin) and flags are skipped.
+pub fn requests(args: &[String], cwd: &Path) -> Vec<OpenRequest> {
+    args.iter().filter(|a| !a.starts_with('-')).map(|a| parse(a, cwd)).collect()
+}
+
+/// Queue `request` for `window` and send it there as `open-request`.
+pub fn open(app: &AppHandle, window: &str, request: OpenRequest) {
+    PENDING.lock().unwrap().push((window.to_string(), request.clone()));
+    let _ = app.emit_to(window, "open-request", request);
+}
+
+/// Files opened with the app through "Open with…" on macOS, which arrive as
+/// a run event rather than as arguments.
+#[cfg_attr(not(any(target_os = "macos", target_os = "ios")), allow(unused_variables))]
+pub fn on_run_event(app: &AppHandle, event: &RunEvent) {
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    if let RunEvent::Opened { urls } = event {
+        for path in urls.iter().filter_map(|url| url.to_file_path().ok()) {
+            open(app, "main", parse(&path.to_string_lossy(), Path::new("/")));
+        }
+    }
+}
+
+/// Take the open requests queued for the calling window. Requests stay
+/// queued until taken, so those made before the page subscribed to
+/// `open-request` aren't lost; a page handling the event takes them from
+/// here too, so none is opened twice.
+#[tauri::command]
+pub async fn take_open_requests(window: WebviewWindow) -> Result<Vec<OpenRequest>, XmlReaderError> {
+    let mut pending = PENDING.lock().unwrap();
+    let (taken, kept) = std::mem::take(&mut *pending)
+        .into_iter()
+        .partition(|(label, _)| label == window.label());
+    *pending = kept;
+    Ok(taken.into_iter().map(|(_, request)| request).collect())
+}
+
+/// Forget the requests of a window that was closed before taking them.
+pub fn forget(window: &str) {
+    PENDING.lock().unwrap().retain(|(label, _)| label != window);
+}
@@ -0,0 +1,426 @@
+use crate::error::XmlReaderError;
+use crate::files::{FileHandle, FileId, FileRegistry};
+use crate::format::element_reader;
+use crate::ops::{self, OpId};
+use crate::preview::root_offset;
+use anyhow::Result;
+use quick_xml::events::{BytesStart, Event};
+use std::collections::HashMap;
+use tauri::{State, WebviewWindow};
+use xxhash_rust::xxh3::Xxh3;
+
+#[derive(serde::Deserialize)]
+#[serde(default)]
+pub struct DiffOptions {
+    /// Attributes that identify an element among its siblings, tried in
+    /// order. Siblings without any of them are paired by position.
+    key_attributes: Vec<String>,
+    /// Trim text and skip whitespace-only text nodes.
+    ignore_whitespace: bool,
+    /// Stop after this many changes.
+    max_changes: usize,
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        DiffOptions {
+            key_attributes: vec!["id".to_string(), "ID".to_string(), "Id".to_string(), "xmi:id".to_string()],
+            ignore_whitespace: true,
+            max_changes: 10_000,
+        }
+    }
+}
+
+#[derive(serde::Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Changed,
+}
+
+#[derive(serde::Serialize, Clone)]
+pub struct Change {
+    kind: ChangeKind,
+    // Element path with key or position predicates; `/@name` for attributes
+    // and `/text()` for text
+    xpath: String,
+    // Start of the element concerned in each file, where it exists
+    offset_a: Option<u64>,
+    offset_b: Option<u64>,
+    old_value: Option<String>,
+    new_value: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct DiffSummary {
+    added: u64,
+    removed: u64,
+    changed: u64,
+    // Stopped at `max_changes`
+    truncated: bool,
+}
+
+/// Compare two files structurally and emit a `diff-change` event per added,
+/// removed or changed element, attribute or text. Elements are paired by tag
+/// and key attribute (see `DiffOptions`), attribute order never matters, and
+/// subtrees are hashed first so only differing ones are walked. Memory is
+/// bounded by the widest set of siblings, not the file size.
+#[tauri::command]
+pub async fn diff_files(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id_a: FileId,
+    file_id_b: FileId,
+    options: Option<DiffOptions>,
+    op_id: Option<OpId>,
+) -> Result<DiffSummary, XmlReaderError> {
+    let a = files.get(file_id_a)?;
+    let b = files.get(file_id_b)?;
+    ops::run(window, op_id, "diff", move |op| {
+        let options = options.unwrap_or_default();
+        let diff = || -> Result<DiffSummary> {
+            let (root_a, root_b) = (root_offset(&a)?, root_offset(&b)?);
+            let mut summary = DiffSummary { added: 0, removed: 0, changed: 0, truncated: false };
+            let on_change = |change: Change| {
+                match change.kind {
+                    ChangeKind::Added => summary.added += 1,
+                    ChangeKind::Removed => summary.removed += 1,
+                    ChangeKind::Changed => summary.changed += 1,
+                }
+                op.emit("diff-change", change);
+            };
+            let truncated = a.consistent(|| {
+                b.consistent(|| diff_internal(&a, root_a, &b, root_b, &options, on_change, |pct| op.progress(pct)))
+            })?;
+            Ok(DiffSummary { truncated, ..summary })
+        };
+        diff().map_err(XmlReaderError::from)
+    })
+    .await
+}
+
+#[derive(serde::Serialize)]
+pub struct ElementDiff {
+    changes: Vec<Change>,
+    truncated: bool,
+}
+
+/// Structured changes between the element at `offset_a` in one file and the
+/// element at `offset_b` in the same or another file, with paths starting at
+/// the compared element. Same matching rules as `diff_files`.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn diff_elements(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id_a: FileId,
+    offset_a: u64,
+    file_id_b: FileId,
+    offset_b: u64,
+    options: Option<DiffOptions>,
+    op_id: Option<OpId>,
+) -> Result<ElementDiff, XmlReaderError> {
+    let a = files.get(file_id_a)?;
+    let b = files.get(file_id_b)?;
+    ops::run(window, op_id, "diff", move |op| {
+        let mut changes = Vec::new();
+        let options = options.unwrap_or_default();
+        let truncated = a.consistent(|| {
+            b.consistent(|| {
+                diff_internal(&a, offset_a, &b, offset_b, &options, |change| changes.push(change), |pct| op.progress(pct))
+            })
+        })?;
+        Ok(ElementDiff { changes, truncated })
+    })
+    .await
+}
+
+/// Diff the elements at `offset_a` and `offset_b`, calling `on_change` for
+/// each difference. Returns whether `max_changes` cut the diff short.
+/// `progress` (0-100) follows the two full scans, which take most of the time.
+pub fn diff_internal(
+    a: &FileHandle,
+    offset_a: u64,
+    b: &FileHandle,
+    offset_b: u64,
+    options: &DiffOptions,
+    mut on_change: impl FnMut(Change),
+    mut progress: impl FnMut(u64),
+) -> Result<bool> {
+    let mut count = 0usize;
+    // Refuses the first change past the limit, so hitting it exactly isn't
+    // reported as truncated
+    let mut sink = |change: Change| {
+        if count == options.max_changes {
+            return false;
+        }
+        count += 1;
+        on_change(change);
+        true
+    };
+    let left = scan_element(a, offset_a, options, &mut |pct| progress(pct * 45 / 100))?;
+    let right = scan_element(b, offset_b, options, &mut |pct| progress(45 + pct * 45 / 100))?;
+    let xpath = format!("/{}", left.name);
+    if left.name != right.name
+        && !sink(Change {
+            kind: ChangeKind::Changed,
+            xpath: xpath.clone(),
+            offset_a: Some(offset_a),
+            offset_b: Some(offset_b),
+            old_value: Some(left.name.clone()),
+            new_value: Some(right.name.clone()),
+        })
+    {
+        return Ok(true);
+    }
+    let finished = diff_scanned(a, &left, b, &right, &xpath, options, &mut sink)?;
+    progress(100);
+    Ok(!finished)
+}
+
+/// One child as seen from its parent's scan.
+struct Child {
+    // `name[@id='x']` or `name[n]`, unique among the siblings
+    key: String,
+    digest: u64,
+    offset: u64,
+}
+
+/// An element's own attributes and text, and a digest per child subtree.
+struct Scanned {
+    name: String,
+    offset: u64,
+    // Sorted by name
+    attributes: Vec<(String, String)>,
+    text: String,
+    children: Vec<Child>,
+}
+
+/// Returns false once the sink asked to stop.
+fn diff_scanned(
+    a: &FileHandle,
+    left: &Scanned,
+    b: &FileHandle,
+    right: &Scanned,
+    xpath: &str,
+    options: &DiffOptions,
+    sink: &mut impl FnMut(Change) -> bool,
+) -> Result<bool> {
+    let change = |kind, xpath: String, old: Option<&str>, new: Option<&str>| Change {
+        kind,
+        xpath,
+        offset_a: Some(left.offset),
+        offset_b: Some(right.offset),
+        old_value: old.map(str::to_string),
+        new_value: new.map(str::to_string),
+    };
+
+    for (name, value) in &left.attributes {
+        let other = right.attributes.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str());
+        let attr_path = format!("{}/@{}", xpath, name);
+        let keep_going = match other {
+            None => sink(change(ChangeKind::Removed, attr_path, Some(value), None)),
+            Some(other) if other != value => sink(change(ChangeKind::Changed, attr_path, Some(value), Some(other))),
+            Some(_) => true,
+        };
+        if !keep_going {
+            return Ok(false);
+        }
+    }
+    for (name, value) in &right.attributes {
+        if !left.attributes.iter().any(|(n, _)| n == name)
+            && !sink(change(ChangeKind::Added, format!("{}/@{}", xpath, name), None, Some(value)))
+        {
+            return Ok(false);
+        }
+    }
+    if left.text != right.text
+        && !sink(change(ChangeKind::Changed, format!("{}/text()", xpath), Some(&left.text), Some(&right.text)))
+    {
+        return Ok(false);
+    }
+
+    let right_keys: HashMap<&str, &Child> = right.children.iter().map(|c| (c.key.as_str(), c)).collect();
+    for child in &left.children {
+        let child_path = format!("{}/{}", xpath, child.key);
+        match right_keys.get(child.key.as_str()) {
+            None => {
+                let removed = Change {
+                    kind: ChangeKind::Removed,
+                    xpath: child_path,
+                    offset_a: Some(child.offset),
+                    offset_b: None,
+                    old_value: None,
+                    new_value: None,
+                };
+                if !sink(removed) {
+                    return Ok(false);
+                }
+            }
+            Some(other) if other.digest != child.digest => {
+                let left = scan_element(a, child.offset, options, &mut |_| {})?;
+                let right = scan_element(b, other.offset, options, &mut |_| {})?;
+                if !diff_scanned(a, &left, b, &right, &child_path, options, sink)? {
+                    return Ok(false);
+                }
+            }
+            Some(_) => {}
+        }
+    }
+    let left_keys: HashMap<&str, &Child> = left.children.iter().map(|c| (c.key.as_str(), c)).collect();
+    for child in &right.children {
+        if left_keys.contains_key(child.key.as_str()) {
+            continue;
+        }
+        let added = Change {
+            kind: ChangeKind::Added,
+            xpath: format!("{}/{}", xpath, child.key),
+            offset_a: None,
+            offset_b: Some(child.offset),
+            old_value: None,
+            new_value: None,
+        };
+        if !sink(added) {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Read the element at `offset` once, hashing each child subtree (name,
+/// sorted attributes, text and grandchildren in order) instead of keeping it.
+/// `progress` (0-100) is measured against the rest of the file, so it only
+/// reaches 100 for the root.
+fn scan_element(
+    handle: &FileHandle,
+    offset: u64,
+    options: &DiffOptions,
+    progress: &mut dyn FnMut(u64),
+) -> Result<Scanned> {
+    let mut reader = element_reader(handle, offset)?;
+    let span = (handle.len() - offset).max(1);
+    let mut last_pct = 0;
+    let mut buf = Vec::new();
+    let (name, attributes, empty) = loop {
+        buf.clear();
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(e) => break (qname(&e), sorted_attributes(&e)?, false),
+            Event::Empty(e) => break (qname(&e), sorted_attributes(&e)?, true),
+            Event::Text(e) if e.iter().all(u8::is_ascii_whitespace) => {}
+            _ => return Err(anyhow::anyhow!("No start tag found at offset {}", offset)),
+        }
+    };
+    let mut scanned = Scanned { name, offset, attributes, text: String::new(), children: Vec::new() };
+    if empty {
+        return Ok(scanned);
+    }
+
+    // Hashers of the open descendants; the first is a direct child
+    let mut open: Vec<Xxh3> = Vec::new();
+    let mut child_start = 0u64;
+    let mut child_key = String::new();
+    let mut positions: HashMap<String, u64> = HashMap::new();
+
+    loop {
+        buf.clear();
+        let pos_before = reader.position();
+        let event = reader.read_event_into(&mut buf)?;
+        match &event {
+            Event::Start(e) | Event::Empty(e) if open.is_empty() => {
+                child_start = pos_before;
+                child_key = child_key_of(e, &options.key_attributes, &mut positions)?;
+                let hasher = element_hasher(e)?;
+                // An empty child is complete right away
+                if matches!(event, Event::Empty(_)) {
+                    scanned.children.push(Child { key: std::mem::take(&mut child_key), digest: hasher.digest(), offset: pos_before });
+                } else {
+                    open.push(hasher);
+                }
+            }
+            Event::Start(e) => open.push(element_hasher(e)?),
+            Event::Empty(e) => {
+                let digest = element_hasher(e)?.digest();
+                open.last_mut().unwrap().update(&digest.to_le_bytes());
+            }
+            Event::End(_) => match open.pop() {
+                Some(hasher) => {
+                    let digest = hasher.digest();
+                    match open.last_mut() {
+                        Some(parent) => parent.update(&digest.to_le_bytes()),
+                        None => scanned.children.push(Child { key: std::mem::take(&mut child_key), digest, offset: child_start }),
+                    }
+                }
+                None => break,
+            },
+            Event::Text(e) => {
+                let text = e.unescape().map(|t| t.into_owned()).unwrap_or_else(|_| String::from_utf8_lossy(e).into_owned());
+                add_text(&mut scanned.text, open.last_mut(), &text, options.ignore_whitespace);
+            }
+            Event::CData(e) => add_text(&mut scanned.text, open.last_mut(), &String::from_utf8_lossy(e), options.ignore_whitespace),
+            Event::Eof => return Err(anyhow::anyhow!("Element is not closed before the end of the file")),
+            _ => {}
+        }
+
+        let pct = (reader.position() - offset) * 100 / span;
+        if pct > last_pct {
+            last_pct = pct;
+            progress(pct);
+        }
+    }
+    Ok(scanned)
+}
+
+fn add_text(own: &mut String, open: Option<&mut Xxh3>, text: &str, ignore_whitespace: bool) {
+    let text = if ignore_whitespace { text.trim() } else { text };
+    if text.is_empty() {
+        return;
+    }
+    match open {
+        Some(hasher) => {
+            hasher.update(b"\x01");
+            hasher.update(text.as_bytes());
+        }
+        None => own.push_str(text),
+    }
+}
+
+fn qname(e: &BytesStart) -> String {
+    String::from_utf8_lossy(e.name().as_ref()).into_owned()
+}
+
+fn sorted_attributes(e: &BytesStart) -> Result<Vec<(String, String)>> {
+    let mut attributes = Vec::new();
+    for attr in e.attributes().with_checks(false) {
+        let attr = attr?;
+        let value = attr.unescape_value().map(|v| v.into_owned()).unwrap_or_else(|_| String::from_utf8_lossy(&attr.value).into_owned());
+        attributes.push((String::from_utf8_lossy(attr.key.as_ref()).into_owned(), value));
+    }
+    attributes.sort();
+    Ok(attributes)
+}
+
+fn element_hasher(e: &BytesStart) -> Result<Xxh3> {
+    let mut hasher = Xxh3::new();
+    hasher.update(e.name().as_ref());
+    for (key, value) in sorted_attributes(e)? {
+        hasher.update(b"\x00");
+        hasher.update(key.as_bytes());
+        hasher.update(b"=");
+        hasher.update(value.as_bytes());
+    }
+    hasher.update(b"\x02");
+    Ok(hasher)
+}
+
+fn child_key_of(e: &BytesStart, key_attributes: &[String], positions: &mut HashMap<String, u64>) -> Result<String> {
+    let name = qname(e);
+    for key in key_attributes {
+        if let Some(attr) = e.try_get_attribute(key.as_str())? {
+            return Ok(format!("{}[@{}='{}']", name, key, attr.unescape_value()?));
+        }
+    }
+    let position = positions.entry(name.clone()).or_default();
+    *position += 1;
+    Ok(format!("{}[{}]", name, position))
+}
@@ -0,0 +1,1801 @@
+use crate::files::{FileHandle, FileId, FileRegistry};
+use crate::export::{element_end, namespace_decls};
+use crate::format::element_reader;
+use crate::io::{self, XmlReader};
+use crate::journal;
+use crate::ops::{self, OpId};
+use crate::save::{save_atomic, SaveError, SourceStamp};
+use crate::xml_ops::SearchTarget;
+use anyhow::{Context, Result};
+use quick_xml::events::Event;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::sync::Mutex;
+use tauri::{AppHandle, Manager, State, WebviewWindow};
+
+/// Replace the bytes in `start..end` of the file with `replacement`.
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct Splice {
+    pub start: u64,
+    pub end: u64,
+    pub replacement: Vec<u8>,
+}
+
+#[derive(serde::Serialize)]
+pub struct EditedRange {
+    offset: u64,
+    old_len: u64,
+    new_len: u64,
+}
+
+#[derive(serde::Serialize)]
+pub struct EditResult {
+    path: String,
+    new_len: u64,
+    /// Replaced ranges in file order, with `offset` in the original file.
+    edits: Vec<EditedRange>,
+}
+
+#[derive(serde::Serialize)]
+pub struct DeletedElement {
+    #[serde(flatten)]
+    edit: EditResult,
+    /// The `bookmarks` passed in, moved to where they are after the delete;
+    /// `None` for those inside the removed range.
+    bookmarks: Vec<Option<u64>>,
+}
+
+impl EditResult {
+    /// Where `offset` in the original file ended up, or `None` when it was in
+    /// a replaced range.
+    pub fn map_offset(&self, offset: u64) -> Option<u64> {
+        let mut shifted = offset as i64;
+        for edit in &self.edits {
+            if offset < edit.offset || (edit.old_len == 0 && offset == edit.offset) {
+                break;
+            }
+            if offset < edit.offset + edit.old_len {
+                return None;
+            }
+            shifted += edit.new_len as i64 - edit.old_len as i64;
+        }
+        Some(shifted as u64)
+    }
+}
+
+/// Set (or add) attribute `name` on the element starting at `offset`. Only the
+/// attribute value changes; quoting style and the rest of the file are kept
+/// byte for byte.
+#[tauri::command]
+pub async fn set_attribute(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    offset: u64,
+    name: String,
+    value: String,
+    op_id: Option<OpId>,
+) -> Result<EditResult, SaveError> {
+    let handle = files.get(file_id)?;
+    ops::run(window, op_id, "edit", move |op| {
+        Ok(apply(&handle, None, |pct| op.progress(pct), || set_attribute_splice(&handle, offset, &name, &value))?)
+    })
+    .await
+}
+
+fn set_attribute_splice(handle: &FileHandle, offset: u64, name: &str, value: &str) -> Result<Splice> {
+    if !is_name(name) {
+        return Err(anyhow::anyhow!("'{}' is not a valid attribute name", name));
+    }
+    let tag = read_start_tag(handle, offset)?;
+    let attrs = attribute_spans(&tag)?;
+    let mut splice = attribute_splice(&tag, &attrs, name, value);
+    splice.start += offset;
+    splice.end += offset;
+    Ok(splice)
+}
+
+/// Set attribute `name` in the raw start tag `tag`, with offsets relative to
+/// the tag.
+fn attribute_splice(tag: &[u8], attrs: &[AttributeSpan], name: &str, value: &str) -> Splice {
+    match attrs.iter().find(|a| &tag[a.name.clone()] == name.as_bytes()) {
+        Some(attr) => Splice {
+            start: attr.value.start as u64,
+            end: attr.value.end as u64,
+            replacement: escape_attribute(value, attr.quote).into_bytes(),
+        },
+        None => {
+            // Right after the last attribute (or the tag name), so whitespace
+            // before `>` / `/>` stays where it was
+            let at = attrs.last().map_or(name_end(tag), |a| a.value.end + 1) as u64;
+            Splice {
+                start: at,
+                end: at,
+                replacement: format!(" {}=\"{}\"", name, escape_attribute(value, b'"')).into_bytes(),
+            }
+        }
+    }
+}
+
+/// Replace the content of the text-only element starting at `offset` with
+/// `text`. An empty element (`<a/>`) is expanded to hold it.
+#[tauri::command]
+pub async fn set_element_text(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    offset: u64,
+    new_text: String,
+    op_id: Option<OpId>,
+) -> Result<EditResult, SaveError> {
+    let handle = files.get(file_id)?;
+    ops::run(window, op_id, "edit", move |op| {
+        Ok(apply(&handle, None, |pct| op.progress(pct), || set_element_text_splice(&handle, offset, &new_text))?)
+    })
+    .await
+}
+
+fn set_element_text_splice(handle: &FileHandle, offset: u64, text: &str) -> Result<Splice> {
+    let mut reader = element_reader(handle, offset)?;
+    let mut buf = Vec::new();
+    Ok(match reader.read_event_into(&mut buf)? {
+        Event::Empty(e) => {
+            let name = String::from_utf8_lossy(e.name().as_ref()).into_owned();
+            let tag_end = reader.position();
+            Splice {
+                start: tag_end - 2,
+                end: tag_end,
+                replacement: format!(">{}</{}>", escape_text(text), name).into_bytes(),
+            }
+        }
+        Event::Start(_) => {
+            let start = reader.position();
+            let end = loop {
+                buf.clear();
+                let pos_before = reader.position();
+                match reader.read_event_into(&mut buf)? {
+                    Event::End(_) => break pos_before,
+                    Event::Start(_) | Event::Empty(_) => {
+                        return Err(anyhow::anyhow!("The element has child elements; only text-only elements can be set"))
+                    }
+                    Event::Eof => return Err(anyhow::anyhow!("Element is not closed before the end of the file")),
+                    _ => {}
+                }
+            };
+            Splice {
+                start,
+                end,
+                replacement: escape_text(text).into_bytes(),
+            }
+        }
+        _ => return Err(anyhow::anyhow!("No element starts at offset {}", offset)),
+    })
+}
+
+/// Remove the element starting at `offset`, writing the result to `out_path`
+/// or back to the file. With `trim_whitespace` the whitespace before the
+/// element goes too, so it doesn't leave an empty line behind.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn delete_element(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    offset: u64,
+    out_path: Option<String>,
+    trim_whitespace: bool,
+    bookmarks: Vec<u64>,
+    op_id: Option<OpId>,
+) -> Result<DeletedElement, SaveError> {
+    let handle = files.get(file_id)?;
+    ops::run(window, op_id, "edit", move |op| {
+        let edit = apply(&handle, out_path.as_deref(), |pct| op.progress(pct), || delete_element_splice(&handle, offset, trim_whitespace))?;
+        let bookmarks = bookmarks.iter().map(|&b| edit.map_offset(b)).collect();
+        Ok(DeletedElement { edit, bookmarks })
+    })
+    .await
+}
+
+fn delete_element_splice(handle: &FileHandle, offset: u64, trim_whitespace: bool) -> Result<Splice> {
+    let mut reader = element_reader(handle, offset)?;
+    let mut buf = Vec::new();
+    let end = match reader.read_event_into(&mut buf)? {
+        Event::Empty(_) => reader.position(),
+        Event::Start(_) => element_end(&mut reader, &mut buf)?,
+        _ => return Err(anyhow::anyhow!("No element starts at offset {}", offset)),
+    };
+    let start = if trim_whitespace { whitespace_start(handle, offset)? } else { offset };
+    Ok(Splice {
+        start,
+        end,
+        replacement: Vec::new(),
+    })
+}
+
+/// Start of the run of whitespace that ends at `offset`, looking back at most
+/// 64 KB.
+fn whitespace_start(handle: &FileHandle, offset: u64) -> Result<u64> {
+    let before = read_before(handle, offset, 64 * 1024)?;
+    let kept = before.iter().rposition(|b| !b.is_ascii_whitespace()).map_or(0, |i| i + 1);
+    Ok(offset - (before.len() - kept) as u64)
+}
+
+/// Up to `max` bytes ending at `offset`.
+fn read_before(handle: &FileHandle, offset: u64, max: u64) -> Result<Vec<u8>> {
+    let from = offset.saturating_sub(max);
+    let mut before = vec![0; (offset - from) as usize];
+    let mut source = handle.reader()?;
+    source.seek(SeekFrom::Start(from))?;
+    source.read_exact(&mut before)?;
+    Ok(before)
+}
+
+#[derive(serde::Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum InsertPosition {
+    Before,
+    After,
+}
+
+/// Insert `xml_fragment` (one or more elements) as a sibling before or after
+/// the element starting at `anchor_offset`. When the anchor sits on its own
+/// line the fragment gets its own line too, with every fragment line indented
+/// like the anchor.
+#[tauri::command]
+pub async fn insert_element(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    anchor_offset: u64,
+    position: InsertPosition,
+    xml_fragment: String,
+    op_id: Option<OpId>,
+) -> Result<EditResult, SaveError> {
+    let handle = files.get(file_id)?;
+    ops::run(window, op_id, "edit", move |op| {
+        Ok(apply(&handle, None, |pct| op.progress(pct), || insert_element_splice(&handle, anchor_offset, position, &xml_fragment))?)
+    })
+    .await
+}
+
+fn insert_element_splice(handle: &FileHandle, anchor: u64, position: InsertPosition, fragment: &str) -> Result<Splice> {
+    let fragment = fragment.trim();
+    check_fragment(fragment)?;
+
+    let mut reader = element_reader(handle, anchor)?;
+    let mut buf = Vec::new();
+    let end = match reader.read_event_into(&mut buf)? {
+        Event::Empty(_) => reader.position(),
+        Event::Start(_) => element_end(&mut reader, &mut buf)?,
+        _ => return Err(anyhow::anyhow!("No element starts at offset {}", anchor)),
+    };
+
+    let text = match line_indent(handle, anchor)? {
+        Some((newline, indent)) => {
+            let fragment = fragment.lines().collect::<Vec<_>>().join(&format!("{}{}", newline, indent));
+            match position {
+                InsertPosition::Before => format!("{}{}{}", fragment, newline, indent),
+                InsertPosition::After => format!("{}{}{}", newline, indent, fragment),
+            }
+        }
+        None => fragment.to_string(),
+    };
+    let at = match position {
+        InsertPosition::Before => anchor,
+        InsertPosition::After => end,
+    };
+    Ok(Splice {
+        start: at,
+        end: at,
+        replacement: text.into_bytes(),
+    })
+}
+
+/// Line break and indentation in front of `offset`, if only spaces and tabs
+/// separate it from the start of its line.
+fn line_indent(handle: &FileHandle, offset: u64) -> Result<Option<(String, String)>> {
+    let before = read_before(handle, offset, 4096)?;
+    let Some(nl) = before.iter().rposition(|&b| b == b'\n') else {
+        return Ok(None);
+    };
+    let indent = &before[nl + 1..];
+    if !indent.iter().all(|&b| b == b' ' || b == b'\t') {
+        return Ok(None);
+    }
+    let newline = if nl > 0 && before[nl - 1] == b'\r' { "\r\n" } else { "\n" };
+    Ok(Some((newline.to_string(), String::from_utf8_lossy(indent).into_owned())))
+}
+
+/// A fragment must be one or more complete elements, with nothing but
+/// whitespace, comments and PIs between them.
+fn check_fragment(fragment: &str) -> Result<()> {
+    let mut reader = quick_xml::Reader::from_str(fragment);
+    let mut depth = 0u32;
+    let mut elements = 0;
+    loop {
+        let event = reader
+            .read_event()
+            .map_err(|e| anyhow::anyhow!("Fragment is not well-formed at position {}: {}", reader.buffer_position(), e))?;
+        if let Event::Start(e) | Event::Empty(e) = &event {
+            for attr in e.attributes() {
+                attr.map_err(|e| anyhow::anyhow!("Fragment has a malformed attribute: {}", e))?;
+            }
+        }
+        match event {
+            Event::Start(_) => depth += 1,
+            Event::End(_) => {
+                depth -= 1;
+                if depth == 0 {
+                    elements += 1;
+                }
+            }
+            Event::Empty(_) if depth == 0 => elements += 1,
+            Event::Text(t) if depth == 0 && !t.iter().all(u8::is_ascii_whitespace) => {
+                return Err(anyhow::anyhow!("Fragment has text outside of its elements"))
+            }
+            Event::CData(_) if depth == 0 => return Err(anyhow::anyhow!("Fragment has text outside of its elements")),
+            Event::Decl(_) | Event::DocType(_) => return Err(anyhow::anyhow!("Fragment can't have a declaration or DOCTYPE")),
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    match (depth, elements) {
+        (0, 0) => Err(anyhow::anyhow!("Fragment has no element")),
+        (0, _) => Ok(()),
+        _ => Err(anyhow::anyhow!("Fragment has unclosed elements")),
+    }
+}
+
+/// Changes listed by `replace_all`; the rest are only counted.
+const REPLACE_PREVIEW_LIMIT: usize = 10_000;
+
+#[derive(serde::Serialize)]
+pub struct PlannedChange {
+    offset: u64,
+    before: String,
+    after: String,
+}
+
+#[derive(serde::Serialize)]
+pub struct ReplaceResult {
+    /// The first `REPLACE_PREVIEW_LIMIT` changes, in file order.
+    changes: Vec<PlannedChange>,
+    total: u64,
+    /// `None` on a dry run.
+    edit: Option<EditResult>,
+}
+
+/// Replace every case-insensitive occurrence of `query` in what `search_node`
+/// searches for `search_type`: attribute values, and tag names (end tags
+/// included) for `tag` / `any`. Values are matched with entities expanded,
+/// so `&` finds `&amp;` and `amp` doesn't. With `dry_run` only the planned
+/// changes are returned; otherwise the file is rewritten once with all of
+/// them. Emits `replace-progress` (0-100).
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn replace_all(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    query: String,
+    search_type: String,
+    replacement: String,
+    dry_run: bool,
+    op_id: Option<OpId>,
+) -> Result<ReplaceResult, SaveError> {
+    let handle = files.get(file_id)?;
+    ops::run(window, op_id, "replace", move |op| {
+        Ok(replace_all_internal(&handle, &query, &search_type, &replacement, dry_run, |pct| {
+            op.progress(pct);
+        })?)
+    })
+    .await
+}
+
+fn replace_all_internal(
+    handle: &FileHandle,
+    query: &str,
+    search_type: &str,
+    replacement: &str,
+    dry_run: bool,
+    mut progress: impl FnMut(u64),
+) -> Result<ReplaceResult> {
+    if query.is_empty() {
+        return Err(anyhow::anyhow!("Nothing to replace"));
+    }
+    let needle = folded(query);
+    let target = SearchTarget::new(search_type);
+    let tags = target.tags();
+    let since = source_stamp(handle)?;
+
+    let file = handle.reader()?;
+    let file_len = file.len().max(1);
+    let mut reader = XmlReader::scan(file);
+    reader.check_end_names(false);
+
+    let mut splices = Vec::new();
+    let mut changes = Vec::new();
+    let mut plan = |start: u64, before: &[u8], after: Vec<u8>| {
+        if changes.len() < REPLACE_PREVIEW_LIMIT {
+            changes.push(PlannedChange {
+                offset: start,
+                before: String::from_utf8_lossy(before).into_owned(),
+                after: String::from_utf8_lossy(&after).into_owned(),
+            });
+        }
+        splices.push(Splice {
+            start,
+            end: start + before.len() as u64,
+            replacement: after,
+        });
+    };
+    // New names of the open elements, for their end tags
+    let mut renamed: Vec<Option<Vec<u8>>> = Vec::new();
+    let mut buf = Vec::new();
+    let mut last_pct = 0;
+
+    loop {
+        buf.clear();
+        let pos_before = reader.position();
+        let event = reader.read_event_into(&mut buf)?;
+        match &event {
+            Event::Start(e) | Event::Empty(e) => {
+                let mut tag = Vec::with_capacity(e.len() + 1);
+                tag.push(b'<');
+                tag.extend_from_slice(e);
+                let name = &tag[1..name_end(&tag)];
+                let new_name = match std::str::from_utf8(name) {
+                    Ok(name) if tags => replace_ignore_case(name, &needle, replacement).map(String::into_bytes),
+                    _ => None,
+                };
+                if let Some(new_name) = &new_name {
+                    if !std::str::from_utf8(new_name).is_ok_and(is_name) {
+                        return Err(anyhow::anyhow!(
+                            "Replacing in <{}> would give the invalid name '{}'",
+                            String::from_utf8_lossy(name),
+                            String::from_utf8_lossy(new_name)
+                        ));
+                    }
+                    plan(pos_before + 1, name, new_name.clone());
+                }
+                for attr in attribute_spans(&tag)? {
+                    if !target.attribute(&tag[attr.name]) {
+                        continue;
+                    }
+                    // Values with entities of the DTD can't be expanded here and are left alone
+                    let Some(value) = attribute_value(&tag[attr.value.clone()]) else {
+                        continue;
+                    };
+                    if let Some(value) = replace_ignore_case(&value, &needle, replacement) {
+                        plan(pos_before + attr.value.start as u64, &tag[attr.value], escape_attribute(&value, attr.quote).into_bytes());
+                    }
+                }
+                if matches!(event, Event::Start(_)) {
+                    renamed.push(new_name);
+                }
+            }
+            Event::End(e) => {
+                if let Some(Some(new_name)) = renamed.pop() {
+                    plan(pos_before + 2, e.name().as_ref(), new_name);
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+
+        let pct = reader.position() * 100 / file_len;
+        if pct > last_pct {
+            last_pct = pct;
+            progress(if dry_run { pct } else { pct / 2 });
+        }
+    }
+
+    let total = splices.len() as u64;
+    let edit = if dry_run || splices.is_empty() {
+        None
+    } else {
+        Some(rewrite_with_progress(handle, splices, None, since.as_ref(), |pct| progress(50 + pct / 2))?)
+    };
+    progress(100);
+    Ok(ReplaceResult { changes, total, edit })
+}
+
+/// `text` lowercased the way `replace_ignore_case` compares it.
+fn folded(text: &str) -> Vec<char> {
+    text.chars().flat_map(char::to_lowercase).collect()
+}
+
+/// `haystack` with every case-insensitive occurrence of `needle` (see
+/// `folded`) replaced, or `None` if there is none.
+fn replace_ignore_case(haystack: &str, needle: &[char], replacement: &str) -> Option<String> {
+    let mut out = String::new();
+    let mut rest = haystack;
+    let mut found = false;
+    while let Some(c) = rest.chars().next() {
+        match folded_prefix(rest, needle) {
+            Some(len) => {
+                out.push_str(replacement);
+                rest = &rest[len..];
+                found = true;
+            }
+            None => {
+                out.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    found.then_some(out)
+}
+
+/// Length in bytes of the start of `text` that lowercases to `needle`, if
+/// whole characters of it do.
+fn folded_prefix(text: &str, needle: &[char]) -> Option<usize> {
+    let mut matched = 0;
+    for (i, c) in text.char_indices() {
+        if matched == needle.len() {
+            return Some(i);
+        }
+        for lower in c.to_lowercase() {
+            if needle.get(matched) != Some(&lower) {
+                return None;
+            }
+            matched += 1;
+        }
+    }
+    (matched == needle.len()).then_some(text.len())
+}
+
+/// The value of an attribute as written between its quotes, normalized and
+/// with entities expanded, or `None` if it uses entities declared in a DTD.
+fn attribute_value(raw: &[u8]) -> Option<String> {
+    let raw = std::str::from_utf8(raw).ok()?;
+    // Literal line breaks and tabs read as spaces (attribute normalization)
+    let raw = raw.replace("\r\n", " ").replace(['\t', '\n', '\r'], " ");
+    quick_xml::escape::unescape(&raw).ok().map(|value| value.into_owned())
+}
+
+#[derive(serde::Serialize)]
+pub struct RenamedTags {
+    /// Start, end and empty-element tags renamed (or that would be).
+    tags: u64,
+    /// `None` on a dry run.
+    edit: Option<EditResult>,
+}
+
+/// Rename every `old_name` element to `new_name`, touching only the names in
+/// its tags. Without `namespace_aware` names are compared as written
+/// (`ns:Item`). With it, `old_name` is `{uri}local` (or a bare local name
+/// for no namespace), matched whatever prefix the file uses, and `new_name`
+/// is the new local name; prefixes are kept. With `dry_run` only the tags are
+/// counted. Emits `rename-progress` (0-100).
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn rename_tag(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    old_name: String,
+    new_name: String,
+    namespace_aware: bool,
+    dry_run: bool,
+    op_id: Option<OpId>,
+) -> Result<RenamedTags, SaveError> {
+    let handle = files.get(file_id)?;
+    ops::run(window, op_id, "rename", move |op| {
+        Ok(rename_tag_internal(&handle, &old_name, &new_name, namespace_aware, dry_run, |pct| {
+            op.progress(pct);
+        })?)
+    })
+    .await
+}
+
+fn rename_tag_internal(
+    handle: &FileHandle,
+    old_name: &str,
+    new_name: &str,
+    namespace_aware: bool,
+    dry_run: bool,
+    mut progress: impl FnMut(u64),
+) -> Result<RenamedTags> {
+    if !is_name(new_name) || (namespace_aware && new_name.contains(':')) {
+        return Err(anyhow::anyhow!("'{}' is not a valid element name", new_name));
+    }
+    // (namespace, local name) when namespace aware
+    let wanted = match old_name.strip_prefix('{').and_then(|rest| rest.split_once('}')) {
+        Some((uri, local)) => (uri, local),
+        None => ("", old_name),
+    };
+    let since = source_stamp(handle)?;
+
+    let file = handle.reader()?;
+    let file_len = file.len().max(1);
+    let mut reader = XmlReader::scan(file);
+    reader.check_end_names(false);
+
+    let mut splices = Vec::new();
+    struct Open {
+        decls: Vec<(String, String)>,
+        renamed: Option<Vec<u8>>,
+    }
+    let mut stack: Vec<Open> = Vec::new();
+    let mut buf = Vec::new();
+    let mut last_pct = 0;
+
+    loop {
+        buf.clear();
+        let pos_before = reader.position();
+        let event = reader.read_event_into(&mut buf)?;
+        match &event {
+            Event::Start(e) | Event::Empty(e) => {
+                let decls = if namespace_aware { namespace_decls(e) } else { Vec::new() };
+                let qname = e.name();
+                let renamed = if namespace_aware {
+                    let (prefix, local) = match qname.as_ref().iter().position(|&b| b == b':') {
+                        Some(i) => (&qname.as_ref()[..i], &qname.as_ref()[i + 1..]),
+                        None => (&b""[..], qname.as_ref()),
+                    };
+                    let key = if prefix.is_empty() {
+                        "xmlns".to_string()
+                    } else {
+                        format!("xmlns:{}", String::from_utf8_lossy(prefix))
+                    };
+                    let uri = decls
+                        .iter()
+                        .chain(stack.iter().rev().flat_map(|open| open.decls.iter()))
+                        .find(|(k, _)| *k == key)
+                        .map_or("", |(_, v)| v.as_str());
+                    (uri == wanted.0 && local == wanted.1.as_bytes()).then(|| {
+                        let mut name = prefix.to_vec();
+                        if !prefix.is_empty() {
+                            name.push(b':');
+                        }
+                        name.extend_from_slice(new_name.as_bytes());
+                        name
+                    })
+                } else {
+                    (qname.as_ref() == old_name.as_bytes()).then(|| new_name.as_bytes().to_vec())
+                };
+                if let Some(name) = &renamed {
+                    splices.push(Splice {
+                        start: pos_before + 1,
+                        end: pos_before + 1 + qname.as_ref().len() as u64,
+                        replacement: name.clone(),
+                    });
+                }
+                if matches!(event, Event::Start(_)) {
+                    stack.push(Open { decls, renamed });
+                }
+            }
+            Event::End(e) => {
+                if let Some(Open { renamed: Some(name), .. }) = stack.pop() {
+                    splices.push(Splice {
+                        start: pos_before + 2,
+                        end: pos_before + 2 + e.name().as_ref().len() as u64,
+                        replacement: name,
+                    });
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+
+        let pct = reader.position() * 100 / file_len;
+        if pct > last_pct {
+            last_pct = pct;
+            progress(if dry_run { pct } else { pct / 2 });
+        }
+    }
+
+    let tags = splices.len() as u64;
+    let edit = if dry_run || splices.is_empty() {
+        None
+    } else {
+        Some(rewrite_with_progress(handle, splices, None, since.as_ref(), |pct| progress(50 + pct / 2))?)
+    };
+    progress(100);
+    Ok(RenamedTags { tags, edit })
+}
+
+/// Children of a sorted element are reordered in memory.
+const SORT_MAX_BYTES: u64 = 512 * 1024 * 1024;
+
+/// Reorder the direct children of the element at `parent_offset` by their
+/// `by_attr` value, as numbers with `numeric`. The sort is stable and
+/// children without the attribute (or, with `numeric`, without a number in
+/// it) go last. Whitespace, text and comments between children stay where
+/// they are, so indentation is unchanged. Writes to `out_path`, or back to
+/// the file.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn sort_children(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    parent_offset: u64,
+    by_attr: String,
+    numeric: bool,
+    out_path: Option<String>,
+    op_id: Option<OpId>,
+) -> Result<EditResult, SaveError> {
+    let handle = files.get(file_id)?;
+    ops::run(window, op_id, "edit", move |op| {
+        let since = source_stamp(&handle)?;
+        let splices = sort_children_splices(&handle, parent_offset, &by_attr, numeric)?;
+        Ok(rewrite_with_progress(&handle, splices, out_path.as_deref(), since.as_ref(), |pct| op.progress(pct))?)
+    })
+    .await
+}
+
+fn sort_children_splices(handle: &FileHandle, parent_offset: u64, by_attr: &str, numeric: bool) -> Result<Vec<Splice>> {
+    #[derive(PartialEq, PartialOrd)]
+    enum Key {
+        Number(f64),
+        Text(String),
+        Missing,
+    }
+
+    let mut reader = element_reader(handle, parent_offset)?;
+    let mut buf = Vec::new();
+    match reader.read_event_into(&mut buf)? {
+        Event::Start(_) => {}
+        Event::Empty(_) => return Ok(Vec::new()),
+        _ => return Err(anyhow::anyhow!("No element starts at offset {}", parent_offset)),
+    }
+
+    // (start, end, key) of each child
+    let mut children = Vec::new();
+    loop {
+        buf.clear();
+        let pos_before = reader.position();
+        let event = reader.read_event_into(&mut buf)?;
+        let e = match &event {
+            Event::Start(e) | Event::Empty(e) => e,
+            Event::End(_) => break,
+            Event::Eof => return Err(anyhow::anyhow!("Element is not closed before the end of the file")),
+            _ => continue,
+        };
+        let value = e
+            .attributes()
+            .flatten()
+            .find(|a| a.key.as_ref() == by_attr.as_bytes())
+            .map(|a| a.unescape_value().map(|v| v.into_owned()).unwrap_or_else(|_| String::from_utf8_lossy(&a.value).into_owned()));
+        let key = match value {
+            Some(v) if numeric => v.trim().parse().ok().filter(|n: &f64| !n.is_nan()).map_or(Key::Missing, Key::Number),
+            Some(v) => Key::Text(v),
+            None => Key::Missing,
+        };
+        let end = if matches!(event, Event::Start(_)) { element_end(&mut reader, &mut buf)? } else { reader.position() };
+        children.push((pos_before, end, key));
+    }
+
+    let total: u64 = children.iter().map(|(start, end, _)| end - start).sum();
+    if total > SORT_MAX_BYTES {
+        return Err(anyhow::anyhow!("The children take {} MB, too much to sort in memory", total / (1024 * 1024)));
+    }
+    let mut order: Vec<usize> = (0..children.len()).collect();
+    // NaN is kept out of the keys, so every pair compares
+    order.sort_by(|&a, &b| children[a].2.partial_cmp(&children[b].2).unwrap());
+
+    let mut source = handle.reader()?;
+    let mut splices = Vec::new();
+    for (slot, &child) in order.iter().enumerate() {
+        if slot == child {
+            continue;
+        }
+        let (start, end, _) = children[child];
+        let mut bytes = vec![0; (end - start) as usize];
+        source.seek(SeekFrom::Start(start))?;
+        source.read_exact(&mut bytes)?;
+        splices.push(Splice {
+            start: children[slot].0,
+            end: children[slot].1,
+            replacement: bytes,
+        });
+    }
+    Ok(splices)
+}
+
+/// CSV keys reported back as never found.
+const CSV_UNMATCHED_LIMIT: usize = 1000;
+
+#[derive(serde::Serialize)]
+pub struct CsvEdits {
+    /// Mappings read from the CSV.
+    rows: usize,
+    /// Elements whose `set_attr` was set.
+    updated: u64,
+    /// CSV keys no element matched, up to `CSV_UNMATCHED_LIMIT`.
+    unmatched: Vec<String>,
+    edit: Option<EditResult>,
+}
+
+/// Set `set_attr` on every element whose `match_attr` value is in the first
+/// column of the CSV at `csv_path`, to the value in the second column, in one
+/// pass over the file. A header row naming `match_attr` is skipped. Writes to
+/// `out_path`, or back to the file. Emits `csv-edits-progress` (0-100).
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn apply_csv_edits(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    csv_path: String,
+    match_attr: String,
+    set_attr: String,
+    out_path: Option<String>,
+    op_id: Option<OpId>,
+) -> Result<CsvEdits, SaveError> {
+    let handle = files.get(file_id)?;
+    ops::run(window, op_id, "csv-edits", move |op| {
+        Ok(apply_csv_edits_internal(&handle, &csv_path, &match_attr, &set_attr, out_path.as_deref(), |pct| {
+            op.progress(pct);
+        })?)
+    })
+    .await
+}
+
+fn apply_csv_edits_internal(
+    handle: &FileHandle,
+    csv_path: &str,
+    match_attr: &str,
+    set_attr: &str,
+    out_path: Option<&str>,
+    mut progress: impl FnMut(u64),
+) -> Result<CsvEdits> {
+    if !is_name(set_attr) {
+        return Err(anyhow::anyhow!("'{}' is not a valid attribute name", set_attr));
+    }
+    let csv = std::fs::read_to_string(csv_path).with_context(|| format!("Could not read {}", csv_path))?;
+    let mut mapping: HashMap<String, (String, bool)> = HashMap::new();
+    for (i, record) in parse_csv(&csv).into_iter().enumerate() {
+        match record.as_slice() {
+            [key, ..] if i == 0 && key.trim() == match_attr => {}
+            [key, value, ..] => {
+                mapping.insert(key.clone(), (value.clone(), false));
+            }
+            [key] if key.is_empty() => {}
+            _ => return Err(anyhow::anyhow!("Row {} of {} needs two columns", i + 1, csv_path)),
+        }
+    }
+    let since = source_stamp(handle)?;
+
+    let file = handle.reader()?;
+    let file_len = file.len().max(1);
+    let mut reader = XmlReader::scan(file);
+    reader.check_end_names(false);
+    let mut splices = Vec::new();
+    let mut buf = Vec::new();
+    let mut last_pct = 0;
+
+    loop {
+        buf.clear();
+        let pos_before = reader.position();
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(e) | Event::Empty(e) => {
+                let mut tag = Vec::with_capacity(e.len() + 1);
+                tag.push(b'<');
+                tag.extend_from_slice(&e);
+                let attrs = attribute_spans(&tag)?;
+                let Some(key) = attrs.iter().find(|a| &tag[a.name.clone()] == match_attr.as_bytes()).map(|a| {
+                    let raw = String::from_utf8_lossy(&tag[a.value.clone()]);
+                    quick_xml::escape::unescape(&raw).map(|v| v.into_owned()).unwrap_or_else(|_| raw.into_owned())
+                }) else {
+                    continue;
+                };
+                if let Some((value, used)) = mapping.get_mut(&key) {
+                    *used = true;
+                    let mut splice = attribute_splice(&tag, &attrs, set_attr, value);
+                    splice.start += pos_before;
+                    splice.end += pos_before;
+                    splices.push(splice);
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+
+        let pct = reader.position() * 100 / file_len;
+        if pct > last_pct {
+            last_pct = pct;
+            progress(pct / 2);
+        }
+    }
+
+    let updated = splices.len() as u64;
+    let mut unmatched: Vec<String> = mapping.iter().filter(|(_, (_, used))| !used).map(|(key, _)| key.clone()).collect();
+    unmatched.sort();
+    unmatched.truncate(CSV_UNMATCHED_LIMIT);
+    let edit = if splices.is_empty() && out_path.is_none() {
+        None
+    } else {
+        Some(rewrite_with_progress(handle, splices, out_path, since.as_ref(), |pct| progress(50 + pct / 2))?)
+    };
+    progress(100);
+    Ok(CsvEdits {
+        rows: mapping.len(),
+        updated,
+        unmatched,
+        edit,
+    })
+}
+
+/// Records of a CSV file: comma separated, fields optionally in double quotes
+/// with `""` for a quote inside them.
+fn parse_csv(text: &str) -> Vec<Vec<String>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text.trim_start_matches('\u{feff}').chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' if quoted => quoted = false,
+            '"' if field.is_empty() => quoted = true,
+            ',' if !quoted => record.push(std::mem::take(&mut field)),
+            '\r' if !quoted && chars.peek() == Some(&'\n') => {}
+            '\n' if !quoted => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            c => field.push(c),
+        }
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    records
+}
+
+/// Elements commented out are read into memory to escape them.
+const COMMENT_MAX_BYTES: u64 = 64 * 1024 * 1024;
+/// How `comment_out_element` writes a `-` and an `&` it has to escape.
+const COMMENT_DASH: &[u8] = b"&#45;";
+const COMMENT_AMP: &[u8] = b"&#38;";
+
+/// Turn the element at `offset` into a comment, e.g. to disable a block of
+/// configuration. `--` can't appear in a comment, so every `-` right after
+/// another one (and a trailing `-`) is written as `&#45;`, and the `&` of an
+/// `&#45;` or `&#38;` already there as `&#38;`, so `uncomment_element` gets
+/// the element back byte for byte.
+#[tauri::command]
+pub async fn comment_out_element(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    offset: u64,
+    op_id: Option<OpId>,
+) -> Result<EditResult, SaveError> {
+    let handle = files.get(file_id)?;
+    ops::run(window, op_id, "edit", move |op| {
+        Ok(apply(&handle, None, |pct| op.progress(pct), || comment_out_splice(&handle, offset))?)
+    })
+    .await
+}
+
+fn comment_out_splice(handle: &FileHandle, offset: u64) -> Result<Splice> {
+    let mut reader = element_reader(handle, offset)?;
+    let mut buf = Vec::new();
+    let end = match reader.read_event_into(&mut buf)? {
+        Event::Empty(_) => reader.position(),
+        Event::Start(_) => element_end(&mut reader, &mut buf)?,
+        _ => return Err(anyhow::anyhow!("No element starts at offset {}", offset)),
+    };
+    if end - offset > COMMENT_MAX_BYTES {
+        return Err(anyhow::anyhow!("The element is too large to comment out ({} MB)", (end - offset) / (1024 * 1024)));
+    }
+    let mut element = vec![0; (end - offset) as usize];
+    let mut source = handle.reader()?;
+    source.seek(SeekFrom::Start(offset))?;
+    source.read_exact(&mut element)?;
+
+    let mut comment = Vec::with_capacity(element.len() + 16);
+    comment.extend_from_slice(b"<!--");
+    for (i, &b) in element.iter().enumerate() {
+        if b == b'-' && (comment.last() == Some(&b'-') || i + 1 == element.len()) {
+            comment.extend_from_slice(COMMENT_DASH);
+        } else if b == b'&' && (element[i..].starts_with(COMMENT_DASH) || element[i..].starts_with(COMMENT_AMP)) {
+            comment.extend_from_slice(COMMENT_AMP);
+        } else {
+            comment.push(b);
+        }
+    }
+    comment.extend_from_slice(b"-->");
+    Ok(Splice {
+        start: offset,
+        end,
+        replacement: comment,
+    })
+}
+
+/// Turn the comment at `offset`, as written by `comment_out_element`, back
+/// into the element it held. Refused if the comment doesn't hold one or more
+/// whole elements.
+#[tauri::command]
+pub async fn uncomment_element(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    offset: u64,
+    op_id: Option<OpId>,
+) -> Result<EditResult, SaveError> {
+    let handle = files.get(file_id)?;
+    ops::run(window, op_id, "edit", move |op| {
+        Ok(apply(&handle, None, |pct| op.progress(pct), || uncomment_splice(&handle, offset))?)
+    })
+    .await
+}
+
+fn uncomment_splice(handle: &FileHandle, offset: u64) -> Result<Splice> {
+    let mut reader = element_reader(handle, offset)?;
+    let mut buf = Vec::new();
+    let Event::Comment(comment) = reader.read_event_into(&mut buf)? else {
+        return Err(anyhow::anyhow!("No comment starts at offset {}", offset));
+    };
+    let end = reader.position();
+    if end - offset > COMMENT_MAX_BYTES {
+        return Err(anyhow::anyhow!("The comment is too large to uncomment ({} MB)", (end - offset) / (1024 * 1024)));
+    }
+
+    let mut element = Vec::with_capacity(comment.len());
+    let mut rest = &comment[..];
+    while let Some(&b) = rest.first() {
+        if rest.starts_with(COMMENT_DASH) {
+            element.push(b'-');
+            rest = &rest[COMMENT_DASH.len()..];
+        } else if rest.starts_with(COMMENT_AMP) {
+            element.push(b'&');
+            rest = &rest[COMMENT_AMP.len()..];
+        } else {
+            element.push(b);
+            rest = &rest[1..];
+        }
+    }
+    let text = std::str::from_utf8(&element).map_err(|_| anyhow::anyhow!("The comment is not valid UTF-8"))?;
+    check_fragment(text.trim()).context("The comment doesn't hold an element")?;
+    Ok(Splice {
+        start: offset,
+        end,
+        replacement: element,
+    })
+}
+
+/// Bytes of each side shown by `preview_changes`.
+const STAGED_PREVIEW_BYTES: u64 = 4096;
+
+/// A change staged in an edit session, with offsets in the file as it was
+/// when the session began.
+#[derive(serde::Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum StagedChange {
+    SetAttribute {
+        offset: u64,
+        name: String,
+        value: String,
+    },
+    SetText {
+        offset: u64,
+        text: String,
+    },
+    Delete {
+        offset: u64,
+        #[serde(default)]
+        trim_whitespace: bool,
+    },
+    Insert {
+        anchor_offset: u64,
+        position: InsertPosition,
+        xml_fragment: String,
+    },
+}
+
+struct EditSession {
+    // The file as it was when the session began, which staged offsets refer to
+    since: SourceStamp,
+    splices: Vec<Splice>,
+}
+
+/// Changes staged per file, applied together by `commit_edit` so a huge file
+/// is rewritten once instead of once per change. Kept in Tauri managed state.
+#[derive(Default)]
+pub struct EditSessions {
+    sessions: Mutex<HashMap<FileId, EditSession>>,
+}
+
+impl EditSessions {
+    fn with_session<T>(&self, file_id: FileId, f: impl FnOnce(&mut EditSession) -> Result<T>) -> Result<T> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions
+            .get_mut(&file_id)
+            .ok_or_else(|| anyhow::anyhow!("No edit session for file id {}", file_id))?;
+        f(session)
+    }
+
+    /// The changes staged for `file_id` and the stamp of the file they were
+    /// planned against, for the crash journal.
+    pub fn staged(&self, file_id: FileId) -> Option<(SourceStamp, Vec<Splice>)> {
+        let sessions = self.sessions.lock().unwrap();
+        let session = sessions.get(&file_id)?;
+        Some((session.since.clone(), session.splices.clone()))
+    }
+
+    /// Reopen a session with changes recovered from the crash journal.
+    pub fn restore(&self, file_id: FileId, since: SourceStamp, splices: Vec<Splice>) {
+        self.sessions.lock().unwrap().insert(file_id, EditSession { since, splices });
+    }
+}
+
+/// Start staging changes for a file, dropping any session already open for it.
+#[tauri::command]
+pub async fn begin_edit(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    op_id: Option<OpId>,
+) -> Result<(), SaveError> {
+    let handle = files.get(file_id)?;
+    let app = window.app_handle().clone();
+    ops::run(window, op_id, "edit", move |_| {
+        let since = source_stamp(&handle)?.ok_or_else(|| SaveError::NotWritable { path: handle.path.clone() })?;
+        app.state::<EditSessions>().sessions.lock().unwrap().insert(
+            file_id,
+            EditSession {
+                since,
+                splices: Vec::new(),
+            },
+        );
+        journal::record(&app);
+        Ok(())
+    })
+    .await
+}
+
+/// Check `change` against the file and stage it. Changes that overlap one
+/// already staged are refused. Returns the number of staged changes.
+#[tauri::command]
+pub async fn stage_change(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    change: StagedChange,
+    op_id: Option<OpId>,
+) -> Result<usize, SaveError> {
+    let handle = files.get(file_id)?;
+    let app = window.app_handle().clone();
+    ops::run(window, op_id, "edit", move |_| {
+        let splice = match change {
+            StagedChange::SetAttribute { offset, name, value } => set_attribute_splice(&handle, offset, &name, &value),
+            StagedChange::SetText { offset, text } => set_element_text_splice(&handle, offset, &text),
+            StagedChange::Delete { offset, trim_whitespace } => delete_element_splice(&handle, offset, trim_whitespace),
+            StagedChange::Insert {
+                anchor_offset,
+                position,
+                xml_fragment,
+            } => insert_element_splice(&handle, anchor_offset, position, &xml_fragment),
+        }?;
+
+        let staged = app.state::<EditSessions>().with_session(file_id, |session| {
+            if session.splices.iter().any(|s| splice.start < s.end && s.start < splice.end) {
+                return Err(anyhow::anyhow!("The change overlaps one already staged"));
+            }
+            session.splices.push(splice);
+            Ok(session.splices.len())
+        })?;
+        journal::record(&app);
+        Ok(staged)
+    })
+    .await
+}
+
+/// The staged changes in file order, as the bytes they replace and the bytes
+/// replacing them (each cut to `STAGED_PREVIEW_BYTES`).
+#[tauri::command]
+pub async fn preview_changes(
+    files: State<'_, FileRegistry>,
+    sessions: State<'_, EditSessions>,
+    file_id: FileId,
+) -> Result<Vec<PlannedChange>, SaveError> {
+    let handle = files.get(file_id)?;
+    Ok(sessions.with_session(file_id, |session| {
+        session.splices.sort_by_key(|s| s.start);
+        let mut source = handle.reader()?;
+        session
+            .splices
+            .iter()
+            .map(|s| {
+                let mut before = Vec::new();
+                source.seek(SeekFrom::Start(s.start))?;
+                (&mut source).take((s.end - s.start).min(STAGED_PREVIEW_BYTES)).read_to_end(&mut before)?;
+                let after = &s.replacement[..s.replacement.len().min(STAGED_PREVIEW_BYTES as usize)];
+                Ok(PlannedChange {
+                    offset: s.start,
+                    before: String::from_utf8_lossy(&before).into_owned(),
+                    after: String::from_utf8_lossy(after).into_owned(),
+                })
+            })
+            .collect()
+    })?)
+}
+
+/// Apply every staged change in one rewrite and close the session. The
+/// session stays open if the rewrite fails, and the rewrite is refused if the
+/// file changed since `begin_edit`. Emits `edit-progress` (0-100).
+#[tauri::command]
+pub async fn commit_edit(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    op_id: Option<OpId>,
+) -> Result<EditResult, SaveError> {
+    let handle = files.get(file_id)?;
+    let app = window.app_handle().clone();
+    ops::run(window, op_id, "edit", move |op| {
+        let sessions = app.state::<EditSessions>();
+        let (splices, since) =
+            sessions.with_session(file_id, |session| Ok((session.splices.clone(), session.since.clone())))?;
+        let result = rewrite_with_progress(&handle, splices, None, Some(&since), |pct| op.progress(pct))?;
+        sessions.sessions.lock().unwrap().remove(&file_id);
+        journal::record(&app);
+        Ok(result)
+    })
+    .await
+}
+
+/// Drop the staged changes without touching the file.
+#[tauri::command]
+pub async fn abort_edit(app: AppHandle, sessions: State<'_, EditSessions>, file_id: FileId) -> Result<(), SaveError> {
+    sessions.sessions.lock().unwrap().remove(&file_id);
+    journal::record(&app);
+    Ok(())
+}
+
+/// Stamp of the file an edit will be written back to, taken before planning
+/// it; `None` for files that are only read from a copy.
+fn source_stamp(handle: &FileHandle) -> Result<Option<SourceStamp>> {
+    handle.local_path().map(SourceStamp::of).transpose()
+}
+
+/// Plan a single change and write it, refusing if the file changes in between.
+fn apply(
+    handle: &FileHandle,
+    out_path: Option<&str>,
+    progress: impl FnMut(u64),
+    plan: impl FnOnce() -> Result<Splice>,
+) -> Result<EditResult> {
+    let since = source_stamp(handle)?;
+    let splice = plan()?;
+    rewrite_with_progress(handle, vec![splice], out_path, since.as_ref(), progress)
+}
+
+/// Stream the file with `splices` applied into `out_path`, or over the file
+/// itself when it is `None` or names the file some other way, which is
+/// re-opened afterwards. Saved through `save_atomic`, with `since` the stamp
+/// the splices were planned against. Reports how far through the file the
+/// copy is (0-100).
+pub fn rewrite_with_progress(
+    handle: &FileHandle,
+    mut splices: Vec<Splice>,
+    out_path: Option<&str>,
+    since: Option<&SourceStamp>,
+    progress: impl FnMut(u64),
+) -> Result<EditResult> {
+    let in_place = out_path.is_none_or(|path| handle.is_source(path));
+    let target = match (out_path, handle.local_path()) {
+        (_, Some(path)) if in_place => path.to_string(),
+        (Some(path), _) if !in_place => path.to_string(),
+        _ => return Err(SaveError::NotWritable { path: handle.path.clone() }.into()),
+    };
+    splices.sort_by_key(|s| s.start);
+    let len = handle.len();
+    let mut prev_end = 0;
+    for splice in &splices {
+        if splice.start < prev_end || splice.end < splice.start || splice.end > len {
+            return Err(anyhow::anyhow!("Edits overlap or fall outside the file"));
+        }
+        prev_end = splice.end;
+    }
+
+    let source = handle.local_path().zip(since);
+    let new_len = save_atomic(&target, source, |file| -> Result<u64, SaveError> {
+        let written = write_spliced(handle, &splices, file, progress).map_err(|e| match e.downcast::<SaveError>() {
+            Ok(e) => e,
+            Err(e) => SaveError::Write {
+                path: target.clone(),
+                message: e.to_string(),
+            },
+        })?;
+        if in_place {
+            // Drop our own mapping before the file is replaced under it
+            io::invalidate(&target);
+        }
+        Ok(written)
+    })?;
+    if in_place {
+        handle.refresh()?;
+    }
+
+    Ok(EditResult {
+        path: target,
+        new_len,
+        edits: splices
+            .iter()
+            .map(|s| EditedRange {
+                offset: s.start,
+                old_len: s.end - s.start,
+                new_len: s.replacement.len() as u64,
+            })
+            .collect(),
+    })
+}
+
+fn write_spliced(handle: &FileHandle, splices: &[Splice], file: &File, mut progress: impl FnMut(u64)) -> Result<u64> {
+    let mut source = handle.reader()?;
+    let file_len = source.len().max(1);
+    let mut out = BufWriter::with_capacity(1024 * 1024, file);
+    let mut chunk = vec![0; 1024 * 1024];
+    let mut last_pct = 0;
+    let mut written = 0;
+
+    let mut copy_until = |source: &mut io::Source, out: &mut BufWriter<&File>, end: u64| -> Result<u64> {
+        let mut copied = 0;
+        while source.position() < end {
+            let want = chunk.len().min((end - source.position()) as usize);
+            let n = source.read(&mut chunk[..want])?;
+            if n == 0 {
+                return Err(anyhow::anyhow!("File ended before offset {}", end));
+            }
+            out.write_all(&chunk[..n])?;
+            copied += n as u64;
+            let pct = source.position() * 100 / file_len;
+            if pct > last_pct {
+                last_pct = pct;
+                progress(pct);
+            }
+        }
+        Ok(copied)
+    };
+
+    for splice in splices {
+        written += copy_until(&mut source, &mut out, splice.start)?;
+        out.write_all(&splice.replacement)?;
+        written += splice.replacement.len() as u64;
+        source.seek(SeekFrom::Start(splice.end))?;
+    }
+    let len = source.len();
+    written += copy_until(&mut source, &mut out, len)?;
+    out.flush()?;
+    Ok(written)
+}
+
+/// Raw bytes of the start (or empty-element) tag at `offset`.
+fn read_start_tag(handle: &FileHandle, offset: u64) -> Result<Vec<u8>> {
+    let mut reader = element_reader(handle, offset)?;
+    let mut buf = Vec::new();
+    let end = match reader.read_event_into(&mut buf)? {
+        Event::Start(_) | Event::Empty(_) => reader.position(),
+        _ => return Err(anyhow::anyhow!("No element starts at offset {}", offset)),
+    };
+    let mut source = handle.reader()?;
+    source.seek(SeekFrom::Start(offset))?;
+    let mut tag = vec![0; (end - offset) as usize];
+    source.read_exact(&mut tag)?;
+    Ok(tag)
+}
+
+/// Where an attribute sits in a raw start tag.
+struct AttributeSpan {
+    name: std::ops::Range<usize>,
+    /// Between the quotes.
+    value: std::ops::Range<usize>,
+    quote: u8,
+}
+
+fn name_end(tag: &[u8]) -> usize {
+    1 + tag[1..].iter().position(|&b| b.is_ascii_whitespace() || b == b'/' || b == b'>').unwrap_or(tag.len() - 1)
+}
+
+fn attribute_spans(tag: &[u8]) -> Result<Vec<AttributeSpan>> {
+    let malformed = || anyhow::anyhow!("Malformed start tag");
+    let skip_space = |mut i: usize| {
+        while i < tag.len() && tag[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        i
+    };
+    let mut spans = Vec::new();
+    let mut i = name_end(tag);
+    loop {
+        i = skip_space(i);
+        match tag.get(i) {
+            Some(b'/' | b'>') | None => return Ok(spans),
+            _ => {}
+        }
+        let name_start = i;
+        while i < tag.len() && !tag[i].is_ascii_whitespace() && tag[i] != b'=' {
+            i += 1;
+        }
+        let name = name_start..i;
+        i = skip_space(i);
+        if tag.get(i) != Some(&b'=') {
+            return Err(malformed());
+        }
+        i = skip_space(i + 1);
+        let quote = *tag.get(i).filter(|&&q| q == b'"' || q == b'\'').ok_or_else(malformed)?;
+        let value_start = i + 1;
+        let value_len = tag[value_start..].iter().position(|&b| b == quote).ok_or_else(malformed)?;
+        i = value_start + value_len;
+        spans.push(AttributeSpan { name, value: value_start..i, quote });
+        i += 1;
+    }
+}
+
+/// Close enough to the XML Name production to keep the tag well-formed.
+fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_alphabetic() || c == '_' || c == ':')
+        && chars.all(|c| c.is_alphanumeric() || matches!(c, '_' | ':' | '-' | '.'))
+}
+
+/// Escape character data. `>` is escaped too so `]]>` can't appear.
+fn escape_text(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+/// Escape `value` for an attribute quoted with `quote`. Tabs and line breaks
+/// become character references so attribute normalization keeps them.
+fn escape_attribute(value: &str, quote: u8) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '"' if quote == b'"' => out.push_str("&quot;"),
+            '\'' if quote == b'\'' => out.push_str("&apos;"),
+            '\t' => out.push_str("&#9;"),
+            '\n' => out.push_str("&#10;"),
+            '\r' => out.push_str("&#13;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::FileRegistry;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    /// A file holding `content`, removed when dropped.
+    struct TestFile {
+        path: PathBuf,
+        handle: Arc<FileHandle>,
+    }
+
+    impl TestFile {
+        fn new(name: &str, content: &str) -> TestFile {
+            let path = std::env::temp_dir().join(format!("xml-reader-edit-{}-{}.xml", std::process::id(), name));
+            std::fs::write(&path, content).unwrap();
+            let handle = FileRegistry::default().open(path.to_str().unwrap()).unwrap();
+            TestFile { path, handle }
+        }
+
+        fn content(&self) -> String {
+            std::fs::read_to_string(&self.path).unwrap()
+        }
+    }
+
+    impl Drop for TestFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+
+    /// Plan one change against `content` and write it back to the file.
+    fn edited(name: &str, content: &str, plan: impl FnOnce(&FileHandle) -> Result<Splice>) -> Result<String> {
+        let file = TestFile::new(name, content);
+        let edit = apply(&file.handle, None, |_| {}, || plan(&file.handle))?;
+        assert_eq!(file.handle.len(), edit.new_len);
+        Ok(file.content())
+    }
+
+    #[test]
+    fn attribute_spans_of_a_raw_tag() {
+        let tag = b"<a:b  x=\"1\" y = '2'\n z=\"\"/>";
+        assert_eq!(name_end(tag), 4);
+        assert_eq!(name_end(b"<a>"), 2);
+        let spans: Vec<_> = attribute_spans(tag)
+            .unwrap()
+            .into_iter()
+            .map(|a| (&tag[a.name], &tag[a.value], a.quote))
+            .collect();
+        assert_eq!(spans, [(&b"x"[..], &b"1"[..], b'"'), (b"y", b"2", b'\''), (b"z", b"", b'"')]);
+        assert!(attribute_spans(b"<a x>").is_err());
+        assert!(attribute_spans(b"<a x=\"1>").is_err());
+    }
+
+    #[test]
+    fn set_attribute_keeps_the_rest_of_the_tag() {
+        let xml = "<r>\n  <item id='1'  b=\"2\"\t/>\n</r>\n";
+        let set = |name: &'static str, value: &'static str| {
+            edited(&format!("set-{}", name), xml, move |h| set_attribute_splice(h, 6, name, value)).unwrap()
+        };
+        assert_eq!(set("id", "it's"), "<r>\n  <item id='it&apos;s'  b=\"2\"\t/>\n</r>\n");
+        assert_eq!(set("b", "\"<&>\""), "<r>\n  <item id='1'  b=\"&quot;&lt;&amp;>&quot;\"\t/>\n</r>\n");
+        assert_eq!(set("new", "a\tb\n"), "<r>\n  <item id='1'  b=\"2\" new=\"a&#9;b&#10;\"\t/>\n</r>\n");
+        assert_eq!(
+            edited("set-bare", "<r><a/></r>", |h| set_attribute_splice(h, 3, "x", "1")).unwrap(),
+            "<r><a x=\"1\"/></r>"
+        );
+        assert!(edited("set-invalid", xml, |h| set_attribute_splice(h, 6, "1x", "v")).is_err());
+        assert!(edited("set-not-a-tag", xml, |h| set_attribute_splice(h, 4, "x", "v")).is_err());
+    }
+
+    #[test]
+    fn map_offset_moves_past_edits() {
+        let edit = EditResult {
+            path: String::new(),
+            new_len: 0,
+            edits: vec![
+                EditedRange { offset: 10, old_len: 5, new_len: 2 },
+                EditedRange { offset: 20, old_len: 0, new_len: 4 },
+            ],
+        };
+        let mapped: Vec<_> = [0, 9, 10, 14, 15, 19, 20, 30].iter().map(|&o| edit.map_offset(o)).collect();
+        assert_eq!(mapped, [Some(0), Some(9), None, None, Some(12), Some(16), Some(17), Some(31)]);
+    }
+
+    #[test]
+    fn set_element_text_escapes_and_expands() {
+        let xml = "<r><a x=\"1\">old &amp; text</a><b/><c><d/></c></r>";
+        assert_eq!(
+            edited("text-set", xml, |h| set_element_text_splice(h, 3, "<new> & ]]>")).unwrap(),
+            "<r><a x=\"1\">&lt;new&gt; &amp; ]]&gt;</a><b/><c><d/></c></r>"
+        );
+        assert_eq!(
+            edited("text-empty", xml, |h| set_element_text_splice(h, 30, "t")).unwrap(),
+            "<r><a x=\"1\">old &amp; text</a><b>t</b><c><d/></c></r>"
+        );
+        assert!(edited("text-children", xml, |h| set_element_text_splice(h, 34, "t")).is_err());
+    }
+
+    #[test]
+    fn delete_element_and_its_indentation() {
+        let xml = "<r>\n  <a>x</a>\n  <b/>\n</r>\n";
+        assert_eq!(edited("delete", xml, |h| delete_element_splice(h, 6, false)).unwrap(), "<r>\n  \n  <b/>\n</r>\n");
+        assert_eq!(edited("delete-trim", xml, |h| delete_element_splice(h, 6, true)).unwrap(), "<r>\n  <b/>\n</r>\n");
+        assert_eq!(edited("delete-empty", xml, |h| delete_element_splice(h, 17, true)).unwrap(), "<r>\n  <a>x</a>\n</r>\n");
+
+        // To another file, with bookmarks moved past the removed range
+        let file = TestFile::new("delete-out", xml);
+        let out = TestFile::new("delete-out-target", "old");
+        let edit = apply(&file.handle, out.path.to_str(), |_| {}, || delete_element_splice(&file.handle, 6, true)).unwrap();
+        assert_eq!(out.content(), "<r>\n  <b/>\n</r>\n");
+        assert_eq!(file.content(), xml);
+        let bookmarks: Vec<_> = [0, 6, 17].iter().map(|&b| edit.map_offset(b)).collect();
+        assert_eq!(bookmarks, [Some(0), None, Some(6)]);
+    }
+
+    #[test]
+    fn check_fragment_wants_whole_elements() {
+        assert!(check_fragment("<a/>").is_ok());
+        assert!(check_fragment("<a>t</a>\n<!-- c --><?pi x?>\n<b x='1'/>").is_ok());
+        for bad in ["", "text", "<a/>text", "<a>", "<a></b>", "<a x=1/>", "<?xml version='1.0'?><a/>", "<![CDATA[x]]>"] {
+            assert!(check_fragment(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn insert_element_takes_the_anchor_indentation() {
+        let xml = "<r>\r\n\t<a>x</a>\r\n</r>";
+        assert_eq!(
+            edited("insert-before", xml, |h| insert_element_splice(h, 6, InsertPosition::Before, "<b>\n  <c/>\n</b>")).unwrap(),
+            "<r>\r\n\t<b>\r\n\t  <c/>\r\n\t</b>\r\n\t<a>x</a>\r\n</r>"
+        );
+        assert_eq!(
+            edited("insert-after", xml, |h| insert_element_splice(h, 6, InsertPosition::After, " <b/> ")).unwrap(),
+            "<r>\r\n\t<a>x</a>\r\n\t<b/>\r\n</r>"
+        );
+        // Inline anchors get the fragment as it is
+        assert_eq!(
+            edited("insert-inline", "<r><a/></r>", |h| insert_element_splice(h, 3, InsertPosition::After, "<b/>")).unwrap(),
+            "<r><a/><b/></r>"
+        );
+        assert!(edited("insert-bad", xml, |h| insert_element_splice(h, 6, InsertPosition::After, "<b>")).is_err());
+    }
+
+    #[test]
+    fn staged_splices_are_written_in_one_pass() {
+        let file = TestFile::new("staged", "<r><a x=\"1\"/><b>t</b><c/></r>");
+        let since = source_stamp(&file.handle).unwrap().unwrap();
+        let sessions = EditSessions::default();
+        sessions.restore(1, since.clone(), Vec::new());
+        for plan in [
+            delete_element_splice(&file.handle, 21, false),
+            set_attribute_splice(&file.handle, 3, "x", "2"),
+            set_element_text_splice(&file.handle, 13, "u"),
+        ] {
+            let splice = plan.unwrap();
+            sessions
+                .with_session(1, |session| {
+                    session.splices.push(splice);
+                    Ok(())
+                })
+                .unwrap();
+        }
+        let (stamp, splices) = sessions.staged(1).unwrap();
+        assert!(stamp == since);
+
+        let edit = rewrite_with_progress(&file.handle, splices, None, Some(&stamp), |_| {}).unwrap();
+        assert_eq!(file.content(), "<r><a x=\"2\"/><b>u</b></r>");
+        let offsets: Vec<_> = edit.edits.iter().map(|e| e.offset).collect();
+        assert_eq!(offsets, [9, 16, 21]);
+        assert_eq!(file.handle.len(), edit.new_len);
+        assert!(sessions.with_session(2, |_| Ok(())).is_err());
+    }
+
+    #[test]
+    fn rewrite_refuses_bad_splices_and_changed_files() {
+        let file = TestFile::new("refused", "<r><a/></r>");
+        let since = source_stamp(&file.handle).unwrap().unwrap();
+        let splice = |start, end| Splice { start, end, replacement: Vec::new() };
+        for splices in [vec![splice(3, 6), splice(5, 7)], vec![splice(4, 3)], vec![splice(8, 12)]] {
+            assert!(rewrite_with_progress(&file.handle, splices, None, Some(&since), |_| {}).is_err());
+        }
+        assert_eq!(file.content(), "<r><a/></r>");
+
+        std::fs::write(&file.path, "<r><a/><b/></r>").unwrap();
+        let err = rewrite_with_progress(&file.handle, vec![splice(3, 7)], None, Some(&since), |_| {}).err().unwrap();
+        assert!(matches!(err.downcast_ref(), Some(SaveError::SourceChanged { .. })));
+        assert_eq!(file.content(), "<r><a/><b/></r>");
+    }
+
+    #[test]
+    fn rename_tag_touches_only_names() {
+        let rename = |name: &str, xml: &str, old: &str, new: &str, namespace_aware: bool| {
+            let file = TestFile::new(name, xml);
+            let renamed = rename_tag_internal(&file.handle, old, new, namespace_aware, false, |_| {}).unwrap();
+            (renamed.tags, file.content())
+        };
+        assert_eq!(
+            rename("rename", "<r><a x='a'>a<a/></a><ab/></r>", "a", "item", false),
+            (3, "<r><item x='a'>a<item/></item><ab/></r>".to_string())
+        );
+        let xml = "<r xmlns='urn:x' xmlns:y='urn:y'><a/><y:a/><b xmlns='urn:y'><a></a></b></r>";
+        assert_eq!(
+            rename("rename-ns", xml, "{urn:y}a", "c", true),
+            (3, "<r xmlns='urn:x' xmlns:y='urn:y'><a/><y:c/><b xmlns='urn:y'><c></c></b></r>".to_string())
+        );
+
+        let file = TestFile::new("rename-dry", xml);
+        let dry = rename_tag_internal(&file.handle, "a", "c", false, true, |_| {}).unwrap();
+        assert!(dry.tags == 3 && dry.edit.is_none());
+        assert_eq!(file.content(), xml);
+        assert!(rename_tag_internal(&file.handle, "a", "y:c", true, false, |_| {}).is_err());
+    }
+
+    #[test]
+    fn sort_children_keeps_whitespace_between_them() {
+        let xml = "<r>\n  <i n='10'/>\n  <!-- c -->\n  <i n='9'><x/></i>\n  <i/>\n  <i n='b'/>\n</r>";
+        let sorted = |name: &str, numeric: bool| {
+            let file = TestFile::new(name, xml);
+            let since = source_stamp(&file.handle).unwrap();
+            let splices = sort_children_splices(&file.handle, 0, "n", numeric).unwrap();
+            rewrite_with_progress(&file.handle, splices, None, since.as_ref(), |_| {}).unwrap();
+            file.content()
+        };
+        assert_eq!(
+            sorted("sort-numeric", true),
+            "<r>\n  <i n='9'><x/></i>\n  <!-- c -->\n  <i n='10'/>\n  <i/>\n  <i n='b'/>\n</r>"
+        );
+        assert_eq!(
+            sorted("sort-text", false),
+            "<r>\n  <i n='10'/>\n  <!-- c -->\n  <i n='9'><x/></i>\n  <i n='b'/>\n  <i/>\n</r>"
+        );
+    }
+
+    #[test]
+    fn parse_csv_records() {
+        let csv = "\u{feff}id,name\r\n1,\"a, \"\"b\"\"\"\n2,\"multi\nline\"\n\n3,";
+        let records = parse_csv(csv);
+        assert_eq!(
+            records,
+            [
+                vec!["id", "name"],
+                vec!["1", "a, \"b\""],
+                vec!["2", "multi\nline"],
+                vec![""],
+                vec!["3", ""],
+            ]
+        );
+    }
+
+    #[test]
+    fn apply_csv_edits_sets_mapped_attributes() {
+        let file = TestFile::new("csv", "<r><i id='1'/><i id=\"a&amp;b\" s='old'/><i id='3'/></r>");
+        let csv = TestFile::new("csv-mapping", "id,s\n1,one\na&b,\"x<y\"\n9,nine\n");
+        let edits = apply_csv_edits_internal(&file.handle, csv.path.to_str().unwrap(), "id", "s", None, |_| {}).unwrap();
+        assert_eq!((edits.rows, edits.updated, edits.unmatched), (3, 2, vec!["9".to_string()]));
+        assert_eq!(file.content(), "<r><i id='1' s=\"one\"/><i id=\"a&amp;b\" s='x&lt;y'/><i id='3'/></r>");
+
+        std::fs::write(&csv.path, "1,a,b\n2\n").unwrap();
+        assert!(apply_csv_edits_internal(&file.handle, csv.path.to_str().unwrap(), "id", "s", None, |_| {}).is_err());
+    }
+
+    #[test]
+    fn comment_out_round_trips() {
+        let elements = [
+            "<a x='--'>a--b-<!-- c -->-</a>",
+            "<a>&#45; &#38; &amp;#45; &#38;#45; &#4</a>",
+            "<a/>",
+        ];
+        for (i, element) in elements.iter().enumerate() {
+            let xml = format!("<r>\n  {}\n</r>", element);
+            let file = TestFile::new(&format!("comment-{}", i), &xml);
+            apply(&file.handle, None, |_| {}, || comment_out_splice(&file.handle, 6)).unwrap();
+            let commented = file.content();
+            let comment = &commented[6..commented.len() - 5];
+            assert!(comment.starts_with("<!--") && comment.ends_with("-->"), "{}", comment);
+            assert!(!comment[4..comment.len() - 3].contains("--") && !comment.ends_with("--->"), "{}", comment);
+
+            apply(&file.handle, None, |_| {}, || uncomment_splice(&file.handle, 6)).unwrap();
+            assert_eq!(file.content(), xml);
+        }
+        assert_eq!(
+            edited("comment-escaped", "<r><a>-&#45;</a></r>", |h| comment_out_splice(h, 3)).unwrap(),
+            "<r><!--<a>-&#38;#45;</a>--></r>"
+        );
+        assert!(edited("uncomment-text", "<r><!-- note --></r>", |h| uncomment_splice(h, 3)).is_err());
+        assert!(edited("uncomment-element", "<r><a/></r>", |h| uncomment_splice(h, 3)).is_err());
+    }
+
+    #[test]
+    fn out_path_naming_the_file_is_an_in_place_save() {
+        let file = TestFile::new("same-file", "<r><a/></r>");
+        let dir = file.path.parent().unwrap();
+        let spelled = format!("{}/./{}", dir.display(), file.path.file_name().unwrap().to_str().unwrap());
+        let edit = apply(&file.handle, Some(&spelled), |_| {}, || delete_element_splice(&file.handle, 3, false)).unwrap();
+        assert_eq!(edit.path, file.handle.path);
+        assert_eq!(file.content(), "<r></r>");
+        // Re-opened, so the next edit plans against what is on disk now
+        assert_eq!(file.handle.len(), 7);
+        apply(&file.handle, None, |_| {}, || set_attribute_splice(&file.handle, 0, "x", "1")).unwrap();
+        assert_eq!(file.content(), "<r x=\"1\"></r>");
+    }
+
+    fn replace(name: &str, content: &str, query: &str, replacement: &str) -> String {
+        let file = TestFile::new(name, content);
+        replace_all_internal(&file.handle, query, "any", replacement, false, |_| {}).unwrap();
+        file.content()
+    }
+
+    #[test]
+    fn replace_all_leaves_entities_whole() {
+        let xml = r#"<r name="a &amp; b &lt; c&#10;10"/>"#;
+        assert_eq!(replace("entities-amp", xml, "amp", "x"), xml);
+        assert_eq!(replace("entities-lt", xml, "lt", "x"), xml);
+        assert_eq!(replace("entities-10", xml, "10", "x"), r#"<r name="a &amp; b &lt; c&#10;x"/>"#);
+    }
+
+    #[test]
+    fn replace_all_matches_expanded_values() {
+        let xml = r#"<r name="a &amp; b" id='x'/>"#;
+        assert_eq!(replace("expanded", xml, "&", "and"), r#"<r name="a and b" id='x'/>"#);
+        // Written back escaped for its own quote
+        assert_eq!(replace("quotes", xml, "x", "it's \"<q>\""), r#"<r name="a &amp; b" id='it&apos;s "&lt;q>"'/>"#);
+    }
+
+    #[test]
+    fn replace_all_folds_case_on_both_sides() {
+        assert_eq!(replace("case-ascii", r#"<r name="ABC abc"/>"#, "aBc", "x"), r#"<r name="x x"/>"#);
+        assert_eq!(replace("case-unicode", r#"<r name="ÉCOLE école"/>"#, "École", "x"), r#"<r name="x x"/>"#);
+        assert_eq!(replace("case-tag", "<Straße></Straße>", "STRASSE", "x"), "<Straße></Straße>");
+        assert_eq!(replace("case-tag-match", "<ÄPFEL></ÄPFEL>", "äpfel", "fruit"), "<fruit></fruit>");
+    }
+}
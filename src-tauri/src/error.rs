@@ -0,0 +1,109 @@
+use crate::files::{FileHandle, FileId};
+use crate::io::LimitError;
+use std::io::Read;
+
+/// Why a command failed, sent to the frontend as `{ kind, ... }` so it can
+/// pick a message or offer a retry without matching on text. Edits report a
+/// `SaveError` instead, which says what happened to the file on disk.
+#[derive(Debug, serde::Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum XmlReaderError {
+    /// The path doesn't exist (any more).
+    NotFound { message: String },
+    /// The id was never opened or the file was closed since.
+    UnknownFile { file_id: FileId },
+    /// Reading or writing failed: permissions, a lock, the network, a full disk.
+    Io { message: String },
+    /// The XML is malformed where the command needed to parse it. `line` is
+    /// 1-based, and only known for errors raised on an open file.
+    ParseError { offset: Option<u64>, line: Option<u64>, message: String },
+    /// The file went over one of the `ReaderLimits`.
+    Limit { message: String },
+    /// Stopped by the matching cancel command.
+    Cancelled,
+    /// The file's size or modification time changed while a command read it
+    /// in several steps, so its result could mix old and new bytes.
+    FileChanged { path: String },
+    /// Valid input the app doesn't handle: XPath functions, Schematron
+    /// variables.
+    Unsupported { message: String },
+    /// Anything else, mostly bad arguments.
+    Failed { message: String },
+}
+
+impl XmlReaderError {
+    /// Convert an error from a command on `handle`, working out the line of
+    /// a parse error from its offset.
+    pub fn in_file(handle: &FileHandle, e: anyhow::Error) -> XmlReaderError {
+        match XmlReaderError::from(e) {
+            XmlReaderError::ParseError {
+                offset: Some(offset),
+                line: None,
+                message,
+            } => XmlReaderError::ParseError {
+                offset: Some(offset),
+                line: line_at(handle, offset).ok(),
+                message,
+            },
+            e => e,
+        }
+    }
+}
+
+impl std::fmt::Display for XmlReaderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            XmlReaderError::NotFound { message }
+            | XmlReaderError::Io { message }
+            | XmlReaderError::ParseError { message, .. }
+            | XmlReaderError::Limit { message }
+            | XmlReaderError::Unsupported { message }
+            | XmlReaderError::Failed { message } => f.write_str(message),
+            XmlReaderError::UnknownFile { file_id } => write!(f, "Unknown file id {}", file_id),
+            XmlReaderError::Cancelled => f.write_str("Cancelled"),
+            XmlReaderError::FileChanged { path } => write!(f, "{} changed on disk while it was being read", path),
+        }
+    }
+}
+
+impl std::error::Error for XmlReaderError {}
+
+impl From<anyhow::Error> for XmlReaderError {
+    fn from(e: anyhow::Error) -> Self {
+        let e = match e.downcast::<XmlReaderError>() {
+            Ok(e) => return e,
+            Err(e) => e,
+        };
+        // With the causes, so context added to an io error keeps its reason
+        let message = format!("{:#}", e);
+        if e.is::<LimitError>() {
+            return XmlReaderError::Limit { message };
+        }
+        if e.is::<quick_xml::Error>() {
+            return XmlReaderError::ParseError {
+                offset: None,
+                line: None,
+                message,
+            };
+        }
+        match e.chain().find_map(|cause| cause.downcast_ref::<std::io::Error>()) {
+            Some(io) if io.kind() == std::io::ErrorKind::NotFound => XmlReaderError::NotFound { message },
+            Some(_) => XmlReaderError::Io { message },
+            None => XmlReaderError::Failed { message },
+        }
+    }
+}
+
+/// 1-based line of `offset`.
+fn line_at(handle: &FileHandle, offset: u64) -> anyhow::Result<u64> {
+    let mut source = handle.reader()?.take(offset);
+    let mut chunk = vec![0; 1024 * 1024];
+    let mut line = 1;
+    loop {
+        let n = source.read(&mut chunk)?;
+        if n == 0 {
+            return Ok(line);
+        }
+        line += chunk[..n].iter().filter(|&&b| b == b'\n').count() as u64;
+    }
+}
@@ -0,0 +1,1916 @@
+use crate::error::XmlReaderError;
+use crate::files::{FileHandle, FileId, FileRegistry};
+use crate::format::{element_reader, start_tag_at, JsonBuilder, JsonOptions};
+use crate::io::{self, XmlReader};
+use crate::ops::{self, OpId};
+use crate::save::save_atomic;
+use crate::xml_ops::{element_matches_bytes, open_elements_at, SearchTarget};
+use anyhow::Result;
+use base64::Engine;
+use encoding_rs::{CoderResult, Encoding, UTF_16BE, UTF_16LE};
+use quick_xml::events::{BytesStart, BytesText, Event};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use tauri::{State, WebviewWindow};
+
+#[derive(serde::Serialize)]
+pub struct Exported {
+    out_path: String,
+    bytes: u64,
+    // Rows, matches or elements written
+    records: u64,
+}
+
+/// Write the element at `offset` to `out_path` as a standalone document,
+/// optionally with the file's XML declaration and with the namespace
+/// declarations it inherits from its ancestors copied onto its start tag.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn export_element(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    offset: u64,
+    out_path: String,
+    include_prolog: bool,
+    include_namespace_decls: bool,
+    op_id: Option<OpId>,
+) -> Result<Exported, XmlReaderError> {
+    let handle = files.get(file_id)?;
+    ops::run(window, op_id, "export", move |_| {
+        export_to(&handle, &out_path, |out| {
+            export_element_internal(&handle, offset, &out_path, out, include_prolog, include_namespace_decls)
+        })
+        .map_err(|e| XmlReaderError::in_file(&handle, e))
+    })
+    .await
+}
+
+fn export_element_internal(
+    handle: &FileHandle,
+    offset: u64,
+    out_path: &str,
+    out: &File,
+    include_prolog: bool,
+    include_namespace_decls: bool,
+) -> Result<Exported> {
+    let mut reader = element_reader(handle, offset)?;
+    let mut buf = Vec::new();
+    let (declared, empty) = loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(e) => break (namespace_decls(&e), false),
+            Event::Empty(e) => break (namespace_decls(&e), true),
+            Event::Text(e) if e.iter().all(u8::is_ascii_whitespace) => {}
+            _ => return Err(anyhow::anyhow!("No start tag found at offset {}", offset)),
+        }
+        buf.clear();
+    };
+    let tag_end = reader.position();
+    let end = if empty { tag_end } else { element_end(&mut reader, &mut buf)? };
+
+    let mut file = handle.reader()?;
+    let mut start_tag = vec![0u8; (tag_end - offset) as usize];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut start_tag)?;
+    // Whitespace skipped before the tag isn't part of the element
+    let tag_start = start_tag.iter().position(|&b| b == b'<').unwrap_or(0);
+    start_tag.drain(..tag_start);
+
+    if include_namespace_decls {
+        let missing: String = inherited_namespaces(handle, offset)?
+            .into_iter()
+            .filter(|(key, _)| !declared.iter().any(|(k, _)| k == key))
+            .map(|(key, value)| {
+                // Values are raw, possibly from a single-quoted attribute
+                let value = quick_xml::escape::unescape(&value)?;
+                Ok(format!(" {}=\"{}\"", key, quick_xml::escape::escape(&value)))
+            })
+            .collect::<Result<_>>()?;
+        let close = if empty { 2 } else { 1 };
+        let at = start_tag.len().saturating_sub(close);
+        start_tag.splice(at..at, missing.into_bytes());
+    }
+
+    let mut out = BufWriter::new(out);
+    let mut written = 0u64;
+    if include_prolog {
+        let decl = xml_declaration(handle)?;
+        out.write_all(decl.as_bytes())?;
+        out.write_all(b"\n")?;
+        written += decl.len() as u64 + 1;
+    }
+    out.write_all(&start_tag)?;
+    written += start_tag.len() as u64;
+
+    // The rest of the element is copied byte for byte
+    file.seek(SeekFrom::Start(tag_end))?;
+    written += std::io::copy(&mut file.take(end - tag_end), &mut out)?;
+    out.flush()?;
+
+    Ok(Exported {
+        out_path: out_path.to_string(),
+        bytes: written,
+        records: 1,
+    })
+}
+
+/// Write every element matching `xpath_or_tag` as a CSV row. `xpath_or_tag` is
+/// either a tag name matched at any depth or an absolute path like
+/// `/Root/Rows/Row`. Columns are `@attr` (attribute of the row), `child` (text
+/// of a direct child), `child/@attr` or `.` (the row's own text); with no
+/// columns they are taken from the first row. Emits `export-progress` (0-100).
+#[tauri::command]
+pub async fn export_table(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    xpath_or_tag: String,
+    columns: Vec<String>,
+    out_path: String,
+    op_id: Option<OpId>,
+) -> Result<Exported, XmlReaderError> {
+    let handle = files.get(file_id)?;
+    ops::run(window, op_id, "export", move |op| {
+        export_to(&handle, &out_path, |out| {
+            export_table_internal(&handle, &xpath_or_tag, columns, &out_path, out, |pct| {
+                op.progress(pct);
+            })
+        })
+        .map_err(|e| XmlReaderError::in_file(&handle, e))
+    })
+    .await
+}
+
+/// Values collected for the row being read.
+#[derive(Default)]
+struct Row {
+    // Column key (`@id`, `Name`, `Name/@lang`, `.`) to value, in document order
+    values: Vec<(String, String)>,
+}
+
+impl Row {
+    fn set(&mut self, key: String, value: String) {
+        if !self.values.iter().any(|(k, _)| *k == key) {
+            self.values.push((key, value));
+        }
+    }
+
+    fn append(&mut self, key: &str, text: &str) {
+        match self.values.iter_mut().find(|(k, _)| k == key) {
+            Some((_, value)) => value.push_str(text),
+            None => self.values.push((key.to_string(), text.to_string())),
+        }
+    }
+
+    fn get(&self, key: &str) -> &str {
+        self.values
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.trim())
+            .unwrap_or("")
+    }
+}
+
+fn export_table_internal(
+    handle: &FileHandle,
+    xpath_or_tag: &str,
+    mut columns: Vec<String>,
+    out_path: &str,
+    out: &File,
+    mut progress: impl FnMut(u64),
+) -> Result<Exported> {
+    let path: Option<Vec<&str>> = xpath_or_tag
+        .strip_prefix('/')
+        .map(|p| p.split('/').filter(|s| !s.is_empty()).collect());
+
+    let file = handle.reader()?;
+    let file_len = file.len().max(1);
+    let mut reader = XmlReader::scan(file);
+    reader.check_end_names(false);
+
+    let mut out = CountingWriter::new(BufWriter::new(out));
+    let mut header_written = false;
+    let mut records = 0u64;
+
+    let mut buf = Vec::new();
+    let mut stack: Vec<String> = Vec::new();
+    // Depth of the current row element, and the direct child being read
+    let mut row_depth: Option<usize> = None;
+    let mut child: Option<String> = None;
+    let mut row = Row::default();
+    let mut last_pct = 0;
+
+    loop {
+        buf.clear();
+        let event = reader.read_event_into(&mut buf)?;
+        let start = match &event {
+            Event::Start(e) | Event::Empty(e) => Some(e),
+            _ => None,
+        };
+
+        if let Some(e) = start {
+            let name = String::from_utf8_lossy(e.name().as_ref()).into_owned();
+            stack.push(name.clone());
+            let depth = stack.len();
+            match row_depth {
+                None => {
+                    let matches = match &path {
+                        Some(path) => stack.len() == path.len() && stack.iter().zip(path).all(|(a, b)| a == b),
+                        None => name == xpath_or_tag,
+                    };
+                    if matches {
+                        row_depth = Some(depth);
+                        row = Row::default();
+                        for attribute in e.attributes().flatten() {
+                            let key = format!("@{}", String::from_utf8_lossy(attribute.key.as_ref()));
+                            row.set(key, attribute.unescape_value()?.into_owned());
+                        }
+                    }
+                }
+                Some(d) if depth == d + 1 => {
+                    for attribute in e.attributes().flatten() {
+                        let key = format!("{}/@{}", name, String::from_utf8_lossy(attribute.key.as_ref()));
+                        row.set(key, attribute.unescape_value()?.into_owned());
+                    }
+                    if row.values.iter().any(|(k, _)| *k == name) {
+                        // Only the first child with a given name is a column
+                        child = None;
+                    } else {
+                        row.set(name.clone(), String::new());
+                        child = Some(name);
+                    }
+                }
+                Some(_) => {}
+            }
+        }
+
+        let ended = match event {
+            Event::End(_) | Event::Empty(_) => true,
+            Event::Text(e) => {
+                if let Some(d) = row_depth {
+                    let text = e.unescape()?;
+                    if stack.len() == d {
+                        row.append(".", &text);
+                    } else if let Some(name) = &child {
+                        row.append(name, &text);
+                    }
+                }
+                false
+            }
+            Event::CData(e) => {
+                if let Some(name) = row_depth.and(child.as_ref()) {
+                    row.append(name, &String::from_utf8_lossy(&e));
+                }
+                false
+            }
+            Event::Eof => break,
+            _ => false,
+        };
+
+        if ended {
+            let depth = stack.len();
+            stack.pop();
+            match row_depth {
+                Some(d) if depth == d => {
+                    if columns.is_empty() {
+                        columns = row.values.iter().map(|(k, _)| k.clone()).filter(|k| k != ".").collect();
+                    }
+                    if !header_written {
+                        write_csv_row(&mut out, columns.iter().map(String::as_str))?;
+                        header_written = true;
+                    }
+                    write_csv_row(&mut out, columns.iter().map(|c| row.get(c)))?;
+                    records += 1;
+                    row_depth = None;
+                }
+                Some(d) if depth == d + 1 => child = None,
+                _ => {}
+            }
+        }
+
+        let pct = reader.position() * 100 / file_len;
+        if pct > last_pct {
+            last_pct = pct;
+            progress(pct);
+        }
+    }
+
+    if !header_written {
+        write_csv_row(&mut out, columns.iter().map(String::as_str))?;
+    }
+    out.flush()?;
+    progress(100);
+    Ok(Exported {
+        out_path: out_path.to_string(),
+        bytes: out.count,
+        records,
+    })
+}
+
+/// Start tag text kept per match in `export_matches`.
+const MATCH_TEXT_BYTES: usize = 1024;
+
+#[derive(serde::Serialize)]
+struct MatchRecord {
+    xpath: String,
+    offset: u64,
+    line: u64,
+    text: String,
+}
+
+/// Run a find-all scan with the same matching as `search_node` and write one
+/// record per match to `out_path`, as a JSON array or CSV (`format` is `json`
+/// or `csv`). Emits `export-progress` (0-100).
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn export_matches(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    query: String,
+    search_type: String,
+    format: String,
+    out_path: String,
+    op_id: Option<OpId>,
+) -> Result<Exported, XmlReaderError> {
+    let handle = files.get(file_id)?;
+    ops::run(window, op_id, "export", move |op| {
+        export_to(&handle, &out_path, |out| {
+            export_matches_internal(&handle, &query, &search_type, &format, &out_path, out, |pct| {
+                op.progress(pct);
+            })
+        })
+        .map_err(|e| XmlReaderError::in_file(&handle, e))
+    })
+    .await
+}
+
+fn export_matches_internal(
+    handle: &FileHandle,
+    query: &str,
+    search_type: &str,
+    format: &str,
+    out_path: &str,
+    out: &File,
+    mut progress: impl FnMut(u64),
+) -> Result<Exported> {
+    let csv = match format {
+        "csv" => true,
+        "json" => false,
+        _ => return Err(anyhow::anyhow!("Unknown export format '{}'", format)),
+    };
+
+    let file = handle.reader()?;
+    let file_len = file.len().max(1);
+    let mut reader = XmlReader::scan(file);
+    reader.check_end_names(false);
+
+    let mut out = CountingWriter::new(BufWriter::new(out));
+    if csv {
+        write_csv_row(&mut out, ["xpath", "offset", "line", "text"].into_iter())?;
+    } else {
+        out.write_all(b"[")?;
+    }
+
+    let query_bytes = query.to_lowercase().into_bytes();
+    let target = SearchTarget::new(search_type);
+    let mut buf = Vec::new();
+    let mut stack: Vec<String> = Vec::new();
+    let mut line = 1u64;
+    let mut records = 0u64;
+    let mut last_pct = 0;
+
+    loop {
+        buf.clear();
+        let pos_before = reader.position();
+        let event = reader.read_event_into(&mut buf)?;
+        let raw: &[u8] = match &event {
+            Event::Start(e) | Event::Empty(e) => e,
+            Event::End(e) => e,
+            Event::Text(e) => e,
+            Event::CData(e) => e,
+            Event::Comment(e) => e,
+            Event::Decl(e) => e,
+            Event::PI(e) => e,
+            Event::DocType(e) => e,
+            Event::Eof => &[],
+        };
+        // Markup delimiters never hold newlines, so the event bytes count them all
+        let start_line = line;
+        line += raw.iter().filter(|&&b| b == b'\n').count() as u64;
+
+        match &event {
+            Event::Start(e) | Event::Empty(e) => {
+                let name = String::from_utf8_lossy(e.name().as_ref()).into_owned();
+                if element_matches_bytes(e, &query_bytes, &target) {
+                    let tag = &raw[..raw.len().min(MATCH_TEXT_BYTES)];
+                    let close = if matches!(event, Event::Empty(_)) { "/>" } else { ">" };
+                    let record = MatchRecord {
+                        xpath: format!("/{}", stack.iter().chain([&name]).map(String::as_str).collect::<Vec<_>>().join("/")),
+                        offset: pos_before,
+                        line: start_line,
+                        text: format!("<{}{}", String::from_utf8_lossy(tag), close),
+                    };
+                    if csv {
+                        let (offset, line) = (record.offset.to_string(), record.line.to_string());
+                        write_csv_row(&mut out, [record.xpath.as_str(), &offset, &line, &record.text].into_iter())?;
+                    } else {
+                        out.write_all(if records == 0 { b"\n  " } else { b",\n  " })?;
+                        serde_json::to_writer(&mut out, &record)?;
+                    }
+                    records += 1;
+                }
+                if matches!(event, Event::Start(_)) {
+                    stack.push(name);
+                }
+            }
+            Event::End(_) => {
+                stack.pop();
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+
+        let pct = reader.position() * 100 / file_len;
+        if pct > last_pct {
+            last_pct = pct;
+            progress(pct);
+        }
+    }
+
+    if !csv {
+        out.write_all(b"\n]\n")?;
+    }
+    out.flush()?;
+    progress(100);
+    Ok(Exported {
+        out_path: out_path.to_string(),
+        bytes: out.count,
+        records,
+    })
+}
+
+/// Copy every element matching `query` (same matching as `search_node`) into
+/// a new document under a synthetic `<matches>` root, in document order.
+/// Matches inside an already copied match are not repeated, and namespace
+/// declarations inherited from ancestors are added to each copied start tag.
+/// Emits `export-progress` (0-100).
+#[tauri::command]
+pub async fn extract_matching(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    query: String,
+    search_type: String,
+    out_path: String,
+    op_id: Option<OpId>,
+) -> Result<Exported, XmlReaderError> {
+    let handle = files.get(file_id)?;
+    ops::run(window, op_id, "export", move |op| {
+        export_to(&handle, &out_path, |out| {
+            extract_matching_internal(&handle, &query, &search_type, &out_path, out, |pct| {
+                op.progress(pct);
+            })
+        })
+        .map_err(|e| XmlReaderError::in_file(&handle, e))
+    })
+    .await
+}
+
+fn extract_matching_internal(
+    handle: &FileHandle,
+    query: &str,
+    search_type: &str,
+    out_path: &str,
+    out: &File,
+    mut progress: impl FnMut(u64),
+) -> Result<Exported> {
+    let file = handle.reader()?;
+    let file_len = file.len().max(1);
+    let mut reader = XmlReader::scan(file);
+    reader.check_end_names(false);
+    let mut copy_from = handle.reader()?;
+
+    let mut out = CountingWriter::new(BufWriter::new(out));
+    write!(out, "{}\n<matches>", xml_declaration(handle)?)?;
+
+    let query_bytes = query.to_lowercase().into_bytes();
+    let target = SearchTarget::new(search_type);
+    let mut buf = Vec::new();
+    // Namespace declarations of each open element
+    let mut stack: Vec<Vec<(String, String)>> = Vec::new();
+    // Depth and body start of the match being copied
+    let mut copying: Option<(usize, u64)> = None;
+    let mut records = 0u64;
+    let mut last_pct = 0;
+
+    loop {
+        buf.clear();
+        let event = reader.read_event_into(&mut buf)?;
+        let pos_after = reader.position();
+
+        match &event {
+            Event::Start(e) | Event::Empty(e) => {
+                let empty = matches!(event, Event::Empty(_));
+                if copying.is_none() && element_matches_bytes(e, &query_bytes, &target) {
+                    let declared = namespace_decls(e);
+                    let mut missing: Vec<(String, String)> = Vec::new();
+                    for (key, value) in stack.iter().flatten() {
+                        if declared.iter().any(|(k, _)| k == key) {
+                            continue;
+                        }
+                        match missing.iter_mut().find(|(k, _)| k == key) {
+                            Some(existing) => existing.1 = value.clone(),
+                            None => missing.push((key.clone(), value.clone())),
+                        }
+                    }
+                    out.write_all(b"\n<")?;
+                    out.write_all(e)?;
+                    for (key, value) in missing {
+                        write!(out, " {}=\"{}\"", key, value)?;
+                    }
+                    out.write_all(if empty { b"/>" } else { b">" })?;
+                    records += 1;
+                    if !empty {
+                        copying = Some((stack.len(), pos_after));
+                    }
+                }
+                if !empty {
+                    stack.push(namespace_decls(e));
+                }
+            }
+            Event::End(_) => {
+                stack.pop();
+                if let Some((depth, body_start)) = copying {
+                    if stack.len() == depth {
+                        copy_from.seek(SeekFrom::Start(body_start))?;
+                        std::io::copy(&mut (&mut copy_from).take(pos_after - body_start), &mut out)?;
+                        copying = None;
+                    }
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+
+        let pct = pos_after * 100 / file_len;
+        if pct > last_pct {
+            last_pct = pct;
+            progress(pct);
+        }
+    }
+
+    if copying.is_some() {
+        return Err(anyhow::anyhow!("Element is not closed before the end of the file"));
+    }
+    out.write_all(b"\n</matches>\n")?;
+    out.flush()?;
+    progress(100);
+    Ok(Exported {
+        out_path: out_path.to_string(),
+        bytes: out.count,
+        records,
+    })
+}
+
+/// Write one JSON object per `record_tag` element to `out_path`, one per line,
+/// converted like `element_to_json` (`pretty` is ignored). Each line is the
+/// record's value; records holding only text become `{"#text": ...}` (per
+/// `text_key`). Records nested in a record are part of the outer one. Emits
+/// `export-progress` (0-100).
+#[tauri::command]
+pub async fn convert_to_ndjson(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    record_tag: String,
+    out_path: String,
+    options: Option<JsonOptions>,
+    op_id: Option<OpId>,
+) -> Result<Exported, XmlReaderError> {
+    let handle = files.get(file_id)?;
+    ops::run(window, op_id, "export", move |op| {
+        export_to(&handle, &out_path, |out| {
+            convert_to_ndjson_internal(&handle, &record_tag, &out_path, out, &options.unwrap_or_default(), |pct| {
+                op.progress(pct);
+            })
+        })
+        .map_err(|e| XmlReaderError::in_file(&handle, e))
+    })
+    .await
+}
+
+fn convert_to_ndjson_internal(
+    handle: &FileHandle,
+    record_tag: &str,
+    out_path: &str,
+    out: &File,
+    options: &JsonOptions,
+    mut progress: impl FnMut(u64),
+) -> Result<Exported> {
+    let file = handle.reader()?;
+    let file_len = file.len().max(1);
+    let mut reader = XmlReader::scan(file);
+    reader.check_end_names(false);
+
+    let mut out = CountingWriter::new(BufWriter::new(out));
+    let mut builder = JsonBuilder::new(options);
+    let mut buf = Vec::new();
+    let mut records = 0u64;
+    let mut last_pct = 0;
+
+    loop {
+        buf.clear();
+        let event = reader.read_event_into(&mut buf)?;
+        let starts_record = match &event {
+            Event::Start(e) | Event::Empty(e) => {
+                e.name().as_ref() == record_tag.as_bytes() || e.local_name().as_ref() == record_tag.as_bytes()
+            }
+            Event::Eof => break,
+            _ => false,
+        };
+
+        if builder.is_open() || starts_record {
+            if let Some((_, value)) = builder.event(&event)? {
+                let value = match value {
+                    Value::Object(_) => value,
+                    text => Value::Object([(builder.text_key().to_string(), text)].into_iter().collect()),
+                };
+                serde_json::to_writer(&mut out, &value)?;
+                out.write_all(b"\n")?;
+                records += 1;
+            }
+        }
+
+        let pct = reader.position() * 100 / file_len;
+        if pct > last_pct {
+            last_pct = pct;
+            progress(pct);
+        }
+    }
+
+    if builder.is_open() {
+        return Err(anyhow::anyhow!("Element is not closed before the end of the file"));
+    }
+    out.flush()?;
+    progress(100);
+    Ok(Exported {
+        out_path: out_path.to_string(),
+        bytes: out.count,
+        records,
+    })
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Redaction {
+    // Replace with `***`
+    Mask,
+    // Replace with a short SHA-256 of the value, so equal values still match
+    // across the file and joins keep working
+    Hash,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+pub struct RedactRule {
+    // `@attr` (on any element), `element/@attr` or `element` (all text inside it)
+    target: String,
+    redaction: Redaction,
+}
+
+/// Copy the file to `out_path` with the values selected by `rules` replaced,
+/// leaving everything else byte-for-byte as it was. Element names in rules
+/// match either the qualified or the local name. Emits `export-progress`
+/// (0-100); `records` is the number of values replaced.
+#[tauri::command]
+pub async fn export_redacted(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    rules: Vec<RedactRule>,
+    out_path: String,
+    op_id: Option<OpId>,
+) -> Result<Exported, XmlReaderError> {
+    let handle = files.get(file_id)?;
+    ops::run(window, op_id, "export", move |op| {
+        export_to(&handle, &out_path, |out| {
+            export_redacted_internal(&handle, &rules, &out_path, out, |pct| {
+                op.progress(pct);
+            })
+        })
+        .map_err(|e| XmlReaderError::in_file(&handle, e))
+    })
+    .await
+}
+
+fn export_redacted_internal(
+    handle: &FileHandle,
+    rules: &[RedactRule],
+    out_path: &str,
+    out: &File,
+    mut progress: impl FnMut(u64),
+) -> Result<Exported> {
+    // (element or None for any, attribute or None for the element's text)
+    let mut targets = Vec::new();
+    for rule in rules {
+        let target = match rule.target.split_once('@') {
+            Some(("", attr)) => (None, Some(attr)),
+            Some((element, attr)) if element.ends_with('/') => (Some(&element[..element.len() - 1]), Some(attr)),
+            None if !rule.target.is_empty() => (Some(rule.target.as_str()), None),
+            _ => return Err(anyhow::anyhow!("Invalid redaction target '{}'", rule.target)),
+        };
+        targets.push((target, rule.redaction));
+    }
+    let names_match = |rule: &str, e: &BytesStart| e.name().as_ref() == rule.as_bytes() || e.local_name().as_ref() == rule.as_bytes();
+
+    let file = handle.reader()?;
+    let file_len = file.len().max(1);
+    let mut reader = XmlReader::scan(file);
+    reader.check_end_names(false);
+
+    let mut writer = quick_xml::Writer::new(CountingWriter::new(BufWriter::new(out)));
+    let mut buf = Vec::new();
+    let mut depth = 0usize;
+    // Depth of the redacted element whose text is being replaced
+    let mut redacting: Option<(usize, Redaction)> = None;
+    let mut records = 0u64;
+    let mut last_pct = 0;
+
+    loop {
+        buf.clear();
+        let event = reader.read_event_into(&mut buf)?;
+        match &event {
+            Event::Start(e) | Event::Empty(e) => {
+                let mut replaced = Vec::new();
+                for attr in e.attributes().with_checks(false) {
+                    let attr = attr?;
+                    let rule = targets.iter().find(|((element, attr_name), _)| {
+                        attr_name.is_some_and(|a| a.as_bytes() == attr.key.as_ref())
+                            && element.is_none_or(|element| names_match(element, e))
+                    });
+                    let value = attr.unescape_value()?.into_owned();
+                    let value = match rule {
+                        Some((_, redaction)) => {
+                            records += 1;
+                            redacted(&value, *redaction)
+                        }
+                        None => value,
+                    };
+                    replaced.push((String::from_utf8_lossy(attr.key.as_ref()).into_owned(), value, rule.is_some()));
+                }
+
+                if replaced.iter().any(|(_, _, changed)| *changed) {
+                    let mut tag = BytesStart::new(String::from_utf8_lossy(e.name().as_ref()).into_owned());
+                    for (key, value, _) in &replaced {
+                        tag.push_attribute((key.as_str(), value.as_str()));
+                    }
+                    writer.write_event(if matches!(event, Event::Empty(_)) { Event::Empty(tag) } else { Event::Start(tag) })?;
+                } else {
+                    writer.write_event(event.borrow())?;
+                }
+
+                if matches!(event, Event::Start(_)) {
+                    depth += 1;
+                    if redacting.is_none() {
+                        let rule = targets.iter().find(|((element, attr), _)| attr.is_none() && element.is_some_and(|el| names_match(el, e)));
+                        redacting = rule.map(|(_, redaction)| (depth, *redaction));
+                    }
+                }
+            }
+            Event::End(_) => {
+                if redacting.is_some_and(|(at, _)| at == depth) {
+                    redacting = None;
+                }
+                depth = depth.saturating_sub(1);
+                writer.write_event(event.borrow())?;
+            }
+            Event::Text(e) if redacting.is_some() && !e.iter().all(u8::is_ascii_whitespace) => {
+                let text = e.unescape().map(|t| t.into_owned()).unwrap_or_else(|_| String::from_utf8_lossy(e).into_owned());
+                records += 1;
+                writer.write_event(Event::Text(BytesText::new(&redacted(&text, redacting.unwrap().1))))?;
+            }
+            Event::CData(e) if redacting.is_some() => {
+                records += 1;
+                writer.write_event(Event::Text(BytesText::new(&redacted(&String::from_utf8_lossy(e), redacting.unwrap().1))))?;
+            }
+            Event::Eof => break,
+            _ => writer.write_event(event.borrow())?,
+        }
+
+        let pct = reader.position() * 100 / file_len;
+        if pct > last_pct {
+            last_pct = pct;
+            progress(pct);
+        }
+    }
+
+    let mut out = writer.into_inner();
+    out.flush()?;
+    progress(100);
+    Ok(Exported {
+        out_path: out_path.to_string(),
+        bytes: out.count,
+        records,
+    })
+}
+
+fn redacted(value: &str, redaction: Redaction) -> String {
+    match redaction {
+        Redaction::Mask => "***".to_string(),
+        Redaction::Hash => Sha256::digest(value.as_bytes())[..8].iter().map(|b| format!("{:02x}", b)).collect(),
+    }
+}
+
+/// Base64 text decoded per block in `extract_binary`; a multiple of 4.
+const BASE64_BLOCK: usize = 64 * 1024;
+
+#[derive(serde::Serialize)]
+pub struct ExtractedBinary {
+    out_path: String,
+    bytes: u64,
+    // Detected from the leading bytes, e.g. `image/png` / `png`
+    mime_type: Option<String>,
+    extension: Option<String>,
+}
+
+/// Base64-decode the text of the element at `offset` (whitespace and line
+/// breaks ignored) and write the raw bytes to `out_path`. The file type is
+/// detected from its magic bytes.
+#[tauri::command]
+pub async fn extract_binary(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    offset: u64,
+    out_path: String,
+    op_id: Option<OpId>,
+) -> Result<ExtractedBinary, XmlReaderError> {
+    let handle = files.get(file_id)?;
+    ops::run(window, op_id, "export", move |_| {
+        export_to(&handle, &out_path, |out| extract_binary_internal(&handle, offset, &out_path, out))
+        .map_err(|e| XmlReaderError::in_file(&handle, e))
+    })
+    .await
+}
+
+fn extract_binary_internal(handle: &FileHandle, offset: u64, out_path: &str, out: &File) -> Result<ExtractedBinary> {
+    let engine = base64::engine::GeneralPurpose::new(
+        &base64::alphabet::STANDARD,
+        base64::engine::GeneralPurposeConfig::new().with_decode_padding_mode(base64::engine::DecodePaddingMode::Indifferent),
+    );
+    let mut reader = element_reader(handle, offset)?;
+    let mut buf = Vec::new();
+    loop {
+        buf.clear();
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(_) => break,
+            Event::Empty(_) => return Err(anyhow::anyhow!("The element at offset {} is empty", offset)),
+            Event::Text(e) if e.iter().all(u8::is_ascii_whitespace) => {}
+            _ => return Err(anyhow::anyhow!("No start tag found at offset {}", offset)),
+        }
+    }
+
+    let mut out = CountingWriter::new(BufWriter::new(out));
+    let mut pending: Vec<u8> = Vec::new();
+    let mut head: Vec<u8> = Vec::new();
+    let decode = |pending: &[u8], out: &mut CountingWriter<BufWriter<&File>>, head: &mut Vec<u8>| -> Result<()> {
+        let bytes = engine
+            .decode(pending)
+            .map_err(|e| anyhow::anyhow!("The element's text is not valid base64: {}", e))?;
+        if head.len() < 16 {
+            head.extend(bytes.iter().take(16 - head.len()));
+        }
+        out.write_all(&bytes)?;
+        Ok(())
+    };
+
+    let mut depth = 1u32;
+    while depth > 0 {
+        buf.clear();
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(_) => depth += 1,
+            Event::End(_) => depth -= 1,
+            Event::Text(e) => pending.extend(e.iter().filter(|b| !b.is_ascii_whitespace())),
+            Event::CData(e) => pending.extend(e.iter().filter(|b| !b.is_ascii_whitespace())),
+            Event::Eof => return Err(anyhow::anyhow!("Element is not closed before the end of the file")),
+            _ => {}
+        }
+        if pending.len() >= BASE64_BLOCK {
+            let rest = pending.split_off(BASE64_BLOCK);
+            decode(&pending, &mut out, &mut head)?;
+            pending = rest;
+        }
+    }
+    if !pending.is_empty() {
+        decode(&pending, &mut out, &mut head)?;
+    }
+    if out.count == 0 {
+        return Err(anyhow::anyhow!("The element at offset {} has no text to decode", offset));
+    }
+    out.flush()?;
+
+    let detected = sniff_binary(&head);
+    Ok(ExtractedBinary {
+        out_path: out_path.to_string(),
+        bytes: out.count,
+        mime_type: detected.map(|(mime, _)| mime.to_string()),
+        extension: detected.map(|(_, ext)| ext.to_string()),
+    })
+}
+
+/// MIME type and file extension for common formats, from their magic bytes.
+fn sniff_binary(head: &[u8]) -> Option<(&'static str, &'static str)> {
+    const SIGNATURES: &[(&[u8], &str, &str)] = &[
+        (b"\x89PNG\r\n\x1a\n", "image/png", "png"),
+        (b"\xff\xd8\xff", "image/jpeg", "jpg"),
+        (b"GIF87a", "image/gif", "gif"),
+        (b"GIF89a", "image/gif", "gif"),
+        (b"II*\0", "image/tiff", "tif"),
+        (b"MM\0*", "image/tiff", "tif"),
+        (b"BM", "image/bmp", "bmp"),
+        (b"%PDF-", "application/pdf", "pdf"),
+        (b"PK\x03\x04", "application/zip", "zip"),
+        (b"\x1f\x8b", "application/gzip", "gz"),
+        (b"\xd0\xcf\x11\xe0\xa1\xb1\x1a\xe1", "application/x-ole-storage", "doc"),
+        (b"<?xml", "application/xml", "xml"),
+        (b"{\\rtf", "application/rtf", "rtf"),
+    ];
+    if head.len() >= 12 && &head[..4] == b"RIFF" && &head[8..12] == b"WEBP" {
+        return Some(("image/webp", "webp"));
+    }
+    SIGNATURES
+        .iter()
+        .find(|(magic, _, _)| head.starts_with(magic))
+        .map(|&(_, mime, ext)| (mime, ext))
+}
+
+#[derive(serde::Serialize)]
+pub struct Reencoded {
+    out_path: String,
+    bytes: u64,
+    source_encoding: String,
+    target_encoding: String,
+    // Some characters don't exist in the target and were written as `&#N;`
+    lossy: bool,
+}
+
+/// Rewrite the file in `target_encoding` (any WHATWG label, e.g. `UTF-8`,
+/// `UTF-16LE`, `windows-1252`) with the declaration updated to match. The
+/// source encoding comes from the BOM or declaration. UTF-16 output gets a
+/// BOM; characters the target can't represent become character references.
+/// Emits `export-progress` (0-100).
+#[tauri::command]
+pub async fn reencode_file(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    out_path: String,
+    target_encoding: String,
+    op_id: Option<OpId>,
+) -> Result<Reencoded, XmlReaderError> {
+    let handle = files.get(file_id)?;
+    ops::run(window, op_id, "export", move |op| {
+        export_to(&handle, &out_path, |out| {
+            reencode_file_internal(&handle, &out_path, out, &target_encoding, |pct| {
+                op.progress(pct);
+            })
+        })
+        .map_err(|e| XmlReaderError::in_file(&handle, e))
+    })
+    .await
+}
+
+fn reencode_file_internal(
+    handle: &FileHandle,
+    out_path: &str,
+    out: &File,
+    target_encoding: &str,
+    mut progress: impl FnMut(u64),
+) -> Result<Reencoded> {
+    let target = Encoding::for_label(target_encoding.trim().as_bytes())
+        .ok_or_else(|| anyhow::anyhow!("Unknown encoding '{}'", target_encoding))?;
+    let utf16 = [UTF_16LE, UTF_16BE].contains(&target);
+    let declared_name = if utf16 { "UTF-16" } else { target.name() };
+
+    let mut file = handle.reader()?;
+    let file_len = file.len().max(1);
+    let mut head = Vec::new();
+    (&mut file).take(1024).read_to_end(&mut head)?;
+    file.seek(SeekFrom::Start(0))?;
+    let source = io::detect_encoding(&head);
+    // Follows a BOM over the detected encoding, and drops it
+    let mut decoder = source.new_decoder();
+    let mut encoder = target.new_encoder();
+
+    let mut out = CountingWriter::new(BufWriter::new(out));
+    if target == UTF_16LE {
+        out.write_all(b"\xff\xfe")?;
+    } else if target == UTF_16BE {
+        out.write_all(b"\xfe\xff")?;
+    }
+
+    let mut input = vec![0u8; 64 * 1024];
+    let mut encoded = Vec::new();
+    let mut text = String::new();
+    let mut read_so_far = 0u64;
+    let mut first = true;
+    let mut lossy = false;
+    let mut last_pct = 0;
+
+    loop {
+        let n = file.read(&mut input)?;
+        let last = n == 0;
+        text.clear();
+        text.reserve(decoder.max_utf8_buffer_length(n).unwrap_or(n * 3 + 16));
+        let (_, _, malformed) = decoder.decode_to_string(&input[..n], &mut text, last);
+        if malformed {
+            return Err(anyhow::anyhow!("The file is not valid {} near byte {}", source.name(), read_so_far));
+        }
+        read_so_far += n as u64;
+        if first && !text.is_empty() {
+            text = with_encoding_declaration(&text, declared_name);
+            first = false;
+        }
+
+        if utf16 {
+            for unit in text.encode_utf16() {
+                let bytes = if target == UTF_16LE { unit.to_le_bytes() } else { unit.to_be_bytes() };
+                out.write_all(&bytes)?;
+            }
+        } else {
+            let mut rest = text.as_str();
+            loop {
+                encoded.clear();
+                encoded.reserve(rest.len() + 64);
+                let (result, read, unmappable) = encoder.encode_from_utf8_to_vec(rest, &mut encoded, last);
+                lossy |= unmappable;
+                out.write_all(&encoded)?;
+                rest = &rest[read..];
+                if result == CoderResult::InputEmpty {
+                    break;
+                }
+            }
+        }
+        if last {
+            break;
+        }
+
+        let pct = read_so_far * 100 / file_len;
+        if pct > last_pct {
+            last_pct = pct;
+            progress(pct);
+        }
+    }
+
+    out.flush()?;
+    progress(100);
+    Ok(Reencoded {
+        out_path: out_path.to_string(),
+        bytes: out.count,
+        source_encoding: decoder.encoding().name().to_string(),
+        target_encoding: declared_name.to_string(),
+        lossy,
+    })
+}
+
+/// `text` with its XML declaration naming `encoding`: the existing
+/// `encoding` is replaced or one is added after `version`. Documents without
+/// a declaration get one unless the target is UTF-8, the default.
+fn with_encoding_declaration(text: &str, encoding: &str) -> String {
+    let decl_end = text
+        .strip_prefix("<?xml")
+        .filter(|rest| rest.starts_with(|c: char| c.is_ascii_whitespace()))
+        .and_then(|_| text.find("?>"));
+    let Some(decl_end) = decl_end else {
+        if encoding == "UTF-8" {
+            return text.to_string();
+        }
+        return format!("<?xml version=\"1.0\" encoding=\"{}\"?>\n{}", encoding, text);
+    };
+
+    let decl = &text[..decl_end];
+    let value_range = |name: &str| -> Option<(usize, usize)> {
+        let at = decl.find(name)? + name.len();
+        let quote_at = at + decl[at..].find(['"', '\''])?;
+        let quote = decl[quote_at..].chars().next()?;
+        let close = quote_at + 1 + decl[quote_at + 1..].find(quote)?;
+        Some((quote_at + 1, close))
+    };
+    let mut result = String::with_capacity(text.len() + 32);
+    match value_range("encoding") {
+        Some((start, end)) => {
+            result.push_str(&text[..start]);
+            result.push_str(encoding);
+            result.push_str(&text[end..]);
+        }
+        None => {
+            let insert_at = value_range("version").map_or(5, |(_, end)| end + 1);
+            result.push_str(&text[..insert_at]);
+            result.push_str(&format!(" encoding=\"{}\"", encoding));
+            result.push_str(&text[insert_at..]);
+        }
+    }
+    result
+}
+
+/// Write one `path = "value"` line per attribute and text node, e.g.
+/// `/Root/Item[3]/@name = "Foo"`, with values quoted and escaped as JSON
+/// strings. Steps below the root carry their position among same-name
+/// siblings. Elements at `max_depth` (0 for no limit) are not expanded: their
+/// line holds all the text inside them. Emits `export-progress` (0-100).
+#[tauri::command]
+pub async fn flatten(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    out_path: String,
+    max_depth: usize,
+    op_id: Option<OpId>,
+) -> Result<Exported, XmlReaderError> {
+    let handle = files.get(file_id)?;
+    ops::run(window, op_id, "export", move |op| {
+        export_to(&handle, &out_path, |out| {
+            flatten_internal(&handle, &out_path, out, max_depth, |pct| {
+                op.progress(pct);
+            })
+        })
+        .map_err(|e| XmlReaderError::in_file(&handle, e))
+    })
+    .await
+}
+
+fn flatten_internal(handle: &FileHandle, out_path: &str, out: &File, max_depth: usize, mut progress: impl FnMut(u64)) -> Result<Exported> {
+    struct Frame {
+        path: String,
+        // Children seen so far per name
+        positions: HashMap<String, u64>,
+    }
+
+    let file = handle.reader()?;
+    let file_len = file.len().max(1);
+    let mut reader = XmlReader::scan(file);
+    reader.check_end_names(false);
+
+    let mut out = CountingWriter::new(BufWriter::new(out));
+    let mut buf = Vec::new();
+    let mut stack: Vec<Frame> = Vec::new();
+    // Text of the collapsed element at `max_depth`, and how deep inside it we are
+    let mut collapsed: Option<(String, usize)> = None;
+    let mut records = 0u64;
+    let mut last_pct = 0;
+
+    let mut write_line = |out: &mut CountingWriter<BufWriter<&File>>, path: &str, value: &str| -> Result<()> {
+        writeln!(out, "{} = {}", path, serde_json::to_string(value)?)?;
+        records += 1;
+        Ok(())
+    };
+
+    loop {
+        buf.clear();
+        let event = reader.read_event_into(&mut buf)?;
+        match &event {
+            Event::Start(_) | Event::Empty(_) if collapsed.is_some() => {
+                if let (Event::Start(_), Some((_, depth))) = (&event, collapsed.as_mut()) {
+                    *depth += 1;
+                }
+            }
+            Event::Start(e) | Event::Empty(e) => {
+                let name = String::from_utf8_lossy(e.name().as_ref()).into_owned();
+                let path = match stack.last_mut() {
+                    Some(parent) => {
+                        let position = parent.positions.entry(name.clone()).or_default();
+                        *position += 1;
+                        format!("{}/{}[{}]", parent.path, name, position)
+                    }
+                    None => format!("/{}", name),
+                };
+                for attr in e.attributes().with_checks(false) {
+                    let attr = attr?;
+                    let value = attr.unescape_value()?;
+                    write_line(&mut out, &format!("{}/@{}", path, String::from_utf8_lossy(attr.key.as_ref())), &value)?;
+                }
+                let at_limit = max_depth > 0 && stack.len() + 1 >= max_depth;
+                match &event {
+                    Event::Start(_) if at_limit => {
+                        collapsed = Some((String::new(), 0));
+                        stack.push(Frame { path, positions: HashMap::new() });
+                    }
+                    Event::Start(_) => stack.push(Frame { path, positions: HashMap::new() }),
+                    _ => {}
+                }
+            }
+            Event::End(_) => match collapsed.as_mut() {
+                Some((_, depth)) if *depth > 0 => *depth -= 1,
+                Some(_) => {
+                    let (text, _) = collapsed.take().unwrap();
+                    if let Some(frame) = stack.pop() {
+                        if !text.trim().is_empty() {
+                            write_line(&mut out, &frame.path, text.trim())?;
+                        }
+                    }
+                }
+                None => {
+                    stack.pop();
+                }
+            },
+            Event::Text(_) | Event::CData(_) if !stack.is_empty() => {
+                let text = match &event {
+                    Event::Text(e) => e.unescape().map(|t| t.into_owned()).unwrap_or_else(|_| String::from_utf8_lossy(e).into_owned()),
+                    Event::CData(e) => String::from_utf8_lossy(e).into_owned(),
+                    _ => unreachable!(),
+                };
+                match collapsed.as_mut() {
+                    Some((collected, _)) => collected.push_str(&text),
+                    None if !text.trim().is_empty() => write_line(&mut out, &stack.last().unwrap().path, text.trim())?,
+                    None => {}
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+
+        let pct = reader.position() * 100 / file_len;
+        if pct > last_pct {
+            last_pct = pct;
+            progress(pct);
+        }
+    }
+
+    out.flush()?;
+    progress(100);
+    Ok(Exported {
+        out_path: out_path.to_string(),
+        bytes: out.count,
+        records,
+    })
+}
+
+#[derive(serde::Serialize)]
+pub struct SplitFiles {
+    out_paths: Vec<String>,
+    bytes: u64,
+    // Top-level elements written across all files
+    records: u64,
+}
+
+/// Write the root's children in groups of `chunk_elements`, each group into
+/// its own well-formed file in `out_dir` that repeats the prolog and the root
+/// start tag. `name_template` names the files: `{n}` is the 1-based file
+/// number and `{stem}` the source file name without extension, e.g.
+/// `{stem}-{n}.xml`. Emits `split-progress` (0-100); `cancel_split` stops it
+/// and removes the files written so far.
+#[tauri::command]
+pub async fn split_file(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    out_dir: String,
+    chunk_elements: u64,
+    name_template: String,
+    op_id: Option<OpId>,
+) -> Result<SplitFiles, XmlReaderError> {
+    let handle = files.get(file_id)?;
+    ops::run(window, op_id, "split", move |op| {
+        let mut written = Vec::new();
+        let result = handle.consistent(|| {
+            split_file_internal(&handle, &out_dir, chunk_elements, &name_template, &mut written, |pct| {
+                op.progress(pct);
+            })
+        });
+        if result.is_err() {
+            for path in &written {
+                let _ = std::fs::remove_file(path);
+            }
+        }
+        result.map_err(|e| XmlReaderError::in_file(&handle, e))
+    })
+    .await
+}
+
+/// Stop the splits running in the calling window.
+#[tauri::command]
+pub async fn cancel_split(window: WebviewWindow) -> Result<(), XmlReaderError> {
+    ops::cancel_kind(window.label(), "split");
+    Ok(())
+}
+
+/// `written` gets each file as soon as it is created, so the caller can clean
+/// up after a failure.
+fn split_file_internal(
+    handle: &FileHandle,
+    out_dir: &str,
+    chunk_elements: u64,
+    name_template: &str,
+    written: &mut Vec<String>,
+    mut progress: impl FnMut(u64),
+) -> Result<SplitFiles> {
+    if chunk_elements == 0 {
+        return Err(anyhow::anyhow!("chunk_elements must be at least 1"));
+    }
+    if !name_template.contains("{n}") {
+        return Err(anyhow::anyhow!("name_template must contain {{n}} to number the files"));
+    }
+    let stem = std::path::Path::new(&handle.path)
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let file = handle.reader()?;
+    let file_len = file.len().max(1);
+    let mut reader = XmlReader::scan(file);
+    reader.check_end_names(false);
+    let mut copy_from = handle.reader()?;
+
+    let mut buf = Vec::new();
+    let mut depth = 0u32;
+    // Prolog plus root start tag, and the matching close
+    let mut header = Vec::new();
+    let mut footer = String::new();
+    let mut child_start = 0u64;
+    // Where the next copy starts: the end of the previous child in the chunk
+    let mut chunk_pos = None;
+    let mut in_chunk = 0u64;
+    let mut out: Option<CountingWriter<BufWriter<File>>> = None;
+    let mut bytes = 0u64;
+    let mut records = 0u64;
+    let mut last_pct = 0;
+
+    loop {
+        buf.clear();
+        let pos_before = reader.position();
+        let event = reader.read_event_into(&mut buf)?;
+        let pos_after = reader.position();
+
+        let completed = match &event {
+            Event::Start(e) => {
+                depth += 1;
+                if depth == 1 {
+                    header = vec![0u8; pos_after as usize];
+                    copy_from.seek(SeekFrom::Start(0))?;
+                    copy_from.read_exact(&mut header)?;
+                    footer = format!("\n</{}>\n", String::from_utf8_lossy(e.name().as_ref()));
+                } else if depth == 2 {
+                    child_start = pos_before;
+                }
+                None
+            }
+            Event::Empty(_) if depth == 1 => Some(pos_before),
+            Event::End(_) => {
+                depth = depth.saturating_sub(1);
+                if depth == 0 {
+                    break;
+                }
+                (depth == 1).then_some(child_start)
+            }
+            Event::Eof => return Err(anyhow::anyhow!("The root element is not closed before the end of the file")),
+            _ => None,
+        };
+
+        if let Some(start) = completed {
+            if out.is_none() {
+                let name = name_template.replace("{n}", &(written.len() + 1).to_string()).replace("{stem}", &stem);
+                let path = std::path::Path::new(out_dir).join(name).to_string_lossy().into_owned();
+                if handle.is_source(&path) {
+                    return Err(anyhow::anyhow!("Cannot write {}: it is the file being split", path));
+                }
+                written.push(path.clone());
+                let mut file = CountingWriter::new(BufWriter::new(File::create(&path)?));
+                file.write_all(&header)?;
+                out = Some(file);
+            }
+            let from = chunk_pos.unwrap_or(start);
+            let file = out.as_mut().unwrap();
+            copy_from.seek(SeekFrom::Start(from))?;
+            std::io::copy(&mut (&mut copy_from).take(pos_after - from), file)?;
+            chunk_pos = Some(pos_after);
+            in_chunk += 1;
+            records += 1;
+
+            if in_chunk == chunk_elements {
+                bytes += finish_chunk(out.take().unwrap(), &footer)?;
+                chunk_pos = None;
+                in_chunk = 0;
+            }
+        }
+
+        let pct = pos_after * 100 / file_len;
+        if pct > last_pct {
+            last_pct = pct;
+            progress(pct);
+        }
+    }
+
+    if let Some(file) = out.take() {
+        bytes += finish_chunk(file, &footer)?;
+    }
+    progress(100);
+    Ok(SplitFiles {
+        out_paths: written.clone(),
+        bytes,
+        records,
+    })
+}
+
+fn finish_chunk(mut out: CountingWriter<BufWriter<File>>, footer: &str) -> Result<u64> {
+    out.write_all(footer.as_bytes())?;
+    out.flush()?;
+    Ok(out.count)
+}
+
+const XML_NAMESPACE: &str = "http://www.w3.org/XML/1998/namespace";
+
+/// Write the Exclusive XML Canonicalization 1.0 form (without comments) of
+/// the element at `offset`, or of the whole document when `offset` is `None`,
+/// to `out_path`. Namespaces the element inherits are resolved from its
+/// ancestors, so a signed fragment canonicalizes the same as it would in place.
+#[tauri::command]
+pub async fn canonicalize(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    offset: Option<u64>,
+    out_path: String,
+    op_id: Option<OpId>,
+) -> Result<Exported, XmlReaderError> {
+    let handle = files.get(file_id)?;
+    ops::run(window, op_id, "export", move |_| {
+        export_to(&handle, &out_path, |out| canonicalize_internal(&handle, offset, &out_path, out))
+        .map_err(|e| XmlReaderError::in_file(&handle, e))
+    })
+    .await
+}
+
+/// An open element in `canonicalize`: its name and how far `in_scope` and
+/// `rendered` reached before its declarations were added.
+struct C14nScope {
+    name: String,
+    in_scope: usize,
+    rendered: usize,
+}
+
+fn canonicalize_internal(handle: &FileHandle, offset: Option<u64>, out_path: &str, out: &File) -> Result<Exported> {
+    // (prefix, uri) bindings; "" is the default namespace
+    let mut in_scope: Vec<(String, String)> = Vec::new();
+    let mut reader = match offset {
+        Some(offset) => {
+            for (key, value) in inherited_namespaces(handle, offset)? {
+                let prefix = key.strip_prefix("xmlns:").unwrap_or("");
+                in_scope.push((prefix.to_string(), c14n_attr_value(value.as_bytes())?));
+            }
+            element_reader(handle, offset)?
+        }
+        None => {
+            let mut reader = XmlReader::scan(handle.reader()?);
+            reader.check_end_names(false);
+            reader
+        }
+    };
+
+    let mut out = CountingWriter::new(BufWriter::new(out));
+    let mut buf = Vec::new();
+    // Declarations already output on an ancestor
+    let mut rendered: Vec<(String, String)> = Vec::new();
+    let mut scopes: Vec<C14nScope> = Vec::new();
+    let mut records = 0u64;
+    let mut after_root = false;
+
+    loop {
+        buf.clear();
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(e) => {
+                write_c14n_start(&mut out, &e, &mut scopes, &mut in_scope, &mut rendered)?;
+                records += 1;
+            }
+            Event::Empty(e) => {
+                write_c14n_start(&mut out, &e, &mut scopes, &mut in_scope, &mut rendered)?;
+                records += 1;
+                write_c14n_end(&mut out, &mut scopes, &mut in_scope, &mut rendered)?;
+            }
+            Event::End(_) if !scopes.is_empty() => write_c14n_end(&mut out, &mut scopes, &mut in_scope, &mut rendered)?,
+            Event::Text(e) if !scopes.is_empty() => {
+                out.write_all(c14n_escape(&c14n_text(&e)?, false).as_bytes())?;
+            }
+            Event::CData(e) if !scopes.is_empty() => {
+                let text = String::from_utf8_lossy(&e).replace("\r\n", "\n").replace('\r', "\n");
+                out.write_all(c14n_escape(&text, false).as_bytes())?;
+            }
+            Event::PI(e) => {
+                let pi = String::from_utf8_lossy(&e).replace("\r\n", "\n").replace('\r', "\n");
+                let pi = match pi.split_once(|c: char| c.is_ascii_whitespace()) {
+                    Some((target, data)) if !data.trim_start().is_empty() => format!("<?{} {}?>", target, data.trim_start()),
+                    Some((target, _)) => format!("<?{}?>", target),
+                    None => format!("<?{}?>", pi),
+                };
+                // Outside the document element, PIs are separated from it by a newline
+                match (scopes.is_empty(), after_root) {
+                    (false, _) => out.write_all(pi.as_bytes())?,
+                    (true, false) if offset.is_none() => writeln!(out, "{}", pi)?,
+                    (true, true) if offset.is_none() => write!(out, "\n{}", pi)?,
+                    _ => {}
+                }
+            }
+            Event::Eof => break,
+            // Comments, the declaration, the DOCTYPE and whitespace outside the
+            // document element are not part of the canonical form
+            _ => {}
+        }
+        if records > 0 && scopes.is_empty() {
+            after_root = true;
+            if offset.is_some() {
+                break;
+            }
+        }
+    }
+
+    if !scopes.is_empty() {
+        return Err(anyhow::anyhow!("Element is not closed before the end of the file"));
+    }
+    if records == 0 {
+        return Err(anyhow::anyhow!("No element found to canonicalize"));
+    }
+    out.flush()?;
+    Ok(Exported {
+        out_path: out_path.to_string(),
+        bytes: out.count,
+        records,
+    })
+}
+
+/// Start tag with only the namespace declarations that are visibly used by
+/// the element or its attributes and not already output by an ancestor, and
+/// with declarations and attributes in canonical order.
+fn write_c14n_start(
+    out: &mut impl Write,
+    e: &BytesStart,
+    scopes: &mut Vec<C14nScope>,
+    in_scope: &mut Vec<(String, String)>,
+    rendered: &mut Vec<(String, String)>,
+) -> Result<()> {
+    let name = String::from_utf8_lossy(e.name().as_ref()).into_owned();
+    scopes.push(C14nScope {
+        name: name.clone(),
+        in_scope: in_scope.len(),
+        rendered: rendered.len(),
+    });
+
+    let mut attrs = Vec::new();
+    for attr in e.attributes() {
+        let attr = attr?;
+        let key = String::from_utf8_lossy(attr.key.as_ref()).into_owned();
+        let value = c14n_attr_value(&attr.value)?;
+        if key == "xmlns" {
+            in_scope.push((String::new(), value));
+        } else if let Some(prefix) = key.strip_prefix("xmlns:") {
+            in_scope.push((prefix.to_string(), value));
+        } else {
+            attrs.push((key, value));
+        }
+    }
+
+    let lookup = |list: &[(String, String)], prefix: &str| {
+        list.iter().rev().find(|(p, _)| p == prefix).map(|(_, uri)| uri.clone()).unwrap_or_default()
+    };
+    let prefix_of = |qname: &str| qname.split_once(':').map_or(String::new(), |(p, _)| p.to_string());
+
+    let mut used = vec![prefix_of(&name)];
+    for (key, _) in &attrs {
+        let prefix = prefix_of(key);
+        if !prefix.is_empty() && prefix != "xml" && !used.contains(&prefix) {
+            used.push(prefix);
+        }
+    }
+    let mut decls = Vec::new();
+    for prefix in used {
+        let uri = lookup(in_scope, &prefix);
+        if !prefix.is_empty() && uri.is_empty() {
+            return Err(anyhow::anyhow!("Prefix '{}' on <{}> is not bound to a namespace", prefix, name));
+        }
+        if lookup(rendered, &prefix) != uri {
+            decls.push((prefix, uri));
+        }
+    }
+    decls.sort();
+    rendered.extend(decls.iter().cloned());
+
+    // Attributes sort by namespace URI, then local name; unqualified ones first
+    let mut keyed = Vec::new();
+    for (key, value) in attrs {
+        let (uri, local) = match key.split_once(':') {
+            Some(("xml", local)) => (XML_NAMESPACE.to_string(), local.to_string()),
+            Some((prefix, local)) => (lookup(in_scope, prefix), local.to_string()),
+            None => (String::new(), key.clone()),
+        };
+        keyed.push(((uri, local), key, value));
+    }
+    keyed.sort();
+
+    write!(out, "<{}", name)?;
+    for (prefix, uri) in decls {
+        let colon = if prefix.is_empty() { "" } else { ":" };
+        write!(out, " xmlns{}{}=\"{}\"", colon, prefix, c14n_escape(&uri, true))?;
+    }
+    for (_, key, value) in keyed {
+        write!(out, " {}=\"{}\"", key, c14n_escape(&value, true))?;
+    }
+    out.write_all(b">")?;
+    Ok(())
+}
+
+fn write_c14n_end(
+    out: &mut impl Write,
+    scopes: &mut Vec<C14nScope>,
+    in_scope: &mut Vec<(String, String)>,
+    rendered: &mut Vec<(String, String)>,
+) -> Result<()> {
+    if let Some(scope) = scopes.pop() {
+        write!(out, "</{}>", scope.name)?;
+        in_scope.truncate(scope.in_scope);
+        rendered.truncate(scope.rendered);
+    }
+    Ok(())
+}
+
+/// Text content with line endings normalized and references expanded.
+fn c14n_text(raw: &[u8]) -> Result<String> {
+    let text = String::from_utf8_lossy(raw).replace("\r\n", "\n").replace('\r', "\n");
+    Ok(quick_xml::escape::unescape(&text)?.into_owned())
+}
+
+/// Attribute value after XML attribute-value normalization: literal
+/// whitespace becomes spaces, character references survive.
+fn c14n_attr_value(raw: &[u8]) -> Result<String> {
+    let value = String::from_utf8_lossy(raw).replace("\r\n", " ").replace(['\t', '\n', '\r'], " ");
+    Ok(quick_xml::escape::unescape(&value)?.into_owned())
+}
+
+fn c14n_escape(text: &str, attribute: bool) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' if !attribute => escaped.push_str("&gt;"),
+            '"' if attribute => escaped.push_str("&quot;"),
+            '\t' if attribute => escaped.push_str("&#x9;"),
+            '\n' if attribute => escaped.push_str("&#xA;"),
+            '\r' => escaped.push_str("&#xD;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn write_csv_row<'a>(out: &mut impl Write, fields: impl Iterator<Item = &'a str>) -> Result<()> {
+    let mut first = true;
+    for field in fields {
+        if !first {
+            out.write_all(b",")?;
+        }
+        first = false;
+        if field.contains([',', '"', '\n', '\r']) {
+            write!(out, "\"{}\"", field.replace('"', "\"\""))?;
+        } else {
+            out.write_all(field.as_bytes())?;
+        }
+    }
+    out.write_all(b"\r\n")?;
+    Ok(())
+}
+
+/// Run `export` against a temp file that replaces `out_path` through
+/// `save_atomic` once it succeeded, so a failed or cancelled export leaves
+/// whatever was at `out_path` alone. The file being read can't be the target.
+fn export_to<T>(handle: &FileHandle, out_path: &str, export: impl FnOnce(&File) -> Result<T>) -> Result<T> {
+    if handle.is_source(out_path) {
+        return Err(anyhow::anyhow!("Cannot export to {}: it is the file being read", out_path));
+    }
+    save_atomic(out_path, None, |out| handle.consistent(|| export(out)))
+}
+
+/// Counts the bytes written through it, for reporting the export size.
+struct CountingWriter<W> {
+    inner: W,
+    count: u64,
+}
+
+impl<W: Write> CountingWriter<W> {
+    fn new(inner: W) -> Self {
+        CountingWriter { inner, count: 0 }
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Position just past the end tag matching the start tag last read.
+pub fn element_end(reader: &mut XmlReader, buf: &mut Vec<u8>) -> Result<u64> {
+    let mut depth = 1u32;
+    loop {
+        buf.clear();
+        match reader.read_event_into(buf)? {
+            Event::Start(_) => depth += 1,
+            Event::End(_) => {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(reader.position());
+                }
+            }
+            Event::Eof => return Err(anyhow::anyhow!("Element is not closed before the end of the file")),
+            _ => {}
+        }
+    }
+}
+
+/// `xmlns` / `xmlns:*` attributes of a start tag, as (name, raw value).
+pub fn namespace_decls(e: &BytesStart) -> Vec<(String, String)> {
+    e.attributes()
+        .flatten()
+        .filter(|a| a.key.as_ref() == b"xmlns" || a.key.as_ref().starts_with(b"xmlns:"))
+        .map(|a| {
+            (
+                String::from_utf8_lossy(a.key.as_ref()).into_owned(),
+                String::from_utf8_lossy(&a.value).into_owned(),
+            )
+        })
+        .collect()
+}
+
+/// Namespace declarations in scope for the element at `offset`, declared on
+/// its ancestors; inner declarations win over outer ones.
+fn inherited_namespaces(handle: &FileHandle, offset: u64) -> Result<Vec<(String, String)>> {
+    let mut in_scope: Vec<(String, String)> = Vec::new();
+    for (_, ancestor) in open_elements_at(handle, offset)? {
+        for (key, value) in namespace_decls(&start_tag_at(handle, ancestor)?) {
+            match in_scope.iter_mut().find(|(k, _)| *k == key) {
+                Some(existing) => existing.1 = value,
+                None => in_scope.push((key, value)),
+            }
+        }
+    }
+    Ok(in_scope)
+}
+
+/// The file's own XML declaration, or a UTF-8 one if it has none.
+fn xml_declaration(handle: &FileHandle) -> Result<String> {
+    let mut reader = XmlReader::new(handle.reader()?, 4096);
+    let mut buf = Vec::new();
+    loop {
+        buf.clear();
+        match reader.read_event_into(&mut buf)? {
+            Event::Decl(e) => return Ok(format!("<?{}?>", String::from_utf8_lossy(&e))),
+            Event::Text(_) | Event::Comment(_) => {}
+            _ => return Ok("<?xml version=\"1.0\" encoding=\"UTF-8\"?>".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Canonical form of `content`, or of its element at `offset`.
+    fn canonical(name: &str, content: &str, offset: Option<u64>) -> String {
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("xml-reader-c14n-{}-{}.xml", std::process::id(), name));
+        let out_path = dir.join(format!("xml-reader-c14n-{}-{}.out", std::process::id(), name));
+        std::fs::write(&path, content).unwrap();
+        let handle = FileRegistry::default().open(path.to_str().unwrap()).unwrap();
+        let out = out_path.to_str().unwrap();
+        let result = export_to(&handle, out, |file| canonicalize_internal(&handle, offset, out, file));
+        let canonical = std::fs::read_to_string(&out_path);
+        drop(handle);
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(&out_path);
+        result.unwrap();
+        canonical.unwrap()
+    }
+
+    // Examples 3.1 to 3.4 of the Canonical XML 1.0 recommendation, without the
+    // parts that need the DTD, and with the namespace declarations exclusive
+    // canonicalization leaves out
+
+    #[test]
+    fn c14n_outside_the_document_element() {
+        let xml = "<?xml version=\"1.0\"?>\n\n<?xml-stylesheet   href=\"doc.xsl\"\n   type=\"text/xsl\"   ?>\n\n\
+                   <!DOCTYPE doc SYSTEM \"doc.dtd\">\n\n<doc>Hello, world!<!-- Comment 1 --></doc>\n\n\
+                   <?pi-without-data     ?>\n\n<!-- Comment 2 -->\n\n<!-- Comment 3 -->\n";
+        assert_eq!(
+            canonical("outside", xml, None),
+            "<?xml-stylesheet href=\"doc.xsl\"\n   type=\"text/xsl\"   ?>\n<doc>Hello, world!</doc>\n<?pi-without-data?>"
+        );
+    }
+
+    #[test]
+    fn c14n_keeps_whitespace_in_content() {
+        let xml = "<doc>\n   <clean>   </clean>\n   <dirty>   A   B   </dirty>\n   <mixed>\n      A\n      \
+                   <clean>   </clean>\n      B\n      <dirty>   A   B   </dirty>\n      C\n   </mixed>\n</doc>";
+        assert_eq!(canonical("whitespace", xml, None), xml);
+    }
+
+    #[test]
+    fn c14n_start_and_end_tags() {
+        let xml = r#"<doc>
+   <e1   />
+   <e2   ></e2   >
+   <e3   name = "elem3"   id="elem3"   />
+   <e4   name="elem4"   id="elem4"   ></e4>
+   <e5 a:attr="out" b:attr="sorted" attr2="all" attr="I'm"
+      xmlns:b="http://www.ietf.org"
+      xmlns:a="http://www.w3.org"
+      xmlns="http://example.org"/>
+   <e6 xmlns="" xmlns:a="http://www.w3.org">
+      <e7 xmlns="http://www.ietf.org">
+         <e8 xmlns="" xmlns:a="http://www.w3.org">
+            <e9 xmlns="" xmlns:a="http://www.ietf.org"/>
+         </e8>
+      </e7>
+   </e6>
+</doc>"#;
+        let expected = r#"<doc>
+   <e1></e1>
+   <e2></e2>
+   <e3 id="elem3" name="elem3"></e3>
+   <e4 id="elem4" name="elem4"></e4>
+   <e5 xmlns="http://example.org" xmlns:a="http://www.w3.org" xmlns:b="http://www.ietf.org" attr="I'm" attr2="all" b:attr="sorted" a:attr="out"></e5>
+   <e6>
+      <e7 xmlns="http://www.ietf.org">
+         <e8 xmlns="">
+            <e9></e9>
+         </e8>
+      </e7>
+   </e6>
+</doc>"#;
+        assert_eq!(canonical("tags", xml, None), expected);
+    }
+
+    #[test]
+    fn c14n_character_modifications() {
+        let xml = r#"<doc>
+   <text>First line&#x0d;&#10;Second line</text>
+   <value>&#x32;</value>
+   <compute><![CDATA[value>"0" && value<"10" ?"valid":"error"]]></compute>
+   <compute expr='value>"0" &amp;&amp; value&lt;"10" ?"valid":"error"'>valid</compute>
+   <norm attr=' &apos;   &#x20;&#13;&#xa;&#9;   &apos; '/>
+</doc>"#;
+        let expected = "<doc>
+   <text>First line&#xD;\nSecond line</text>
+   <value>2</value>
+   <compute>value&gt;\"0\" &amp;&amp; value&lt;\"10\" ?\"valid\":\"error\"</compute>
+   <compute expr=\"value>&quot;0&quot; &amp;&amp; value&lt;&quot;10&quot; ?&quot;valid&quot;:&quot;error&quot;\">valid</compute>
+   <norm attr=\" '    &#xD;&#xA;&#x9;   ' \"></norm>
+</doc>";
+        assert_eq!(canonical("characters", xml, None), expected);
+    }
+
+    #[test]
+    fn c14n_subset_takes_inherited_namespaces() {
+        // The example of section 2.2 of Exclusive XML Canonicalization 1.0
+        let xml = "<n0:local xmlns:n0=\"foo:bar\" xmlns:n3=\"ftp://example.org\">\n  \
+                   <n1:elem2 xmlns:n1=\"http://example.net\" xml:lang=\"en\">\n    \
+                   <n3:stuff xmlns:n3=\"ftp://example.org\"/>\n  </n1:elem2>\n</n0:local>";
+        let offset = xml.find("<n1:elem2").unwrap() as u64;
+        assert_eq!(
+            canonical("subset", xml, Some(offset)),
+            "<n1:elem2 xmlns:n1=\"http://example.net\" xml:lang=\"en\">\n    \
+             <n3:stuff xmlns:n3=\"ftp://example.org\"></n3:stuff>\n  </n1:elem2>"
+        );
+
+        // A prefix declared only on an ancestor is declared on the subset
+        let xml = "<a:r xmlns:a=\"urn:a\"><a:b x=\"1\"/></a:r>";
+        assert_eq!(canonical("inherited", xml, Some(20)), "<a:b xmlns:a=\"urn:a\" x=\"1\"></a:b>");
+    }
+
+    #[test]
+    fn export_element_declares_inherited_namespaces() {
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("xml-reader-export-{}-inherited.xml", std::process::id()));
+        let out_path = dir.join(format!("xml-reader-export-{}-inherited.out", std::process::id()));
+        let xml = "<r xmlns:a='urn:\"a\"&amp;' xmlns:b=\"urn:b\"><s xmlns:b=\"urn:c\"><a:t b:x=\"1\"/></s></r>";
+        std::fs::write(&path, xml).unwrap();
+        let handle = FileRegistry::default().open(path.to_str().unwrap()).unwrap();
+        let out = out_path.to_str().unwrap();
+        let offset = xml.find("<a:t").unwrap() as u64;
+        let result = export_to(&handle, out, |file| export_element_internal(&handle, offset, out, file, false, true));
+        let exported = std::fs::read_to_string(&out_path);
+        drop(handle);
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(&out_path);
+        result.unwrap();
+        assert_eq!(
+            exported.unwrap(),
+            "<a:t b:x=\"1\" xmlns:a=\"urn:&quot;a&quot;&amp;\" xmlns:b=\"urn:c\"/>"
+        );
+    }
+}
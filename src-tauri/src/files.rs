@@ -0,0 +1,284 @@
+use crate::error::XmlReaderError;
+use crate::io::{self, FilePool, Source};
+use crate::preview::Preview;
+use crate::save::{same_file, SourceStamp};
+use crate::settings;
+use crate::xmi::XmiIndex;
+use anyhow::Result;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+
+pub type FileId = u64;
+
+/// Elements open at some offset, outermost first: name and start offset.
+pub type OpenElements = Vec<(String, u64)>;
+
+/// `read_chunk` arguments: offset, size and align mode.
+pub type ChunkKey = (u64, u32, String);
+
+/// Chunks read ahead of a view that scrolls forward, see `xml_ops::read_chunk`.
+#[derive(Default)]
+pub struct ReadAhead {
+    /// End of the last chunk served; a read starting there is sequential.
+    pub next: Option<u64>,
+    /// Encoded `read_chunk` responses, oldest first.
+    pub chunks: VecDeque<(ChunkKey, Vec<u8>)>,
+    /// Chunks a prefetch thread is reading now.
+    pub pending: Vec<ChunkKey>,
+    /// Bumped by `refresh`, so a prefetch started before it can't store
+    /// bytes of the old file.
+    pub generation: u64,
+}
+
+/// How search, navigation and XPath treat malformed XML in a file.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ParsingMode {
+    /// Read past mismatched end tags and stray characters, as far as the
+    /// parser allows. The default setting.
+    Lenient,
+    /// Fail with a `ParseError` where the XML is wrong, so results are never
+    /// silently based on a broken structure.
+    Strict,
+}
+
+/// A file opened through `open_file`. Commands look it up by id instead of
+/// re-opening the path on every call.
+pub struct FileHandle {
+    pub id: FileId,
+    pub path: String,
+    // What is actually read: `path` itself, or a decoded copy of binary XML
+    data_path: String,
+    // `data_path` is a temp file created by the app, removed with the handle
+    temp: bool,
+    // Shared handle for short random-access reads (chunks, context)
+    source: Mutex<Source>,
+    // Idle handles behind `reader()`
+    pool: Arc<FilePool>,
+    /// Filled in by a background thread right after opening.
+    pub preview: OnceLock<Preview>,
+    strict: AtomicBool,
+    // Open elements at offsets forward scans went past, see `checkpoint_before`
+    checkpoints: Mutex<BTreeMap<u64, OpenElements>>,
+    read_ahead: Mutex<ReadAhead>,
+    // Label of the window that opened it, see `window`
+    window: OnceLock<String>,
+    /// Built by the first `xmi` command, see `xmi::index`.
+    pub xmi: Mutex<Option<Arc<XmiIndex>>>,
+}
+
+impl FileHandle {
+    pub fn len(&self) -> u64 {
+        self.source.lock().unwrap().len()
+    }
+
+    /// The shared random-access handle. Don't hold it across calls that need
+    /// it again; long scans should use `reader()` instead.
+    pub fn source(&self) -> MutexGuard<'_, Source> {
+        self.source.lock().unwrap()
+    }
+
+    /// An independent cursor over the file, for streaming scans that run
+    /// alongside chunk reads. Its handle is reused by later calls once it's
+    /// dropped.
+    pub fn reader(&self) -> Result<Source> {
+        Source::pooled(&self.data_path, &self.pool)
+    }
+
+    /// The file edits can be written back to: `None` when what is read is a
+    /// decoded or copied temp file, or a remote file.
+    pub fn local_path(&self) -> Option<&str> {
+        (!self.temp && !io::is_remote(&self.path)).then_some(self.path.as_str())
+    }
+
+    /// Whether writing `path` would replace this file, or the decoded copy
+    /// it is read from.
+    pub fn is_source(&self, path: &str) -> bool {
+        same_file(path, &self.path) || same_file(path, &self.data_path)
+    }
+
+    /// Label of the window the file was opened in. Events about the file
+    /// (preview, changes on disk, session) are sent only there.
+    pub fn window(&self) -> &str {
+        self.window.get().map_or("main", String::as_str)
+    }
+
+    pub fn set_window(&self, label: &str) {
+        let _ = self.window.set(label.to_string());
+    }
+
+    pub fn set_parsing_mode(&self, mode: ParsingMode) {
+        self.strict.store(mode == ParsingMode::Strict, Ordering::SeqCst);
+    }
+
+    /// Whether search, navigation and XPath should parse this file with
+    /// `XmlReader::set_strict`.
+    pub fn strict(&self) -> bool {
+        self.strict.load(Ordering::SeqCst)
+    }
+
+    /// The recorded checkpoint nearest before (or at) `offset`: a position
+    /// between two events and the elements open there, so a scan that needs
+    /// the ancestors of `offset` can start from it rather than from byte 0.
+    pub fn checkpoint_before(&self, offset: u64) -> Option<(u64, OpenElements)> {
+        let checkpoints = self.checkpoints.lock().unwrap();
+        let (&at, open) = checkpoints.range(..=offset).next_back()?;
+        Some((at, open.clone()))
+    }
+
+    /// Remember which elements are open at `offset`, a position between two
+    /// events reached by parsing forward from a known state.
+    pub fn add_checkpoint(&self, offset: u64, open: &OpenElements) {
+        self.checkpoints.lock().unwrap().insert(offset, open.clone());
+    }
+
+    /// Forget every checkpoint, so the next scan for ancestors starts from
+    /// byte 0.
+    pub fn clear_checkpoints(&self) {
+        self.checkpoints.lock().unwrap().clear();
+    }
+
+    /// Run `read`, a command reading the file in several steps, and fail
+    /// with `FileChanged` if the file's size or modification time differ
+    /// afterwards, rather than return a result mixing old and new bytes.
+    /// Remote files aren't checked.
+    pub fn consistent<T>(&self, read: impl FnOnce() -> Result<T>) -> Result<T> {
+        let before = self.stamp();
+        let result = read();
+        if before.is_some() && self.stamp() != before {
+            return Err(XmlReaderError::FileChanged { path: self.path.clone() }.into());
+        }
+        result
+    }
+
+    fn stamp(&self) -> Option<SourceStamp> {
+        if io::is_remote(&self.data_path) {
+            return None;
+        }
+        SourceStamp::of(&self.data_path).ok()
+    }
+
+    pub fn read_ahead(&self) -> MutexGuard<'_, ReadAhead> {
+        self.read_ahead.lock().unwrap()
+    }
+
+    /// Re-open the shared handle after the file changed on disk.
+    pub fn refresh(&self) -> Result<()> {
+        io::invalidate(&self.data_path);
+        self.pool.clear();
+        self.clear_checkpoints();
+        *self.xmi.lock().unwrap() = None;
+        {
+            let mut read_ahead = self.read_ahead();
+            *read_ahead = ReadAhead {
+                generation: read_ahead.generation + 1,
+                ..ReadAhead::default()
+            };
+        }
+        *self.source.lock().unwrap() = Source::open(&self.data_path)?;
+        Ok(())
+    }
+}
+
+impl Drop for FileHandle {
+    fn drop(&mut self) {
+        if self.temp {
+            let _ = std::fs::remove_file(&self.data_path);
+        }
+    }
+}
+
+/// Open files, kept in Tauri managed state.
+#[derive(Default)]
+pub struct FileRegistry {
+    next_id: AtomicU64,
+    files: Mutex<HashMap<FileId, Arc<FileHandle>>>,
+}
+
+impl FileRegistry {
+    pub fn open(&self, path: &str) -> Result<Arc<FileHandle>> {
+        match io::transcode_binary(path)? {
+            Some(decoded) => self.insert(path, decoded.to_string_lossy().into_owned(), true),
+            None => self.insert(path, path.to_string(), false),
+        }
+    }
+
+    /// Open a temp file the app wrote itself (pasted text, snapshot copies),
+    /// shown as `path`. The temp file is deleted once the handle goes away.
+    pub fn open_temp(&self, path: &str, data_path: &str) -> Result<Arc<FileHandle>> {
+        self.insert(path, data_path.to_string(), true)
+    }
+
+    /// Forget a file and drop its cached mapping. The handle itself (and any
+    /// temp file) goes away once in-flight commands release it.
+    pub fn close(&self, id: FileId) -> Result<()> {
+        let handle = self
+            .files
+            .lock()
+            .unwrap()
+            .remove(&id)
+            .ok_or(XmlReaderError::UnknownFile { file_id: id })?;
+        io::invalidate(&handle.data_path);
+        Ok(())
+    }
+
+    /// Forget every file opened in window `label`, once it's closed. Returns
+    /// their ids.
+    pub fn close_window(&self, label: &str) -> Vec<FileId> {
+        let closed: Vec<FileId> = {
+            let files = self.files.lock().unwrap();
+            files.values().filter(|h| h.window() == label).map(|h| h.id).collect()
+        };
+        for &id in &closed {
+            let _ = self.close(id);
+        }
+        closed
+    }
+
+    fn insert(&self, path: &str, data_path: String, temp: bool) -> Result<Arc<FileHandle>> {
+        let source = match Source::open(&data_path) {
+            Ok(source) => source,
+            Err(e) => {
+                if temp {
+                    let _ = std::fs::remove_file(&data_path);
+                }
+                return Err(e);
+            }
+        };
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst) + 1;
+        let handle = Arc::new(FileHandle {
+            id,
+            path: path.to_string(),
+            data_path,
+            temp,
+            source: Mutex::new(source),
+            pool: Arc::default(),
+            preview: OnceLock::new(),
+            strict: AtomicBool::new(settings::current().parsing_mode == ParsingMode::Strict),
+            checkpoints: Mutex::default(),
+            read_ahead: Mutex::default(),
+            window: OnceLock::new(),
+            xmi: Mutex::default(),
+        });
+        self.files.lock().unwrap().insert(id, handle.clone());
+        Ok(handle)
+    }
+
+    /// Every open file, in the order they were opened.
+    pub fn handles(&self) -> Vec<Arc<FileHandle>> {
+        let mut handles: Vec<_> = self.files.lock().unwrap().values().cloned().collect();
+        handles.sort_by_key(|h| h.id);
+        handles
+    }
+
+    pub fn get(&self, id: FileId) -> Result<Arc<FileHandle>> {
+        Ok(self
+            .files
+            .lock()
+            .unwrap()
+            .get(&id)
+            .cloned()
+            .ok_or(XmlReaderError::UnknownFile { file_id: id })?)
+    }
+}
@@ -0,0 +1,479 @@
+use crate::error::XmlReaderError;
+use crate::export::element_end;
+use crate::files::{FileHandle, FileId, FileRegistry};
+use crate::io::XmlReader;
+use crate::ops::{self, OpId};
+use crate::xml_ops::open_elements_at;
+use anyhow::Result;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Writer;
+use serde_json::{Map, Value};
+use std::borrow::Cow;
+use std::io::{Read, Seek, SeekFrom};
+use tauri::{State, WebviewWindow};
+
+/// Output cap for `minify_element`, which takes no `max_bytes`.
+const MINIFY_MAX_BYTES: usize = 16 * 1024 * 1024;
+
+#[derive(serde::Serialize)]
+pub struct FormattedElement {
+    text: String,
+    // Byte range of the element in the file
+    offset: u64,
+    end: u64,
+    // Output reached `max_bytes` and was cut
+    truncated: bool,
+}
+
+/// The element at `offset`, re-indented by `indent` spaces per level.
+#[tauri::command]
+pub async fn format_element(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    offset: u64,
+    indent: usize,
+    max_bytes: usize,
+    op_id: Option<OpId>,
+) -> Result<FormattedElement, XmlReaderError> {
+    let handle = files.get(file_id)?;
+    ops::run(window, op_id, "format", move |_| {
+        rewrite_element(&handle, offset, Some(indent), max_bytes).map_err(|e| XmlReaderError::in_file(&handle, e))
+    })
+    .await
+}
+
+/// The element at `offset` without whitespace between tags, for pasting into
+/// config files or diff tools that expect compact XML.
+#[tauri::command]
+pub async fn minify_element(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    offset: u64,
+    op_id: Option<OpId>,
+) -> Result<FormattedElement, XmlReaderError> {
+    let handle = files.get(file_id)?;
+    ops::run(window, op_id, "format", move |_| {
+        rewrite_element(&handle, offset, None, MINIFY_MAX_BYTES).map_err(|e| XmlReaderError::in_file(&handle, e))
+    })
+    .await
+}
+
+/// Largest element `element_to_json` converts; the whole tree is built in memory.
+const JSON_MAX_ELEMENT_BYTES: u64 = 16 * 1024 * 1024;
+
+#[derive(serde::Deserialize)]
+#[serde(default)]
+pub struct JsonOptions {
+    /// Prefix for attribute keys, e.g. `@id`.
+    attribute_prefix: String,
+    skip_attributes: bool,
+    /// Key for the text of elements that also have attributes or children.
+    text_key: String,
+    /// Make every child element an array, not only repeated ones, so the
+    /// shape of the output doesn't depend on the data.
+    always_array: bool,
+    pretty: bool,
+}
+
+impl Default for JsonOptions {
+    fn default() -> Self {
+        JsonOptions {
+            attribute_prefix: "@".to_string(),
+            skip_attributes: false,
+            text_key: "#text".to_string(),
+            always_array: false,
+            pretty: true,
+        }
+    }
+}
+
+/// The element at `offset` as JSON: `{"name": value}`, where value is the
+/// text of a plain element, or an object of attributes, children and text.
+#[tauri::command]
+pub async fn element_to_json(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    offset: u64,
+    options: Option<JsonOptions>,
+    op_id: Option<OpId>,
+) -> Result<String, XmlReaderError> {
+    let handle = files.get(file_id)?;
+    ops::run(window, op_id, "format", move |_| {
+        element_to_json_internal(&handle, offset, &options.unwrap_or_default())
+            .map_err(|e| XmlReaderError::in_file(&handle, e))
+    })
+    .await
+}
+
+fn element_to_json_internal(handle: &FileHandle, offset: u64, options: &JsonOptions) -> Result<String> {
+    let mut reader = element_reader(handle, offset)?;
+    let mut buf = Vec::new();
+    let mut builder = JsonBuilder::new(options);
+
+    let json = loop {
+        if reader.position() - offset > JSON_MAX_ELEMENT_BYTES {
+            return Err(anyhow::anyhow!(
+                "Element is larger than {} MB; export it to a file instead",
+                JSON_MAX_ELEMENT_BYTES / 1024 / 1024
+            ));
+        }
+        buf.clear();
+        let event = reader.read_event_into(&mut buf)?;
+        match event {
+            Event::End(_) if !builder.is_open() => return Err(anyhow::anyhow!("No start tag found at offset {}", offset)),
+            Event::Eof => return Err(anyhow::anyhow!("Element is not closed before the end of the file")),
+            _ => {}
+        }
+        if let Some((name, value)) = builder.event(&event)? {
+            let mut object = Map::new();
+            object.insert(name, value);
+            break Value::Object(object);
+        }
+    };
+
+    Ok(if options.pretty {
+        serde_json::to_string_pretty(&json)?
+    } else {
+        serde_json::to_string(&json)?
+    })
+}
+
+/// Builds the JSON for one element from its events, as `element_to_json`
+/// describes: the text of a plain element, or an object of attributes,
+/// children and text.
+pub struct JsonBuilder<'a> {
+    options: &'a JsonOptions,
+    stack: Vec<JsonFrame>,
+}
+
+struct JsonFrame {
+    name: String,
+    fields: Map<String, Value>,
+    text: String,
+}
+
+impl<'a> JsonBuilder<'a> {
+    pub fn new(options: &'a JsonOptions) -> Self {
+        JsonBuilder { options, stack: Vec::new() }
+    }
+
+    /// Whether an element has been started and not finished yet.
+    pub fn is_open(&self) -> bool {
+        !self.stack.is_empty()
+    }
+
+    /// Feed the next event, starting with the element's start tag. Returns the
+    /// element's name and value once its end tag has been fed.
+    pub fn event(&mut self, event: &Event) -> Result<Option<(String, Value)>> {
+        let finished = match event {
+            Event::Start(e) => {
+                let frame = self.open(e)?;
+                self.stack.push(frame);
+                None
+            }
+            Event::Empty(e) => Some(self.close(self.open(e)?)),
+            Event::End(_) => self.stack.pop().map(|frame| self.close(frame)),
+            Event::Text(e) => {
+                if let Some(frame) = self.stack.last_mut() {
+                    frame.text.push_str(&e.unescape()?);
+                }
+                None
+            }
+            Event::CData(e) => {
+                if let Some(frame) = self.stack.last_mut() {
+                    frame.text.push_str(&String::from_utf8_lossy(e));
+                }
+                None
+            }
+            _ => None,
+        };
+        Ok(match finished {
+            Some((name, value)) => match self.stack.last_mut() {
+                Some(parent) => {
+                    Self::add_child(parent, name, value, self.options.always_array);
+                    None
+                }
+                None => Some((name, value)),
+            },
+            None => None,
+        })
+    }
+
+    fn open(&self, e: &BytesStart) -> Result<JsonFrame> {
+        let mut fields = Map::new();
+        if !self.options.skip_attributes {
+            for attribute in e.attributes() {
+                let attribute = attribute?;
+                let key = format!("{}{}", self.options.attribute_prefix, String::from_utf8_lossy(attribute.key.as_ref()));
+                let value = attribute.unescape_value()?.into_owned();
+                fields.insert(key, Value::String(value));
+            }
+        }
+        Ok(JsonFrame {
+            name: String::from_utf8_lossy(e.name().as_ref()).into_owned(),
+            fields,
+            text: String::new(),
+        })
+    }
+
+    fn close(&self, frame: JsonFrame) -> (String, Value) {
+        let blank = frame.text.trim().is_empty();
+        let value = if frame.fields.is_empty() {
+            Value::String(if blank { String::new() } else { frame.text })
+        } else {
+            let mut fields = frame.fields;
+            if !blank {
+                fields.insert(self.options.text_key.clone(), Value::String(frame.text.trim().to_string()));
+            }
+            Value::Object(fields)
+        };
+        (frame.name, value)
+    }
+
+    fn add_child(parent: &mut JsonFrame, name: String, value: Value, always_array: bool) {
+        match parent.fields.get_mut(&name) {
+            Some(Value::Array(items)) => items.push(value),
+            Some(existing) => *existing = Value::Array(vec![existing.take(), value]),
+            None if always_array => {
+                parent.fields.insert(name, Value::Array(vec![value]));
+            }
+            None => {
+                parent.fields.insert(name, value);
+            }
+        }
+    }
+
+    pub fn text_key(&self) -> &str {
+        &self.options.text_key
+    }
+}
+
+/// Largest element `get_element_with_context` returns; the wrapper is only
+/// useful if the result stays well-formed, so bigger elements are an error
+/// rather than truncated.
+const CONTEXT_MAX_ELEMENT_BYTES: u64 = 16 * 1024 * 1024;
+
+/// The element at `offset` wrapped in the start and end tags of its ancestors
+/// (with their attributes, siblings left out), as a minimal well-formed
+/// fragment for pasting into validators.
+#[tauri::command]
+pub async fn get_element_with_context(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    offset: u64,
+    op_id: Option<OpId>,
+) -> Result<FormattedElement, XmlReaderError> {
+    let handle = files.get(file_id)?;
+    ops::run(window, op_id, "format", move |_| {
+        element_with_context(&handle, offset).map_err(|e| XmlReaderError::in_file(&handle, e))
+    })
+    .await
+}
+
+fn element_with_context(handle: &FileHandle, offset: u64) -> Result<FormattedElement> {
+    // Raw start tags and names of the open ancestors
+    let mut ancestors: Vec<(String, String)> = Vec::new();
+    for (name, ancestor) in open_elements_at(handle, offset)? {
+        let tag = start_tag_at(handle, ancestor)?;
+        ancestors.push((format!("<{}>", String::from_utf8_lossy(&tag)), name));
+    }
+
+    let mut buf = Vec::new();
+    let mut reader = element_reader(handle, offset)?;
+    let empty = loop {
+        buf.clear();
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(_) => break false,
+            Event::Empty(_) => break true,
+            Event::Text(e) if e.iter().all(u8::is_ascii_whitespace) => {}
+            _ => return Err(anyhow::anyhow!("No start tag found at offset {}", offset)),
+        }
+    };
+    let end = if empty { reader.position() } else { element_end(&mut reader, &mut buf)? };
+    if end - offset > CONTEXT_MAX_ELEMENT_BYTES {
+        return Err(anyhow::anyhow!("Element is larger than {} bytes", CONTEXT_MAX_ELEMENT_BYTES));
+    }
+
+    let mut element = vec![0u8; (end - offset) as usize];
+    let mut file = handle.reader()?;
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut element)?;
+    let element = String::from_utf8_lossy(&element);
+
+    let mut text = String::new();
+    for (depth, (tag, _)) in ancestors.iter().enumerate() {
+        text.push_str(&format!("{}{}\n", "  ".repeat(depth), tag));
+    }
+    text.push_str(&"  ".repeat(ancestors.len()));
+    text.push_str(element.trim_start());
+    text.push('\n');
+    for (depth, (_, name)) in ancestors.iter().enumerate().rev() {
+        text.push_str(&format!("{}</{}>\n", "  ".repeat(depth), name));
+    }
+
+    Ok(FormattedElement {
+        text,
+        offset,
+        end,
+        truncated: false,
+    })
+}
+
+/// A reader positioned on the start tag at `offset`.
+pub fn element_reader(handle: &FileHandle, offset: u64) -> Result<XmlReader> {
+    let mut file = handle.reader()?;
+    file.seek(SeekFrom::Start(offset))?;
+    let mut reader = XmlReader::new(file, 64 * 1024);
+    reader.check_end_names(false);
+    Ok(reader)
+}
+
+/// The start tag of the element at `offset`, such as an ancestor's offset
+/// from `open_elements_at`.
+pub fn start_tag_at(handle: &FileHandle, offset: u64) -> Result<BytesStart<'static>> {
+    let mut reader = element_reader(handle, offset)?;
+    let mut buf = Vec::new();
+    match reader.read_event_into(&mut buf)? {
+        Event::Start(e) | Event::Empty(e) => Ok(e.into_owned()),
+        _ => Err(anyhow::anyhow!("No element starts at offset {}", offset)),
+    }
+}
+
+/// Stream the element at `offset` through quick-xml's writer, dropping
+/// whitespace-only text (except under `xml:space="preserve"`) and re-spacing
+/// attributes, so the writer's indentation (none without `indent`) is the only
+/// layout left.
+fn rewrite_element(handle: &FileHandle, offset: u64, indent: Option<usize>, max_bytes: usize) -> Result<FormattedElement> {
+    let mut reader = element_reader(handle, offset)?;
+    let mut writer = match indent {
+        Some(n) => Writer::new_with_indent(Vec::new(), b' ', n),
+        None => Writer::new(Vec::new()),
+    };
+
+    let mut buf = Vec::new();
+    let mut depth = 0u32;
+    // Depth at which xml:space="preserve" was switched on
+    let mut preserve_from: Option<u32> = None;
+    let mut truncated = false;
+
+    loop {
+        buf.clear();
+        let event = reader.read_event_into(&mut buf)?;
+        match event {
+            Event::Start(e) => {
+                depth += 1;
+                if preserve_from.is_none() && preserves_space(&e) {
+                    preserve_from = Some(depth);
+                }
+                writer.write_event(Event::Start(respaced(&e)))?;
+            }
+            Event::Empty(e) => {
+                writer.write_event(Event::Empty(respaced(&e)))?;
+            }
+            Event::End(e) => {
+                if depth == 0 {
+                    return Err(anyhow::anyhow!("No start tag found at offset {}", offset));
+                }
+                if preserve_from == Some(depth) {
+                    preserve_from = None;
+                }
+                depth -= 1;
+                writer.write_event(Event::End(e))?;
+            }
+            Event::Text(e) => {
+                if depth == 0 {
+                    continue;
+                }
+                if preserve_from.is_some() || !e.iter().all(u8::is_ascii_whitespace) {
+                    writer.write_event(Event::Text(e))?;
+                }
+            }
+            Event::Eof => break,
+            Event::Decl(_) | Event::DocType(_) if depth == 0 => continue,
+            e => {
+                if depth == 0 {
+                    return Err(anyhow::anyhow!("No start tag found at offset {}", offset));
+                }
+                writer.write_event(e)?;
+            }
+        }
+
+        if writer.get_ref().len() > max_bytes {
+            truncated = true;
+            break;
+        }
+        if depth == 0 {
+            break;
+        }
+    }
+
+    let end = reader.position();
+    let mut out = writer.into_inner();
+    if truncated {
+        // Cut on a character boundary
+        let mut cut = max_bytes;
+        while cut > 0 && out[cut] & 0xC0 == 0x80 {
+            cut -= 1;
+        }
+        out.truncate(cut);
+    }
+    Ok(FormattedElement {
+        text: String::from_utf8_lossy(&out).into_owned(),
+        offset,
+        end,
+        truncated,
+    })
+}
+
+fn preserves_space(e: &BytesStart) -> bool {
+    e.attributes()
+        .flatten()
+        .any(|a| a.key.as_ref() == b"xml:space" && a.value.as_ref() == b"preserve")
+}
+
+/// The same start tag with single spaces between attributes. Values are
+/// re-quoted with `"`, so a literal `"` from a single-quoted value is escaped.
+fn respaced<'a>(e: &'a BytesStart<'a>) -> BytesStart<'a> {
+    let mut tag = BytesStart::new(String::from_utf8_lossy(e.name().as_ref()).into_owned());
+    for mut attribute in e.attributes().flatten() {
+        if attribute.value.contains(&b'"') {
+            let value: Vec<u8> = attribute
+                .value
+                .iter()
+                .flat_map(|b| if *b == b'"' { &b"&quot;"[..] } else { std::slice::from_ref(b) })
+                .copied()
+                .collect();
+            attribute.value = Cow::Owned(value);
+        }
+        tag.push_attribute(attribute);
+    }
+    tag
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn element_with_context_wraps_in_ancestors() {
+        let path = std::env::temp_dir().join(format!("xml-reader-format-{}-context.xml", std::process::id()));
+        let xml = "<r a=\"1\"><s/><t b=\"2\">x<u>y</u></t></r>";
+        std::fs::write(&path, xml).unwrap();
+        let handle = FileRegistry::default().open(path.to_str().unwrap()).unwrap();
+        let offset = xml.find("<u>").unwrap() as u64;
+        let context = element_with_context(&handle, offset);
+        // From a checkpoint inside the root, only the rest is parsed
+        handle.add_checkpoint(xml.find("<t").unwrap() as u64, &vec![("r".to_string(), 0)]);
+        let again = element_with_context(&handle, offset);
+        drop(handle);
+        let _ = std::fs::remove_file(&path);
+        let context = context.unwrap();
+        assert_eq!(context.text, "<r a=\"1\">\n  <t b=\"2\">\n    <u>y</u>\n  </t>\n</r>\n");
+        assert_eq!((context.offset, context.end), (offset, offset + 8));
+        assert_eq!(again.unwrap().text, context.text);
+    }
+}
@@ -0,0 +1,274 @@
+use crate::error::XmlReaderError;
+use crate::files::{FileHandle, FileId, FileRegistry};
+use crate::io::XmlReader;
+use crate::ops::{self, OpId};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use quick_xml::events::{BytesStart, Event};
+use tauri::{State, WebviewWindow};
+
+#[derive(serde::Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum GeoFormat {
+    Gpx,
+    Kml,
+}
+
+#[derive(serde::Serialize)]
+pub struct Bounds {
+    min_lat: f64,
+    min_lon: f64,
+    max_lat: f64,
+    max_lon: f64,
+}
+
+#[derive(serde::Serialize)]
+pub struct GeoSummary {
+    format: GeoFormat,
+    /// GPX `<trk>`s; KML `<LineString>`s and `<gx:Track>`s.
+    tracks: u64,
+    /// GPX `<trkseg>`s.
+    segments: u64,
+    /// Points of the tracks.
+    track_points: u64,
+    /// GPX `<wpt>`s; KML `<Point>`s.
+    waypoints: u64,
+    /// GPX `<rte>`s.
+    routes: u64,
+    route_points: u64,
+    /// KML `<Placemark>`s.
+    placemarks: u64,
+    /// Earliest and latest point time, RFC 3339 in UTC.
+    start_time: Option<String>,
+    end_time: Option<String>,
+    /// Of every coordinate in the file; `None` if it has none.
+    bounds: Option<Bounds>,
+}
+
+/// What an open element is, as far as the summary cares.
+#[derive(Clone, Copy, PartialEq)]
+enum Tag {
+    /// GPX `<trkpt>`, `<rtept>` or `<wpt>`, whose `<time>` counts.
+    GpxPoint,
+    GpxTime,
+    /// KML `<when>`, `<begin>` or `<end>`.
+    KmlTime,
+    Coordinates,
+    /// `<gx:coord>`: `lon lat alt`.
+    Coord,
+    LineString,
+    Other,
+}
+
+/// Track, point and waypoint counts, time range and bounding box of a GPX
+/// or KML file, in one pass. The format is told by the root element. Emits
+/// `stats-progress` (0-100).
+#[tauri::command]
+pub async fn gpx_summary(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    op_id: Option<OpId>,
+) -> Result<GeoSummary, XmlReaderError> {
+    let handle = files.get(file_id)?;
+    ops::run(window, op_id, "stats", move |op| {
+        gpx_summary_internal(&handle, |pct| {
+            op.progress(pct);
+        })
+        .map_err(|e| XmlReaderError::in_file(&handle, e))
+    })
+    .await
+}
+
+fn gpx_summary_internal(handle: &FileHandle, mut progress: impl FnMut(u64)) -> Result<GeoSummary> {
+    let file = handle.reader()?;
+    let file_len = file.len().max(1);
+    let mut reader = XmlReader::scan(file);
+    reader.check_end_names(false);
+    let mut buf = Vec::new();
+    let mut last_pct = 0;
+
+    let mut summary: Option<GeoSummary> = None;
+    let mut stack: Vec<Tag> = Vec::new();
+    let mut times: Option<(DateTime<Utc>, DateTime<Utc>)> = None;
+    let mut bounds: Option<Bounds> = None;
+
+    loop {
+        buf.clear();
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(e) => {
+                let tag = start(&mut summary, &e, &mut bounds)?;
+                stack.push(tag);
+            }
+            Event::Empty(e) => {
+                start(&mut summary, &e, &mut bounds)?;
+            }
+            Event::End(_) => {
+                stack.pop();
+            }
+            Event::Text(e) => {
+                let Some(summary) = summary.as_mut() else {
+                    continue;
+                };
+                let parent = stack.len().checked_sub(2).map(|i| stack[i]);
+                match stack.last() {
+                    Some(Tag::GpxTime) if parent == Some(Tag::GpxPoint) => add_time(&mut times, &e.unescape()?),
+                    Some(Tag::KmlTime) => add_time(&mut times, &e.unescape()?),
+                    Some(Tag::Coordinates) => {
+                        let text = e.unescape()?;
+                        for tuple in text.split_whitespace() {
+                            let mut parts = tuple.split(',').map(|n| n.parse::<f64>());
+                            if let (Some(Ok(lon)), Some(Ok(lat))) = (parts.next(), parts.next()) {
+                                add_point(&mut bounds, lat, lon);
+                                if parent == Some(Tag::LineString) {
+                                    summary.track_points += 1;
+                                }
+                            }
+                        }
+                    }
+                    Some(Tag::Coord) => {
+                        let text = e.unescape()?;
+                        let mut parts = text.split_whitespace().map(|n| n.parse::<f64>());
+                        if let (Some(Ok(lon)), Some(Ok(lat))) = (parts.next(), parts.next()) {
+                            add_point(&mut bounds, lat, lon);
+                            summary.track_points += 1;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+
+        let pct = reader.position() * 100 / file_len;
+        if pct > last_pct {
+            last_pct = pct;
+            progress(pct);
+        }
+    }
+
+    let mut summary = summary.ok_or_else(|| anyhow::anyhow!("Not a GPX or KML file: it has no root element"))?;
+    if let Some((first, last)) = times {
+        summary.start_time = Some(first.to_rfc3339());
+        summary.end_time = Some(last.to_rfc3339());
+    }
+    summary.bounds = bounds;
+    Ok(summary)
+}
+
+/// Count the element `e` starts, the root telling the format, and take the
+/// coordinates of a GPX point.
+fn start(summary: &mut Option<GeoSummary>, e: &BytesStart, bounds: &mut Option<Bounds>) -> Result<Tag> {
+    let local = e.local_name();
+    let name = local.as_ref();
+    let Some(summary) = summary else {
+        let format = match name {
+            b"gpx" => GeoFormat::Gpx,
+            b"kml" => GeoFormat::Kml,
+            _ => {
+                let root = String::from_utf8_lossy(e.name().as_ref()).into_owned();
+                return Err(anyhow::anyhow!("Not a GPX or KML file: the root element is <{}>", root));
+            }
+        };
+        *summary = Some(GeoSummary {
+            format,
+            tracks: 0,
+            segments: 0,
+            track_points: 0,
+            waypoints: 0,
+            routes: 0,
+            route_points: 0,
+            placemarks: 0,
+            start_time: None,
+            end_time: None,
+            bounds: None,
+        });
+        return Ok(Tag::Other);
+    };
+    let tag = match (summary.format, name) {
+        (GeoFormat::Gpx, b"trk") => {
+            summary.tracks += 1;
+            Tag::Other
+        }
+        (GeoFormat::Gpx, b"trkseg") => {
+            summary.segments += 1;
+            Tag::Other
+        }
+        (GeoFormat::Gpx, b"rte") => {
+            summary.routes += 1;
+            Tag::Other
+        }
+        (GeoFormat::Gpx, b"trkpt" | b"rtept" | b"wpt") => {
+            match name {
+                b"trkpt" => summary.track_points += 1,
+                b"rtept" => summary.route_points += 1,
+                _ => summary.waypoints += 1,
+            }
+            let coordinate = |key: &[u8]| {
+                let attr = e.attributes().flatten().find(|a| a.key.as_ref() == key)?;
+                String::from_utf8_lossy(&attr.value).trim().parse::<f64>().ok()
+            };
+            if let (Some(lat), Some(lon)) = (coordinate(b"lat"), coordinate(b"lon")) {
+                add_point(bounds, lat, lon);
+            }
+            Tag::GpxPoint
+        }
+        (GeoFormat::Gpx, b"time") => Tag::GpxTime,
+        (GeoFormat::Kml, b"Placemark") => {
+            summary.placemarks += 1;
+            Tag::Other
+        }
+        (GeoFormat::Kml, b"LineString") => {
+            summary.tracks += 1;
+            Tag::LineString
+        }
+        (GeoFormat::Kml, b"Track") => {
+            summary.tracks += 1;
+            Tag::Other
+        }
+        (GeoFormat::Kml, b"Point") => {
+            summary.waypoints += 1;
+            Tag::Other
+        }
+        (GeoFormat::Kml, b"coordinates") => Tag::Coordinates,
+        (GeoFormat::Kml, b"coord") => Tag::Coord,
+        (GeoFormat::Kml, b"when" | b"begin" | b"end") => Tag::KmlTime,
+        _ => Tag::Other,
+    };
+    Ok(tag)
+}
+
+fn add_point(bounds: &mut Option<Bounds>, lat: f64, lon: f64) {
+    if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) {
+        return;
+    }
+    match bounds {
+        Some(b) => {
+            b.min_lat = b.min_lat.min(lat);
+            b.min_lon = b.min_lon.min(lon);
+            b.max_lat = b.max_lat.max(lat);
+            b.max_lon = b.max_lon.max(lon);
+        }
+        None => {
+            *bounds = Some(Bounds {
+                min_lat: lat,
+                min_lon: lon,
+                max_lat: lat,
+                max_lon: lon,
+            })
+        }
+    }
+}
+
+/// Widen the time range with `text`, if it is an RFC 3339 time.
+fn add_time(times: &mut Option<(DateTime<Utc>, DateTime<Utc>)>, text: &str) {
+    let Ok(time) = DateTime::parse_from_rfc3339(text.trim()) else {
+        return;
+    };
+    let time = time.with_timezone(&Utc);
+    *times = Some(match *times {
+        Some((first, last)) => (first.min(time), last.max(time)),
+        None => (time, time),
+    });
+}
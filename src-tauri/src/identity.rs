@@ -0,0 +1,106 @@
+use crate::error::XmlReaderError;
+use crate::io::{self, Source};
+use crate::ops::{self, OpId};
+use anyhow::Result;
+use std::io::Read;
+use std::time::UNIX_EPOCH;
+use tauri::WebviewWindow;
+use xxhash_rust::xxh3::Xxh3;
+
+const HASH_BLOCK_SIZE: usize = 4 * 1024 * 1024;
+
+/// Size, modification time and content hash of a file. The hash doesn't
+/// depend on the path, so a copy under another name has the same identity
+/// apart from `path` and `modified`.
+#[derive(serde::Serialize, serde::Deserialize, Clone, PartialEq)]
+pub struct FileIdentity {
+    pub path: String,
+    pub size: u64,
+    /// Milliseconds since the Unix epoch, when the filesystem reports it.
+    pub modified: Option<u64>,
+    /// XXH3-64 of the content, as 16 hex digits.
+    pub hash: String,
+}
+
+#[derive(serde::Serialize, Clone)]
+struct IdentityProgress {
+    path: String,
+    hashed: u64,
+    total: u64,
+}
+
+/// Emits `identity-progress` with the bytes hashed, and `hash-progress`
+/// (0-100), while hashing.
+#[tauri::command]
+pub async fn get_file_identity(
+    window: WebviewWindow,
+    path: String,
+    op_id: Option<OpId>,
+) -> Result<FileIdentity, XmlReaderError> {
+    ops::run(window, op_id, "hash", move |op| {
+        file_identity(&path, |hashed, total| {
+            let progress = IdentityProgress {
+                path: path.clone(),
+                hashed,
+                total,
+            };
+            op.emit("identity-progress", progress);
+            op.progress(hashed * 100 / total.max(1));
+        })
+        .map_err(XmlReaderError::from)
+    })
+    .await
+}
+
+/// Hash the whole file incrementally. `progress` gets the bytes hashed so far
+/// and the total size after every block.
+pub fn file_identity(path: &str, mut progress: impl FnMut(u64, u64)) -> Result<FileIdentity> {
+    let mut source = Source::open(path)?;
+    let size = source.len();
+    let modified = if io::is_remote(path) {
+        None
+    } else {
+        std::fs::metadata(path)?
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_millis() as u64)
+    };
+
+    let mut hasher = Xxh3::new();
+    let mut buf = vec![0u8; HASH_BLOCK_SIZE];
+    let mut hashed = 0u64;
+    loop {
+        ops::check()?;
+        let n = match source.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        hasher.update(&buf[..n]);
+        hashed += n as u64;
+        progress(hashed, size);
+    }
+
+    Ok(FileIdentity {
+        path: path.to_string(),
+        size,
+        modified,
+        hash: format!("{:016x}", hasher.digest()),
+    })
+}
+
+/// Whether `path` still has the size and modification time `identity` was
+/// taken with, so its hash can be trusted without reading the file again.
+pub fn still_matches(identity: &FileIdentity, path: &str) -> bool {
+    let Ok(meta) = std::fs::metadata(path) else {
+        return false;
+    };
+    let modified = meta
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_millis() as u64);
+    identity.size == meta.len() && identity.modified == modified
+}
@@ -0,0 +1,808 @@
+mod binary;
+mod remote;
+
+use crate::error::XmlReaderError;
+use anyhow::{Context, Result};
+use memmap2::Mmap;
+use quick_xml::events::Event;
+use remote::RemoteFile;
+
+pub use remote::s3::S3Config;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::SystemTime;
+
+/// When set, files are memory-mapped instead of being read through `File`
+/// syscalls. Off by default: a mapped file that is truncated by another process
+/// while we read it can crash the app, so this stays opt-in.
+static MMAP_ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn set_mmap_enabled(enabled: bool) {
+    MMAP_ENABLED.store(enabled, Ordering::SeqCst);
+    if !enabled {
+        mmap_cache().lock().unwrap().clear();
+    }
+}
+
+pub fn is_remote(path: &str) -> bool {
+    remote::is_remote(path)
+}
+
+/// Drop anything cached for `path` after it changed on disk (or on the
+/// server), or once it is closed.
+pub fn invalidate(path: &str) {
+    if remote::is_remote(path) {
+        remote::invalidate(path);
+    } else {
+        mmap_cache().lock().unwrap().remove(path);
+    }
+}
+
+pub fn set_s3_config(config: S3Config) {
+    remote::s3::set_config(config);
+}
+
+pub fn mmap_enabled() -> bool {
+    MMAP_ENABLED.load(Ordering::SeqCst)
+}
+
+/// A mapping is reused across commands as long as the file still has the same
+/// length and modification time it had when it was mapped.
+struct CachedMap {
+    len: u64,
+    modified: Option<SystemTime>,
+    map: Arc<Mmap>,
+}
+
+fn mmap_cache() -> &'static Mutex<HashMap<String, CachedMap>> {
+    static CACHE: OnceLock<Mutex<HashMap<String, CachedMap>>> = OnceLock::new();
+    CACHE.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn mapped(path: &str) -> Result<Arc<Mmap>> {
+    let file = open_shared(path)?;
+    let meta = file.metadata()?;
+    let modified = meta.modified().ok();
+
+    let mut cache = mmap_cache().lock().unwrap();
+    if let Some(cached) = cache.get(path) {
+        if cached.len == meta.len() && cached.modified == modified {
+            return Ok(cached.map.clone());
+        }
+    }
+
+    // SAFETY: the mapping is read-only; see MMAP_ENABLED for the truncation caveat.
+    let map = Arc::new(unsafe { Mmap::map(&file)? });
+    cache.insert(
+        path.to_string(),
+        CachedMap {
+            len: meta.len(),
+            modified,
+            map: map.clone(),
+        },
+    );
+    Ok(map)
+}
+
+/// Open a local file for reading while other processes keep writing, renaming
+/// or deleting it, so files still held by the application generating them can
+/// be read. Outside Windows there are no share modes to ask for.
+fn open_shared(path: &str) -> Result<File> {
+    #[cfg(windows)]
+    let opened = {
+        use std::os::windows::fs::OpenOptionsExt;
+        const FILE_SHARE_READ: u32 = 0x1;
+        const FILE_SHARE_WRITE: u32 = 0x2;
+        const FILE_SHARE_DELETE: u32 = 0x4;
+        std::fs::OpenOptions::new()
+            .read(true)
+            .share_mode(FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE)
+            .open(path)
+    };
+    #[cfg(not(windows))]
+    let opened = File::open(path);
+
+    opened.map_err(|e| {
+        // ERROR_SHARING_VIOLATION / ERROR_LOCK_VIOLATION: another process opened
+        // the file without sharing read access
+        if cfg!(windows) && matches!(e.raw_os_error(), Some(32 | 33)) {
+            anyhow::anyhow!("{} is locked by another process; open a snapshot copy instead", path)
+        } else {
+            e.into()
+        }
+    })
+}
+
+/// Copy a local file to a temp file, for reading files whose lock prevents
+/// opening them in place.
+pub fn snapshot(path: &str) -> Result<PathBuf> {
+    let copy = temp_path("snapshot");
+    let copied = std::fs::copy(path, &copy).with_context(|| format!("Could not snapshot {}", path));
+    if copied.is_err() {
+        let _ = std::fs::remove_file(&copy);
+    }
+    copied?;
+    Ok(copy)
+}
+
+/// A fresh path in the temp directory for files the app creates itself.
+pub fn temp_path(label: &str) -> PathBuf {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    std::env::temp_dir().join(format!(
+        "xml-reader-{}-{}-{}.xml",
+        label,
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::SeqCst)
+    ))
+}
+
+/// Write `content` to a new temp file and return its path.
+pub fn write_temp(label: &str, content: &[u8]) -> Result<PathBuf> {
+    let path = temp_path(label);
+    std::fs::write(&path, content)?;
+    Ok(path)
+}
+
+/// If `path` holds binary XML (EXI, Fast Infoset, WBXML), decode it into a temp XML
+/// file and return that file's path. Plain XML returns `None`.
+pub fn transcode_binary(path: &str) -> Result<Option<PathBuf>> {
+    let mut source = Source::open(path)?;
+    let mut header = Vec::with_capacity(256);
+    (&mut source).take(256).read_to_end(&mut header)?;
+
+    match binary::detect(&header) {
+        Some(format) => {
+            source.seek(SeekFrom::Start(0))?;
+            binary::transcode(format, source).map(Some)
+        }
+        None => Ok(None),
+    }
+}
+
+/// Encoding of an XML file from its first bytes: a BOM, `<?` in UTF-16, or
+/// the `encoding` in the declaration; UTF-8 when none of them says otherwise.
+pub fn detect_encoding(head: &[u8]) -> &'static encoding_rs::Encoding {
+    if let Some((encoding, _)) = encoding_rs::Encoding::for_bom(head) {
+        return encoding;
+    }
+    if head.starts_with(b"<\0?\0") {
+        return encoding_rs::UTF_16LE;
+    }
+    if head.starts_with(b"\0<\0?") {
+        return encoding_rs::UTF_16BE;
+    }
+    let declared = head
+        .strip_prefix(b"<?xml")
+        .and_then(|rest| rest.split(|&b| b == b'>').next())
+        .and_then(|decl| pseudo_attribute(decl, b"encoding"))
+        .and_then(encoding_rs::Encoding::for_label);
+    match declared {
+        // Bytes that spell out the declaration in ASCII can't be UTF-16
+        Some(encoding) if encoding != encoding_rs::UTF_16LE && encoding != encoding_rs::UTF_16BE => encoding,
+        _ => encoding_rs::UTF_8,
+    }
+}
+
+/// Value of `name="..."` inside an XML declaration.
+fn pseudo_attribute<'a>(decl: &'a [u8], name: &[u8]) -> Option<&'a [u8]> {
+    let at = decl.windows(name.len()).position(|w| w == name)?;
+    let rest = &decl[at + name.len()..];
+    let rest = &rest[rest.iter().position(|&b| b == b'=')? + 1..];
+    let start = rest.iter().position(|&b| b == b'"' || b == b'\'')?;
+    let quote = rest[start];
+    let len = rest[start + 1..].iter().position(|&b| b == quote)?;
+    Some(&rest[start + 1..start + 1 + len])
+}
+
+/// Copy everything from stdin into a temp file so piped input can be read
+/// randomly like any other file. `progress` gets the bytes copied so far.
+pub fn spool_stdin(mut progress: impl FnMut(u64)) -> Result<PathBuf> {
+    let path = std::env::temp_dir().join(format!("xml-reader-stdin-{}.xml", std::process::id()));
+    let mut out = BufWriter::new(File::create(&path)?);
+    let mut stdin = std::io::stdin().lock();
+    let mut buf = vec![0u8; 1024 * 1024];
+    let mut total = 0u64;
+
+    loop {
+        let n = match stdin.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        out.write_all(&buf[..n])?;
+        total += n as u64;
+        progress(total);
+    }
+    out.flush()?;
+    Ok(path)
+}
+
+/// Idle handles kept per file; more than this are closed when released.
+const POOL_MAX_IDLE: usize = 4;
+
+/// Idle handles to one open file. A `Source` made with `Source::pooled` hands
+/// its handle back here when dropped, so the next scan doesn't open the file
+/// again (which on a path under on-access virus scanning costs far more than
+/// the read).
+#[derive(Default)]
+pub struct FilePool {
+    idle: Mutex<Vec<File>>,
+    generation: AtomicU64,
+}
+
+impl FilePool {
+    /// Close the idle handles, once the file was replaced on disk and they
+    /// still point at the old one. Handles in use are closed when released.
+    pub fn clear(&self) {
+        let mut idle = self.idle.lock().unwrap();
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        idle.clear();
+    }
+}
+
+/// A plain file handle, returned to its pool (if any) on drop.
+struct PooledFile {
+    file: Option<File>,
+    pool: Option<Arc<FilePool>>,
+    generation: u64,
+}
+
+impl PooledFile {
+    fn get(&mut self) -> &mut File {
+        self.file.as_mut().expect("file is only taken on drop")
+    }
+}
+
+impl Drop for PooledFile {
+    fn drop(&mut self) {
+        if let (Some(file), Some(pool)) = (self.file.take(), &self.pool) {
+            let mut idle = pool.idle.lock().unwrap();
+            if idle.len() < POOL_MAX_IDLE && pool.generation.load(Ordering::SeqCst) == self.generation {
+                idle.push(file);
+            }
+        }
+    }
+}
+
+enum Kind {
+    File(PooledFile),
+    Mapped(Arc<Mmap>),
+    Remote(RemoteFile),
+}
+
+/// Random-access byte source for a file, backed by plain `File` reads, a shared
+/// memory map, or ranged requests for `http://`, `https://` and `s3://` paths.
+/// Implements `Read + Seek` so it can be handed to `BufReader`/`quick_xml::Reader`
+/// like a `File`.
+pub struct Source {
+    kind: Kind,
+    len: u64,
+    pos: u64,
+}
+
+impl Source {
+    pub fn open(path: &str) -> Result<Source> {
+        Source::open_in(path, None)
+    }
+
+    /// Like `open`, but reuse an idle handle from `pool` and give it back
+    /// when done. Only plain file reads use the pool; mappings are shared
+    /// through their own cache.
+    pub fn pooled(path: &str, pool: &Arc<FilePool>) -> Result<Source> {
+        Source::open_in(path, Some(pool))
+    }
+
+    fn open_in(path: &str, pool: Option<&Arc<FilePool>>) -> Result<Source> {
+        if remote::is_remote(path) {
+            let remote = RemoteFile::open(path)?;
+            return Ok(Source {
+                len: remote.len(),
+                kind: Kind::Remote(remote),
+                pos: 0,
+            });
+        }
+
+        if mmap_enabled() {
+            let map = mapped(path)?;
+            let len = map.len() as u64;
+            // Empty files cannot be mapped on every platform; plain reads are fine there.
+            if len > 0 {
+                return Ok(Source {
+                    kind: Kind::Mapped(map),
+                    len,
+                    pos: 0,
+                });
+            }
+        }
+
+        // Read before taking a handle: a stale one taken just before a `clear`
+        // must not be tagged with the new generation and pooled again
+        let generation = pool.map_or(0, |pool| pool.generation.load(Ordering::SeqCst));
+        let idle = pool.and_then(|pool| pool.idle.lock().unwrap().pop());
+        let file = match idle {
+            Some(mut file) => {
+                file.seek(SeekFrom::Start(0))?;
+                file
+            }
+            None => open_shared(path)?,
+        };
+        // Taken again each time: the file may have grown since it was opened
+        let len = file.metadata()?.len();
+        Ok(Source {
+            kind: Kind::File(PooledFile {
+                file: Some(file),
+                pool: pool.cloned(),
+                generation,
+            }),
+            len,
+            pos: 0,
+        })
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Current absolute read position.
+    pub fn position(&self) -> u64 {
+        self.pos
+    }
+}
+
+/// Limits every `XmlReader` enforces, so no file can make a scan recurse or
+/// expand without bound. quick-xml itself never expands custom entities;
+/// these hold once anything does.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy)]
+#[serde(default)]
+pub struct ReaderLimits {
+    /// Deepest element nesting, counted from where the reader starts.
+    pub max_depth: u32,
+    /// Largest text any entity declared in a DOCTYPE may expand to, in bytes.
+    pub max_entity_expansion: u64,
+    /// Accept `SYSTEM` / `PUBLIC` entity declarations.
+    pub allow_external_entities: bool,
+}
+
+impl Default for ReaderLimits {
+    fn default() -> Self {
+        DEFAULT_LIMITS
+    }
+}
+
+const DEFAULT_LIMITS: ReaderLimits = ReaderLimits {
+    max_depth: 10_000,
+    max_entity_expansion: 1024 * 1024,
+    allow_external_entities: false,
+};
+
+static LIMITS: Mutex<ReaderLimits> = Mutex::new(DEFAULT_LIMITS);
+
+pub fn set_reader_limits(limits: ReaderLimits) {
+    *LIMITS.lock().unwrap() = limits;
+}
+
+/// How much memory scans may take. Workstations with RAM to spare can raise
+/// all three; on small machines a lower budget keeps many concurrent scans
+/// from each holding a full-size buffer.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy)]
+#[serde(default)]
+pub struct MemorySettings {
+    /// Read buffer of each streaming scan, in bytes.
+    pub reader_buffer: usize,
+    /// Remote (HTTP/S3) blocks kept in memory, in bytes, shared by all URLs.
+    pub chunk_cache: usize,
+    /// Most bytes the buffers of all live `XmlReader`s may take together; 0
+    /// for no limit. Readers created past it get smaller buffers, down to
+    /// `MIN_READER_BUFFER`.
+    pub memory_budget: usize,
+}
+
+impl Default for MemorySettings {
+    fn default() -> Self {
+        DEFAULT_MEMORY
+    }
+}
+
+const DEFAULT_MEMORY: MemorySettings = MemorySettings {
+    reader_buffer: 1024 * 1024,
+    chunk_cache: 64 * 1024 * 1024,
+    memory_budget: 64 * 1024 * 1024,
+};
+
+/// Smallest buffer a reader is shrunk to when the budget is used up.
+const MIN_READER_BUFFER: usize = 16 * 1024;
+
+static MEMORY: Mutex<MemorySettings> = Mutex::new(DEFAULT_MEMORY);
+// Bytes of reader buffers handed out and not yet dropped
+static BUFFERS_IN_USE: AtomicUsize = AtomicUsize::new(0);
+
+pub fn set_memory_settings(settings: MemorySettings) {
+    *MEMORY.lock().unwrap() = settings;
+}
+
+pub fn memory_settings() -> MemorySettings {
+    *MEMORY.lock().unwrap()
+}
+
+/// Buffer size for streaming scans, the `reader_buffer` setting.
+pub fn scan_buffer() -> usize {
+    memory_settings().reader_buffer.max(MIN_READER_BUFFER)
+}
+
+/// Take up to `wanted` bytes of the memory budget for a reader buffer.
+/// Callers must hand the result back with `release_buffer`.
+fn reserve_buffer(wanted: usize) -> usize {
+    let budget = memory_settings().memory_budget;
+    let granted = if budget == 0 {
+        wanted
+    } else {
+        let left = budget.saturating_sub(BUFFERS_IN_USE.load(Ordering::Relaxed));
+        wanted.min(left.max(MIN_READER_BUFFER))
+    };
+    BUFFERS_IN_USE.fetch_add(granted, Ordering::Relaxed);
+    granted
+}
+
+fn release_buffer(size: usize) {
+    BUFFERS_IN_USE.fetch_sub(size, Ordering::Relaxed);
+}
+
+/// A `ReaderLimits` limit a file went over.
+#[derive(Debug)]
+pub enum LimitError {
+    Depth { max: u32, offset: u64 },
+    EntityExpansion { name: String, max: u64 },
+    RecursiveEntity { name: String },
+    ExternalEntity { name: String },
+}
+
+impl std::fmt::Display for LimitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LimitError::Depth { max, offset } => write!(f, "Elements are nested deeper than {} at offset {}", max, offset),
+            LimitError::EntityExpansion { name, max } => write!(f, "Entity &{}; expands to more than {} bytes", name, max),
+            LimitError::RecursiveEntity { name } => write!(f, "Entity &{}; refers to itself", name),
+            LimitError::ExternalEntity { name } => {
+                write!(f, "Entity &{}; refers to an external resource; external entities are disabled", name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LimitError {}
+
+/// `quick_xml::Reader` over a `Source` that knows its absolute file position.
+/// Derefs to the inner reader for `check_end_names` etc.; `read_event_into`
+/// is wrapped to enforce the `ReaderLimits` in effect when it was created,
+/// and the stricter checks of `set_strict`.
+pub struct XmlReader {
+    inner: quick_xml::Reader<BufReader<Source>>,
+    // Position of the source when the reader was created
+    base: u64,
+    limits: ReaderLimits,
+    depth: u32,
+    // Names of the elements opened since the reader started, in strict mode
+    strict: Option<Vec<Vec<u8>>>,
+    // Set when created for an `ops::Operation`, checked on every event, which
+    // also yields to interactive reads
+    cancelled: Option<Arc<AtomicBool>>,
+    // Buffer bytes taken from the memory budget, given back on drop
+    reserved: usize,
+}
+
+impl XmlReader {
+    /// A reader with a buffer of up to `capacity` bytes; less when the
+    /// `MemorySettings` budget is running out.
+    pub fn new(source: Source, capacity: usize) -> XmlReader {
+        let base = source.position();
+        let reserved = reserve_buffer(capacity);
+        XmlReader {
+            inner: quick_xml::Reader::from_reader(BufReader::with_capacity(reserved, source)),
+            base,
+            limits: *LIMITS.lock().unwrap(),
+            depth: 0,
+            strict: None,
+            cancelled: crate::ops::current(),
+            reserved,
+        }
+    }
+
+    /// A reader for a long streaming scan, with the configured `scan_buffer`.
+    pub fn scan(source: Source) -> XmlReader {
+        XmlReader::new(source, scan_buffer())
+    }
+
+    pub fn read_event_into<'b>(&mut self, buf: &'b mut Vec<u8>) -> Result<Event<'b>> {
+        if let Some(cancelled) = &self.cancelled {
+            if cancelled.load(Ordering::Relaxed) {
+                return Err(XmlReaderError::Cancelled.into());
+            }
+            crate::ops::yield_to_foreground();
+        }
+        let pos_before = self.position();
+        let event = self.inner.read_event_into(buf).map_err(|e| XmlReaderError::ParseError {
+            offset: Some(self.position()),
+            line: None,
+            message: e.to_string(),
+        })?;
+        match &event {
+            Event::Start(_) => {
+                self.depth += 1;
+                if self.depth > self.limits.max_depth {
+                    return Err(LimitError::Depth {
+                        max: self.limits.max_depth,
+                        offset: pos_before,
+                    }
+                    .into());
+                }
+            }
+            Event::End(_) => self.depth = self.depth.saturating_sub(1),
+            Event::DocType(doctype) => check_entities(doctype, &self.limits)?,
+            _ => {}
+        }
+        if let Some(open) = &mut self.strict {
+            check_strict(&event, pos_before, open)?;
+        }
+        Ok(event)
+    }
+
+    /// Fail on end tags that don't match their start tag and on control
+    /// characters XML doesn't allow, instead of reading past them. End tags
+    /// of elements opened before the reader's start position aren't checked.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict.then(Vec::new);
+    }
+
+    /// Absolute file position of the parser, see `parser_position`.
+    pub fn position(&self) -> u64 {
+        let buffered = self.inner.get_ref();
+        let consumed = buffered.get_ref().position() - buffered.buffer().len() as u64;
+        parser_position(consumed, self.base, self.inner.buffer_position())
+    }
+}
+
+/// Absolute parser position of a reader started at `base` whose `BufReader`
+/// has handed out everything up to `consumed`. `buffer_position` is a `usize`
+/// relative to `base` and wraps past 4GB on 32-bit targets, so it only
+/// contributes the 0/1 byte quick-xml may have consumed ahead (a `<` after a
+/// text event), which is taken modulo 2^32 on every target.
+fn parser_position(consumed: u64, base: u64, buffer_position: usize) -> u64 {
+    let ahead = ((consumed - base) as u32).wrapping_sub(buffer_position as u32);
+    consumed - ahead as u64
+}
+
+/// The strict-mode checks for `event`, read at `offset`; `open` holds the
+/// names of the elements it is nested in.
+fn check_strict(event: &Event, offset: u64, open: &mut Vec<Vec<u8>>) -> std::result::Result<(), XmlReaderError> {
+    let error = |offset: u64, message: String| XmlReaderError::ParseError {
+        offset: Some(offset),
+        line: None,
+        message,
+    };
+    // Where the event's bytes start, after the markup
+    let (bytes, start): (&[u8], u64) = match event {
+        Event::Start(e) => {
+            open.push(e.name().as_ref().to_vec());
+            (e, 1)
+        }
+        Event::End(e) => {
+            let name = e.name();
+            if let Some(expected) = open.pop().filter(|expected| expected.as_slice() != name.as_ref()) {
+                return Err(error(
+                    offset,
+                    format!(
+                        "Expected </{}>, found </{}>",
+                        String::from_utf8_lossy(&expected),
+                        String::from_utf8_lossy(name.as_ref())
+                    ),
+                ));
+            }
+            return Ok(());
+        }
+        Event::Empty(e) => (e, 1),
+        Event::Text(e) => (e, 0),
+        Event::CData(e) => (e, 9),
+        Event::Comment(e) => (e, 4),
+        _ => return Ok(()),
+    };
+    match bytes.iter().position(|&b| b < 0x20 && !matches!(b, b'\t' | b'\n' | b'\r')) {
+        Some(i) => Err(error(
+            offset + start + i as u64,
+            format!("Character U+{:04X} is not allowed in XML", bytes[i]),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Check the entities declared in a DOCTYPE's internal subset against `limits`.
+fn check_entities(doctype: &[u8], limits: &ReaderLimits) -> std::result::Result<(), LimitError> {
+    let text = String::from_utf8_lossy(doctype);
+    // name -> literal value
+    let mut entities: HashMap<String, String> = HashMap::new();
+    for decl in text.split("<!ENTITY").skip(1) {
+        let decl = decl.trim_start().trim_start_matches('%').trim_start();
+        let Some((name, rest)) = decl.split_once(|c: char| c.is_whitespace()) else {
+            continue;
+        };
+        let rest = rest.trim_start();
+        if rest.starts_with("SYSTEM") || rest.starts_with("PUBLIC") {
+            if !limits.allow_external_entities {
+                return Err(LimitError::ExternalEntity { name: name.to_string() });
+            }
+            continue;
+        }
+        let Some(quote) = rest.chars().next().filter(|&q| q == '"' || q == '\'') else {
+            continue;
+        };
+        let value = rest[1..].split(quote).next().unwrap_or_default();
+        entities.insert(name.to_string(), value.to_string());
+    }
+
+    let mut sizes: HashMap<&str, u64> = HashMap::new();
+    for name in entities.keys() {
+        expanded_len(name, &entities, &mut sizes, &mut Vec::new(), limits.max_entity_expansion)?;
+    }
+    Ok(())
+}
+
+/// Length of entity `name` with every reference to another declared entity
+/// expanded, memoized in `sizes`. `open` holds the entities being expanded.
+fn expanded_len<'a>(
+    name: &'a str,
+    entities: &'a HashMap<String, String>,
+    sizes: &mut HashMap<&'a str, u64>,
+    open: &mut Vec<&'a str>,
+    max: u64,
+) -> std::result::Result<u64, LimitError> {
+    if let Some(&len) = sizes.get(name) {
+        return Ok(len);
+    }
+    if open.contains(&name) {
+        return Err(LimitError::RecursiveEntity { name: name.to_string() });
+    }
+    open.push(name);
+    let value = &entities[name];
+    let mut len = 0u64;
+    let mut rest = value.as_str();
+    while let Some(amp) = rest.find('&') {
+        len += amp as u64;
+        rest = &rest[amp + 1..];
+        match rest.find(';') {
+            Some(end) if entities.contains_key(&rest[..end]) => {
+                let (key, _) = entities.get_key_value(&rest[..end]).unwrap();
+                len += expanded_len(key, entities, sizes, open, max)?;
+                rest = &rest[end + 1..];
+            }
+            // Predefined and character references expand to one character
+            Some(end) => {
+                len += 1;
+                rest = &rest[end + 1..];
+            }
+            None => len += 1,
+        }
+        if len > max {
+            return Err(LimitError::EntityExpansion { name: name.to_string(), max });
+        }
+    }
+    len += rest.len() as u64;
+    if len > max {
+        return Err(LimitError::EntityExpansion { name: name.to_string(), max });
+    }
+    open.pop();
+    sizes.insert(name, len);
+    Ok(len)
+}
+
+impl std::ops::Deref for XmlReader {
+    type Target = quick_xml::Reader<BufReader<Source>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl std::ops::DerefMut for XmlReader {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.inner
+    }
+}
+
+impl Drop for XmlReader {
+    fn drop(&mut self) {
+        release_buffer(self.reserved);
+    }
+}
+
+impl Read for Source {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = match &mut self.kind {
+            Kind::File(file) => file.get().read(buf)?,
+            Kind::Mapped(map) => {
+                let start = self.pos.min(self.len) as usize;
+                let n = buf.len().min(map.len() - start);
+                buf[..n].copy_from_slice(&map[start..start + n]);
+                n
+            }
+            Kind::Remote(remote) => remote.read_at(self.pos, buf)?,
+        };
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for Source {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.pos = match &mut self.kind {
+            Kind::File(file) => file.get().seek(pos)?,
+            Kind::Mapped(_) | Kind::Remote(_) => {
+                let target = match pos {
+                    SeekFrom::Start(n) => Some(n),
+                    SeekFrom::End(n) => self.len.checked_add_signed(n),
+                    SeekFrom::Current(n) => self.pos.checked_add_signed(n),
+                };
+                target.ok_or_else(|| {
+                    std::io::Error::new(std::io::ErrorKind::InvalidInput, "invalid seek to a negative position")
+                })?
+            }
+        };
+        Ok(self.pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FOUR_GB: u64 = 1 << 32;
+
+    #[test]
+    fn parser_position_below_4gb() {
+        assert_eq!(parser_position(100, 0, 100), 100);
+        assert_eq!(parser_position(100, 0, 99), 99);
+        assert_eq!(parser_position(1100, 1000, 99), 1099);
+    }
+
+    #[test]
+    fn parser_position_past_4gb() {
+        // What `buffer_position()` reports on a 32-bit target
+        let wrapped = |n: u64| n as u32 as usize;
+        assert_eq!(parser_position(FOUR_GB + 10, 0, wrapped(FOUR_GB + 10)), FOUR_GB + 10);
+        assert_eq!(parser_position(FOUR_GB + 10, 0, wrapped(FOUR_GB + 9)), FOUR_GB + 9);
+        // One byte ahead across the wrap itself
+        assert_eq!(parser_position(FOUR_GB, 0, wrapped(FOUR_GB - 1)), FOUR_GB - 1);
+        // Started past 4GB: only the distance from `base` counts
+        assert_eq!(parser_position(2 * FOUR_GB + 5, FOUR_GB, wrapped(FOUR_GB + 4)), 2 * FOUR_GB + 4);
+    }
+
+    #[test]
+    fn reader_position_in_sparse_file() {
+        let path = std::env::temp_dir().join(format!("xml-reader-io-{}.xml", std::process::id()));
+        let offset = FOUR_GB + 7;
+        {
+            let mut file = File::create(&path).unwrap();
+            file.set_len(offset).unwrap();
+            file.seek(SeekFrom::Start(offset)).unwrap();
+            file.write_all(b"<a>text</a>").unwrap();
+        }
+        let path_str = path.to_str().unwrap();
+        let mut source = Source::open(path_str).unwrap();
+        source.seek(SeekFrom::Start(offset)).unwrap();
+        let mut reader = XmlReader::new(source, 4096);
+        let mut buf = Vec::new();
+        let mut positions = Vec::new();
+        loop {
+            buf.clear();
+            if let Event::Eof = reader.read_event_into(&mut buf).unwrap() {
+                break;
+            }
+            positions.push(reader.position() - offset);
+        }
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(positions, [3, 7, 11]);
+    }
+}
@@ -0,0 +1,107 @@
+#[cfg(feature = "binary-xml")]
+mod exi;
+#[cfg(feature = "binary-xml")]
+mod fastinfoset;
+#[cfg(feature = "binary-xml")]
+mod wbxml;
+
+use anyhow::Result;
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryFormat {
+    Exi,
+    FastInfoset,
+    Wbxml,
+}
+
+impl BinaryFormat {
+    fn name(self) -> &'static str {
+        match self {
+            BinaryFormat::Exi => "EXI",
+            BinaryFormat::FastInfoset => "Fast Infoset",
+            BinaryFormat::Wbxml => "WBXML",
+        }
+    }
+}
+
+/// Recognize binary XML from the first bytes of a file.
+pub fn detect(header: &[u8]) -> Option<BinaryFormat> {
+    // Fast Infoset may be preceded by an XML declaration naming it
+    let mut fi = header;
+    if fi.starts_with(b"<?xml") {
+        let end = fi.windows(2).position(|w| w == b"?>")?;
+        fi = &fi[end + 2..];
+    }
+    if fi.starts_with(&[0xE0, 0x00, 0x00, 0x01]) {
+        return Some(BinaryFormat::FastInfoset);
+    }
+
+    // EXI: optional "$EXI" cookie, then distinguishing bits `10`. Without the
+    // cookie, only accept a final version 1 header; those bytes can't start a
+    // UTF-8 text file.
+    if header.starts_with(b"$EXI") || matches!(header.first(), Some(0x80 | 0xA0)) {
+        return Some(BinaryFormat::Exi);
+    }
+
+    // WBXML: version 1.0-1.3, a public id, then a known charset
+    if matches!(header.first(), Some(0x00..=0x03)) && wbxml_charset(&header[1..]).is_some() {
+        return Some(BinaryFormat::Wbxml);
+    }
+    None
+}
+
+/// Charset of a WBXML document, given the bytes after its version byte.
+fn wbxml_charset(header: &[u8]) -> Option<u32> {
+    let mut bytes = header.iter();
+    let mut multibyte = || {
+        let mut value = 0u32;
+        for _ in 0..5 {
+            let b = *bytes.next()?;
+            value = value << 7 | (b & 0x7F) as u32;
+            if b & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+        None
+    };
+    let public_id = multibyte()?;
+    if public_id == 0 {
+        // Public id given as a string table index
+        multibyte()?;
+    }
+    // US-ASCII, ISO-8859-1, UTF-8
+    multibyte().filter(|charset| matches!(charset, 3 | 4 | 106))
+}
+
+/// Decode a binary XML file into a temp file holding the equivalent XML text,
+/// so every other command can work on it unchanged.
+pub fn transcode(format: BinaryFormat, mut input: impl Read) -> Result<PathBuf> {
+    let mut data = Vec::new();
+    input.read_to_end(&mut data)?;
+
+    let path = super::temp_path(&format.name().to_lowercase().replace(' ', "-"));
+    let mut out = BufWriter::new(File::create(&path)?);
+    decode(format, &data, &mut out)?;
+    out.flush()?;
+    Ok(path)
+}
+
+#[cfg(feature = "binary-xml")]
+fn decode(format: BinaryFormat, data: &[u8], out: &mut impl Write) -> Result<()> {
+    match format {
+        BinaryFormat::Exi => exi::decode(data, out),
+        BinaryFormat::FastInfoset => fastinfoset::decode(data, out),
+        BinaryFormat::Wbxml => wbxml::decode(data, out),
+    }
+}
+
+#[cfg(not(feature = "binary-xml"))]
+fn decode(format: BinaryFormat, _data: &[u8], _out: &mut impl Write) -> Result<()> {
+    Err(anyhow::anyhow!(
+        "{} input detected, but this build was compiled without the `binary-xml` feature",
+        format.name()
+    ))
+}
@@ -0,0 +1,412 @@
+//! EXI (W3C Efficient XML Interchange 1.0) decoder for schema-less streams
+//! with the default options: bit-packed alignment, no preserved comments,
+//! processing instructions, DTDs or prefixes. Streams that carry an options
+//! header are rejected, since they may need schemas or compression.
+
+use anyhow::{anyhow, Result};
+use quick_xml::escape::escape;
+use std::collections::HashMap;
+use std::io::Write;
+
+const XML_NAMESPACE: &str = "http://www.w3.org/XML/1998/namespace";
+const XSI_NAMESPACE: &str = "http://www.w3.org/2001/XMLSchema-instance";
+
+/// Namespace index and local-name index in the string table.
+type QName = (usize, usize);
+
+#[derive(Clone, Copy, PartialEq)]
+enum Production {
+    Attribute(QName),
+    Element(QName),
+    Characters,
+    EndElement,
+}
+
+/// Built-in element grammar, with the productions learned so far (newest
+/// first, since each one takes event code 0).
+#[derive(Default)]
+struct Grammar {
+    start_tag: Vec<Production>,
+    content: Vec<Production>,
+}
+
+struct Frame {
+    name: QName,
+    in_content: bool,
+    // Start tag not written yet, so attributes can still be appended
+    open_tag: Option<String>,
+    // Namespaces declared on this element
+    declared: Vec<usize>,
+}
+
+struct Bits<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit: u8,
+}
+
+impl Bits<'_> {
+    fn read(&mut self, n: u32) -> Result<u32> {
+        let mut value = 0u32;
+        for _ in 0..n {
+            let byte = *self
+                .data
+                .get(self.pos)
+                .ok_or_else(|| anyhow!("Unexpected end of EXI stream"))?;
+            value = value << 1 | ((byte >> (7 - self.bit)) & 1) as u32;
+            self.bit += 1;
+            if self.bit == 8 {
+                self.bit = 0;
+                self.pos += 1;
+            }
+        }
+        Ok(value)
+    }
+
+    /// Unsigned integer in 7-bit groups, least significant first.
+    fn uint(&mut self) -> Result<usize> {
+        let mut value = 0usize;
+        for shift in (0..63).step_by(7) {
+            let octet = self.read(8)?;
+            value |= ((octet & 0x7F) as usize) << shift;
+            if octet & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(anyhow!("Unsigned integer too large in EXI stream"))
+    }
+
+    fn string(&mut self, len: usize) -> Result<String> {
+        (0..len)
+            .map(|_| {
+                let c = self.uint()?;
+                char::from_u32(c as u32).ok_or_else(|| anyhow!("Invalid character {:#x} in EXI stream", c))
+            })
+            .collect()
+    }
+}
+
+/// Bits needed to tell `n` values apart.
+fn width(n: usize) -> u32 {
+    if n <= 1 {
+        0
+    } else {
+        usize::BITS - (n - 1).leading_zeros()
+    }
+}
+
+struct Decoder<'a, W> {
+    bits: Bits<'a>,
+    out: W,
+    uris: Vec<String>,
+    local_names: Vec<Vec<String>>,
+    values: Vec<String>,
+    local_values: HashMap<QName, Vec<String>>,
+    grammars: HashMap<QName, Grammar>,
+    stack: Vec<Frame>,
+}
+
+pub fn decode(data: &[u8], out: &mut impl Write) -> Result<()> {
+    let data = data.strip_prefix(b"$EXI").unwrap_or(data);
+    let mut bits = Bits { data, pos: 0, bit: 0 };
+
+    if bits.read(2)? != 0b10 {
+        return Err(anyhow!("Not an EXI stream"));
+    }
+    if bits.read(1)? == 1 {
+        return Err(anyhow!("EXI streams with an options header are not supported"));
+    }
+    let preview = bits.read(1)? == 1;
+    let mut version = 1;
+    loop {
+        let part = bits.read(4)?;
+        version += part;
+        if part < 15 {
+            break;
+        }
+    }
+    if preview || version != 1 {
+        return Err(anyhow!("Unsupported EXI version {}", version));
+    }
+
+    let mut decoder = Decoder {
+        bits,
+        out,
+        uris: vec![String::new(), XML_NAMESPACE.to_string(), XSI_NAMESPACE.to_string()],
+        local_names: vec![
+            Vec::new(),
+            ["base", "id", "lang", "space"].map(String::from).to_vec(),
+            ["nil", "type"].map(String::from).to_vec(),
+        ],
+        values: Vec::new(),
+        local_values: HashMap::new(),
+        grammars: HashMap::new(),
+        stack: Vec::new(),
+    };
+    decoder.document()
+}
+
+impl<W: Write> Decoder<'_, W> {
+    fn document(&mut self) -> Result<()> {
+        writeln!(self.out, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+        // SD and the root SE(*) both have a single choice, so take no bits
+        let root = self.qname()?;
+        self.start_element(root)?;
+
+        while let Some(frame) = self.stack.last() {
+            let name = frame.name;
+            let grammar = self.grammars.entry(name).or_default();
+            let (production, learn) = if frame.in_content {
+                let learned = grammar.content.len();
+                let code = self.bits.read(width(learned + 2))? as usize;
+                if code < learned {
+                    (grammar.content[code], false)
+                } else if code == learned {
+                    (Production::EndElement, false)
+                } else if self.bits.read(1)? == 0 {
+                    (Production::Element((0, 0)), true)
+                } else {
+                    (Production::Characters, true)
+                }
+            } else {
+                let learned = grammar.start_tag.len();
+                let code = self.bits.read(width(learned + 1))? as usize;
+                if code < learned {
+                    (grammar.start_tag[code], false)
+                } else {
+                    match self.bits.read(2)? {
+                        0 => (Production::EndElement, true),
+                        1 => (Production::Attribute((0, 0)), true),
+                        2 => (Production::Element((0, 0)), true),
+                        _ => (Production::Characters, true),
+                    }
+                }
+            };
+            self.event(name, production, learn)?;
+        }
+
+        writeln!(self.out)?;
+        Ok(())
+    }
+
+    /// Apply one event to the current element. `learn` marks events matched
+    /// through the generic second-level productions, whose name (if any) still
+    /// has to be read and which add a learned production to the grammar.
+    fn event(&mut self, element: QName, production: Production, learn: bool) -> Result<()> {
+        let in_content = self.stack.last().is_some_and(|f| f.in_content);
+        let learned = |grammars: &mut HashMap<QName, Grammar>, production: Production| {
+            let grammar = grammars.entry(element).or_default();
+            let list = if in_content { &mut grammar.content } else { &mut grammar.start_tag };
+            if !list.contains(&production) {
+                list.insert(0, production);
+            }
+        };
+
+        match production {
+            Production::Attribute(name) => {
+                let name = if learn { self.qname()? } else { name };
+                if learn {
+                    learned(&mut self.grammars, Production::Attribute(name));
+                }
+                let mut declaration = self.declare(name.0);
+                let value = if name == (2, 1) {
+                    // xsi:type holds a qualified name
+                    let type_name = self.qname()?;
+                    declaration += &self.declare(type_name.0);
+                    self.name(type_name)
+                } else {
+                    self.value(name)?
+                };
+                let attribute = self.name(name);
+                let frame = self.stack.last_mut().unwrap();
+                if let Some(tag) = frame.open_tag.as_mut() {
+                    tag.push_str(&declaration);
+                    tag.push_str(&format!(" {}=\"{}\"", attribute, escape(&value)));
+                }
+            }
+            Production::Element(name) => {
+                let name = if learn { self.qname()? } else { name };
+                if learn {
+                    learned(&mut self.grammars, Production::Element(name));
+                }
+                self.flush_tag(false)?;
+                self.stack.last_mut().unwrap().in_content = true;
+                self.start_element(name)?;
+            }
+            Production::Characters => {
+                if learn {
+                    learned(&mut self.grammars, Production::Characters);
+                }
+                let text = self.value(element)?;
+                self.flush_tag(false)?;
+                self.stack.last_mut().unwrap().in_content = true;
+                write!(self.out, "{}", escape(&text))?;
+            }
+            Production::EndElement => {
+                if learn {
+                    learned(&mut self.grammars, Production::EndElement);
+                }
+                if !self.flush_tag(true)? {
+                    write!(self.out, "</{}>", self.name(element))?;
+                }
+                self.stack.pop();
+            }
+        }
+        Ok(())
+    }
+
+    fn start_element(&mut self, name: QName) -> Result<()> {
+        self.stack.push(Frame {
+            name,
+            in_content: false,
+            open_tag: None,
+            declared: Vec::new(),
+        });
+        let declaration = self.declare(name.0);
+        let tag = format!("<{}{}", self.name(name), declaration);
+        self.stack.last_mut().unwrap().open_tag = Some(tag);
+        Ok(())
+    }
+
+    /// Write out the current element's start tag if it is still pending.
+    /// Returns whether it was written as an empty element.
+    fn flush_tag(&mut self, empty: bool) -> Result<bool> {
+        match self.stack.last_mut().and_then(|f| f.open_tag.take()) {
+            Some(tag) => {
+                write!(self.out, "{}{}", tag, if empty { "/>" } else { ">" })?;
+                Ok(empty)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Prefixes aren't preserved, so each namespace gets a generated one,
+    /// declared on the first element in scope that uses it.
+    fn prefix(&self, uri: usize) -> String {
+        match uri {
+            0 => String::new(),
+            1 => "xml".to_string(),
+            2 => "xsi".to_string(),
+            n => format!("ns{}", n - 2),
+        }
+    }
+
+    fn declare(&mut self, uri: usize) -> String {
+        if uri == 0 || uri == 1 || self.stack.iter().any(|f| f.declared.contains(&uri)) {
+            return String::new();
+        }
+        if let Some(frame) = self.stack.last_mut() {
+            frame.declared.push(uri);
+        }
+        format!(" xmlns:{}=\"{}\"", self.prefix(uri), escape(&self.uris[uri]))
+    }
+
+    fn name(&self, (uri, local): QName) -> String {
+        let prefix = self.prefix(uri);
+        let local = &self.local_names[uri][local];
+        if prefix.is_empty() {
+            local.clone()
+        } else {
+            format!("{}:{}", prefix, local)
+        }
+    }
+
+    fn qname(&mut self) -> Result<QName> {
+        let uri = match self.bits.read(width(self.uris.len() + 1))? as usize {
+            0 => {
+                let len = self.bits.uint()?;
+                let uri = self.bits.string(len)?;
+                self.uris.push(uri);
+                self.local_names.push(Vec::new());
+                self.uris.len() - 1
+            }
+            n => n - 1,
+        };
+        if uri >= self.uris.len() {
+            return Err(anyhow!("Namespace index {} out of range", uri));
+        }
+
+        let local = match self.bits.uint()? {
+            0 => {
+                let names = self.local_names[uri].len();
+                let i = self.bits.read(width(names))? as usize;
+                if i >= names {
+                    return Err(anyhow!("Local name index {} out of range", i));
+                }
+                i
+            }
+            n => {
+                let name = self.bits.string(n - 1)?;
+                self.local_names[uri].push(name);
+                self.local_names[uri].len() - 1
+            }
+        };
+        Ok((uri, local))
+    }
+
+    /// Attribute or character content, through the local and global value
+    /// partitions of the string table.
+    fn value(&mut self, name: QName) -> Result<String> {
+        let local = self.local_values.entry(name).or_default();
+        match self.bits.uint()? {
+            0 => {
+                let i = self.bits.read(width(local.len()))? as usize;
+                local.get(i).cloned().ok_or_else(|| anyhow!("Value index {} out of range", i))
+            }
+            1 => {
+                let i = self.bits.read(width(self.values.len()))? as usize;
+                self.values.get(i).cloned().ok_or_else(|| anyhow!("Value index {} out of range", i))
+            }
+            n => {
+                let value = self.bits.string(n - 2)?;
+                if !value.is_empty() {
+                    local.push(value.clone());
+                    self.values.push(value.clone());
+                }
+                Ok(value)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decoded(data: &[u8]) -> String {
+        let mut out = Vec::new();
+        decode(data, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn decode_learned_productions_and_value_hits() {
+        // <a id="1"><b>x</b><b>x</b></a>: the second <b> comes from the
+        // production learned for the first and its "x" from the value table
+        let data = [
+            0x80, 0x40, 0x98, 0x54, 0x0d, 0xa5, 0x90, 0x0c, 0xc7, 0x20, 0x4c, 0x58, 0x1b, 0xc2, 0x40, 0x20, 0x01,
+        ];
+        let xml = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<a id=\"1\"><b>x</b><b>x</b></a>\n";
+        assert_eq!(decoded(&data), xml);
+        assert_eq!(decoded(&[b"$EXI".as_slice(), &data].concat()), xml);
+    }
+
+    #[test]
+    fn decode_namespaced_empty_element() {
+        let data = [0x80, 0x01, 0x5d, 0x5c, 0x9b, 0x8e, 0x9e, 0x00, 0x9c, 0x80];
+        assert_eq!(
+            decoded(&data),
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<ns1:r xmlns:ns1=\"urn:x\"/>\n"
+        );
+    }
+
+    #[test]
+    fn decode_rejects_other_headers() {
+        let mut out = Vec::new();
+        // Distinguishing bits missing, then an options header
+        assert!(decode(&[0x00], &mut out).is_err());
+        assert!(decode(&[0xa0], &mut out).is_err());
+        // Truncated body
+        assert!(decode(&[0x80, 0x40], &mut out).is_err());
+    }
+}
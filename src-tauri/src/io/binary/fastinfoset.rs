@@ -0,0 +1,578 @@
+//! Fast Infoset (ITU-T X.891) decoder. Handles self-contained documents: no
+//! external initial vocabulary, no notations or unparsed entities, and
+//! character data as literal UTF-8/UTF-16 or one of the built-in encoding
+//! algorithms. Restricted alphabets are rejected.
+
+use anyhow::{anyhow, Result};
+use base64::Engine;
+use quick_xml::escape::escape;
+use std::io::Write;
+
+const XML_NAMESPACE: &str = "http://www.w3.org/XML/1998/namespace";
+const TERMINATOR: u8 = 0xF0;
+const DOUBLE_TERMINATOR: u8 = 0xFF;
+
+#[derive(Clone)]
+struct QName {
+    prefix: String,
+    local: String,
+}
+
+impl QName {
+    fn text(&self) -> String {
+        if self.prefix.is_empty() {
+            self.local.clone()
+        } else {
+            format!("{}:{}", self.prefix, self.local)
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Table {
+    Prefix,
+    Namespace,
+    LocalName,
+    OtherNcName,
+    OtherUri,
+    AttributeValue,
+    Chunk,
+    OtherString,
+}
+
+struct Decoder<'a, W> {
+    data: &'a [u8],
+    pos: usize,
+    out: W,
+    // Terminators still owed after a double terminator
+    pending: usize,
+    tables: [Vec<String>; 8],
+    element_names: Vec<QName>,
+    attribute_names: Vec<QName>,
+}
+
+pub fn decode(data: &[u8], out: &mut impl Write) -> Result<()> {
+    let mut data = data;
+    if data.starts_with(b"<?xml") {
+        let end = data
+            .windows(2)
+            .position(|w| w == b"?>")
+            .ok_or_else(|| anyhow!("Unterminated XML declaration"))?;
+        data = &data[end + 2..];
+    }
+    if !data.starts_with(&[0xE0, 0x00, 0x00, 0x01]) {
+        return Err(anyhow!("Not a Fast Infoset document"));
+    }
+
+    let mut decoder = Decoder {
+        data,
+        pos: 4,
+        out,
+        pending: 0,
+        tables: Default::default(),
+        element_names: Vec::new(),
+        attribute_names: Vec::new(),
+    };
+    // Built-in entries of the initial vocabulary
+    decoder.table(Table::Prefix).push("xml".to_string());
+    decoder.table(Table::Namespace).push(XML_NAMESPACE.to_string());
+    decoder.document()
+}
+
+impl<W: Write> Decoder<'_, W> {
+    fn document(&mut self) -> Result<()> {
+        let flags = self.byte()?;
+        if flags & 0x78 != 0 {
+            return Err(anyhow!(
+                "Unsupported Fast Infoset document: additional data, external vocabulary, notations or unparsed entities"
+            ));
+        }
+        if flags & 0x04 != 0 {
+            // Character encoding scheme; the output is always UTF-8
+            let b = self.byte()?;
+            let len = self.len_2nd(b)?;
+            self.bytes(len)?;
+        }
+        let standalone = if flags & 0x02 != 0 {
+            Some(self.byte()? & 0x01 == 1)
+        } else {
+            None
+        };
+        let version = if flags & 0x01 != 0 {
+            let b = self.byte()?;
+            self.non_identifying(b, Table::OtherString)?
+        } else {
+            "1.0".to_string()
+        };
+
+        write!(self.out, "<?xml version=\"{}\" encoding=\"UTF-8\"", escape(&version))?;
+        if let Some(standalone) = standalone {
+            write!(self.out, " standalone=\"{}\"", if standalone { "yes" } else { "no" })?;
+        }
+        writeln!(self.out, "?>")?;
+
+        loop {
+            match self.next()? {
+                TERMINATOR => break,
+                b if b & 0x80 == 0 => self.element(b)?,
+                0xE1 => {
+                    let pi = self.processing_instruction()?;
+                    writeln!(self.out, "{}", pi)?;
+                }
+                0xE2 => {
+                    let comment = self.comment()?;
+                    writeln!(self.out, "{}", comment)?;
+                }
+                b if b & 0xFC == 0xC4 => self.doctype(b)?,
+                b => return Err(anyhow!("Unexpected byte {:#04x} at offset {}", b, self.pos - 1)),
+            }
+        }
+        writeln!(self.out)?;
+        Ok(())
+    }
+
+    /// Decode an element and everything below it, starting from its first
+    /// byte. Nesting is tracked on a stack rather than by recursion.
+    fn element(&mut self, first: u8) -> Result<()> {
+        let mut open: Vec<String> = Vec::new();
+        let mut b = first;
+        loop {
+            let name = self.start_tag(b)?;
+            open.push(name);
+
+            loop {
+                let c = self.next()?;
+                match c {
+                    TERMINATOR => {
+                        let name = open.pop().unwrap_or_default();
+                        write!(self.out, "</{}>", name)?;
+                        if open.is_empty() {
+                            return Ok(());
+                        }
+                    }
+                    c if c & 0x80 == 0 => {
+                        b = c;
+                        break;
+                    }
+                    c if c & 0xC0 == 0x80 => {
+                        let text = self.characters(c)?;
+                        write!(self.out, "{}", escape(&text))?;
+                    }
+                    0xE1 => {
+                        let pi = self.processing_instruction()?;
+                        write!(self.out, "{}", pi)?;
+                    }
+                    0xE2 => {
+                        let comment = self.comment()?;
+                        write!(self.out, "{}", comment)?;
+                    }
+                    c if c & 0xFC == 0xC8 => {
+                        return Err(anyhow!("Unexpanded entity references are not supported"));
+                    }
+                    c => return Err(anyhow!("Unexpected byte {:#04x} at offset {}", c, self.pos - 1)),
+                }
+            }
+        }
+    }
+
+    /// Write the start tag of an element with its namespace declarations and
+    /// attributes; returns the element's qualified name.
+    fn start_tag(&mut self, b: u8) -> Result<String> {
+        let has_attributes = b & 0x40 != 0;
+        let mut b = b;
+        let mut declarations = String::new();
+
+        if b & 0x3C == 0x38 {
+            loop {
+                let n = self.byte()?;
+                if n == TERMINATOR {
+                    break;
+                }
+                if n & 0xFC != 0xCC {
+                    return Err(anyhow!("Bad namespace attribute at offset {}", self.pos - 1));
+                }
+                let prefix = if n & 0x02 != 0 {
+                    let x = self.byte()?;
+                    self.identifying(x, Table::Prefix)?
+                } else {
+                    String::new()
+                };
+                let namespace = if n & 0x01 != 0 {
+                    let x = self.byte()?;
+                    self.identifying(x, Table::Namespace)?
+                } else {
+                    String::new()
+                };
+                if prefix.is_empty() {
+                    declarations += &format!(" xmlns=\"{}\"", escape(&namespace));
+                } else {
+                    declarations += &format!(" xmlns:{}=\"{}\"", prefix, escape(&namespace));
+                }
+            }
+            b = self.byte()?;
+        }
+
+        let name = if b & 0x3C == 0x3C {
+            let name = self.literal_qname(b)?;
+            self.element_names.push(name.clone());
+            name
+        } else {
+            let i = self.index_3rd(b)?;
+            self.element_names
+                .get(i)
+                .cloned()
+                .ok_or_else(|| anyhow!("Unknown element name index {}", i))?
+        };
+        let name = name.text();
+        write!(self.out, "<{}{}", name, declarations)?;
+
+        if has_attributes {
+            loop {
+                let a = self.next()?;
+                if a == TERMINATOR {
+                    break;
+                }
+                if a & 0x80 != 0 {
+                    return Err(anyhow!("Bad attribute at offset {}", self.pos - 1));
+                }
+                let attribute = if a & 0x7C == 0x78 {
+                    let name = self.literal_qname(a)?;
+                    self.attribute_names.push(name.clone());
+                    name
+                } else {
+                    let i = self.index_2nd(a)?;
+                    self.attribute_names
+                        .get(i)
+                        .cloned()
+                        .ok_or_else(|| anyhow!("Unknown attribute name index {}", i))?
+                };
+                let v = self.byte()?;
+                let value = self.non_identifying(v, Table::AttributeValue)?;
+                write!(self.out, " {}=\"{}\"", attribute.text(), escape(&value))?;
+            }
+        }
+        write!(self.out, ">")?;
+        Ok(name)
+    }
+
+    fn literal_qname(&mut self, b: u8) -> Result<QName> {
+        let prefix = if b & 0x02 != 0 {
+            let x = self.byte()?;
+            self.identifying(x, Table::Prefix)?
+        } else {
+            String::new()
+        };
+        if b & 0x01 != 0 {
+            // The namespace itself is declared by a namespace attribute
+            let x = self.byte()?;
+            self.identifying(x, Table::Namespace)?;
+        }
+        let x = self.byte()?;
+        let local = self.identifying(x, Table::LocalName)?;
+        Ok(QName { prefix, local })
+    }
+
+    fn characters(&mut self, b: u8) -> Result<String> {
+        if b & 0x20 != 0 {
+            let i = self.index_4th(b)?;
+            return self.lookup(Table::Chunk, i);
+        }
+        let value = match (b >> 2) & 0x03 {
+            0 => {
+                let len = self.len_7th(b)?;
+                self.utf8(len)?
+            }
+            1 => {
+                let len = self.len_7th(b)?;
+                self.utf16(len)?
+            }
+            kind => {
+                let n = self.byte()?;
+                let index = (b & 0x03) << 6 | n >> 2;
+                let len = self.len_7th(n)?;
+                self.encoded(kind == 3, index, len)?
+            }
+        };
+        if b & 0x10 != 0 {
+            self.table(Table::Chunk).push(value.clone());
+        }
+        Ok(value)
+    }
+
+    fn comment(&mut self) -> Result<String> {
+        let b = self.byte()?;
+        let text = self.non_identifying(b, Table::OtherString)?;
+        Ok(format!("<!--{}-->", text))
+    }
+
+    fn processing_instruction(&mut self) -> Result<String> {
+        let b = self.byte()?;
+        let target = self.identifying(b, Table::OtherNcName)?;
+        let b = self.byte()?;
+        let content = self.non_identifying(b, Table::OtherString)?;
+        Ok(format!("<?{} {}?>", target, content))
+    }
+
+    /// The document type declaration carries no root name, so it is read and
+    /// dropped rather than written back out.
+    fn doctype(&mut self, b: u8) -> Result<()> {
+        for flag in [0x02, 0x01] {
+            if b & flag != 0 {
+                let x = self.byte()?;
+                self.identifying(x, Table::OtherUri)?;
+            }
+        }
+        loop {
+            match self.next()? {
+                TERMINATOR => return Ok(()),
+                0xE1 => {
+                    self.processing_instruction()?;
+                }
+                c => return Err(anyhow!("Unexpected byte {:#04x} in document type declaration", c)),
+            }
+        }
+    }
+
+    /// Identifying string or index (C.13), added to `table` when literal.
+    fn identifying(&mut self, b: u8, table: Table) -> Result<String> {
+        if b & 0x80 != 0 {
+            let i = self.index_2nd(b)?;
+            return self.lookup(table, i);
+        }
+        let len = self.len_2nd(b)?;
+        let value = self.utf8(len)?;
+        self.table(table).push(value.clone());
+        Ok(value)
+    }
+
+    /// Non-identifying string or index (C.14), starting on the first bit.
+    fn non_identifying(&mut self, b: u8, table: Table) -> Result<String> {
+        if b & 0x80 != 0 {
+            let i = self.index_2nd(b)?;
+            return self.lookup(table, i);
+        }
+        let value = match (b >> 4) & 0x03 {
+            0 => {
+                let len = self.len_5th(b)?;
+                self.utf8(len)?
+            }
+            1 => {
+                let len = self.len_5th(b)?;
+                self.utf16(len)?
+            }
+            kind => {
+                let n = self.byte()?;
+                let index = (b & 0x0F) << 4 | n >> 4;
+                let len = self.len_5th(n)?;
+                self.encoded(kind == 3, index, len)?
+            }
+        };
+        if b & 0x40 != 0 {
+            self.table(table).push(value.clone());
+        }
+        Ok(value)
+    }
+
+    /// Octets produced by a built-in encoding algorithm, rendered as text.
+    fn encoded(&mut self, algorithm: bool, index: u8, len: usize) -> Result<String> {
+        if !algorithm {
+            return Err(anyhow!("Restricted alphabets are not supported"));
+        }
+        let octets = self.bytes(len)?;
+        let numbers = |size: usize, f: &dyn Fn(&[u8]) -> String| -> String {
+            octets.chunks_exact(size).map(f).collect::<Vec<_>>().join(" ")
+        };
+        Ok(match index {
+            0 => octets.iter().map(|b| format!("{:02x}", b)).collect(),
+            1 => base64::engine::general_purpose::STANDARD.encode(octets),
+            2 => numbers(2, &|c| i16::from_be_bytes([c[0], c[1]]).to_string()),
+            3 => numbers(4, &|c| i32::from_be_bytes(c.try_into().unwrap()).to_string()),
+            4 => numbers(8, &|c| i64::from_be_bytes(c.try_into().unwrap()).to_string()),
+            6 => numbers(4, &|c| f32::from_be_bytes(c.try_into().unwrap()).to_string()),
+            7 => numbers(8, &|c| f64::from_be_bytes(c.try_into().unwrap()).to_string()),
+            8 => numbers(16, &|c| {
+                let hex: String = c.iter().map(|b| format!("{:02x}", b)).collect();
+                format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+            }),
+            9 => String::from_utf8_lossy(octets).into_owned(),
+            _ => return Err(anyhow!("Unsupported encoding algorithm {}", index + 1)),
+        })
+    }
+
+    fn table(&mut self, table: Table) -> &mut Vec<String> {
+        &mut self.tables[table as usize]
+    }
+
+    fn lookup(&mut self, table: Table, i: usize) -> Result<String> {
+        self.table(table)
+            .get(i)
+            .cloned()
+            .ok_or_else(|| anyhow!("Vocabulary index {} out of range", i + 1))
+    }
+
+    /// Next item byte, handing out the second half of a double terminator
+    /// before reading further.
+    fn next(&mut self) -> Result<u8> {
+        if self.pending > 0 {
+            self.pending -= 1;
+            return Ok(TERMINATOR);
+        }
+        let b = self.byte()?;
+        if b == DOUBLE_TERMINATOR {
+            self.pending += 1;
+            return Ok(TERMINATOR);
+        }
+        Ok(b)
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        let b = *self
+            .data
+            .get(self.pos)
+            .ok_or_else(|| anyhow!("Unexpected end of Fast Infoset document"))?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&[u8]> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|&end| end <= self.data.len())
+            .ok_or_else(|| anyhow!("Unexpected end of Fast Infoset document"))?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<usize> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize)
+    }
+
+    fn u16_after(&mut self) -> Result<usize> {
+        let hi = self.byte()? as usize;
+        let lo = self.byte()? as usize;
+        Ok(hi << 8 | lo)
+    }
+
+    fn utf8(&mut self, len: usize) -> Result<String> {
+        Ok(String::from_utf8_lossy(self.bytes(len)?).into_owned())
+    }
+
+    fn utf16(&mut self, len: usize) -> Result<String> {
+        let units: Vec<u16> = self
+            .bytes(len)?
+            .chunks_exact(2)
+            .map(|c| u16::from_be_bytes([c[0], c[1]]))
+            .collect();
+        Ok(String::from_utf16_lossy(&units))
+    }
+
+    // Integers (zero-based indexes) starting on the 2nd, 3rd and 4th bit of `b`
+
+    fn index_2nd(&mut self, b: u8) -> Result<usize> {
+        let b = b as usize;
+        Ok(match b & 0x70 {
+            0x00..=0x30 => b & 0x3F,
+            0x40 | 0x50 => ((b & 0x1F) << 8 | self.byte()? as usize) + 64,
+            0x60 => ((b & 0x0F) << 16 | self.u16_after()?) + 8256,
+            _ => return Err(anyhow!("Bad index encoding at offset {}", self.pos - 1)),
+        })
+    }
+
+    fn index_3rd(&mut self, b: u8) -> Result<usize> {
+        let b = b as usize;
+        Ok(match b & 0x38 {
+            0x00..=0x18 => b & 0x1F,
+            0x20 => ((b & 0x07) << 8 | self.byte()? as usize) + 32,
+            0x28 => ((b & 0x07) << 16 | self.u16_after()?) + 2080,
+            0x30 if b & 0x07 == 0 => ((self.byte()? as usize & 0x0F) << 16 | self.u16_after()?) + 526368,
+            _ => return Err(anyhow!("Bad index encoding at offset {}", self.pos - 1)),
+        })
+    }
+
+    fn index_4th(&mut self, b: u8) -> Result<usize> {
+        let b = b as usize;
+        Ok(match b & 0x1C {
+            0x00..=0x0C => b & 0x0F,
+            0x10 | 0x14 => ((b & 0x07) << 8 | self.byte()? as usize) + 16,
+            0x18 => ((b & 0x03) << 16 | self.u16_after()?) + 2064,
+            0x1C if b & 0x03 == 0 => ((self.byte()? as usize & 0x0F) << 16 | self.u16_after()?) + 264208,
+            _ => return Err(anyhow!("Bad index encoding at offset {}", self.pos - 1)),
+        })
+    }
+
+    // Octet string lengths starting on the 2nd, 5th and 7th bit of `b`
+
+    fn len_2nd(&mut self, b: u8) -> Result<usize> {
+        Ok(match b & 0x60 {
+            0x00 | 0x20 => (b & 0x3F) as usize + 1,
+            0x40 => self.byte()? as usize + 65,
+            _ => self.u32()? + 321,
+        })
+    }
+
+    fn len_5th(&mut self, b: u8) -> Result<usize> {
+        Ok(match b & 0x0C {
+            0x00 | 0x04 => (b & 0x07) as usize + 1,
+            0x08 => self.byte()? as usize + 9,
+            _ => self.u32()? + 265,
+        })
+    }
+
+    fn len_7th(&mut self, b: u8) -> Result<usize> {
+        Ok(match b & 0x03 {
+            0x00 | 0x01 => (b & 0x01) as usize + 1,
+            0x02 => self.byte()? as usize + 3,
+            _ => self.u32()? + 259,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decoded(data: &[u8]) -> String {
+        let mut out = Vec::new();
+        decode(data, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn decode_element_names_attributes_and_text() {
+        // <root a="v">text<child/><child/></root>, the second <child/> by its
+        // index in the element name table, closed with a double terminator
+        let mut data = vec![0xe0, 0x00, 0x00, 0x01, 0x00];
+        data.extend_from_slice(b"\x7c\x03root\x78\x00a\x00v\xf0");
+        data.extend_from_slice(b"\x82\x01text");
+        data.extend_from_slice(b"\x3c\x04child\xf0\x01\xff\xf0");
+        assert_eq!(
+            decoded(&data),
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<root a=\"v\">text<child></child><child></child></root>\n"
+        );
+    }
+
+    #[test]
+    fn decode_namespace_attributes() {
+        // <p:e xmlns:p="urn:p"/>, with the prefix and namespace of the name
+        // taken from the tables the declaration filled
+        let mut data = vec![0xe0, 0x00, 0x00, 0x01, 0x00];
+        data.extend_from_slice(b"\x38\xcf\x00p\x04urn:p\xf0");
+        data.extend_from_slice(b"\x3f\x81\x81\x00e\xf0\xf0");
+        assert_eq!(
+            decoded(&data),
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<p:e xmlns:p=\"urn:p\"></p:e>\n"
+        );
+    }
+
+    #[test]
+    fn decode_encoded_character_data() {
+        // A base64 chunk (algorithm 2) of 3 octets inside <d>
+        let mut data = vec![0xe0, 0x00, 0x00, 0x01, 0x00];
+        data.extend_from_slice(b"\x3c\x00d\x8c\x06\x00abc\xf0\xf0");
+        assert_eq!(decoded(&data), "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<d>YWJj</d>\n");
+        assert!(decode(b"\xe0\x00\x00\x02\x00", &mut Vec::new()).is_err());
+    }
+}
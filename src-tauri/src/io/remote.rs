@@ -0,0 +1,132 @@
+pub mod s3;
+
+use anyhow::Result;
+use std::collections::{HashMap, VecDeque};
+use std::io::Read;
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// Size of a single ranged GET. Sequential scans fetch one block per request,
+/// random access (chunks, context reads) usually hits a single cached block.
+const BLOCK_SIZE: u64 = 256 * 1024;
+
+/// Blocks the cache holds, from the `chunk_cache` memory setting (64MB = 256 blocks by default).
+fn max_cached_blocks() -> usize {
+    (super::memory_settings().chunk_cache / BLOCK_SIZE as usize).max(1)
+}
+
+pub fn is_remote(path: &str) -> bool {
+    path.starts_with("http://") || path.starts_with("https://") || path.starts_with("s3://")
+}
+
+/// Start a request for `url`, signing it when it points into an S3 bucket.
+fn request(method: &str, url: &str) -> Result<ureq::Request> {
+    if url.starts_with("s3://") {
+        s3::request(method, url)
+    } else {
+        Ok(ureq::request(method, url))
+    }
+}
+
+#[derive(Default)]
+struct BlockCache {
+    lengths: HashMap<String, u64>,
+    blocks: HashMap<(String, u64), Arc<Vec<u8>>>,
+    // Insertion order, oldest first, for eviction
+    order: VecDeque<(String, u64)>,
+}
+
+fn cache() -> &'static Mutex<BlockCache> {
+    static CACHE: OnceLock<Mutex<BlockCache>> = OnceLock::new();
+    CACHE.get_or_init(|| Mutex::new(BlockCache::default()))
+}
+
+/// Forget the length and blocks cached for `url`, so it is fetched again
+/// the next time it is opened.
+pub fn invalidate(url: &str) {
+    let mut cache = cache().lock().unwrap();
+    cache.lengths.remove(url);
+    cache.blocks.retain(|(cached, _), _| cached != url);
+    cache.order.retain(|(cached, _)| cached != url);
+}
+
+/// A file on an HTTP(S) server that supports `Range` requests, or an object in
+/// an S3-compatible bucket.
+pub struct RemoteFile {
+    url: String,
+    len: u64,
+}
+
+impl RemoteFile {
+    pub fn open(url: &str) -> Result<RemoteFile> {
+        if let Some(&len) = cache().lock().unwrap().lengths.get(url) {
+            return Ok(RemoteFile { url: url.to_string(), len });
+        }
+
+        let resp = request("HEAD", url)?.call()?;
+        let len = resp
+            .header("Content-Length")
+            .and_then(|v| v.trim().parse::<u64>().ok())
+            .ok_or_else(|| anyhow::anyhow!("Server did not report a Content-Length for {}", url))?;
+        if resp.header("Accept-Ranges").is_some_and(|v| v.eq_ignore_ascii_case("none")) {
+            return Err(anyhow::anyhow!("Server does not support range requests for {}", url));
+        }
+
+        cache().lock().unwrap().lengths.insert(url.to_string(), len);
+        Ok(RemoteFile { url: url.to_string(), len })
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Copy bytes starting at `pos` into `buf`, stopping at the end of the
+    /// block that contains `pos`. Returns 0 at end of file.
+    pub fn read_at(&self, pos: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+        if pos >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+        let index = pos / BLOCK_SIZE;
+        let block = self.block(index)?;
+        let start = (pos - index * BLOCK_SIZE) as usize;
+        let n = buf.len().min(block.len().saturating_sub(start));
+        buf[..n].copy_from_slice(&block[start..start + n]);
+        Ok(n)
+    }
+
+    fn block(&self, index: u64) -> std::io::Result<Arc<Vec<u8>>> {
+        let key = (self.url.clone(), index);
+        if let Some(block) = cache().lock().unwrap().blocks.get(&key) {
+            return Ok(block.clone());
+        }
+
+        let start = index * BLOCK_SIZE;
+        let end = (start + BLOCK_SIZE).min(self.len) - 1;
+        let block = Arc::new(fetch_range(&self.url, start, end).map_err(std::io::Error::other)?);
+
+        let mut cache = cache().lock().unwrap();
+        // `while`: the setting may have shrunk since the last insert
+        while cache.order.len() >= max_cached_blocks() {
+            match cache.order.pop_front() {
+                Some(oldest) => cache.blocks.remove(&oldest),
+                None => break,
+            };
+        }
+        cache.order.push_back(key.clone());
+        cache.blocks.insert(key, block.clone());
+        Ok(block)
+    }
+}
+
+/// GET the inclusive byte range `start..=end` of `url`.
+fn fetch_range(url: &str, start: u64, end: u64) -> Result<Vec<u8>> {
+    let resp = request("GET", url)?
+        .set("Range", &format!("bytes={}-{}", start, end))
+        .call()?;
+    if resp.status() != 206 {
+        return Err(anyhow::anyhow!("Expected a partial response from {}, got HTTP {}", url, resp.status()));
+    }
+
+    let mut data = Vec::with_capacity((end - start + 1) as usize);
+    resp.into_reader().take(end - start + 1).read_to_end(&mut data)?;
+    Ok(data)
+}
@@ -0,0 +1,228 @@
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+use std::sync::Mutex;
+
+/// Credentials and endpoint used for `s3://bucket/key` paths. Anything left
+/// unset falls back to the usual `AWS_*` environment variables.
+#[derive(serde::Deserialize, Clone, Default)]
+pub struct S3Config {
+    pub access_key_id: Option<String>,
+    pub secret_access_key: Option<String>,
+    pub session_token: Option<String>,
+    pub region: Option<String>,
+    /// Custom endpoint for S3-compatible stores (MinIO, Ceph, ...). Uses
+    /// path-style addressing when set.
+    pub endpoint: Option<String>,
+}
+
+static CONFIG: Mutex<Option<S3Config>> = Mutex::new(None);
+
+pub fn set_config(config: S3Config) {
+    *CONFIG.lock().unwrap() = Some(config);
+}
+
+struct Resolved {
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: Option<String>,
+    region: String,
+    endpoint: Option<String>,
+}
+
+fn resolve() -> Result<Resolved> {
+    let config = CONFIG.lock().unwrap().clone().unwrap_or_default();
+    let env = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+
+    let access_key_id = config
+        .access_key_id
+        .or_else(|| env("AWS_ACCESS_KEY_ID"))
+        .ok_or_else(|| anyhow::anyhow!("No S3 access key configured"))?;
+    let secret_access_key = config
+        .secret_access_key
+        .or_else(|| env("AWS_SECRET_ACCESS_KEY"))
+        .ok_or_else(|| anyhow::anyhow!("No S3 secret key configured"))?;
+
+    Ok(Resolved {
+        access_key_id,
+        secret_access_key,
+        session_token: config.session_token.or_else(|| env("AWS_SESSION_TOKEN")),
+        region: config
+            .region
+            .or_else(|| env("AWS_REGION"))
+            .or_else(|| env("AWS_DEFAULT_REGION"))
+            .unwrap_or_else(|| "us-east-1".to_string()),
+        endpoint: config.endpoint.or_else(|| env("AWS_ENDPOINT_URL")),
+    })
+}
+
+/// Build a SigV4-signed request for `s3://bucket/key`.
+pub fn request(method: &str, uri: &str) -> Result<ureq::Request> {
+    let rest = uri
+        .strip_prefix("s3://")
+        .ok_or_else(|| anyhow::anyhow!("Not an s3:// URI: {}", uri))?;
+    let (bucket, key) = rest
+        .split_once('/')
+        .filter(|(b, k)| !b.is_empty() && !k.is_empty())
+        .ok_or_else(|| anyhow::anyhow!("Expected s3://bucket/key, got {}", uri))?;
+
+    let creds = resolve()?;
+    let key_path = encode_path(key);
+    let (host, canonical_uri, url) = match &creds.endpoint {
+        Some(endpoint) => {
+            let endpoint = endpoint.trim_end_matches('/');
+            let host = endpoint.split("://").nth(1).unwrap_or(endpoint).to_string();
+            let path = format!("/{}/{}", encode_path(bucket), key_path);
+            (host, path.clone(), format!("{}{}", endpoint, path))
+        }
+        None => {
+            let host = format!("{}.s3.{}.amazonaws.com", bucket, creds.region);
+            let path = format!("/{}", key_path);
+            (host.clone(), path.clone(), format!("https://{}{}", host, path))
+        }
+    };
+
+    let now = chrono::Utc::now();
+    let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let date = &amz_date[..8];
+    let payload_hash = "UNSIGNED-PAYLOAD";
+
+    let mut headers = vec![
+        ("host", host),
+        ("x-amz-content-sha256", payload_hash.to_string()),
+        ("x-amz-date", amz_date.clone()),
+    ];
+    if let Some(token) = &creds.session_token {
+        headers.push(("x-amz-security-token", token.clone()));
+    }
+    let signature = sign(method, &canonical_uri, &headers, payload_hash, &creds.secret_access_key, &creds.region, &amz_date);
+    let scope = format!("{}/{}/s3/aws4_request", date, creds.region);
+    let signed_headers = headers.iter().map(|(k, _)| *k).collect::<Vec<_>>().join(";");
+
+    let mut req = ureq::request(method, &url).set(
+        "Authorization",
+        &format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            creds.access_key_id, scope, signed_headers, signature
+        ),
+    );
+    // `host` is set by the HTTP client itself
+    for (k, v) in headers.iter().skip(1) {
+        req = req.set(k, v);
+    }
+    Ok(req)
+}
+
+/// SigV4 signature of a request without a query string, made at `amz_date`
+/// (`%Y%m%dT%H%M%SZ`). `headers` are the signed headers, lowercase and sorted
+/// by name.
+fn sign(
+    method: &str,
+    canonical_uri: &str,
+    headers: &[(&str, String)],
+    payload_hash: &str,
+    secret_access_key: &str,
+    region: &str,
+    amz_date: &str,
+) -> String {
+    let canonical_headers: String = headers.iter().map(|(k, v)| format!("{}:{}\n", k, v.trim())).collect();
+    let signed_headers = headers.iter().map(|(k, _)| *k).collect::<Vec<_>>().join(";");
+
+    let canonical_request = format!(
+        "{}\n{}\n\n{}\n{}\n{}",
+        method, canonical_uri, canonical_headers, signed_headers, payload_hash
+    );
+    let date = &amz_date[..8];
+    let scope = format!("{}/{}/s3/aws4_request", date, region);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        scope,
+        hex(&Sha256::digest(canonical_request.as_bytes()))
+    );
+
+    let mut signing_key = hmac_sha256(format!("AWS4{}", secret_access_key).as_bytes(), date.as_bytes());
+    for part in [region, "s3", "aws4_request"] {
+        signing_key = hmac_sha256(&signing_key, part.as_bytes());
+    }
+    hex(&hmac_sha256(&signing_key, string_to_sign.as_bytes()))
+}
+
+/// URI-encode an object key the way SigV4 expects: everything except
+/// unreserved characters and the `/` separators.
+fn encode_path(key: &str) -> String {
+    let mut out = String::with_capacity(key.len());
+    for b in key.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => out.push(b as char),
+            _ => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    out
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    const BLOCK: usize = 64;
+    let mut block_key = [0u8; BLOCK];
+    if key.len() > BLOCK {
+        block_key[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block_key[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = Sha256::new();
+    inner.update(block_key.map(|b| b ^ 0x36));
+    inner.update(data);
+    let mut outer = Sha256::new();
+    outer.update(block_key.map(|b| b ^ 0x5c));
+    outer.update(inner.finalize());
+    outer.finalize().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hmac_sha256_rfc4231() {
+        assert_eq!(
+            hex(&hmac_sha256(b"Jefe", b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        // Keys longer than a block are hashed first
+        assert_eq!(
+            hex(&hmac_sha256(&[0xaa; 131], b"Test Using Larger Than Block-Size Key - Hash Key First")),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+        );
+    }
+
+    #[test]
+    fn sign_aws_get_object_example() {
+        // The GET Object example of the S3 SigV4 header authentication docs
+        let empty_hash = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        let headers = [
+            ("host", "examplebucket.s3.amazonaws.com".to_string()),
+            ("range", "bytes=0-9".to_string()),
+            ("x-amz-content-sha256", empty_hash.to_string()),
+            ("x-amz-date", "20130524T000000Z".to_string()),
+        ];
+        let signature = sign(
+            "GET",
+            "/test.txt",
+            &headers,
+            empty_hash,
+            "wJalrXUtnFEMI/K7MDENG/bPxRfiCYEXAMPLEKEY",
+            "us-east-1",
+            "20130524T000000Z",
+        );
+        assert_eq!(signature, "f0e8bdb87c964420e857bd35b5d6ed310bd44f0170aba48dd91039c6036bdb41");
+    }
+
+    #[test]
+    fn encode_path_keeps_separators() {
+        assert_eq!(encode_path("a b/ü~x+y.xml"), "a%20b/%C3%BC~x%2By.xml");
+    }
+}
@@ -0,0 +1,291 @@
+use crate::edit::{EditSessions, Splice};
+use crate::error::XmlReaderError;
+use crate::files::{FileId, FileRegistry, OpenElements};
+use crate::save::SourceStamp;
+use crate::session::SessionState;
+use crate::xml_ops::{self, OpenFailure, OpenedFile};
+use anyhow::Result;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tauri::{AppHandle, Manager, State, WebviewWindow};
+
+/// How often the journal is brought up to date with progress nothing else
+/// records, like the checkpoints of a running scan.
+const JOURNAL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Edits staged on a file and the stamp of the file they were planned
+/// against.
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+struct StagedEdits {
+    since: SourceStamp,
+    splices: Vec<Splice>,
+}
+
+/// What the journal keeps of one open file.
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+struct JournalEntry {
+    path: String,
+    /// The file when the entry was written; the checkpoint is only reused if
+    /// it still matches.
+    stamp: Option<SourceStamp>,
+    state: SessionState,
+    bookmarks: Vec<u64>,
+    staged: Option<StagedEdits>,
+    /// Furthest point forward scans indexed, and the elements open there.
+    checkpoint: Option<(u64, OpenElements)>,
+}
+
+#[derive(Clone, Default)]
+struct View {
+    state: SessionState,
+    bookmarks: Vec<u64>,
+}
+
+/// The previous run's journal entry for a file, as `get_previous_session`
+/// reports it.
+#[derive(serde::Serialize)]
+pub struct PreviousFile {
+    path: String,
+    state: SessionState,
+    bookmarks: Vec<u64>,
+    staged: usize,
+}
+
+#[derive(serde::Serialize)]
+pub struct RestoredFile {
+    file: OpenedFile,
+    state: SessionState,
+    bookmarks: Vec<u64>,
+    /// Staged edits put back into an edit session.
+    staged: usize,
+    /// Staged edits dropped because the file changed since the crash.
+    stale_edits: bool,
+}
+
+#[derive(serde::Serialize)]
+pub struct RestoredSession {
+    restored: Vec<RestoredFile>,
+    failed: Vec<OpenFailure>,
+}
+
+/// A small journal of the work in progress, rewritten as it changes: open
+/// files, where the user was in them, bookmarks not saved anywhere else,
+/// staged edits and indexing progress. It is deleted on a clean exit, so
+/// finding one at startup means the last run crashed and its state can be
+/// offered back. Kept in Tauri managed state.
+pub struct Journal {
+    store: Option<PathBuf>,
+    // The crashed run's journal, moved aside until restored or discarded
+    previous: Option<PathBuf>,
+    views: Mutex<HashMap<FileId, View>>,
+    // Last bytes written, so unchanged state isn't rewritten
+    written: Mutex<Vec<u8>>,
+    closed: AtomicBool,
+}
+
+impl Journal {
+    /// Use `store` as the journal. One left there by a crashed run is moved
+    /// aside for `get_previous_session`.
+    pub fn load(store: Option<PathBuf>) -> Journal {
+        let previous = store.as_ref().map(|store| store.with_extension("previous.json"));
+        if let (Some(store), Some(previous)) = (&store, &previous) {
+            if store.exists() {
+                let _ = std::fs::rename(store, previous);
+            }
+        }
+        Journal {
+            store,
+            previous,
+            views: Mutex::default(),
+            written: Mutex::default(),
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    fn previous_entries(&self) -> Option<Vec<JournalEntry>> {
+        let bytes = std::fs::read(self.previous.as_ref()?).ok()?;
+        serde_json::from_slice(&bytes).ok()
+    }
+
+    fn discard_previous(&self) {
+        if let Some(previous) = &self.previous {
+            let _ = std::fs::remove_file(previous);
+        }
+    }
+
+    fn write(&self, bytes: &[u8]) -> Result<()> {
+        let Some(store) = &self.store else {
+            return Ok(());
+        };
+        if let Some(dir) = store.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        // Write then rename, so a crash mid-write keeps the last journal
+        let tmp = store.with_extension("json.tmp");
+        std::fs::write(&tmp, bytes)?;
+        std::fs::rename(&tmp, store)?;
+        Ok(())
+    }
+}
+
+/// The state to journal now: every open file that can be reopened by path.
+fn snapshot(app: &AppHandle) -> Vec<JournalEntry> {
+    let files = app.state::<FileRegistry>();
+    let edits = app.state::<EditSessions>();
+    let journal = app.state::<Journal>();
+    let handles = files.handles();
+    let mut views = journal.views.lock().unwrap();
+    views.retain(|id, _| handles.iter().any(|h| h.id == *id));
+    handles
+        .iter()
+        .filter_map(|handle| {
+            let path = handle.local_path()?;
+            let view = views.get(&handle.id).cloned().unwrap_or_default();
+            Some(JournalEntry {
+                path: path.to_string(),
+                stamp: SourceStamp::of(path).ok(),
+                state: view.state,
+                bookmarks: view.bookmarks,
+                staged: edits.staged(handle.id).map(|(since, splices)| StagedEdits { since, splices }),
+                checkpoint: handle.checkpoint_before(u64::MAX),
+            })
+        })
+        .collect()
+}
+
+/// Bring the journal up to date. Called after every change worth
+/// recovering, and periodically by `start`.
+pub fn record(app: &AppHandle) {
+    let journal = app.state::<Journal>();
+    if journal.closed.load(Ordering::SeqCst) {
+        return;
+    }
+    let Ok(bytes) = serde_json::to_vec(&snapshot(app)) else {
+        return;
+    };
+    let mut written = journal.written.lock().unwrap();
+    if *written != bytes && journal.write(&bytes).is_ok() {
+        *written = bytes;
+    }
+}
+
+/// Record the journal every `JOURNAL_INTERVAL` until the app exits.
+pub fn start(app: AppHandle) {
+    std::thread::spawn(move || {
+        while !app.state::<Journal>().closed.load(Ordering::SeqCst) {
+            std::thread::sleep(JOURNAL_INTERVAL);
+            record(&app);
+        }
+    });
+}
+
+/// A clean exit: stop recording and delete the journal, so the next start
+/// doesn't offer to restore this session.
+pub fn close(app: &AppHandle) {
+    let journal = app.state::<Journal>();
+    journal.closed.store(true, Ordering::SeqCst);
+    // Wait for a write in progress
+    let _written = journal.written.lock().unwrap();
+    if let Some(store) = &journal.store {
+        let _ = std::fs::remove_file(store);
+    }
+}
+
+/// Remember where the user is in a file and the bookmarks made in it, for
+/// the journal. The frontend calls this as they change.
+#[tauri::command]
+pub async fn journal_view(
+    app: AppHandle,
+    journal: State<'_, Journal>,
+    file_id: FileId,
+    state: SessionState,
+    bookmarks: Vec<u64>,
+) -> Result<(), XmlReaderError> {
+    journal.views.lock().unwrap().insert(file_id, View { state, bookmarks });
+    record(&app);
+    Ok(())
+}
+
+/// The files of the last run, if it crashed and its session hasn't been
+/// restored or discarded yet.
+#[tauri::command]
+pub async fn get_previous_session(journal: State<'_, Journal>) -> Result<Option<Vec<PreviousFile>>, XmlReaderError> {
+    Ok(journal.previous_entries().map(|entries| {
+        entries
+            .into_iter()
+            .map(|entry| PreviousFile {
+                path: entry.path,
+                state: entry.state,
+                bookmarks: entry.bookmarks,
+                staged: entry.staged.map_or(0, |staged| staged.splices.len()),
+            })
+            .collect()
+    }))
+}
+
+/// Reopen the crashed run's files in the calling window. Staged edits come
+/// back as an open edit session, and indexing progress is reused, as long
+/// as the file hasn't changed since.
+#[tauri::command]
+pub async fn restore_previous_session(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    edits: State<'_, EditSessions>,
+    journal: State<'_, Journal>,
+) -> Result<RestoredSession, XmlReaderError> {
+    let entries = journal.previous_entries().unwrap_or_default();
+    let mut session = RestoredSession {
+        restored: Vec::new(),
+        failed: Vec::new(),
+    };
+    for entry in entries {
+        let (handle, file) = match xml_ops::open_path(&window, &files, entry.path.clone()) {
+            Ok(opened) => opened,
+            Err(failure) => {
+                session.failed.push(failure);
+                continue;
+            }
+        };
+        let stamp = SourceStamp::of(&entry.path).ok();
+        if let Some((offset, open)) = &entry.checkpoint {
+            if stamp.is_some() && stamp == entry.stamp {
+                handle.add_checkpoint(*offset, open);
+            }
+        }
+        let mut staged = 0;
+        let mut stale_edits = false;
+        if let Some(edit) = entry.staged {
+            if stamp.as_ref() == Some(&edit.since) {
+                staged = edit.splices.len();
+                edits.restore(handle.id, edit.since, edit.splices);
+            } else {
+                stale_edits = true;
+            }
+        }
+        let view = View {
+            state: entry.state.clone(),
+            bookmarks: entry.bookmarks.clone(),
+        };
+        journal.views.lock().unwrap().insert(handle.id, view);
+        session.restored.push(RestoredFile {
+            file,
+            state: entry.state,
+            bookmarks: entry.bookmarks,
+            staged,
+            stale_edits,
+        });
+    }
+    journal.discard_previous();
+    record(window.app_handle());
+    Ok(session)
+}
+
+/// Forget the crashed run's session without restoring it.
+#[tauri::command]
+pub async fn discard_previous_session(journal: State<'_, Journal>) -> Result<(), XmlReaderError> {
+    journal.discard_previous();
+    Ok(())
+}
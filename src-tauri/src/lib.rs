@@ -1,27 +1,211 @@
+mod about;
+mod catalog;
+mod cli;
+mod diff;
+mod edit;
+mod error;
+mod export;
+mod files;
+mod format;
+mod geo;
+mod identity;
+mod io;
+mod journal;
+mod logging;
+mod ops;
+mod preview;
+mod query;
+mod recent;
+mod save;
+mod schema;
+mod script;
+mod session;
+mod settings;
+mod stats;
+mod validate;
+mod watch;
+mod windows;
+mod workspace;
+mod xmi;
 mod xml_ops;
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
-    tauri::Builder::default()
+    let builder = tauri::Builder::default();
+    // Registered first, so a second launch exits before doing anything else
+    #[cfg(desktop)]
+    let builder = builder.plugin(tauri_plugin_single_instance::init(windows::forwarded));
+    builder
         .plugin(tauri_plugin_opener::init())
+        .manage(files::FileRegistry::default())
+        .manage(edit::EditSessions::default())
         .setup(|app| {
             use tauri::Manager;
+            logging::init(app.path().app_data_dir().ok().map(|dir| dir.join("logs")));
             let win = app.get_webview_window("main").unwrap();
+            settings::load(app.path().app_data_dir().ok().map(|dir| dir.join("settings.json")));
             let version = app.package_info().version.to_string();
             let _ = win.set_title(&format!("xml-reader v{}", version));
+
+            let store = app.path().app_data_dir().ok().map(|dir| dir.join("recent.json"));
+            app.manage(recent::RecentFiles::load(store));
+            let store = app.path().app_data_dir().ok().map(|dir| dir.join("sessions.json"));
+            app.manage(session::Sessions::load(store));
+            let store = app.path().app_data_dir().ok().map(|dir| dir.join("workspaces.json"));
+            app.manage(workspace::Workspaces::load(store));
+            let store = app.path().app_data_dir().ok().map(|dir| dir.join("journal.json"));
+            app.manage(journal::Journal::load(store));
+            journal::start(app.handle().clone());
+
+            // `xml-reader big.xml:123456`
+            let args: Vec<String> = std::env::args().skip(1).collect();
+            for request in cli::requests(&args, &std::env::current_dir().unwrap_or_default()) {
+                cli::open(app.handle(), "main", request);
+            }
+
+            // `some_export_tool | xml-reader -`
+            if std::env::args().skip(1).any(|a| a == "-") {
+                let handle = app.handle().clone();
+                std::thread::spawn(move || xml_ops::ingest_stdin(handle));
+            }
             Ok(())
         })
+        .on_window_event(|window, event| {
+            use tauri::Manager;
+            if let tauri::WindowEvent::Destroyed = event {
+                windows::closed(window.app_handle(), window.label());
+            }
+        })
         .invoke_handler(tauri::generate_handler![
+            about::get_app_info,
             xml_ops::open_file,
+            xml_ops::open_files,
+            xml_ops::open_from_text,
+            xml_ops::open_snapshot,
+            xml_ops::close_file,
+            xml_ops::get_stdin_file,
+            cli::take_open_requests,
+            windows::new_window,
+            windows::take_window_file,
             xml_ops::read_chunk,
+            xml_ops::read_lines,
+            xml_ops::read_hex,
             xml_ops::search_node,
             xml_ops::cancel_search,
+            ops::cancel_operation,
+            logging::get_recent_logs,
             xml_ops::get_first_child,
             xml_ops::get_last_child,
             xml_ops::resolve_xpath,
             xml_ops::find_parent,
-            xml_ops::read_element_at_offset
+            xml_ops::build_index,
+            xml_ops::read_element_at_offset,
+            xml_ops::execute_batch,
+            xml_ops::read_element_range,
+            xml_ops::profile_operations,
+            xml_ops::set_mmap_mode,
+            xml_ops::set_element_scan_limit,
+            xml_ops::set_reader_limits,
+            xml_ops::set_memory_settings,
+            xml_ops::get_memory_settings,
+            xml_ops::set_parsing_mode,
+            settings::get_settings,
+            settings::set_settings,
+            settings::export_settings,
+            settings::import_settings,
+            catalog::set_catalogs,
+            catalog::resolve_external_id,
+            xml_ops::set_s3_credentials,
+            preview::get_preview,
+            preview::get_document_info,
+            preview::render_svg_preview,
+            query::run_query,
+            script::run_script,
+            format::format_element,
+            format::minify_element,
+            format::element_to_json,
+            format::get_element_with_context,
+            diff::diff_files,
+            diff::diff_elements,
+            edit::set_attribute,
+            edit::set_element_text,
+            edit::delete_element,
+            edit::insert_element,
+            edit::replace_all,
+            edit::rename_tag,
+            edit::sort_children,
+            edit::apply_csv_edits,
+            edit::comment_out_element,
+            edit::uncomment_element,
+            edit::begin_edit,
+            edit::stage_change,
+            edit::preview_changes,
+            edit::commit_edit,
+            edit::abort_edit,
+            save::set_keep_backup,
+            export::export_element,
+            export::export_table,
+            export::export_matches,
+            export::extract_matching,
+            export::export_redacted,
+            export::convert_to_ndjson,
+            export::extract_binary,
+            export::reencode_file,
+            export::flatten,
+            export::canonicalize,
+            export::split_file,
+            export::cancel_split,
+            identity::get_file_identity,
+            recent::get_recent,
+            recent::pin_recent,
+            recent::remove_recent,
+            recent::update_recent,
+            session::save_session,
+            session::load_session,
+            journal::journal_view,
+            journal::get_previous_session,
+            journal::restore_previous_session,
+            journal::discard_previous_session,
+            workspace::create_workspace,
+            workspace::delete_workspace,
+            workspace::list_workspaces,
+            workspace::list_workspace,
+            workspace::add_file,
+            workspace::remove_file,
+            workspace::add_bookmark,
+            workspace::remove_bookmark,
+            workspace::open_workspace,
+            schema::infer_schema,
+            validate::check_wellformed,
+            validate::validate_schematron,
+            validate::find_duplicate_ids,
+            validate::check_references,
+            validate::audit_encoding,
+            validate::check_namespaces,
+            validate::find_tag_mismatches,
+            validate::scan_for_errors,
+            geo::gpx_summary,
+            stats::get_statistics,
+            stats::attribute_histogram,
+            stats::size_breakdown,
+            stats::structure_fingerprint,
+            stats::tag_timeline,
+            stats::text_stats,
+            watch::watch_file,
+            watch::unwatch_file,
+            watch::follow_file,
+            watch::unfollow_file,
+            xmi::list_packages,
+            xmi::list_elements,
+            xmi::get_element_details,
+            xmi::list_connectors
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app, event| {
+            cli::on_run_event(app, &event);
+            if let tauri::RunEvent::Exit = event {
+                journal::close(app);
+            }
+        });
 }
@@ -0,0 +1,127 @@
+use crate::error::XmlReaderError;
+use crate::xml_ops::blocking;
+use anyhow::Result;
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use tracing::Level;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+
+/// Daily log files kept; older ones are deleted as new ones start.
+const LOG_FILES_KEPT: usize = 7;
+const LOG_PREFIX: &str = "xml-reader";
+
+// Where logs are written, set once by `init`
+static LOG_DIR: OnceLock<PathBuf> = OnceLock::new();
+// Flushes the background writer when the app exits
+static GUARD: OnceLock<WorkerGuard> = OnceLock::new();
+
+/// Write `tracing` events of level INFO and up to a log file in `dir`,
+/// starting a new file every day. Without a directory nothing is logged.
+pub fn init(dir: Option<PathBuf>) {
+    let Some(dir) = dir else {
+        return;
+    };
+    // Pruning old files complains unless the directory exists first
+    let _ = std::fs::create_dir_all(&dir);
+    let appender = RollingFileAppender::builder()
+        .rotation(Rotation::DAILY)
+        .filename_prefix(LOG_PREFIX)
+        .filename_suffix("log")
+        .max_log_files(LOG_FILES_KEPT)
+        .build(&dir);
+    let Ok(appender) = appender else {
+        return;
+    };
+    let (writer, guard) = tracing_appender::non_blocking(appender);
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer(writer)
+        .with_ansi(false)
+        .with_max_level(Level::INFO)
+        .finish();
+    if tracing::subscriber::set_global_default(subscriber).is_ok() {
+        let _ = GUARD.set(guard);
+        let _ = LOG_DIR.set(dir);
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct LogEntry {
+    time: String,
+    level: String,
+    target: String,
+    message: String,
+}
+
+/// Parse a line as written by `init`: time, level, `target:` and message.
+fn parse_line(line: &str) -> Option<LogEntry> {
+    let (time, rest) = line.split_once(' ')?;
+    let rest = rest.trim_start();
+    let (level, rest) = rest.split_once(' ')?;
+    level.parse::<Level>().ok()?;
+    let (target, message) = rest.split_once(": ").unwrap_or(("", rest));
+    Some(LogEntry {
+        time: time.to_string(),
+        level: level.to_string(),
+        target: target.to_string(),
+        message: message.to_string(),
+    })
+}
+
+/// The last `limit` log entries at `level` or more severe, newest first,
+/// across the kept log files.
+fn recent_logs(level: Level, limit: usize) -> Result<Vec<LogEntry>> {
+    let Some(dir) = LOG_DIR.get() else {
+        return Ok(Vec::new());
+    };
+    let mut files: Vec<PathBuf> = std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.file_name().is_some_and(|n| n.to_string_lossy().starts_with(LOG_PREFIX)))
+        .collect();
+    // Dated names sort oldest first
+    files.sort();
+
+    let mut entries = Vec::new();
+    for path in files.iter().rev() {
+        let mut lines = Vec::new();
+        for line in BufReader::new(std::fs::File::open(path)?).lines() {
+            lines.push(line?);
+        }
+        // Lines without a level continue the message above them
+        let mut continued: Vec<String> = Vec::new();
+        for line in lines.into_iter().rev() {
+            match parse_line(&line) {
+                Some(mut entry) => {
+                    for more in continued.drain(..).rev() {
+                        entry.message.push('\n');
+                        entry.message.push_str(&more);
+                    }
+                    // `Level` orders more verbose levels as greater
+                    if entry.level.parse::<Level>().is_ok_and(|l| l <= level) {
+                        entries.push(entry);
+                        if entries.len() == limit {
+                            return Ok(entries);
+                        }
+                    }
+                }
+                None => continued.push(line),
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// Recent backend log entries to attach to a bug report: slow operations,
+/// errors, cancellations. `level` is the least severe level included
+/// (`"error"`, `"warn"`, `"info"`), INFO when left out.
+#[tauri::command]
+pub async fn get_recent_logs(level: Option<String>, limit: usize) -> Result<Vec<LogEntry>, XmlReaderError> {
+    let level = match level {
+        Some(level) => level
+            .parse::<Level>()
+            .map_err(|_| anyhow::anyhow!("Unknown log level {}", level))?,
+        None => Level::INFO,
+    };
+    blocking(move || recent_logs(level, limit).map_err(XmlReaderError::from)).await
+}
@@ -0,0 +1,220 @@
+use crate::error::XmlReaderError;
+use crate::xml_ops::blocking;
+use std::cell::{Cell, RefCell};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tauri::{Emitter, WebviewWindow};
+
+/// Chosen by the frontend when it starts a long command, so it can match
+/// `op-progress` events to it and cancel it before the command returns.
+pub type OpId = u64;
+
+/// An operation running now, and the window that started it.
+struct Running {
+    id: Option<OpId>,
+    window: String,
+    kind: &'static str,
+    cancelled: Arc<AtomicBool>,
+}
+
+static RUNNING: Mutex<Vec<Running>> = Mutex::new(Vec::new());
+
+/// Interactive reads (chunks, hex) running now; operations pause their
+/// reads while there are any, see `yield_to_foreground`.
+static FOREGROUND: AtomicUsize = AtomicUsize::new(0);
+/// Longest an operation waits for interactive reads in one go, so a view
+/// that keeps reading can't stall it completely.
+const MAX_YIELD: Duration = Duration::from_millis(50);
+/// Work an operation does between two yields, so it steps aside now and
+/// then rather than on every event it reads.
+const YIELD_INTERVAL: Duration = Duration::from_millis(25);
+/// Operations taking longer are logged, see `logging`.
+const SLOW_OPERATION: Duration = Duration::from_secs(2);
+
+thread_local! {
+    // Cancel flag of the operation running on this thread, see `check`
+    static CURRENT: RefCell<Option<Arc<AtomicBool>>> = const { RefCell::new(None) };
+    // When this thread last yielded, see `yield_to_foreground`
+    static LAST_YIELD: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+#[derive(Clone, serde::Serialize)]
+struct OpProgress {
+    op_id: Option<OpId>,
+    kind: &'static str,
+    pct: u64,
+}
+
+/// A long-running command: export, validation, diff, stats, search, line
+/// counting, edits, queries. Every `XmlReader` created while it runs stops with `Cancelled`
+/// once `cancel_operation` is called with its id. Its events go only to the
+/// window that started it.
+pub struct Operation {
+    window: WebviewWindow,
+    id: Option<OpId>,
+    kind: &'static str,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Operation {
+    /// Report progress (0-100), as `op-progress` and as the `<kind>-progress`
+    /// event the command sent before operations existed.
+    pub fn progress(&self, pct: u64) {
+        self.emit(&format!("{}-progress", self.kind), pct);
+        let progress = OpProgress {
+            op_id: self.id,
+            kind: self.kind,
+            pct,
+        };
+        self.emit("op-progress", progress);
+    }
+
+    /// Send `event` to the window that started the operation.
+    pub fn emit<S: serde::Serialize + Clone>(&self, event: &str, payload: S) {
+        let _ = self.window.emit_to(self.window.label(), event, payload);
+    }
+}
+
+impl Drop for Operation {
+    fn drop(&mut self) {
+        RUNNING
+            .lock()
+            .unwrap()
+            .retain(|running| !Arc::ptr_eq(&running.cancelled, &self.cancelled));
+    }
+}
+
+/// Run a command's work on the blocking pool as operation `op_id` (if the
+/// frontend gave one), started from `window`. `kind` names its progress
+/// event.
+pub async fn run<T, E>(
+    window: WebviewWindow,
+    op_id: Option<OpId>,
+    kind: &'static str,
+    work: impl FnOnce(&Operation) -> Result<T, E> + Send + 'static,
+) -> Result<T, E>
+where
+    T: Send + 'static,
+    E: From<anyhow::Error> + std::fmt::Display + Send + 'static,
+{
+    let cancelled = Arc::new(AtomicBool::new(false));
+    RUNNING.lock().unwrap().push(Running {
+        id: op_id,
+        window: window.label().to_string(),
+        kind,
+        cancelled: cancelled.clone(),
+    });
+    let op = Operation {
+        window,
+        id: op_id,
+        kind,
+        cancelled: cancelled.clone(),
+    };
+    let started = Instant::now();
+    let flag = cancelled.clone();
+    let result = blocking(move || enter(Some(flag), || work(&op))).await;
+
+    let millis = started.elapsed().as_millis() as u64;
+    if cancelled.load(Ordering::SeqCst) {
+        tracing::info!(kind, millis, "operation cancelled");
+    } else if let Err(e) = &result {
+        tracing::warn!(kind, millis, "operation failed: {}", e);
+    } else if started.elapsed() >= SLOW_OPERATION {
+        tracing::info!(kind, millis, "slow operation");
+    }
+    result
+}
+
+/// Run `work` on this thread as part of the operation `cancelled` belongs
+/// to (see `current`), for operations that fan work out to other threads.
+pub fn enter<R>(cancelled: Option<Arc<AtomicBool>>, work: impl FnOnce() -> R) -> R {
+    // Pool threads are reused, so the flag must not outlive the work
+    struct Current(Option<Arc<AtomicBool>>);
+    impl Drop for Current {
+        fn drop(&mut self) {
+            CURRENT.with(|current| *current.borrow_mut() = self.0.take());
+        }
+    }
+    let previous = CURRENT.with(|current| std::mem::replace(&mut *current.borrow_mut(), cancelled));
+    let _current = Current(previous);
+    work()
+}
+
+/// Stop operation `op_id` at its next read. Returns whether it was running.
+#[tauri::command]
+pub async fn cancel_operation(op_id: OpId) -> Result<bool, XmlReaderError> {
+    Ok(cancel(|running| running.id == Some(op_id)))
+}
+
+/// Stop the operations of `kind` started from `window`, for the commands
+/// that cancel "the" search or split of a window. Returns whether any ran.
+pub fn cancel_kind(window: &str, kind: &str) -> bool {
+    cancel(|running| running.window == window && running.kind == kind)
+}
+
+/// Stop every operation started from `window`, once it's closed.
+pub fn cancel_window(window: &str) {
+    cancel(|running| running.window == window);
+}
+
+fn cancel(matches: impl Fn(&Running) -> bool) -> bool {
+    let mut found = false;
+    for running in RUNNING.lock().unwrap().iter().filter(|running| matches(running)) {
+        running.cancelled.store(true, Ordering::SeqCst);
+        found = true;
+    }
+    found
+}
+
+/// Cancel flag of the operation this thread is running, if any.
+pub fn current() -> Option<Arc<AtomicBool>> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+/// `Cancelled` if the operation this thread is running was cancelled. For
+/// loops that read without an `XmlReader`; also yields to interactive reads.
+pub fn check() -> anyhow::Result<()> {
+    match current() {
+        Some(cancelled) if cancelled.load(Ordering::Relaxed) => Err(XmlReaderError::Cancelled.into()),
+        Some(_) => {
+            yield_to_foreground();
+            Ok(())
+        }
+        None => Ok(()),
+    }
+}
+
+/// Held by an interactive command while it reads, see `foreground`.
+pub struct Foreground(());
+
+impl Drop for Foreground {
+    fn drop(&mut self) {
+        FOREGROUND.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Mark a short interactive read (the view scrolling) as running until the
+/// guard is dropped. Operations reading at the same time step aside so it
+/// isn't slowed down by their IO, so reads of unbounded length (navigation
+/// scanning for an end tag) must not hold it.
+pub fn foreground() -> Foreground {
+    FOREGROUND.fetch_add(1, Ordering::SeqCst);
+    Foreground(())
+}
+
+/// Called by operations between reads: wait while interactive reads run,
+/// for at most `MAX_YIELD`, and at most once per `YIELD_INTERVAL` of work.
+pub fn yield_to_foreground() {
+    if FOREGROUND.load(Ordering::Relaxed) == 0 {
+        return;
+    }
+    let start = Instant::now();
+    if LAST_YIELD.get().is_some_and(|last| start.duration_since(last) < YIELD_INTERVAL) {
+        return;
+    }
+    while FOREGROUND.load(Ordering::Relaxed) > 0 && start.elapsed() < MAX_YIELD {
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    LAST_YIELD.set(Some(Instant::now()));
+}
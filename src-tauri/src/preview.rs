@@ -0,0 +1,709 @@
+use crate::catalog;
+use crate::error::XmlReaderError;
+use crate::files::{FileHandle, FileId, FileRegistry};
+use crate::format::element_reader;
+use crate::io::{self, Source, XmlReader};
+use crate::ops::{self, OpId};
+use crate::xml_ops::blocking;
+use anyhow::Result;
+use quick_xml::events::attributes::Attribute;
+use quick_xml::events::{BytesEnd, BytesStart, Event};
+use quick_xml::name::QName;
+use quick_xml::Writer;
+use std::io::{Read, Seek, SeekFrom};
+use std::sync::Arc;
+use tauri::{AppHandle, Emitter, State, WebviewWindow};
+
+/// Number of top-level children included in the preview.
+const PREVIEW_CHILDREN: usize = 20;
+/// Text kept per child; longer children are cut and flagged `truncated`.
+const PREVIEW_CHILD_BYTES: u64 = 2048;
+/// Stop once this much of the file has been parsed, so a huge first child
+/// can't turn the "quick" preview into a full scan.
+const PREVIEW_SCAN_LIMIT: u64 = 16 * 1024 * 1024;
+
+#[derive(serde::Serialize, Clone)]
+pub struct PreviewChild {
+    name: String,
+    offset: u64,
+    end: u64,
+    text: String,
+    truncated: bool,
+}
+
+#[derive(serde::Serialize, Clone)]
+pub struct Preview {
+    // Everything up to and including the root start tag
+    prolog: String,
+    children: Vec<PreviewChild>,
+}
+
+/// Build the preview on a background thread and send `preview-ready` with the
+/// file id to its window once `get_preview` can return it.
+pub fn spawn(app: AppHandle, handle: Arc<FileHandle>) {
+    std::thread::spawn(move || {
+        if let Ok(preview) = build_preview(&handle) {
+            let _ = handle.preview.set(preview);
+            let _ = app.emit_to(handle.window(), "preview-ready", handle.id);
+        }
+    });
+}
+
+/// The preview, or `None` while it is still being generated.
+#[tauri::command]
+pub async fn get_preview(files: State<'_, FileRegistry>, file_id: FileId) -> Result<Option<Preview>, XmlReaderError> {
+    let handle = files.get(file_id)?;
+    Ok(handle.preview.get().cloned())
+}
+
+#[derive(serde::Serialize)]
+pub struct EntityDecl {
+    name: String,
+    /// `<!ENTITY % name ...>`, usable only inside the DTD.
+    parameter: bool,
+    /// Replacement text of an internal entity.
+    value: Option<String>,
+    public_id: Option<String>,
+    system_id: Option<String>,
+    /// Local copy of an external entity, from the XML catalogs.
+    resolved: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct DocType {
+    offset: u64,
+    end: u64,
+    /// Name of the root element the DOCTYPE declares.
+    name: String,
+    public_id: Option<String>,
+    system_id: Option<String>,
+    /// Local copy of the external DTD, from the XML catalogs.
+    resolved: Option<String>,
+    /// Everything between `[` and `]`.
+    internal_subset: Option<String>,
+    entities: Vec<EntityDecl>,
+    /// Names from `<!ELEMENT>` declarations.
+    elements: Vec<String>,
+}
+
+/// A schema named by `xsi:schemaLocation` or `xsi:noNamespaceSchemaLocation`.
+#[derive(serde::Serialize)]
+pub struct SchemaRef {
+    namespace: Option<String>,
+    location: String,
+    /// Local copy from the XML catalogs.
+    resolved: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct DocumentInfo {
+    version: Option<String>,
+    /// As written in the XML declaration.
+    declared_encoding: Option<String>,
+    /// What the file is read as: BOM, then declaration, then UTF-8.
+    encoding: String,
+    standalone: Option<bool>,
+    doctype: Option<DocType>,
+    root_name: Option<String>,
+    root_offset: Option<u64>,
+    /// Schemas the root element points to.
+    schemas: Vec<SchemaRef>,
+}
+
+/// The XML declaration, DOCTYPE (with its internal subset parsed into entity
+/// and element declarations) and root element of a file. Only the prolog is
+/// read.
+#[tauri::command]
+pub async fn get_document_info(files: State<'_, FileRegistry>, file_id: FileId) -> Result<DocumentInfo, XmlReaderError> {
+    let handle = files.get(file_id)?;
+    blocking(move || {
+        document_info(&handle).map_err(|e| XmlReaderError::in_file(&handle, e))
+    })
+    .await
+}
+
+fn document_info(handle: &FileHandle) -> Result<DocumentInfo> {
+    let mut head = Vec::with_capacity(1024);
+    handle.reader()?.take(1024).read_to_end(&mut head)?;
+    let mut info = DocumentInfo {
+        version: None,
+        declared_encoding: None,
+        encoding: io::detect_encoding(&head).name().to_string(),
+        standalone: None,
+        doctype: None,
+        root_name: None,
+        root_offset: None,
+        schemas: Vec::new(),
+    };
+
+    let mut reader = XmlReader::new(handle.reader()?, 64 * 1024);
+    let mut buf = Vec::new();
+    let text = |bytes: &[u8]| String::from_utf8_lossy(bytes).into_owned();
+    loop {
+        buf.clear();
+        let pos_before = reader.position();
+        match reader.read_event_into(&mut buf)? {
+            Event::Decl(decl) => {
+                info.version = decl.version().ok().map(|v| text(&v));
+                info.declared_encoding = decl.encoding().and_then(|e| e.ok()).map(|e| text(&e));
+                info.standalone = decl.standalone().and_then(|s| s.ok()).map(|s| s.as_ref() == b"yes");
+            }
+            Event::DocType(e) => info.doctype = Some(parse_doctype(&text(&e), pos_before, reader.position())),
+            Event::Start(e) | Event::Empty(e) => {
+                info.root_name = Some(text(e.name().as_ref()));
+                info.root_offset = Some(pos_before);
+                info.schemas = schema_refs(&e);
+                break;
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(info)
+}
+
+fn schema_refs(root: &BytesStart) -> Vec<SchemaRef> {
+    let schema = |namespace: Option<&str>, location: &str| SchemaRef {
+        resolved: catalog::resolve_uri(location)
+            .or_else(|| namespace.and_then(catalog::resolve_uri))
+            .or_else(|| catalog::resolve(None, Some(location))),
+        namespace: namespace.map(str::to_string),
+        location: location.to_string(),
+    };
+    let mut schemas = Vec::new();
+    for attr in root.attributes().with_checks(false).flatten() {
+        let value = String::from_utf8_lossy(&attr.value).into_owned();
+        match attr.key.local_name().as_ref() {
+            // Pairs of namespace and location
+            b"schemaLocation" => {
+                let words: Vec<&str> = value.split_whitespace().collect();
+                schemas.extend(words.chunks_exact(2).map(|pair| schema(Some(pair[0]), pair[1])));
+            }
+            b"noNamespaceSchemaLocation" => schemas.extend(value.split_whitespace().map(|location| schema(None, location))),
+            _ => {}
+        }
+    }
+    schemas
+}
+
+/// Parse the text quick-xml gives for `<!DOCTYPE ...>`: everything between
+/// `<!DOCTYPE` and the final `>`.
+fn parse_doctype(text: &str, offset: u64, end: u64) -> DocType {
+    let (external, subset) = match (text.find('['), text.rfind(']')) {
+        (Some(open), Some(close)) if open < close => (&text[..open], Some(&text[open + 1..close])),
+        _ => (text, None),
+    };
+    let mut words = quoted_words(external).into_iter();
+    let name = words.next().unwrap_or_default();
+    let (public_id, system_id) = external_id(&mut words);
+
+    let mut entities = Vec::new();
+    let mut elements = Vec::new();
+    for decl in subset.map(markup_decls).unwrap_or_default() {
+        if let Some(rest) = decl.strip_prefix("<!ENTITY") {
+            let mut words = quoted_words(rest).into_iter().peekable();
+            let parameter = words.next_if(|w| w == "%").is_some();
+            let Some(name) = words.next() else { continue };
+            let value = words.next_if(|w| w != "SYSTEM" && w != "PUBLIC");
+            let (public_id, system_id) = external_id(&mut words);
+            entities.push(EntityDecl {
+                name,
+                parameter,
+                value,
+                resolved: catalog::resolve(public_id.as_deref(), system_id.as_deref()),
+                public_id,
+                system_id,
+            });
+        } else if let Some(rest) = decl.strip_prefix("<!ELEMENT") {
+            elements.extend(rest.split_whitespace().next().map(str::to_string));
+        }
+    }
+
+    DocType {
+        offset,
+        end,
+        name,
+        resolved: catalog::resolve(public_id.as_deref(), system_id.as_deref()),
+        public_id,
+        system_id,
+        internal_subset: subset.map(str::to_string),
+        entities,
+        elements,
+    }
+}
+
+/// `PUBLIC "pub" "sys"` or `SYSTEM "sys"` as (public, system) ids.
+fn external_id(words: &mut impl Iterator<Item = String>) -> (Option<String>, Option<String>) {
+    match words.next().as_deref() {
+        Some("PUBLIC") => (words.next(), words.next()),
+        Some("SYSTEM") => (None, words.next()),
+        _ => (None, None),
+    }
+}
+
+/// Whitespace-separated words, with a quoted literal (which may contain
+/// spaces, `<` and `>`) as one word without its quotes.
+fn quoted_words(text: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut rest = text.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let (word, len) = if c == '"' || c == '\'' {
+            match rest[1..].find(c) {
+                Some(close) => (&rest[1..close + 1], close + 2),
+                None => (&rest[1..], rest.len()),
+            }
+        } else {
+            let len = rest.find(|c: char| c.is_whitespace() || c == '"' || c == '\'').unwrap_or(rest.len());
+            (rest[..len].trim_end_matches('>'), len)
+        };
+        words.push(word.to_string());
+        rest = rest[len..].trim_start();
+    }
+    words.retain(|w| !w.is_empty());
+    words
+}
+
+/// The `<!...>` declarations of an internal subset, skipping comments, PIs
+/// and parameter-entity references, and not ending a declaration at a `>`
+/// inside a quoted literal.
+fn markup_decls(subset: &str) -> Vec<&str> {
+    let mut decls = Vec::new();
+    let mut rest = subset;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start..];
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.split_once("-->").map_or("", |(_, after)| after);
+            continue;
+        }
+        if let Some(pi) = rest.strip_prefix("<?") {
+            rest = pi.split_once("?>").map_or("", |(_, after)| after);
+            continue;
+        }
+        let mut quote = None;
+        let end = rest.char_indices().skip(1).find_map(|(i, c)| {
+            match (quote, c) {
+                (None, '"' | '\'') => quote = Some(c),
+                (Some(q), c) if c == q => quote = None,
+                (None, '>') => return Some(i + 1),
+                _ => {}
+            }
+            None
+        });
+        let end = end.unwrap_or(rest.len());
+        decls.push(&rest[..end]);
+        rest = &rest[end..];
+    }
+    decls
+}
+
+/// Start of the document element.
+pub fn root_offset(handle: &FileHandle) -> Result<u64> {
+    let mut reader = XmlReader::new(handle.reader()?, 64 * 1024);
+    let mut buf = Vec::new();
+    loop {
+        buf.clear();
+        let pos_before = reader.position();
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(_) | Event::Empty(_) => return Ok(pos_before),
+            Event::Eof => return Err(anyhow::anyhow!("{} has no root element", handle.path)),
+            _ => {}
+        }
+    }
+}
+
+fn build_preview(handle: &FileHandle) -> Result<Preview> {
+    let mut reader = XmlReader::new(handle.reader()?, 64 * 1024);
+    reader.check_end_names(false);
+    let mut file = handle.reader()?;
+
+    let mut buf = Vec::new();
+    let mut depth = 0u32;
+    let mut prolog_end = None;
+    let mut child: Option<(String, u64)> = None;
+    let mut children = Vec::new();
+
+    while children.len() < PREVIEW_CHILDREN && reader.position() < PREVIEW_SCAN_LIMIT {
+        let pos_before = reader.position();
+        let event = reader.read_event_into(&mut buf);
+        let pos_after = reader.position();
+
+        match event {
+            Ok(Event::Start(ref e)) => {
+                depth += 1;
+                if depth == 1 {
+                    prolog_end = Some(pos_after);
+                } else if depth == 2 {
+                    child = Some((String::from_utf8_lossy(e.name().as_ref()).to_string(), pos_before));
+                }
+            }
+            Ok(Event::End(_)) => {
+                if depth == 2 {
+                    if let Some((name, start)) = child.take() {
+                        children.push(read_child(&mut file, name, start, pos_after)?);
+                    }
+                }
+                depth = depth.saturating_sub(1);
+                if depth == 0 {
+                    break;
+                }
+            }
+            Ok(Event::Empty(ref e)) => {
+                if depth == 0 {
+                    prolog_end = Some(pos_after);
+                    break;
+                }
+                if depth == 1 {
+                    let name = String::from_utf8_lossy(e.name().as_ref()).to_string();
+                    children.push(read_child(&mut file, name, pos_before, pos_after)?);
+                }
+            }
+            Ok(Event::Eof) | Err(_) => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    let prolog_len = prolog_end.unwrap_or(0).min(PREVIEW_SCAN_LIMIT);
+    let mut prolog = vec![0u8; prolog_len as usize];
+    file.seek(SeekFrom::Start(0))?;
+    file.read_exact(&mut prolog)?;
+
+    Ok(Preview {
+        prolog: String::from_utf8_lossy(&prolog).to_string(),
+        children,
+    })
+}
+
+fn read_child(file: &mut Source, name: String, start: u64, end: u64) -> Result<PreviewChild> {
+    let len = (end - start).min(PREVIEW_CHILD_BYTES);
+    let mut buf = vec![0u8; len as usize];
+    file.seek(SeekFrom::Start(start))?;
+    file.read_exact(&mut buf)?;
+
+    Ok(PreviewChild {
+        name,
+        offset: start,
+        end,
+        text: String::from_utf8_lossy(&buf).to_string(),
+        truncated: end - start > PREVIEW_CHILD_BYTES,
+    })
+}
+
+/// Largest SVG `render_svg_preview` returns, whatever `max_size` asks for.
+const SVG_PREVIEW_MAX_BYTES: usize = 32 * 1024 * 1024;
+/// Elements left out of SVG previews with everything inside them: scripts,
+/// and anything that embeds or fetches another document.
+const SVG_DROPPED: [&str; 9] = [
+    "script",
+    "foreignObject",
+    "iframe",
+    "object",
+    "embed",
+    "audio",
+    "video",
+    "handler",
+    "listener",
+];
+/// Raster formats an SVG preview may embed as `data:` URLs.
+const SVG_DATA_IMAGES: [&str; 4] = ["data:image/png", "data:image/jpeg", "data:image/gif", "data:image/webp"];
+
+#[derive(serde::Serialize)]
+pub struct SvgPreview {
+    svg: String,
+    /// Elements, attributes and stylesheets left out as unsafe.
+    removed: u64,
+}
+
+/// The `<svg>` element at `offset` (the root element when left out, for SVG
+/// files) as a standalone document that is safe to render in the preview
+/// pane: scripts, event handlers, embedded documents and references to
+/// anything outside the SVG itself are removed, as are comments and elements
+/// and attributes of other vocabularies (editor metadata). Fails if the
+/// element is over `max_size` bytes.
+#[tauri::command]
+pub async fn render_svg_preview(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    offset: Option<u64>,
+    max_size: usize,
+    op_id: Option<OpId>,
+) -> Result<SvgPreview, XmlReaderError> {
+    let handle = files.get(file_id)?;
+    ops::run(window, op_id, "preview", move |_| {
+        handle
+            .consistent(|| svg_preview(&handle, offset, max_size.min(SVG_PREVIEW_MAX_BYTES)))
+            .map_err(|e| XmlReaderError::in_file(&handle, e))
+    })
+    .await
+}
+
+fn svg_preview(handle: &FileHandle, offset: Option<u64>, max_size: usize) -> Result<SvgPreview> {
+    let offset = match offset {
+        Some(offset) => offset,
+        None => root_offset(handle)?,
+    };
+    let mut reader = element_reader(handle, offset)?;
+    let mut writer = Writer::new(Vec::new());
+    let mut buf = Vec::new();
+    let mut removed = 0;
+    // Prefix of the `<svg>` element, if any, which the output drops
+    let mut prefix: Option<Vec<u8>> = None;
+    let mut depth = 0u32;
+    // Depth of the element being left out
+    let mut skipping: Option<u32> = None;
+    // Depth of the `<style>` element whose CSS is being copied
+    let mut style: Option<u32> = None;
+
+    loop {
+        buf.clear();
+        let event = reader.read_event_into(&mut buf)?;
+        if reader.position() - offset > max_size as u64 {
+            return Err(anyhow::anyhow!("The SVG at offset {} is larger than {} bytes", offset, max_size));
+        }
+        let (e, empty) = match event {
+            Event::Start(e) => (e, false),
+            Event::Empty(e) => (e, true),
+            Event::End(e) => {
+                depth -= 1;
+                if skipping == Some(depth) {
+                    skipping = None;
+                } else if skipping.is_none() {
+                    let name = String::from_utf8_lossy(svg_name(e.name().as_ref(), &prefix).unwrap_or_default()).into_owned();
+                    writer.write_event(Event::End(BytesEnd::new(name)))?;
+                }
+                if style == Some(depth) {
+                    style = None;
+                }
+                if depth == 0 {
+                    break;
+                }
+                continue;
+            }
+            Event::Text(e) if skipping.is_none() => {
+                // Checked as the renderer will read it, with references expanded
+                if style.is_some() && !e.unescape().is_ok_and(|css| safe_css(&css)) {
+                    removed += 1;
+                } else {
+                    writer.write_event(Event::Text(e))?;
+                }
+                continue;
+            }
+            Event::CData(e) if skipping.is_none() => {
+                if style.is_some() && !safe_css(&String::from_utf8_lossy(&e)) {
+                    removed += 1;
+                } else {
+                    writer.write_event(Event::CData(e))?;
+                }
+                continue;
+            }
+            Event::Eof => return Err(anyhow::anyhow!("Element is not closed before the end of the file")),
+            _ => continue,
+        };
+
+        if depth == 0 {
+            let name = e.name();
+            if name.local_name().as_ref() != b"svg" {
+                return Err(anyhow::anyhow!("No <svg> element at offset {}", offset));
+            }
+            prefix = name.prefix().map(|p| p.as_ref().to_vec());
+        }
+        if skipping.is_none() {
+            match svg_element(&e, &prefix, depth == 0) {
+                Some((out, dropped)) => {
+                    removed += dropped;
+                    if out.local_name().as_ref() == b"style" {
+                        style = Some(depth);
+                    }
+                    writer.write_event(if empty { Event::Empty(out) } else { Event::Start(out) })?;
+                }
+                None => {
+                    if SVG_DROPPED.iter().any(|d| d.as_bytes() == e.local_name().as_ref()) || is_href_animation(&e) {
+                        removed += 1;
+                    }
+                    if !empty {
+                        skipping = Some(depth);
+                    }
+                }
+            }
+        }
+        if !empty {
+            depth += 1;
+        } else if depth == 0 {
+            break;
+        }
+    }
+    Ok(SvgPreview {
+        svg: String::from_utf8_lossy(&writer.into_inner()).into_owned(),
+        removed,
+    })
+}
+
+/// `name` without the `<svg>` element's prefix, or `None` for an element of
+/// another vocabulary.
+fn svg_name<'a>(name: &'a [u8], prefix: &Option<Vec<u8>>) -> Option<&'a [u8]> {
+    match prefix {
+        Some(prefix) => name.strip_prefix(prefix.as_slice())?.strip_prefix(b":"),
+        None => (!name.contains(&b':')).then_some(name),
+    }
+}
+
+/// An animation that would change where a link points, which a URL check on
+/// the `href` itself can't see.
+fn is_href_animation(e: &BytesStart) -> bool {
+    matches!(e.local_name().as_ref(), b"set" | b"animate")
+        && e.attributes().flatten().any(|a| {
+            a.key.as_ref() == b"attributeName" && matches!(a.value.as_ref(), b"href" | b"xlink:href")
+        })
+}
+
+/// The start tag to write for `e` and the number of attributes dropped as
+/// unsafe, or `None` to leave the element out.
+fn svg_element(e: &BytesStart, prefix: &Option<Vec<u8>>, root: bool) -> Option<(BytesStart<'static>, u64)> {
+    let qname = e.name();
+    let name = svg_name(qname.as_ref(), prefix)?;
+    if SVG_DROPPED.iter().any(|d| d.as_bytes() == name) || is_href_animation(e) {
+        return None;
+    }
+    let mut out = BytesStart::new(String::from_utf8_lossy(name).into_owned());
+    if root {
+        out.push_attribute(("xmlns", "http://www.w3.org/2000/svg"));
+        out.push_attribute(("xmlns:xlink", "http://www.w3.org/1999/xlink"));
+    }
+    let mut dropped = 0;
+    for attr in e.attributes().with_checks(false).flatten() {
+        let key = attr.key.as_ref();
+        // Attributes in the SVG namespace lose their prefix, like elements
+        let (local, out_key) = match key.split(|&b| b == b':').collect::<Vec<_>>()[..] {
+            [local] if key != b"xmlns" => (local, key),
+            [p, local] if prefix.as_deref() == Some(p) => (local, local),
+            [p, local] if p == b"xlink" || p == b"xml" => (local, key),
+            // Namespace declarations (the root gets its own) and other vocabularies
+            _ => continue,
+        };
+        // Presentation attributes (`fill`, `filter`, `mask`...) take `url()`
+        // like CSS does, so every value gets the CSS check
+        let value = attr.unescape_value().map(|v| v.to_ascii_lowercase());
+        let safe = match value.as_deref().map(str::trim) {
+            Err(_) => false,
+            Ok(_) if local.len() > 2 && local[..2].eq_ignore_ascii_case(b"on") => false,
+            Ok(value) if local == b"href" => {
+                value.starts_with('#') || SVG_DATA_IMAGES.iter().any(|image| value.starts_with(image))
+            }
+            Ok(value) => safe_css(value),
+        };
+        if !safe {
+            dropped += 1;
+            continue;
+        }
+        out.push_attribute(Attribute {
+            key: QName(out_key),
+            value: attr.value.clone(),
+        });
+    }
+    Some((out, dropped))
+}
+
+/// CSS that fetches nothing and runs nothing: no `@import`, no `url()` but
+/// to fragments of the SVG itself, no script. CSS escapes are decoded first,
+/// so `\75 rl(` is caught as `url(`.
+fn safe_css(css: &str) -> bool {
+    let css = css_unescape(css).to_ascii_lowercase();
+    if ["@import", "expression(", "image-set(", "javascript:"].iter().any(|bad| css.contains(bad)) {
+        return false;
+    }
+    css.match_indices("url(")
+        .all(|(i, _)| css[i + 4..].trim_start().trim_start_matches(['"', '\'']).starts_with('#'))
+}
+
+/// `css` with its backslash escapes decoded: `\75 ` (hex code point, one
+/// optional space after it) and `\u` (the character itself).
+fn css_unescape(css: &str) -> String {
+    let mut out = String::with_capacity(css.len());
+    let mut chars = css.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        let mut hex = String::new();
+        while hex.len() < 6 && chars.peek().is_some_and(|c| c.is_ascii_hexdigit()) {
+            hex.push(chars.next().unwrap());
+        }
+        if hex.is_empty() {
+            out.extend(chars.next());
+            continue;
+        }
+        if chars.peek().is_some_and(|c| c.is_ascii_whitespace()) {
+            chars.next();
+        }
+        let code = u32::from_str_radix(&hex, 16).unwrap_or(0);
+        out.push(char::from_u32(code).filter(|&c| c != '\0').unwrap_or(char::REPLACEMENT_CHARACTER));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The preview of the root `<svg>` of `content`, and how much it left out.
+    fn preview(name: &str, content: &str) -> (String, u64) {
+        let path = std::env::temp_dir().join(format!("xml-reader-svg-{}-{}.svg", std::process::id(), name));
+        std::fs::write(&path, content).unwrap();
+        let handle = FileRegistry::default().open(path.to_str().unwrap()).unwrap();
+        let result = svg_preview(&handle, None, SVG_PREVIEW_MAX_BYTES);
+        drop(handle);
+        let _ = std::fs::remove_file(&path);
+        let SvgPreview { svg, removed } = result.unwrap();
+        // Past the namespaces the root always gets
+        let root = "<svg xmlns=\"http://www.w3.org/2000/svg\" xmlns:xlink=\"http://www.w3.org/1999/xlink\"";
+        (svg.strip_prefix(root).unwrap().to_string(), removed)
+    }
+
+    #[test]
+    fn svg_preview_drops_event_handlers() {
+        let svg = r#"<svg onload="alert(1)"><rect ONCLICK="x()" on="1" width="2"/></svg>"#;
+        assert_eq!(preview("handlers", svg), (r#"><rect on="1" width="2"/></svg>"#.to_string(), 2));
+    }
+
+    #[test]
+    fn svg_preview_keeps_only_local_links() {
+        let svg = r##"<svg><a href="https://example.com/"><use xlink:href="#shape"/></a><image href=" data:image/png;base64,AA"/><image xlink:href="file:///etc/passwd"/><a href="JavaScript:x()"/></svg>"##;
+        assert_eq!(
+            preview("links", svg),
+            (r##"><a><use xlink:href="#shape"/></a><image href=" data:image/png;base64,AA"/><image/><a/></svg>"##.to_string(), 3)
+        );
+        // Nor can an animation point a link elsewhere
+        let svg = r##"<svg><a href="#a"><set attributeName="href" to="https://example.com/"/></a></svg>"##;
+        assert_eq!(preview("animated-link", svg), (r##"><a href="#a"></a></svg>"##.to_string(), 1));
+    }
+
+    #[test]
+    fn svg_preview_checks_css_urls() {
+        let svg = r#"<svg><rect fill="url(#g)" filter="url(https://example.com/f.svg#f)" style="background: URL( 'x.png' )"/></svg>"#;
+        assert_eq!(preview("css-attributes", svg), (r#"><rect fill="url(#g)"/></svg>"#.to_string(), 2));
+        let svg = "<svg><style>rect { fill: url(#g) }</style><style>@import 'x.css';</style>\
+                   <style><![CDATA[a { background: url(x.png) }]]></style></svg>";
+        assert_eq!(preview("css-styles", svg), ("><style>rect { fill: url(#g) }</style><style></style><style></style></svg>".to_string(), 2));
+    }
+
+    #[test]
+    fn svg_preview_decodes_css_escapes() {
+        assert!(!safe_css(r"background: \75 rl(x.png)"));
+        assert!(!safe_css(r"background: \000075rl(x.png)"));
+        assert!(!safe_css(r"background: u\rl(x.png)"));
+        assert!(!safe_css(r"\40 import 'x.css'"));
+        assert!(safe_css(r"fill: \75 rl(#g)"));
+        assert_eq!(css_unescape(r"\41 B\\\0"), "AB\\\u{fffd}");
+        // The same through an entity in an attribute
+        let svg = r#"<svg><rect style="fill: \75 rl(x.png)"/><rect style="fill: &#x75;rl(x.png)"/></svg>"#;
+        assert_eq!(preview("css-escapes", svg), ("><rect/><rect/></svg>".to_string(), 2));
+    }
+
+    #[test]
+    fn svg_preview_drops_scripts_and_embedded_documents() {
+        let svg = r#"<svg xmlns:inkscape="urn:inkscape" inkscape:version="1"><script>alert(1)</script><foreignObject><div xmlns="http://www.w3.org/1999/xhtml"><script/></div></foreignObject><inkscape:grid/><!-- c --><g><iframe/></g></svg>"#;
+        assert_eq!(preview("scripts", svg), ("><g></g></svg>".to_string(), 3));
+    }
+}
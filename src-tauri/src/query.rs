@@ -0,0 +1,656 @@
+use crate::error::XmlReaderError;
+use crate::files::{FileHandle, FileId, FileRegistry};
+use crate::io::XmlReader;
+use crate::ops::{self, OpId};
+use anyhow::Result;
+use quick_xml::events::{BytesStart, Event};
+use std::collections::HashMap;
+use tauri::{State, WebviewWindow};
+
+/// Largest page `run_query` returns in one call.
+pub const QUERY_MAX_LIMIT: usize = 10_000;
+/// Text kept per match for element string-values.
+const QUERY_VALUE_BYTES: usize = 1024;
+
+#[derive(serde::Serialize)]
+pub struct QueryMatch {
+    pub offset: u64,
+    // End of the element; `None` for attribute and text matches
+    pub end: Option<u64>,
+    pub xpath: String,
+    pub name: String,
+    pub value: String,
+    pub truncated: bool,
+}
+
+#[derive(serde::Serialize)]
+pub struct QueryResult {
+    pub matches: Vec<QueryMatch>,
+    pub has_more: bool,
+    // Known once the whole file was scanned: always for `count(...)`, and for
+    // the last page of a path
+    total: Option<u64>,
+}
+
+/// Evaluate an XPath 1.0 subset over the whole file in a single streaming pass
+/// and return matches `skip..skip + limit` in document order.
+///
+/// Supported: absolute location paths with `/` and `//` steps, name tests and
+/// `*`, a final `@name`, `@*` or `text()` step, and predicates made of
+/// positions (`[2]`), attribute tests (`@a`, `@a='v'`, `@a!='v'`),
+/// `contains(@a, 'v')`, `starts-with(@a, 'v')` and `not(...)`, joined with
+/// `and`/`or`. `count(path)` returns only the total. Predicates on child
+/// elements, `last()` and reverse axes need the rest of the document and are
+/// rejected.
+#[tauri::command]
+pub async fn run_query(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    expr: String,
+    skip: usize,
+    limit: usize,
+    op_id: Option<OpId>,
+) -> Result<QueryResult, XmlReaderError> {
+    let handle = files.get(file_id)?;
+    ops::run(window, op_id, "query", move |_| {
+        run_query_internal(&handle, &expr, skip, limit.min(QUERY_MAX_LIMIT))
+            .map_err(|e| XmlReaderError::in_file(&handle, e))
+    })
+    .await
+}
+
+pub fn run_query_internal(handle: &FileHandle, expr: &str, skip: usize, limit: usize) -> Result<QueryResult> {
+    let query = Parser::new(expr).parse()?;
+    let file = handle.reader()?;
+    let mut reader = XmlReader::scan(file);
+    reader.check_end_names(false);
+    reader.set_strict(handle.strict());
+
+    // The document node: steps[0] applies to its children, or to everything
+    // after a leading `//`
+    let mut stack = vec![Frame {
+        states: vec![0],
+        descendant: if query.steps[0].axis == Axis::Descendant { vec![0] } else { Vec::new() },
+        counters: HashMap::new(),
+        name: String::new(),
+        result: None,
+    }];
+    let complete = query.steps.len();
+    let wanted = if query.count { 0 } else { skip + limit };
+    let mut matches = Vec::new();
+    let mut found = 0usize;
+    let mut buf = Vec::new();
+
+    let finished = loop {
+        // Stop once a match past the page proves there are more, and every
+        // match on the page has its end and value
+        if !query.count && found > wanted && stack.iter().all(|f| f.result.is_none()) {
+            break false;
+        }
+        buf.clear();
+        let pos_before = reader.position();
+        let event = reader.read_event_into(&mut buf)?;
+        let pos_after = reader.position();
+
+        match &event {
+            Event::Start(e) | Event::Empty(e) => {
+                let parent = stack.last_mut().unwrap();
+                let states = parent.child_states(&query.steps, e)?;
+                let name = String::from_utf8_lossy(e.name().as_ref()).into_owned();
+                let xpath = format!("{}/{}", path_of(&stack), name);
+                let mut result = None;
+
+                if query.selects(&states) {
+                    match &query.target {
+                        Target::Element => {
+                            if found >= skip && found < wanted {
+                                result = Some(matches.len());
+                                let empty = matches!(event, Event::Empty(_));
+                                matches.push(QueryMatch {
+                                    offset: pos_before,
+                                    end: empty.then_some(pos_after),
+                                    xpath: xpath.clone(),
+                                    name: name.clone(),
+                                    value: String::new(),
+                                    truncated: false,
+                                });
+                            }
+                            found += 1;
+                        }
+                        Target::Attribute(test) => {
+                            for attr in e.attributes().with_checks(false) {
+                                let attr = attr?;
+                                let key = String::from_utf8_lossy(attr.key.as_ref()).into_owned();
+                                if key == "xmlns" || key.starts_with("xmlns:") || !test.matches(&key) {
+                                    continue;
+                                }
+                                if found >= skip && found < wanted {
+                                    let (value, truncated) = capped(&attr.unescape_value()?);
+                                    matches.push(QueryMatch {
+                                        offset: pos_before,
+                                        end: None,
+                                        xpath: format!("{}/@{}", xpath, key),
+                                        name: key,
+                                        value,
+                                        truncated,
+                                    });
+                                }
+                                found += 1;
+                            }
+                        }
+                        Target::Text => {}
+                    }
+                }
+
+                let mut descendant = stack.last().unwrap().descendant.clone();
+                for &k in &states {
+                    if k < complete && query.steps[k].axis == Axis::Descendant && !descendant.contains(&k) {
+                        descendant.push(k);
+                    }
+                }
+                let frame = Frame {
+                    descendant,
+                    states,
+                    counters: HashMap::new(),
+                    name,
+                    result,
+                };
+                if matches!(event, Event::Start(_)) {
+                    stack.push(frame);
+                }
+            }
+            // The document frame stays even on a stray end tag
+            Event::End(_) if stack.len() > 1 => {
+                let frame = stack.pop().unwrap();
+                if let Some(i) = frame.result {
+                    matches[i].end = Some(pos_after);
+                }
+            }
+            Event::Text(_) | Event::CData(_) if stack.len() > 1 => {
+                let text = match &event {
+                    Event::Text(e) => e.unescape().map(|t| t.into_owned()).unwrap_or_else(|_| String::from_utf8_lossy(e).into_owned()),
+                    Event::CData(e) => String::from_utf8_lossy(e).into_owned(),
+                    _ => unreachable!(),
+                };
+                for frame in &stack {
+                    if let Some(i) = frame.result {
+                        append_capped(&mut matches[i], &text);
+                    }
+                }
+
+                let parent = stack.last().unwrap();
+                if matches!(query.target, Target::Text)
+                    && query.selects(&parent.states)
+                    && !text.trim().is_empty()
+                {
+                    if found >= skip && found < wanted {
+                        let (value, truncated) = capped(&text);
+                        matches.push(QueryMatch {
+                            offset: pos_before,
+                            end: None,
+                            xpath: format!("{}/text()", path_of(&stack)),
+                            name: String::new(),
+                            value,
+                            truncated,
+                        });
+                    }
+                    found += 1;
+                }
+            }
+            Event::Eof => break true,
+            _ => {}
+        }
+    };
+
+    Ok(QueryResult {
+        matches,
+        has_more: !query.count && found > wanted,
+        total: finished.then_some(found as u64),
+    })
+}
+
+/// Element path of the innermost open element, without the document frame.
+fn path_of(stack: &[Frame]) -> String {
+    stack.iter().skip(1).map(|f| format!("/{}", f.name)).collect()
+}
+
+fn capped(text: &str) -> (String, bool) {
+    if text.len() <= QUERY_VALUE_BYTES {
+        return (text.to_string(), false);
+    }
+    let mut cut = QUERY_VALUE_BYTES;
+    while !text.is_char_boundary(cut) {
+        cut -= 1;
+    }
+    (text[..cut].to_string(), true)
+}
+
+fn append_capped(m: &mut QueryMatch, text: &str) {
+    if m.truncated {
+        return;
+    }
+    m.value.push_str(text);
+    if m.value.len() > QUERY_VALUE_BYTES {
+        let (value, _) = capped(&m.value);
+        m.value = value;
+        m.truncated = true;
+    }
+}
+
+/// An open element while scanning.
+struct Frame {
+    // k such that steps[..k] match ending at this element
+    states: Vec<usize>,
+    // Descendant steps open for everything below this element
+    descendant: Vec<usize>,
+    // Children seen so far per (step, predicate), for positional predicates
+    counters: HashMap<(usize, usize), usize>,
+    name: String,
+    // Index of this element's entry in the result page
+    result: Option<usize>,
+}
+
+impl Frame {
+    /// States of a child `e` of this frame.
+    fn child_states(&mut self, steps: &[Step], e: &BytesStart) -> Result<Vec<usize>> {
+        let candidates = self
+            .states
+            .iter()
+            .copied()
+            .filter(|&k| k < steps.len() && steps[k].axis == Axis::Child)
+            .chain(self.descendant.iter().copied())
+            .collect::<Vec<_>>();
+        let mut states = Vec::new();
+        for k in candidates {
+            let step = &steps[k];
+            if !step.test.matches(&String::from_utf8_lossy(e.name().as_ref())) {
+                continue;
+            }
+            let mut passed = true;
+            for (j, predicate) in step.predicates.iter().enumerate() {
+                passed = match predicate {
+                    Predicate::Position(n) => {
+                        let seen = self.counters.entry((k, j)).or_default();
+                        *seen += 1;
+                        *seen == *n
+                    }
+                    Predicate::Cond(cond) => cond.eval(e)?,
+                };
+                if !passed {
+                    break;
+                }
+            }
+            if passed && !states.contains(&(k + 1)) {
+                states.push(k + 1);
+            }
+        }
+        Ok(states)
+    }
+}
+
+// ── Expressions ───────────────────────────────────────────────────────────
+
+#[derive(PartialEq)]
+enum Axis {
+    Child,
+    Descendant,
+}
+
+enum NodeTest {
+    Any,
+    Name(String),
+}
+
+impl NodeTest {
+    fn matches(&self, name: &str) -> bool {
+        match self {
+            NodeTest::Any => true,
+            NodeTest::Name(n) => n == name,
+        }
+    }
+}
+
+enum Predicate {
+    Position(usize),
+    Cond(Cond),
+}
+
+enum Cond {
+    Has(NodeTest),
+    Eq(String, String, bool),
+    Contains(String, String),
+    StartsWith(String, String),
+    Not(Box<Cond>),
+    And(Box<Cond>, Box<Cond>),
+    Or(Box<Cond>, Box<Cond>),
+}
+
+impl Cond {
+    fn eval(&self, e: &BytesStart) -> Result<bool> {
+        let attr = |name: &str| -> Result<Option<String>> {
+            match e.try_get_attribute(name)? {
+                Some(a) => Ok(Some(a.unescape_value()?.into_owned())),
+                None => Ok(None),
+            }
+        };
+        Ok(match self {
+            Cond::Has(NodeTest::Name(name)) => attr(name)?.is_some(),
+            Cond::Has(NodeTest::Any) => e.attributes().with_checks(false).flatten().any(|a| {
+                let key = a.key.as_ref();
+                key != b"xmlns" && !key.starts_with(b"xmlns:")
+            }),
+            // As in XPath, `@a != 'v'` needs the attribute to exist
+            Cond::Eq(name, value, equal) => attr(name)?.is_some_and(|v| (v == *value) == *equal),
+            Cond::Contains(name, value) => attr(name)?.is_some_and(|v| v.contains(value.as_str())),
+            Cond::StartsWith(name, value) => attr(name)?.is_some_and(|v| v.starts_with(value.as_str())),
+            Cond::Not(c) => !c.eval(e)?,
+            Cond::And(a, b) => a.eval(e)? && b.eval(e)?,
+            Cond::Or(a, b) => a.eval(e)? || b.eval(e)?,
+        })
+    }
+}
+
+struct Step {
+    axis: Axis,
+    test: NodeTest,
+    predicates: Vec<Predicate>,
+}
+
+/// What a path selects once its element steps have matched.
+enum Target {
+    Element,
+    Attribute(NodeTest),
+    Text,
+}
+
+struct Query {
+    steps: Vec<Step>,
+    target: Target,
+    // The target step came after `//`, so it also selects from the elements
+    // the steps before it matched
+    or_self: bool,
+    count: bool,
+}
+
+impl Query {
+    /// Whether the target is selected from an element with `states`.
+    fn selects(&self, states: &[usize]) -> bool {
+        let complete = self.steps.len();
+        states.contains(&complete) || (self.or_self && states.contains(&(complete - 1)))
+    }
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(src: &'a str) -> Self {
+        Parser { src, pos: 0 }
+    }
+
+    fn parse(mut self) -> Result<Query> {
+        let count = self.eat("count(");
+        let (steps, target, or_self) = self.path()?;
+        if count {
+            self.expect(")")?;
+        }
+        self.skip_ws();
+        if self.pos < self.src.len() {
+            return Err(self.error("unexpected input"));
+        }
+        Ok(Query { steps, target, or_self, count })
+    }
+
+    fn path(&mut self) -> Result<(Vec<Step>, Target, bool)> {
+        let mut steps = Vec::new();
+        self.skip_ws();
+        if !self.rest().starts_with('/') {
+            return Err(self.error("only absolute paths are supported"));
+        }
+        loop {
+            let axis = if self.eat("//") {
+                Axis::Descendant
+            } else if self.eat("/") {
+                Axis::Child
+            } else {
+                break;
+            };
+
+            // A final attribute or text step selects from every element the
+            // path matched, or from them and all their descendants after `//`
+            let target = if self.eat("@") {
+                Some(Target::Attribute(self.name_test()?))
+            } else if self.eat("text()") {
+                Some(Target::Text)
+            } else {
+                None
+            };
+            if let Some(target) = target {
+                let or_self = axis == Axis::Descendant;
+                if or_self {
+                    steps.push(Step { axis, test: NodeTest::Any, predicates: Vec::new() });
+                }
+                if self.rest().starts_with('/') || self.rest().starts_with('[') {
+                    return Err(self.error("attribute and text() steps must come last"));
+                }
+                if steps.is_empty() {
+                    return Err(self.error("the document has no attributes or text"));
+                }
+                return Ok((steps, target, or_self));
+            }
+
+            let test = self.name_test()?;
+            let mut predicates = Vec::new();
+            while self.eat("[") {
+                predicates.push(self.predicate()?);
+                self.expect("]")?;
+            }
+            steps.push(Step { axis, test, predicates });
+        }
+        if steps.is_empty() {
+            return Err(self.error("expected a location step"));
+        }
+        Ok((steps, Target::Element, false))
+    }
+
+    fn predicate(&mut self) -> Result<Predicate> {
+        self.skip_ws();
+        let digits = self.rest().bytes().take_while(u8::is_ascii_digit).count();
+        if digits > 0 {
+            let n: usize = self.src[self.pos..self.pos + digits].parse()?;
+            self.pos += digits;
+            self.skip_ws();
+            if n == 0 {
+                return Err(self.error("positions start at 1"));
+            }
+            return Ok(Predicate::Position(n));
+        }
+        Ok(Predicate::Cond(self.or()?))
+    }
+
+    fn or(&mut self) -> Result<Cond> {
+        let mut cond = self.and()?;
+        while self.eat_keyword("or") {
+            cond = Cond::Or(Box::new(cond), Box::new(self.and()?));
+        }
+        Ok(cond)
+    }
+
+    fn and(&mut self) -> Result<Cond> {
+        let mut cond = self.term()?;
+        while self.eat_keyword("and") {
+            cond = Cond::And(Box::new(cond), Box::new(self.term()?));
+        }
+        Ok(cond)
+    }
+
+    fn term(&mut self) -> Result<Cond> {
+        if self.eat("(") {
+            let cond = self.or()?;
+            self.expect(")")?;
+            return Ok(cond);
+        }
+        if self.eat("not(") {
+            let cond = self.or()?;
+            self.expect(")")?;
+            return Ok(Cond::Not(Box::new(cond)));
+        }
+        for (func, make) in [
+            ("contains(", Cond::Contains as fn(String, String) -> Cond),
+            ("starts-with(", Cond::StartsWith),
+        ] {
+            if self.eat(func) {
+                let name = self.attribute()?;
+                self.expect(",")?;
+                let value = self.literal()?;
+                self.expect(")")?;
+                return Ok(make(name, value));
+            }
+        }
+        if !self.eat("@") {
+            return Err(self.error("predicates can only test attributes and positions"));
+        }
+        let test = self.name_test()?;
+        let equal = if self.eat("!=") {
+            false
+        } else if self.eat("=") {
+            true
+        } else {
+            return Ok(Cond::Has(test));
+        };
+        let NodeTest::Name(name) = test else {
+            return Err(self.error("@* can't be compared"));
+        };
+        Ok(Cond::Eq(name, self.literal()?, equal))
+    }
+
+    fn attribute(&mut self) -> Result<String> {
+        if !self.eat("@") {
+            return Err(self.error("expected an attribute"));
+        }
+        match self.name_test()? {
+            NodeTest::Name(name) => Ok(name),
+            NodeTest::Any => Err(self.error("expected an attribute name")),
+        }
+    }
+
+    fn name_test(&mut self) -> Result<NodeTest> {
+        self.skip_ws();
+        if self.eat("*") {
+            return Ok(NodeTest::Any);
+        }
+        let len = self
+            .rest()
+            .find(|c: char| !(c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | ':')))
+            .unwrap_or(self.rest().len());
+        if len == 0 {
+            return Err(self.error("expected a name"));
+        }
+        let name = self.rest()[..len].to_string();
+        self.pos += len;
+        if self.rest().starts_with('(') || self.rest().starts_with("::") {
+            return Err(self.error(&format!("'{}' is not supported", name)));
+        }
+        Ok(NodeTest::Name(name))
+    }
+
+    fn literal(&mut self) -> Result<String> {
+        self.skip_ws();
+        let digits = self.rest().bytes().take_while(|b| b.is_ascii_digit() || *b == b'.').count();
+        if digits > 0 {
+            let number = self.rest()[..digits].to_string();
+            self.pos += digits;
+            return Ok(number);
+        }
+        let Some(quote) = self.rest().chars().next().filter(|c| matches!(c, '\'' | '"')) else {
+            return Err(self.error("expected a string literal"));
+        };
+        let Some(len) = self.rest()[1..].find(quote) else {
+            return Err(self.error("unterminated string literal"));
+        };
+        let value = self.rest()[1..1 + len].to_string();
+        self.pos += len + 2;
+        Ok(value)
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.src[self.pos..]
+    }
+
+    fn skip_ws(&mut self) {
+        self.pos = self.src.len() - self.rest().trim_start().len();
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_ws();
+        if self.rest().starts_with(token) {
+            self.pos += token.len();
+            return true;
+        }
+        false
+    }
+
+    fn eat_keyword(&mut self, word: &str) -> bool {
+        self.skip_ws();
+        let rest = self.rest();
+        if rest.starts_with(word) && !rest[word.len()..].starts_with(|c: char| c.is_alphanumeric() || c == '-') {
+            self.pos += word.len();
+            return true;
+        }
+        false
+    }
+
+    fn expect(&mut self, token: &str) -> Result<()> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", token)))
+        }
+    }
+
+    fn error(&self, message: &str) -> anyhow::Error {
+        XmlReaderError::Unsupported {
+            message: format!("Unsupported XPath at position {}: {}", self.pos + 1, message),
+        }
+        .into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(content: &str, expr: &str) -> Vec<(String, String)> {
+        let path = std::env::temp_dir().join(format!("xml-reader-query-{}.xml", std::process::id()));
+        std::fs::write(&path, content).unwrap();
+        let handle = FileRegistry::default().open(path.to_str().unwrap()).unwrap();
+        let result = run_query_internal(&handle, expr, 0, 100);
+        drop(handle);
+        let _ = std::fs::remove_file(&path);
+        result.unwrap().matches.into_iter().map(|m| (m.xpath, m.value)).collect()
+    }
+
+    fn pair(xpath: &str, value: &str) -> (String, String) {
+        (xpath.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn descendant_attributes_and_text_include_self() {
+        let content = r#"<r id="0"><a id="1">x<b id="2">y</b><c/></a></r>"#;
+        assert_eq!(query(content, "/r/a//@id"), [pair("/r/a/@id", "1"), pair("/r/a/b/@id", "2")]);
+        assert_eq!(query(content, "/r/a//text()"), [pair("/r/a/text()", "x"), pair("/r/a/b/text()", "y")]);
+        assert_eq!(query(content, "//@id").len(), 3);
+        // Only a `//` right before the target reaches the context element
+        assert_eq!(query(content, "/r/a//*/@id"), [pair("/r/a/b/@id", "2")]);
+        assert_eq!(query(content, "/r/a/@id"), [pair("/r/a/@id", "1")]);
+    }
+
+    #[test]
+    fn nested_matches_are_counted_once() {
+        let content = r#"<r><a id="1"><a id="2"/></a></r>"#;
+        assert_eq!(query(content, "//a//@id"), [pair("/r/a/@id", "1"), pair("/r/a/a/@id", "2")]);
+    }
+}
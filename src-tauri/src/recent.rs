@@ -0,0 +1,161 @@
+use crate::error::XmlReaderError;
+use crate::identity::{self, FileIdentity};
+use crate::io;
+use anyhow::Result;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tauri::{AppHandle, Manager, State};
+
+/// Unpinned entries kept; pinned ones are never evicted.
+const RECENT_LIMIT: usize = 20;
+
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+pub struct RecentFile {
+    path: String,
+    pinned: bool,
+    /// Milliseconds since the Unix epoch.
+    last_opened: i64,
+    last_offset: u64,
+    last_search: Option<String>,
+    identity: Option<FileIdentity>,
+}
+
+/// Recently opened files, persisted as JSON in the app data directory so the
+/// list doesn't depend on the webview's localStorage. Kept in Tauri managed
+/// state.
+pub struct RecentFiles {
+    store: Option<PathBuf>,
+    entries: Mutex<Vec<RecentFile>>,
+}
+
+impl RecentFiles {
+    /// Load the list from `store`; a missing or unreadable file starts empty.
+    pub fn load(store: Option<PathBuf>) -> RecentFiles {
+        let entries = store
+            .as_ref()
+            .and_then(|path| std::fs::read(path).ok())
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default();
+        RecentFiles {
+            store,
+            entries: Mutex::new(entries),
+        }
+    }
+
+    fn save(&self, entries: &[RecentFile]) -> Result<()> {
+        let Some(store) = &self.store else {
+            return Ok(());
+        };
+        if let Some(dir) = store.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        // Write then rename, so a crash never leaves a half-written list
+        let tmp = store.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(entries)?)?;
+        std::fs::rename(&tmp, store)?;
+        Ok(())
+    }
+
+    /// Apply `change` to the list, then sort, evict and persist it.
+    fn update(&self, change: impl FnOnce(&mut Vec<RecentFile>)) -> Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+        change(&mut entries);
+        entries.sort_by(|a, b| b.pinned.cmp(&a.pinned).then(b.last_opened.cmp(&a.last_opened)));
+        let mut unpinned = 0;
+        entries.retain(|e| {
+            unpinned += usize::from(!e.pinned);
+            e.pinned || unpinned <= RECENT_LIMIT
+        });
+        self.save(&entries)
+    }
+}
+
+/// Record that `path` was opened. The content hash is only recomputed, in the
+/// background, when the size or modification time no longer match the stored
+/// identity. Remote files are listed without one.
+pub fn touch(app: &AppHandle, path: &str) {
+    let recent = app.state::<RecentFiles>();
+    let now = chrono::Utc::now().timestamp_millis();
+    let mut stale = true;
+    let _ = recent.update(|entries| match entries.iter_mut().find(|e| e.path == path) {
+        Some(entry) => {
+            entry.last_opened = now;
+            stale = !entry.identity.as_ref().is_some_and(|identity| identity::still_matches(identity, path));
+        }
+        None => entries.push(RecentFile {
+            path: path.to_string(),
+            pinned: false,
+            last_opened: now,
+            last_offset: 0,
+            last_search: None,
+            identity: None,
+        }),
+    });
+
+    if stale && !io::is_remote(path) {
+        let app = app.clone();
+        let path = path.to_string();
+        std::thread::spawn(move || {
+            if let Ok(identity) = identity::file_identity(&path, |_, _| {}) {
+                let recent = app.state::<RecentFiles>();
+                let _ = recent.update(|entries| {
+                    if let Some(entry) = entries.iter_mut().find(|e| e.path == path) {
+                        entry.identity = Some(identity);
+                    }
+                });
+            }
+        });
+    }
+}
+
+/// The identity recorded for `path` on an earlier open, if the file hasn't
+/// changed since.
+pub fn known_identity(app: &AppHandle, path: &str) -> Option<FileIdentity> {
+    let recent = app.state::<RecentFiles>();
+    let entries = recent.entries.lock().unwrap();
+    let identity = entries.iter().find(|e| e.path == path)?.identity.as_ref()?;
+    identity::still_matches(identity, path).then(|| identity.clone())
+}
+
+#[tauri::command]
+pub async fn get_recent(recent: State<'_, RecentFiles>) -> Result<Vec<RecentFile>, XmlReaderError> {
+    Ok(recent.entries.lock().unwrap().clone())
+}
+
+#[tauri::command]
+pub async fn pin_recent(recent: State<'_, RecentFiles>, path: String, pinned: bool) -> Result<(), XmlReaderError> {
+    recent
+        .update(|entries| {
+            if let Some(entry) = entries.iter_mut().find(|e| e.path == path) {
+                entry.pinned = pinned;
+            }
+        })
+        .map_err(XmlReaderError::from)
+}
+
+#[tauri::command]
+pub async fn remove_recent(recent: State<'_, RecentFiles>, path: String) -> Result<(), XmlReaderError> {
+    recent
+        .update(|entries| entries.retain(|e| e.path != path))
+        .map_err(XmlReaderError::from)
+}
+
+/// Remember where the user was in a file, to restore it on the next open.
+#[tauri::command]
+pub async fn update_recent(
+    recent: State<'_, RecentFiles>,
+    path: String,
+    offset: u64,
+    search: Option<String>,
+) -> Result<(), XmlReaderError> {
+    recent
+        .update(|entries| {
+            if let Some(entry) = entries.iter_mut().find(|e| e.path == path) {
+                entry.last_offset = offset;
+                if search.is_some() {
+                    entry.last_search = search;
+                }
+            }
+        })
+        .map_err(XmlReaderError::from)
+}
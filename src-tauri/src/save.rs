@@ -0,0 +1,161 @@
+use crate::error::XmlReaderError;
+use std::fs::File;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::SystemTime;
+
+/// Keep the previous version of an edited file as `<name>.bak`.
+static KEEP_BACKUP: AtomicBool = AtomicBool::new(false);
+
+#[tauri::command]
+pub async fn set_keep_backup(enabled: bool) -> Result<(), XmlReaderError> {
+    KEEP_BACKUP.store(enabled, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Why a save failed, sent to the frontend as `{ kind, ... }`.
+#[derive(Debug, serde::Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SaveError {
+    /// Remote files, decoded binary XML and pasted text have no file to write back to.
+    NotWritable { path: String },
+    /// Another process changed the file since the edit was planned.
+    SourceChanged { path: String },
+    /// Creating, filling or syncing the temp file failed (disk full, permissions).
+    Write { path: String, message: String },
+    /// The previous version could not be kept as `.bak`.
+    Backup { path: String, message: String },
+    /// The temp file could not replace the destination.
+    Rename { path: String, message: String },
+    /// The edit itself was refused: bad offset, malformed fragment and so on.
+    Failed { message: String },
+}
+
+impl std::fmt::Display for SaveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SaveError::NotWritable { path } => write!(f, "{} can't be edited in place; save the changes to another file", path),
+            SaveError::SourceChanged { path } => write!(f, "{} changed on disk since the edit started", path),
+            SaveError::Write { path, message } => write!(f, "Could not write {}: {}", path, message),
+            SaveError::Backup { path, message } => write!(f, "Could not keep a backup of {}: {}", path, message),
+            SaveError::Rename { path, message } => write!(f, "Could not replace {}: {}", path, message),
+            SaveError::Failed { message } => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for SaveError {}
+
+impl From<anyhow::Error> for SaveError {
+    fn from(e: anyhow::Error) -> Self {
+        e.downcast::<SaveError>().unwrap_or_else(|e| SaveError::Failed { message: format!("{:#}", e) })
+    }
+}
+
+/// Length and modification time of a file, to tell whether it changed
+/// between planning an edit and writing it.
+#[derive(Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct SourceStamp {
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+impl SourceStamp {
+    pub fn of(path: &str) -> anyhow::Result<SourceStamp> {
+        let meta = std::fs::metadata(path)?;
+        Ok(SourceStamp {
+            len: meta.len(),
+            modified: meta.modified().ok(),
+        })
+    }
+}
+
+/// Write `target` through a temp file in the same directory: `write` fills
+/// it, it is synced to disk, then renamed over `target` so readers see either
+/// the old or the new file. `source` is the file being rewritten with its
+/// stamp from when the edit was planned; the save is refused if it changed
+/// since, and when it is `target` itself the old version is kept as `.bak`
+/// if that is enabled. Errors of `write` are returned as they are; on any
+/// error only the temp file is removed.
+pub fn save_atomic<T, E: From<SaveError>>(
+    target: &str,
+    source: Option<(&str, &SourceStamp)>,
+    write: impl FnOnce(&File) -> Result<T, E>,
+) -> Result<T, E> {
+    let temp = format!("{}.xml-reader-tmp-{}", target, std::process::id());
+    let result = write_temp(&temp, write).and_then(|written| {
+        if let Some((path, stamp)) = source {
+            if SourceStamp::of(path).ok().as_ref() != Some(stamp) {
+                return Err(SaveError::SourceChanged { path: path.to_string() }.into());
+            }
+            if path == target && KEEP_BACKUP.load(Ordering::SeqCst) {
+                keep_backup(target)?;
+            }
+        }
+        std::fs::rename(&temp, target).map_err(|e| SaveError::Rename {
+            path: target.to_string(),
+            message: e.to_string(),
+        })?;
+        Ok(written)
+    });
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp);
+        return result;
+    }
+    sync_parent(target);
+    result
+}
+
+fn write_temp<T, E: From<SaveError>>(temp: &str, write: impl FnOnce(&File) -> Result<T, E>) -> Result<T, E> {
+    let failed = |e: std::io::Error| SaveError::Write {
+        path: temp.to_string(),
+        message: e.to_string(),
+    };
+    let file = File::create(temp).map_err(failed)?;
+    let written = write(&file)?;
+    file.sync_all().map_err(failed)?;
+    Ok(written)
+}
+
+/// Whether `path` is the file at `source`, through relative paths, symlinks
+/// and (on Unix) hard links.
+pub fn same_file(path: &str, source: &str) -> bool {
+    let (Ok(path), Ok(source)) = (std::fs::canonicalize(path), std::fs::canonicalize(source)) else {
+        return false;
+    };
+    if path == source {
+        return true;
+    }
+    #[cfg(unix)]
+    if let (Ok(a), Ok(b)) = (std::fs::metadata(&path), std::fs::metadata(&source)) {
+        use std::os::unix::fs::MetadataExt;
+        return a.dev() == b.dev() && a.ino() == b.ino();
+    }
+    false
+}
+
+/// Point `<target>.bak` at the current file. A hard link costs nothing for
+/// big files; where links aren't supported the file is copied.
+fn keep_backup(target: &str) -> Result<(), SaveError> {
+    let backup = format!("{}.bak", target);
+    let _ = std::fs::remove_file(&backup);
+    std::fs::hard_link(target, &backup)
+        .or_else(|_| std::fs::copy(target, &backup).map(|_| ()))
+        .map_err(|e| SaveError::Backup {
+            path: target.to_string(),
+            message: e.to_string(),
+        })
+}
+
+/// Make the rename itself durable. Directories can't be opened for this on
+/// Windows, where the rename is already flushed.
+fn sync_parent(target: &str) {
+    #[cfg(unix)]
+    if let Some(dir) = std::path::Path::new(target).parent() {
+        let dir = if dir.as_os_str().is_empty() { std::path::Path::new(".") } else { dir };
+        if let Ok(dir) = File::open(dir) {
+            let _ = dir.sync_all();
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = target;
+}
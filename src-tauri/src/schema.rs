@@ -0,0 +1,403 @@
+use crate::error::XmlReaderError;
+use crate::files::{FileHandle, FileId, FileRegistry};
+use crate::io::XmlReader;
+use crate::ops::{self, OpId};
+use anyhow::Result;
+use quick_xml::events::{BytesStart, Event};
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use tauri::{State, WebviewWindow};
+
+/// Simple type of the values seen for a text node or attribute, from most to
+/// least specific. Merging two different types falls back to the narrowest
+/// type accepting both, usually `String`.
+#[derive(serde::Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+enum SimpleType {
+    Boolean,
+    Integer,
+    Decimal,
+    Date,
+    DateTime,
+    String,
+}
+
+impl SimpleType {
+    fn of(value: &str) -> Option<SimpleType> {
+        let value = value.trim();
+        if value.is_empty() {
+            return None;
+        }
+        Some(if value == "true" || value == "false" {
+            SimpleType::Boolean
+        } else if value.parse::<i64>().is_ok() {
+            SimpleType::Integer
+        } else if value.parse::<f64>().is_ok() && value.bytes().all(|b| b.is_ascii_digit() || b"+-.".contains(&b)) {
+            SimpleType::Decimal
+        } else if chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d").is_ok() {
+            SimpleType::Date
+        } else if chrono::DateTime::parse_from_rfc3339(value).is_ok()
+            || chrono::NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S%.f").is_ok()
+        {
+            SimpleType::DateTime
+        } else {
+            SimpleType::String
+        })
+    }
+
+    fn merge(seen: Option<SimpleType>, value: &str) -> Option<SimpleType> {
+        let Some(new) = SimpleType::of(value) else { return seen };
+        Some(match seen {
+            None => new,
+            Some(old) if old == new => old,
+            Some(SimpleType::Integer | SimpleType::Decimal) if matches!(new, SimpleType::Integer | SimpleType::Decimal) => {
+                SimpleType::Decimal
+            }
+            Some(_) => SimpleType::String,
+        })
+    }
+
+    fn xsd(self) -> &'static str {
+        match self {
+            SimpleType::Boolean => "xs:boolean",
+            SimpleType::Integer => "xs:integer",
+            SimpleType::Decimal => "xs:decimal",
+            SimpleType::Date => "xs:date",
+            SimpleType::DateTime => "xs:dateTime",
+            SimpleType::String => "xs:string",
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+struct AttributeInfo {
+    name: String,
+    // Elements carrying the attribute
+    count: u64,
+    #[serde(rename = "type")]
+    value_type: Option<SimpleType>,
+}
+
+#[derive(serde::Serialize)]
+struct ChildInfo {
+    name: String,
+    // Fewest and most occurrences in one parent
+    min: u64,
+    max: u64,
+}
+
+#[derive(serde::Serialize)]
+struct ElementInfo {
+    name: String,
+    count: u64,
+    attributes: Vec<AttributeInfo>,
+    children: Vec<ChildInfo>,
+    // Children always appeared in the order of `children`
+    ordered: bool,
+    // Type of non-whitespace text directly inside the element
+    text: Option<SimpleType>,
+}
+
+impl ElementInfo {
+    fn required(&self, attr: &AttributeInfo) -> bool {
+        attr.count == self.count
+    }
+
+    fn merge_children(&mut self, seen: &[(String, u64)]) {
+        let earlier = self.count - 1;
+        for child in &mut self.children {
+            if !seen.iter().any(|(name, _)| *name == child.name) {
+                child.min = 0;
+            }
+        }
+        let mut last = None;
+        for (name, n) in seen {
+            let index = match self.children.iter().position(|c| c.name == *name) {
+                Some(i) => {
+                    let child = &mut self.children[i];
+                    child.min = child.min.min(*n);
+                    child.max = child.max.max(*n);
+                    i
+                }
+                None => {
+                    let min = if earlier > 0 { 0 } else { *n };
+                    self.children.push(ChildInfo { name: name.clone(), min, max: *n });
+                    self.children.len() - 1
+                }
+            };
+            // Names must appear in the same relative order in every occurrence
+            if last.is_some_and(|last| index < last) {
+                self.ordered = false;
+            }
+            last = Some(index);
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct InferredSchema {
+    out_path: String,
+    // Elements examined, at most `sample_limit`
+    sampled: u64,
+    element_types: usize,
+}
+
+/// An open element while inferring: its entry in `elements`, its children
+/// in first-seen order with how often each occurred, and whether a child
+/// repeated after another name came between (which breaks `xs:sequence`).
+struct Open {
+    index: usize,
+    children: Vec<(String, u64)>,
+    interleaved: bool,
+}
+
+/// Stream the document and describe every element name seen: attributes and
+/// their types, children with their cardinality, and text content type.
+/// Writes an XSD to `out_path`, or the same information as a JSON report
+/// when `out_path` ends in `.json`. `sample_limit` stops after that many
+/// elements (0 scans everything). Emits `export-progress` (0-100).
+#[tauri::command]
+pub async fn infer_schema(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    sample_limit: u64,
+    out_path: String,
+    op_id: Option<OpId>,
+) -> Result<InferredSchema, XmlReaderError> {
+    let handle = files.get(file_id)?;
+    ops::run(window, op_id, "export", move |op| {
+        infer_schema_internal(&handle, sample_limit, &out_path, |pct| {
+            op.progress(pct);
+        })
+        .map_err(|e| XmlReaderError::in_file(&handle, e))
+    })
+    .await
+}
+
+fn infer_schema_internal(
+    handle: &FileHandle,
+    sample_limit: u64,
+    out_path: &str,
+    mut progress: impl FnMut(u64),
+) -> Result<InferredSchema> {
+    let file = handle.reader()?;
+    let file_len = file.len().max(1);
+    let mut reader = XmlReader::scan(file);
+    reader.check_end_names(false);
+
+    let mut buf = Vec::new();
+    let mut elements: Vec<ElementInfo> = Vec::new();
+    let mut index: HashMap<String, usize> = HashMap::new();
+    let mut stack: Vec<Open> = Vec::new();
+    let mut root: Option<usize> = None;
+    let mut target_namespace = None;
+    let mut sampled = 0u64;
+    let mut last_pct = 0;
+
+    loop {
+        if sample_limit > 0 && sampled >= sample_limit {
+            break;
+        }
+        buf.clear();
+        let event = reader.read_event_into(&mut buf)?;
+
+        match &event {
+            Event::Start(e) | Event::Empty(e) => {
+                sampled += 1;
+                let name = String::from_utf8_lossy(e.local_name().as_ref()).into_owned();
+                let i = *index.entry(name.clone()).or_insert_with(|| {
+                    elements.push(ElementInfo {
+                        name: name.clone(),
+                        count: 0,
+                        attributes: Vec::new(),
+                        children: Vec::new(),
+                        ordered: true,
+                        text: None,
+                    });
+                    elements.len() - 1
+                });
+                if stack.is_empty() && root.is_none() {
+                    root = Some(i);
+                    target_namespace = e
+                        .try_get_attribute("xmlns")?
+                        .map(|a| String::from_utf8_lossy(&a.value).into_owned());
+                }
+                if let Some(parent) = stack.last_mut() {
+                    match parent.children.iter().position(|(n, _)| *n == name) {
+                        Some(at) => {
+                            if at + 1 != parent.children.len() {
+                                parent.interleaved = true;
+                            }
+                            parent.children[at].1 += 1;
+                        }
+                        None => parent.children.push((name, 1)),
+                    }
+                }
+                record_attributes(&mut elements[i], e)?;
+                elements[i].count += 1;
+
+                let open = Open { index: i, children: Vec::new(), interleaved: false };
+                if matches!(event, Event::Start(_)) {
+                    stack.push(open);
+                } else {
+                    close(&mut elements, open);
+                }
+            }
+            Event::End(_) => {
+                if let Some(open) = stack.pop() {
+                    close(&mut elements, open);
+                }
+            }
+            Event::Text(e) => {
+                if let Some(i) = stack.last().map(|o| o.index) {
+                    let text = e.unescape().map(|t| t.into_owned()).unwrap_or_else(|_| String::from_utf8_lossy(e).into_owned());
+                    let info = &mut elements[i];
+                    info.text = SimpleType::merge(info.text, &text);
+                }
+            }
+            Event::CData(e) => {
+                if let Some(i) = stack.last().map(|o| o.index) {
+                    let info = &mut elements[i];
+                    info.text = SimpleType::merge(info.text, &String::from_utf8_lossy(e));
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+
+        let pct = reader.position() * 100 / file_len;
+        if pct > last_pct {
+            last_pct = pct;
+            progress(pct);
+        }
+    }
+
+    // Elements still open when the sample ran out count with what was seen
+    while let Some(open) = stack.pop() {
+        close(&mut elements, open);
+    }
+
+    let root = root.ok_or_else(|| anyhow::anyhow!("No elements found"))?;
+    let output = if out_path.to_lowercase().ends_with(".json") {
+        serde_json::to_string_pretty(&elements)?
+    } else {
+        write_xsd(&elements, root, target_namespace.as_deref())
+    };
+    std::fs::write(out_path, output)?;
+    progress(100);
+    Ok(InferredSchema {
+        out_path: out_path.to_string(),
+        sampled,
+        element_types: elements.len(),
+    })
+}
+
+fn record_attributes(info: &mut ElementInfo, e: &BytesStart) -> Result<()> {
+    for attr in e.attributes().with_checks(false) {
+        let attr = attr?;
+        let key = String::from_utf8_lossy(attr.key.as_ref()).into_owned();
+        if key == "xmlns" || key.starts_with("xmlns:") {
+            continue;
+        }
+        let value = attr.unescape_value().map(|v| v.into_owned()).unwrap_or_default();
+        match info.attributes.iter_mut().find(|a| a.name == key) {
+            Some(existing) => {
+                existing.count += 1;
+                existing.value_type = SimpleType::merge(existing.value_type, &value);
+            }
+            None => info.attributes.push(AttributeInfo {
+                name: key,
+                count: 1,
+                value_type: SimpleType::merge(None, &value),
+            }),
+        }
+    }
+    Ok(())
+}
+
+fn close(elements: &mut [ElementInfo], open: Open) {
+    let info = &mut elements[open.index];
+    info.merge_children(&open.children);
+    if open.interleaved {
+        info.ordered = false;
+    }
+}
+
+/// One global declaration per element name; children refer to them with
+/// `ref`. Children always seen in one order become an `xs:sequence` with
+/// their observed cardinality, anything else an unbounded `xs:choice`.
+/// Prefixed attributes (`xml:lang`, `xsi:type`, ...) belong to other
+/// namespaces and are left out.
+fn write_xsd(elements: &[ElementInfo], root: usize, target_namespace: Option<&str>) -> String {
+    let mut xsd = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xsd.push_str("<xs:schema xmlns:xs=\"http://www.w3.org/2001/XMLSchema\"");
+    if let Some(ns) = target_namespace {
+        let _ = write!(xsd, " targetNamespace=\"{0}\" xmlns=\"{0}\"", ns);
+    }
+    xsd.push_str(" elementFormDefault=\"qualified\">\n");
+
+    // Root first, the rest in the order they were first seen
+    let order = std::iter::once(root).chain((0..elements.len()).filter(|&i| i != root));
+    for info in order.map(|i| &elements[i]) {
+        let attributes: Vec<&AttributeInfo> = info.attributes.iter().filter(|a| !a.name.contains(':')).collect();
+        let text_type = info.text.map_or("xs:string", SimpleType::xsd);
+
+        if info.children.is_empty() && attributes.is_empty() {
+            match info.text {
+                Some(t) => {
+                    let _ = writeln!(xsd, "  <xs:element name=\"{}\" type=\"{}\"/>", info.name, t.xsd());
+                }
+                None => {
+                    let _ = writeln!(xsd, "  <xs:element name=\"{}\">\n    <xs:complexType/>\n  </xs:element>", info.name);
+                }
+            }
+            continue;
+        }
+
+        let _ = writeln!(xsd, "  <xs:element name=\"{}\">", info.name);
+        if info.children.is_empty() && info.text.is_some() {
+            let _ = writeln!(xsd, "    <xs:complexType>\n      <xs:simpleContent>\n        <xs:extension base=\"{}\">", text_type);
+            write_xsd_attributes(&mut xsd, info, &attributes, "          ");
+            xsd.push_str("        </xs:extension>\n      </xs:simpleContent>\n    </xs:complexType>\n");
+        } else {
+            let mixed = if info.text.is_some() { " mixed=\"true\"" } else { "" };
+            let _ = writeln!(xsd, "    <xs:complexType{}>", mixed);
+            if !info.children.is_empty() {
+                if info.ordered {
+                    xsd.push_str("      <xs:sequence>\n");
+                    for child in &info.children {
+                        let max = if child.max > 1 { "unbounded".to_string() } else { child.max.to_string() };
+                        let _ = writeln!(
+                            xsd,
+                            "        <xs:element ref=\"{}\" minOccurs=\"{}\" maxOccurs=\"{}\"/>",
+                            child.name,
+                            child.min.min(1),
+                            max
+                        );
+                    }
+                    xsd.push_str("      </xs:sequence>\n");
+                } else {
+                    xsd.push_str("      <xs:choice minOccurs=\"0\" maxOccurs=\"unbounded\">\n");
+                    for child in &info.children {
+                        let _ = writeln!(xsd, "        <xs:element ref=\"{}\"/>", child.name);
+                    }
+                    xsd.push_str("      </xs:choice>\n");
+                }
+            }
+            write_xsd_attributes(&mut xsd, info, &attributes, "      ");
+            xsd.push_str("    </xs:complexType>\n");
+        }
+        xsd.push_str("  </xs:element>\n");
+    }
+    xsd.push_str("</xs:schema>\n");
+    xsd
+}
+
+fn write_xsd_attributes(xsd: &mut String, info: &ElementInfo, attributes: &[&AttributeInfo], indent: &str) {
+    for attr in attributes {
+        let value_type = attr.value_type.map_or("xs:string", SimpleType::xsd);
+        let required = if info.required(attr) { " use=\"required\"" } else { "" };
+        let _ = writeln!(xsd, "{}<xs:attribute name=\"{}\" type=\"{}\"{}/>", indent, attr.name, value_type, required);
+    }
+}
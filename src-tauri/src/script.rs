@@ -0,0 +1,254 @@
+use crate::error::XmlReaderError;
+use crate::export::element_end;
+use crate::files::{FileHandle, FileId, FileRegistry};
+use crate::format::element_reader;
+use crate::ops::{self, OpId};
+use crate::query::run_query_internal;
+use crate::save::save_atomic;
+use crate::xml_ops;
+use anyhow::Result;
+use quick_xml::events::{BytesStart, Event};
+use rhai::{Array, Dynamic, Engine, EvalAltResult, Map};
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use tauri::{State, WebviewWindow};
+
+/// Most matches `query` returns to a script.
+const SCRIPT_QUERY_LIMIT: usize = 1_000_000;
+/// Most bytes `text` returns for one element.
+const SCRIPT_TEXT_BYTES: u64 = 16 * 1024 * 1024;
+/// Most `print` lines kept; the rest are counted but dropped.
+const SCRIPT_OUTPUT_LINES: usize = 10_000;
+
+#[derive(serde::Serialize, Default)]
+pub struct ScriptOutput {
+    /// Lines printed by the script.
+    output: Vec<String>,
+    /// Lines printed past `SCRIPT_OUTPUT_LINES`.
+    dropped_lines: u64,
+    /// The value of the script's last statement.
+    result: String,
+    /// Files the script wrote with `write_file`.
+    written: Vec<String>,
+}
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+fn script_error(e: anyhow::Error) -> Box<EvalAltResult> {
+    e.to_string().into()
+}
+
+/// The element whose start tag is at `offset` as a script map: `name`,
+/// `offset` and `attrs` (name to unescaped value).
+fn element_map(offset: u64, e: &BytesStart) -> Map {
+    let mut attrs = Map::new();
+    for attr in e.attributes().flatten() {
+        let value = attr
+            .unescape_value()
+            .map(|v| v.into_owned())
+            .unwrap_or_else(|_| String::from_utf8_lossy(&attr.value).into_owned());
+        attrs.insert(String::from_utf8_lossy(attr.key.as_ref()).as_ref().into(), value.into());
+    }
+    let mut element = Map::new();
+    element.insert("name".into(), String::from_utf8_lossy(e.name().as_ref()).into_owned().into());
+    element.insert("offset".into(), (offset as i64).into());
+    element.insert("attrs".into(), attrs.into());
+    element
+}
+
+fn element_at(handle: &FileHandle, offset: u64) -> Result<Map> {
+    let mut reader = element_reader(handle, offset)?;
+    let mut buf = Vec::new();
+    match reader.read_event_into(&mut buf)? {
+        Event::Start(e) | Event::Empty(e) => Ok(element_map(offset, &e)),
+        _ => Err(anyhow::anyhow!("No element starts at offset {}", offset)),
+    }
+}
+
+fn children_of(handle: &FileHandle, offset: u64) -> Result<Array> {
+    let mut reader = element_reader(handle, offset)?;
+    let mut buf = Vec::new();
+    match reader.read_event_into(&mut buf)? {
+        Event::Start(_) => {}
+        Event::Empty(_) => return Ok(Array::new()),
+        _ => return Err(anyhow::anyhow!("No element starts at offset {}", offset)),
+    }
+    let mut children = Array::new();
+    let mut depth = 1u32;
+    loop {
+        buf.clear();
+        let pos_before = reader.position();
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(e) => {
+                if depth == 1 {
+                    children.push(element_map(pos_before, &e).into());
+                }
+                depth += 1;
+            }
+            Event::Empty(e) if depth == 1 => children.push(element_map(pos_before, &e).into()),
+            Event::End(_) => {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(children);
+                }
+            }
+            Event::Eof => return Ok(children),
+            _ => {}
+        }
+    }
+}
+
+/// The element at `offset` as written in the file, cut at
+/// `SCRIPT_TEXT_BYTES`.
+fn text_of(handle: &FileHandle, offset: u64) -> Result<String> {
+    let mut reader = element_reader(handle, offset)?;
+    let mut buf = Vec::new();
+    let end = match reader.read_event_into(&mut buf)? {
+        Event::Start(_) => element_end(&mut reader, &mut buf)?,
+        Event::Empty(_) => reader.position(),
+        _ => return Err(anyhow::anyhow!("No element starts at offset {}", offset)),
+    };
+    let mut source = handle.reader()?;
+    let len = (end - offset).min(SCRIPT_TEXT_BYTES);
+    let mut bytes = vec![0; len as usize];
+    std::io::Seek::seek(&mut source, std::io::SeekFrom::Start(offset))?;
+    std::io::Read::read_exact(&mut source, &mut bytes)?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Enclosing elements of the one at `offset`, outermost first, as maps.
+fn ancestors_of(handle: &FileHandle, offset: u64) -> Result<Array> {
+    xml_ops::open_elements_at(handle, offset)?
+        .into_iter()
+        .map(|(_, start)| element_at(handle, start).map(Dynamic::from))
+        .collect()
+}
+
+/// An engine with the file bindings for `handle`. Scripts can read the file
+/// and write new files, nothing else: Rhai has no file, network or process
+/// access of its own.
+fn engine(handle: Arc<FileHandle>, out: Rc<RefCell<ScriptOutput>>) -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_call_levels(64);
+    engine.set_max_expr_depths(128, 64);
+    engine.set_max_string_size(256 * 1024 * 1024);
+
+    // Stop at the next statement once the operation is cancelled
+    let cancelled = ops::current();
+    engine.on_progress(move |_| {
+        cancelled
+            .as_ref()
+            .is_some_and(|c| c.load(Ordering::Relaxed))
+            .then_some(Dynamic::UNIT)
+    });
+
+    let printed = out.clone();
+    engine.on_print(move |line| {
+        let mut out = printed.borrow_mut();
+        if out.output.len() < SCRIPT_OUTPUT_LINES {
+            out.output.push(line.to_string());
+        } else {
+            out.dropped_lines += 1;
+        }
+    });
+
+    let h = handle.clone();
+    engine.register_fn("query", move |expr: &str| -> ScriptResult<Array> {
+        let result = run_query_internal(&h, expr, 0, SCRIPT_QUERY_LIMIT).map_err(script_error)?;
+        Ok(result
+            .matches
+            .into_iter()
+            .map(|m| {
+                let mut found = Map::new();
+                found.insert("offset".into(), (m.offset as i64).into());
+                found.insert("end".into(), m.end.map_or(Dynamic::UNIT, |end| (end as i64).into()));
+                found.insert("xpath".into(), m.xpath.into());
+                found.insert("name".into(), m.name.into());
+                found.insert("value".into(), m.value.into());
+                found.into()
+            })
+            .collect())
+    });
+    let h = handle.clone();
+    engine.register_fn("element", move |offset: i64| -> ScriptResult<Map> {
+        element_at(&h, offset as u64).map_err(script_error)
+    });
+    let h = handle.clone();
+    engine.register_fn("children", move |offset: i64| -> ScriptResult<Array> {
+        children_of(&h, offset as u64).map_err(script_error)
+    });
+    let h = handle.clone();
+    engine.register_fn("ancestors", move |offset: i64| -> ScriptResult<Array> {
+        ancestors_of(&h, offset as u64).map_err(script_error)
+    });
+    let h = handle.clone();
+    engine.register_fn("parent", move |offset: i64| -> ScriptResult<Dynamic> {
+        let mut ancestors = ancestors_of(&h, offset as u64).map_err(script_error)?;
+        Ok(ancestors.pop().unwrap_or(Dynamic::UNIT))
+    });
+    let h = handle.clone();
+    engine.register_fn("attr", move |offset: i64, name: &str| -> ScriptResult<Dynamic> {
+        let element = element_at(&h, offset as u64).map_err(script_error)?;
+        let attrs = element.get("attrs").and_then(|a| a.read_lock::<Map>().map(|a| a.get(name).cloned()));
+        Ok(attrs.flatten().unwrap_or(Dynamic::UNIT))
+    });
+    let h = handle.clone();
+    engine.register_fn("text", move |offset: i64| -> ScriptResult<String> {
+        text_of(&h, offset as u64).map_err(script_error)
+    });
+    let h = handle;
+    engine.register_fn("write_file", move |path: &str, text: &str| -> ScriptResult<()> {
+        if h.is_source(path) {
+            return Err("A script cannot overwrite the file it reads".into());
+        }
+        save_atomic(path, None, |mut file| -> Result<()> {
+            file.write_all(text.as_bytes())?;
+            Ok(())
+        })
+        .map_err(|e| e.to_string())?;
+        out.borrow_mut().written.push(path.to_string());
+        Ok(())
+    });
+    engine
+}
+
+/// Run a Rhai script against a file, for extraction logic no built-in
+/// command covers. Bindings, with elements as maps of `name`, `offset` and
+/// `attrs`:
+///
+/// - `query(xpath)`: matches of a `run_query` expression, as maps of
+///   `offset`, `end`, `xpath`, `name` and `value`
+/// - `element(offset)`, `children(offset)`, `parent(offset)` (`()` for the
+///   root), `ancestors(offset)` (outermost first)
+/// - `attr(offset, name)`: an attribute value, `()` if missing
+/// - `text(offset)`: the element as written in the file
+/// - `write_file(path, text)`: write an output file
+///
+/// `print` output is returned with the value of the last statement.
+#[tauri::command]
+pub async fn run_script(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    script: String,
+    op_id: Option<OpId>,
+) -> Result<ScriptOutput, XmlReaderError> {
+    let handle = files.get(file_id)?;
+    ops::run(window, op_id, "script", move |_| {
+        let out = Rc::new(RefCell::new(ScriptOutput::default()));
+        let engine = engine(handle.clone(), out.clone());
+        let result = match engine.eval::<Dynamic>(&script) {
+            Ok(value) => value,
+            Err(e) if matches!(*e, EvalAltResult::ErrorTerminated(..)) => return Err(XmlReaderError::Cancelled),
+            Err(e) => return Err(XmlReaderError::in_file(&handle, anyhow::anyhow!("Script failed: {}", e))),
+        };
+        drop(engine);
+        let mut out = Rc::try_unwrap(out).map_or_else(|out| std::mem::take(&mut *out.borrow_mut()), RefCell::into_inner);
+        out.result = result.to_string();
+        Ok(out)
+    })
+    .await
+}
@@ -0,0 +1,188 @@
+use crate::error::XmlReaderError;
+use crate::files::{FileHandle, FileId, FileRegistry};
+use crate::identity::{self, FileIdentity};
+use crate::recent;
+use crate::xml_ops::blocking;
+use anyhow::Result;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tauri::{AppHandle, Emitter, Manager, State};
+
+/// Sessions kept; the least recently saved are dropped first.
+const SESSION_LIMIT: usize = 200;
+
+/// Where the user was in a file, as the frontend saves it.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct SessionState {
+    /// Offset at the top of the view.
+    pub offset: u64,
+    /// Scroll position of the view, in pixels.
+    pub scroll_top: f64,
+    /// Offsets of the expanded tree nodes.
+    pub open_nodes: Vec<u64>,
+    pub last_query: Option<String>,
+    pub last_search_type: Option<String>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+struct Session {
+    identity: FileIdentity,
+    /// Milliseconds since the Unix epoch.
+    saved: i64,
+    state: SessionState,
+}
+
+#[derive(serde::Serialize, Clone)]
+struct SessionRestored {
+    file_id: FileId,
+    state: SessionState,
+}
+
+/// Per-file session state, persisted as JSON in the app data directory and
+/// keyed by file identity: a file reopened from the same path, or moved or
+/// copied elsewhere unchanged, gets its session back. Kept in Tauri managed
+/// state.
+pub struct Sessions {
+    store: Option<PathBuf>,
+    entries: Mutex<Vec<Session>>,
+}
+
+impl Sessions {
+    /// Load the sessions from `store`; a missing or unreadable file starts empty.
+    pub fn load(store: Option<PathBuf>) -> Sessions {
+        let entries = store
+            .as_ref()
+            .and_then(|path| std::fs::read(path).ok())
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default();
+        Sessions {
+            store,
+            entries: Mutex::new(entries),
+        }
+    }
+
+    fn save(&self, entries: &[Session]) -> Result<()> {
+        let Some(store) = &self.store else {
+            return Ok(());
+        };
+        if let Some(dir) = store.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        // Write then rename, so a crash never leaves half-written sessions
+        let tmp = store.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(entries)?)?;
+        std::fs::rename(&tmp, store)?;
+        Ok(())
+    }
+
+    /// The session of the unchanged file at `path`, found without hashing it.
+    fn by_path(&self, path: &str) -> Option<Session> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .iter()
+            .find(|s| s.identity.path == path && identity::still_matches(&s.identity, path))
+            .cloned()
+    }
+
+    fn by_content(&self, identity: &FileIdentity) -> Option<Session> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .iter()
+            .filter(|s| s.identity.size == identity.size && s.identity.hash == identity.hash)
+            .max_by_key(|s| s.saved)
+            .cloned()
+    }
+
+    fn put(&self, identity: FileIdentity, state: SessionState) -> Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|s| s.identity.path != identity.path);
+        entries.push(Session {
+            identity,
+            saved: chrono::Utc::now().timestamp_millis(),
+            state,
+        });
+        entries.sort_by_key(|s| std::cmp::Reverse(s.saved));
+        entries.truncate(SESSION_LIMIT);
+        self.save(&entries)
+    }
+}
+
+/// The identity of the file behind `handle`, hashing it only when neither
+/// a saved session nor the recent list has one that still matches.
+fn identity_of(app: &AppHandle, handle: &FileHandle, path: &str) -> Result<FileIdentity> {
+    if let Some(session) = app.state::<Sessions>().by_path(path) {
+        return Ok(session.identity);
+    }
+    if let Some(identity) = recent::known_identity(app, path) {
+        return Ok(identity);
+    }
+    handle.consistent(|| identity::file_identity(path, |_, _| {}))
+}
+
+/// The saved session of `handle`'s file: by path if it hasn't changed, else
+/// by content hash for a file that was moved or copied.
+fn find(app: &AppHandle, handle: &FileHandle) -> Result<Option<SessionState>> {
+    // Pasted text, snapshots and remote files have no lasting identity
+    let Some(path) = handle.local_path() else {
+        return Ok(None);
+    };
+    let sessions = app.state::<Sessions>();
+    if let Some(session) = sessions.by_path(path) {
+        return Ok(Some(session.state));
+    }
+    // Only hash the file if some session could match it
+    let len = handle.len();
+    if !sessions.entries.lock().unwrap().iter().any(|s| s.identity.size == len) {
+        return Ok(None);
+    }
+    let identity = identity_of(app, handle, path)?;
+    Ok(sessions.by_content(&identity).map(|s| s.state))
+}
+
+/// Look for a saved session of a newly opened file in the background, and
+/// send it to the file's window as `session-restored` if there is one.
+pub fn restore(app: &AppHandle, handle: Arc<FileHandle>) {
+    let app = app.clone();
+    std::thread::spawn(move || {
+        if let Ok(Some(state)) = find(&app, &handle) {
+            let restored = SessionRestored {
+                file_id: handle.id,
+                state,
+            };
+            let _ = app.emit_to(handle.window(), "session-restored", restored);
+        }
+    });
+}
+
+/// Save where the user is in a file. Ignored for files without a lasting
+/// identity: pasted text, snapshots and remote files.
+#[tauri::command]
+pub async fn save_session(
+    app: AppHandle,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    state: SessionState,
+) -> Result<(), XmlReaderError> {
+    let handle = files.get(file_id)?;
+    blocking(move || {
+        let Some(path) = handle.local_path() else {
+            return Ok(());
+        };
+        let save = || app.state::<Sessions>().put(identity_of(&app, &handle, path)?, state);
+        save().map_err(|e| XmlReaderError::in_file(&handle, e))
+    })
+    .await
+}
+
+/// The session saved for a file, also sent as `session-restored` when it's
+/// opened.
+#[tauri::command]
+pub async fn load_session(
+    app: AppHandle,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+) -> Result<Option<SessionState>, XmlReaderError> {
+    let handle = files.get(file_id)?;
+    blocking(move || find(&app, &handle).map_err(|e| XmlReaderError::in_file(&handle, e))).await
+}
@@ -0,0 +1,160 @@
+use crate::error::XmlReaderError;
+use crate::export::RedactRule;
+use crate::files::ParsingMode;
+use crate::io::{self, MemorySettings, ReaderLimits};
+use anyhow::{Context, Result};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// User preferences, persisted as JSON in the app data directory. Every
+/// command started after they change uses the new values.
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+#[serde(default)]
+pub struct Settings {
+    /// Bytes of text before and after an element found by search or
+    /// navigation.
+    pub context_len: u64,
+    /// How far back from where the parser saw an element to look for its `<`.
+    pub scan_back: u64,
+    /// Attributes a search of type `"any"` looks in, besides tag names.
+    pub search_attributes: Vec<String>,
+    /// Parsing mode files are opened in.
+    pub parsing_mode: ParsingMode,
+    /// Bytes navigation reads looking for an element's end tag; 0 for no
+    /// limit. See `xml_ops::set_element_scan_limit`.
+    pub element_scan_limit: u64,
+    pub memory: MemorySettings,
+    pub limits: ReaderLimits,
+    /// Redaction rules offered for `export_redacted`.
+    pub redaction_rules: Vec<RedactRule>,
+    pub search_presets: Vec<SearchPreset>,
+}
+
+/// A saved search, run with `search_node`.
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+pub struct SearchPreset {
+    pub name: String,
+    pub query: String,
+    pub search_type: String,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            context_len: 2000,
+            scan_back: 2048,
+            search_attributes: ["guid", "id", "name", "eaid", "value", "guidref"].map(String::from).to_vec(),
+            parsing_mode: ParsingMode::Lenient,
+            element_scan_limit: 10 * 1024 * 1024,
+            memory: MemorySettings::default(),
+            limits: ReaderLimits::default(),
+            redaction_rules: Vec::new(),
+            search_presets: Vec::new(),
+        }
+    }
+}
+
+fn settings() -> &'static Mutex<Arc<Settings>> {
+    static SETTINGS: OnceLock<Mutex<Arc<Settings>>> = OnceLock::new();
+    SETTINGS.get_or_init(|| Mutex::new(Arc::new(Settings::default())))
+}
+
+// Where settings are saved, set once by `load`
+static STORE: OnceLock<PathBuf> = OnceLock::new();
+
+/// The settings in effect. Take them once per command, not per event.
+pub fn current() -> Arc<Settings> {
+    settings().lock().unwrap().clone()
+}
+
+/// Load the settings saved in `store` and apply them; a missing or
+/// unreadable file leaves the defaults.
+pub fn load(store: Option<PathBuf>) {
+    let loaded: Settings = store
+        .as_ref()
+        .and_then(|path| std::fs::read(path).ok())
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+        .unwrap_or_default();
+    if let Some(store) = store {
+        let _ = STORE.set(store);
+    }
+    apply(loaded);
+}
+
+/// Change the settings with `change`, apply and persist them.
+pub fn update(change: impl FnOnce(&mut Settings)) -> Result<()> {
+    let mut updated = (*current()).clone();
+    change(&mut updated);
+    apply(updated.clone());
+    save(&updated)
+}
+
+/// Make `settings` the current ones, and push those kept elsewhere for
+/// cheap access in hot loops to where they are read.
+fn apply(settings: Settings) {
+    io::set_memory_settings(settings.memory);
+    io::set_reader_limits(settings.limits);
+    *self::settings().lock().unwrap() = Arc::new(settings);
+}
+
+fn save(settings: &Settings) -> Result<()> {
+    let Some(store) = STORE.get() else {
+        return Ok(());
+    };
+    if let Some(dir) = store.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    // Write then rename, so a crash never leaves half-written settings
+    let tmp = store.with_extension("json.tmp");
+    std::fs::write(&tmp, serde_json::to_vec_pretty(settings)?)?;
+    std::fs::rename(&tmp, store)?;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_settings() -> Result<Settings, XmlReaderError> {
+    Ok((*current()).clone())
+}
+
+/// Replace all settings. Fields left out keep their defaults.
+#[tauri::command]
+pub async fn set_settings(settings: Settings) -> Result<(), XmlReaderError> {
+    update(|current| *current = settings).map_err(XmlReaderError::from)
+}
+
+/// Write the current settings to `out_path` as JSON, to share a standard
+/// configuration with other machines.
+#[tauri::command]
+pub async fn export_settings(out_path: String) -> Result<(), XmlReaderError> {
+    let bytes = serde_json::to_vec_pretty(&*current()).map_err(anyhow::Error::from)?;
+    std::fs::write(&out_path, bytes).with_context(|| format!("Could not write {}", out_path))?;
+    Ok(())
+}
+
+/// Apply settings written by `export_settings`. Only the fields in the file
+/// change, so a shared file can carry just the search attributes, say, and
+/// leave machine-specific ones like memory limits alone. Returns the
+/// settings now in effect.
+#[tauri::command]
+pub async fn import_settings(path: String) -> Result<Settings, XmlReaderError> {
+    let bytes = std::fs::read(&path).with_context(|| format!("Could not read {}", path))?;
+    let imported: serde_json::Map<String, serde_json::Value> =
+        serde_json::from_slice(&bytes).map_err(|e| anyhow::anyhow!("{} is not a settings file: {}", path, e))?;
+    let serde_json::Value::Object(mut merged) = serde_json::to_value(&*current()).map_err(anyhow::Error::from)? else {
+        unreachable!("settings serialize as an object");
+    };
+    let mut known = 0;
+    for (key, value) in imported {
+        if merged.contains_key(&key) {
+            merged.insert(key, value);
+            known += 1;
+        }
+    }
+    if known == 0 {
+        return Err(anyhow::anyhow!("{} has no settings in it", path).into());
+    }
+    let settings: Settings = serde_json::from_value(serde_json::Value::Object(merged))
+        .map_err(|e| anyhow::anyhow!("Invalid settings in {}: {}", path, e))?;
+    update(|current| *current = settings.clone())?;
+    Ok(settings)
+}
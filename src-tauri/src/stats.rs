@@ -0,0 +1,759 @@
+use crate::error::XmlReaderError;
+use crate::files::{FileHandle, FileId, FileRegistry};
+use crate::io::XmlReader;
+use crate::ops::{self, OpId};
+use anyhow::Result;
+use quick_xml::events::Event;
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, BinaryHeap, HashMap, HashSet};
+use tauri::{State, WebviewWindow};
+use xxhash_rust::xxh3::Xxh3;
+
+#[derive(serde::Serialize)]
+pub struct LargestElement {
+    name: String,
+    offset: u64,
+    bytes: u64,
+}
+
+#[derive(serde::Serialize)]
+pub struct Statistics {
+    file_len: u64,
+    elements: u64,
+    attributes: u64,
+    /// Text and CDATA content, whitespace-only runs excluded, as raw bytes.
+    text_bytes: u64,
+    distinct_tags: u64,
+    /// The root element is at depth 1.
+    max_depth: u32,
+    avg_depth: f64,
+    /// Below the root, which always spans the whole document.
+    largest_element: Option<LargestElement>,
+    comments: u64,
+    cdata_sections: u64,
+    processing_instructions: u64,
+}
+
+/// Counts for the file-properties dialog, from one pass over the file.
+/// Emits `stats-progress` (0-100).
+#[tauri::command]
+pub async fn get_statistics(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    op_id: Option<OpId>,
+) -> Result<Statistics, XmlReaderError> {
+    let handle = files.get(file_id)?;
+    ops::run(window, op_id, "stats", move |op| {
+        get_statistics_internal(&handle, |pct| {
+            op.progress(pct);
+        })
+        .map_err(|e| XmlReaderError::in_file(&handle, e))
+    })
+    .await
+}
+
+fn get_statistics_internal(handle: &FileHandle, mut progress: impl FnMut(u64)) -> Result<Statistics> {
+    let file = handle.reader()?;
+    let file_len = file.len();
+    let mut reader = XmlReader::scan(file);
+    reader.check_end_names(false);
+    let mut buf = Vec::new();
+    let mut last_pct = 0;
+
+    let mut stats = Statistics {
+        file_len,
+        elements: 0,
+        attributes: 0,
+        text_bytes: 0,
+        distinct_tags: 0,
+        max_depth: 0,
+        avg_depth: 0.0,
+        largest_element: None,
+        comments: 0,
+        cdata_sections: 0,
+        processing_instructions: 0,
+    };
+    let mut tags: HashSet<Vec<u8>> = HashSet::new();
+    // Start offset of each open element
+    let mut stack: Vec<u64> = Vec::new();
+    let mut depth_sum = 0u64;
+    // (offset, bytes) of the largest element below the root
+    let mut largest: Option<(u64, u64)> = None;
+
+    loop {
+        buf.clear();
+        let pos_before = reader.position();
+        let event = reader.read_event_into(&mut buf)?;
+        let empty = matches!(event, Event::Empty(_));
+        match event {
+            Event::Start(e) | Event::Empty(e) => {
+                let depth = stack.len() as u32 + 1;
+                stats.elements += 1;
+                stats.attributes += e.attributes().with_checks(false).count() as u64;
+                stats.max_depth = stats.max_depth.max(depth);
+                depth_sum += depth as u64;
+                if !tags.contains(e.name().as_ref()) {
+                    tags.insert(e.name().as_ref().to_vec());
+                }
+                if empty {
+                    if depth > 1 {
+                        consider(&mut largest, pos_before, reader.position());
+                    }
+                } else {
+                    stack.push(pos_before);
+                }
+            }
+            Event::End(_) => {
+                if let Some(start) = stack.pop().filter(|_| !stack.is_empty()) {
+                    consider(&mut largest, start, reader.position());
+                }
+            }
+            Event::Text(e) if !e.iter().all(u8::is_ascii_whitespace) => stats.text_bytes += e.len() as u64,
+            Event::CData(e) => {
+                stats.cdata_sections += 1;
+                stats.text_bytes += e.len() as u64;
+            }
+            Event::Comment(_) => stats.comments += 1,
+            Event::PI(_) => stats.processing_instructions += 1,
+            Event::Eof => break,
+            _ => {}
+        }
+
+        let pct = reader.position() * 100 / file_len.max(1);
+        if pct > last_pct {
+            last_pct = pct;
+            progress(pct);
+        }
+    }
+    progress(100);
+
+    stats.distinct_tags = tags.len() as u64;
+    if stats.elements > 0 {
+        stats.avg_depth = depth_sum as f64 / stats.elements as f64;
+    }
+    if let Some((offset, bytes)) = largest {
+        stats.largest_element = Some(LargestElement {
+            name: element_name(handle, offset)?,
+            offset,
+            bytes,
+        });
+    }
+    Ok(stats)
+}
+
+fn consider(largest: &mut Option<(u64, u64)>, start: u64, end: u64) {
+    if largest.is_none_or(|(_, bytes)| end - start > bytes) {
+        *largest = Some((start, end - start));
+    }
+}
+
+/// Name of the element starting at `offset`, read again rather than kept
+/// for every element during the scan.
+fn element_name(handle: &FileHandle, offset: u64) -> Result<String> {
+    let mut reader = crate::format::element_reader(handle, offset)?;
+    let mut buf = Vec::new();
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(e) | Event::Empty(e) => return Ok(String::from_utf8_lossy(e.name().as_ref()).into_owned()),
+            Event::Eof => return Err(anyhow::anyhow!("No element at offset {}", offset)),
+            _ => buf.clear(),
+        }
+    }
+}
+
+/// Distinct values `attribute_histogram` keeps counting; values first seen
+/// after that are only counted in `total`.
+const HISTOGRAM_MAX_VALUES: usize = 1_000_000;
+
+#[derive(serde::Serialize)]
+pub struct ValueCount {
+    value: String,
+    count: u64,
+}
+
+#[derive(serde::Serialize)]
+pub struct AttributeHistogram {
+    /// Most frequent first, at most `top_n`.
+    values: Vec<ValueCount>,
+    distinct: u64,
+    /// Elements named `tag` that have the attribute.
+    total: u64,
+    /// Elements named `tag` without it.
+    missing: u64,
+    /// More than `HISTOGRAM_MAX_VALUES` distinct values; the rest weren't counted.
+    truncated: bool,
+}
+
+/// Count the values of attribute `attr` on every `tag` element in one pass.
+/// Both names match either the qualified or the local name. `top_n` of 0
+/// returns every value. Emits `stats-progress` (0-100).
+#[tauri::command]
+pub async fn attribute_histogram(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    tag: String,
+    attr: String,
+    top_n: usize,
+    op_id: Option<OpId>,
+) -> Result<AttributeHistogram, XmlReaderError> {
+    let handle = files.get(file_id)?;
+    ops::run(window, op_id, "stats", move |op| {
+        attribute_histogram_internal(&handle, &tag, &attr, top_n, |pct| {
+            op.progress(pct);
+        })
+        .map_err(|e| XmlReaderError::in_file(&handle, e))
+    })
+    .await
+}
+
+fn attribute_histogram_internal(
+    handle: &FileHandle,
+    tag: &str,
+    attr: &str,
+    top_n: usize,
+    mut progress: impl FnMut(u64),
+) -> Result<AttributeHistogram> {
+    let file = handle.reader()?;
+    let file_len = file.len().max(1);
+    let mut reader = XmlReader::scan(file);
+    reader.check_end_names(false);
+    let mut buf = Vec::new();
+    let mut last_pct = 0;
+    let (tag, attr) = (tag.as_bytes(), attr.as_bytes());
+
+    let mut counts: HashMap<Vec<u8>, u64> = HashMap::new();
+    let (mut total, mut missing) = (0u64, 0u64);
+    let mut truncated = false;
+
+    loop {
+        buf.clear();
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(e) | Event::Empty(e) if e.name().as_ref() == tag || e.local_name().as_ref() == tag => {
+                let value = e
+                    .attributes()
+                    .with_checks(false)
+                    .flatten()
+                    .find(|a| a.key.as_ref() == attr || a.key.local_name().as_ref() == attr)
+                    .map(|a| a.value.into_owned());
+                match value {
+                    Some(value) => {
+                        total += 1;
+                        if let Some(count) = counts.get_mut(&value) {
+                            *count += 1;
+                        } else if counts.len() < HISTOGRAM_MAX_VALUES {
+                            counts.insert(value, 1);
+                        } else {
+                            truncated = true;
+                        }
+                    }
+                    None => missing += 1,
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+
+        let pct = reader.position() * 100 / file_len;
+        if pct > last_pct {
+            last_pct = pct;
+            progress(pct);
+        }
+    }
+    progress(100);
+
+    let distinct = counts.len() as u64;
+    let mut values: Vec<ValueCount> = counts
+        .into_iter()
+        .map(|(value, count)| ValueCount {
+            value: String::from_utf8_lossy(&value).into_owned(),
+            count,
+        })
+        .collect();
+    values.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.value.cmp(&b.value)));
+    if top_n > 0 {
+        values.truncate(top_n);
+    }
+    Ok(AttributeHistogram {
+        values,
+        distinct,
+        total,
+        missing,
+        truncated,
+    })
+}
+
+/// Children kept per element by `size_breakdown`, largest first; the rest are
+/// summed into `other_bytes`.
+const BREAKDOWN_MAX_CHILDREN: usize = 500;
+
+#[derive(serde::Serialize)]
+pub struct SizeNode {
+    name: String,
+    offset: u64,
+    bytes: u64,
+    /// Share of the parent's bytes; 100 for the element asked for.
+    percent: f64,
+    /// Largest first.
+    children: Vec<SizeNode>,
+    /// Children beyond `BREAKDOWN_MAX_CHILDREN`.
+    other_count: u64,
+    other_bytes: u64,
+}
+
+/// Sizes of the subtrees under the element at `offset`, `depth` levels deep,
+/// each with its share of its parent: a treemap of where the bytes are.
+/// Emits `stats-progress` (0-100) over the element.
+#[tauri::command]
+pub async fn size_breakdown(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    offset: u64,
+    depth: u32,
+    op_id: Option<OpId>,
+) -> Result<SizeNode, XmlReaderError> {
+    let handle = files.get(file_id)?;
+    ops::run(window, op_id, "stats", move |op| {
+        size_breakdown_internal(&handle, offset, depth, |pct| {
+            op.progress(pct);
+        })
+        .map_err(|e| XmlReaderError::in_file(&handle, e))
+    })
+    .await
+}
+
+fn size_breakdown_internal(handle: &FileHandle, offset: u64, depth: u32, mut progress: impl FnMut(u64)) -> Result<SizeNode> {
+    let mut reader = crate::format::element_reader(handle, offset)?;
+    let span = (handle.len() - offset).max(1);
+    let mut buf = Vec::new();
+    let mut last_pct = 0;
+    let node = |name: &[u8], offset: u64| SizeNode {
+        name: String::from_utf8_lossy(name).into_owned(),
+        offset,
+        bytes: 0,
+        percent: 0.0,
+        children: Vec::new(),
+        other_count: 0,
+        other_bytes: 0,
+    };
+    // Elements open and within `depth`, the requested one first
+    let mut stack: Vec<SizeNode> = Vec::new();
+    // Open elements nested too deep to be listed
+    let mut hidden = 0u32;
+
+    loop {
+        buf.clear();
+        let pos_before = reader.position();
+        let event = reader.read_event_into(&mut buf)?;
+        let closed = match event {
+            Event::Start(e) if stack.len() as u32 <= depth => {
+                stack.push(node(e.name().as_ref(), pos_before));
+                None
+            }
+            Event::Start(_) => {
+                hidden += 1;
+                None
+            }
+            Event::Empty(e) if stack.len() as u32 <= depth => {
+                let mut leaf = node(e.name().as_ref(), pos_before);
+                leaf.bytes = reader.position() - pos_before;
+                Some(leaf)
+            }
+            Event::End(_) if hidden > 0 => {
+                hidden -= 1;
+                None
+            }
+            Event::End(_) => stack.pop().map(|mut open| {
+                open.bytes = reader.position() - open.offset;
+                open
+            }),
+            Event::Eof => return Err(anyhow::anyhow!("Element is not closed before the end of the file")),
+            _ => None,
+        };
+
+        if let Some(mut closed) = closed {
+            finish(&mut closed);
+            match stack.last_mut() {
+                Some(parent) => parent.children.push(closed),
+                None => {
+                    closed.percent = 100.0;
+                    progress(100);
+                    return Ok(closed);
+                }
+            }
+        }
+
+        let pct = (reader.position() - offset) * 100 / span;
+        if pct > last_pct {
+            last_pct = pct;
+            progress(pct);
+        }
+    }
+}
+
+/// Work out the children's shares of `node` once its size is known, largest
+/// first, and fold the smallest into `other_*`.
+fn finish(node: &mut SizeNode) {
+    let bytes = node.bytes.max(1) as f64;
+    for child in &mut node.children {
+        child.percent = child.bytes as f64 * 100.0 / bytes;
+    }
+    node.children.sort_by_key(|c| Reverse(c.bytes));
+    if node.children.len() > BREAKDOWN_MAX_CHILDREN {
+        let rest = node.children.split_off(BREAKDOWN_MAX_CHILDREN);
+        node.other_count = rest.len() as u64;
+        node.other_bytes = rest.iter().map(|c| c.bytes).sum();
+    }
+}
+
+/// Paths listed by `structure_fingerprint`; all of them go into the hash.
+const FINGERPRINT_MAX_PATHS: usize = 10_000;
+
+#[derive(serde::Serialize)]
+pub struct PathShape {
+    /// `/root/child/...`
+    path: String,
+    /// Attribute names seen on elements at this path, sorted.
+    attributes: Vec<String>,
+    count: u64,
+}
+
+#[derive(serde::Serialize)]
+pub struct StructureFingerprint {
+    /// Hex xxh3 of the distinct paths and their attribute names: equal for
+    /// files with the same shape, whatever their values and element counts.
+    hash: String,
+    /// Sorted by path.
+    paths: Vec<PathShape>,
+    distinct_paths: u64,
+}
+
+/// Summarise the tag hierarchy of a file, ignoring text, attribute values,
+/// order and how often each element repeats, so two exports can be checked
+/// for the same shape before diffing them. Emits `stats-progress` (0-100).
+#[tauri::command]
+pub async fn structure_fingerprint(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    op_id: Option<OpId>,
+) -> Result<StructureFingerprint, XmlReaderError> {
+    let handle = files.get(file_id)?;
+    ops::run(window, op_id, "stats", move |op| {
+        structure_fingerprint_internal(&handle, |pct| {
+            op.progress(pct);
+        })
+        .map_err(|e| XmlReaderError::in_file(&handle, e))
+    })
+    .await
+}
+
+fn structure_fingerprint_internal(handle: &FileHandle, mut progress: impl FnMut(u64)) -> Result<StructureFingerprint> {
+    struct Shape {
+        path: String,
+        attributes: BTreeSet<Vec<u8>>,
+        count: u64,
+    }
+
+    let file = handle.reader()?;
+    let file_len = file.len().max(1);
+    let mut reader = XmlReader::scan(file);
+    reader.check_end_names(false);
+    let mut buf = Vec::new();
+    let mut last_pct = 0;
+
+    let mut shapes: Vec<Shape> = Vec::new();
+    // (parent shape, name) -> shape, so paths aren't rebuilt per element
+    let mut ids: HashMap<(usize, Vec<u8>), usize> = HashMap::new();
+    // Shape of each open element
+    let mut stack: Vec<usize> = Vec::new();
+
+    loop {
+        buf.clear();
+        let event = reader.read_event_into(&mut buf)?;
+        let empty = matches!(event, Event::Empty(_));
+        match event {
+            Event::Start(e) | Event::Empty(e) => {
+                let parent = stack.last().copied().unwrap_or(usize::MAX);
+                let key = (parent, e.name().as_ref().to_vec());
+                let id = match ids.get(&key) {
+                    Some(&id) => id,
+                    None => {
+                        let prefix = shapes.get(parent).map_or("", |p| p.path.as_str());
+                        shapes.push(Shape {
+                            path: format!("{}/{}", prefix, String::from_utf8_lossy(&key.1)),
+                            attributes: BTreeSet::new(),
+                            count: 0,
+                        });
+                        ids.insert(key, shapes.len() - 1);
+                        shapes.len() - 1
+                    }
+                };
+                let shape = &mut shapes[id];
+                shape.count += 1;
+                for attr in e.attributes().with_checks(false).flatten() {
+                    if !shape.attributes.contains(attr.key.as_ref()) {
+                        shape.attributes.insert(attr.key.as_ref().to_vec());
+                    }
+                }
+                if !empty {
+                    stack.push(id);
+                }
+            }
+            Event::End(_) => {
+                stack.pop();
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+
+        let pct = reader.position() * 100 / file_len;
+        if pct > last_pct {
+            last_pct = pct;
+            progress(pct);
+        }
+    }
+    progress(100);
+
+    shapes.sort_by(|a, b| a.path.cmp(&b.path));
+    let mut hasher = Xxh3::new();
+    for shape in &shapes {
+        hasher.update(shape.path.as_bytes());
+        for attr in &shape.attributes {
+            hasher.update(b"@");
+            hasher.update(attr);
+        }
+        hasher.update(b"\n");
+    }
+    let distinct_paths = shapes.len() as u64;
+    shapes.truncate(FINGERPRINT_MAX_PATHS);
+    Ok(StructureFingerprint {
+        hash: format!("{:016x}", hasher.digest()),
+        paths: shapes
+            .into_iter()
+            .map(|shape| PathShape {
+                path: shape.path,
+                attributes: shape.attributes.iter().map(|a| String::from_utf8_lossy(a).into_owned()).collect(),
+                count: shape.count,
+            })
+            .collect(),
+        distinct_paths,
+    })
+}
+
+/// Most regions `tag_timeline` splits a file into.
+const TIMELINE_MAX_BUCKETS: usize = 10_000;
+
+#[derive(serde::Serialize)]
+pub struct TimelineBucket {
+    start: u64,
+    end: u64,
+    count: u64,
+}
+
+#[derive(serde::Serialize)]
+pub struct TagTimeline {
+    buckets: Vec<TimelineBucket>,
+    total: u64,
+}
+
+/// Split the file into `buckets` equal byte ranges (at most
+/// `TIMELINE_MAX_BUCKETS`) and count the `tag` elements starting in each, to
+/// see where in a long log an event type clusters. `tag` matches the
+/// qualified or the local name. Emits `stats-progress` (0-100).
+#[tauri::command]
+pub async fn tag_timeline(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    tag: String,
+    buckets: usize,
+    op_id: Option<OpId>,
+) -> Result<TagTimeline, XmlReaderError> {
+    let handle = files.get(file_id)?;
+    ops::run(window, op_id, "stats", move |op| {
+        tag_timeline_internal(&handle, &tag, buckets, |pct| {
+            op.progress(pct);
+        })
+        .map_err(|e| XmlReaderError::in_file(&handle, e))
+    })
+    .await
+}
+
+fn tag_timeline_internal(handle: &FileHandle, tag: &str, buckets: usize, mut progress: impl FnMut(u64)) -> Result<TagTimeline> {
+    let file = handle.reader()?;
+    let file_len = file.len();
+    let mut reader = XmlReader::scan(file);
+    reader.check_end_names(false);
+    let mut buf = Vec::new();
+    let mut last_pct = 0;
+    let tag = tag.as_bytes();
+
+    let count = buckets.clamp(1, TIMELINE_MAX_BUCKETS) as u64;
+    let bounds = |i: u64| i * file_len / count;
+    let mut buckets: Vec<TimelineBucket> = (0..count)
+        .map(|i| TimelineBucket {
+            start: bounds(i),
+            end: bounds(i + 1),
+            count: 0,
+        })
+        .collect();
+    let mut total = 0u64;
+
+    loop {
+        buf.clear();
+        let pos_before = reader.position();
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(e) | Event::Empty(e) if e.name().as_ref() == tag || e.local_name().as_ref() == tag => {
+                total += 1;
+                let i = (pos_before as u128 * count as u128 / file_len.max(1) as u128) as u64;
+                buckets[i.min(count - 1) as usize].count += 1;
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+
+        let pct = reader.position() * 100 / file_len.max(1);
+        if pct > last_pct {
+            last_pct = pct;
+            progress(pct);
+        }
+    }
+    progress(100);
+
+    Ok(TagTimeline { buckets, total })
+}
+
+/// Text nodes `text_stats` lists, longest first.
+const TEXT_STATS_LONGEST: usize = 20;
+
+/// (bytes, offset, parent offset, cdata) of a text node.
+type TextCandidate = (u64, u64, Option<u64>, bool);
+
+#[derive(serde::Serialize)]
+pub struct TextNode {
+    offset: u64,
+    bytes: u64,
+    /// Element the text is directly in, `None` outside the root.
+    parent: Option<String>,
+    parent_offset: Option<u64>,
+    cdata: bool,
+}
+
+#[derive(serde::Serialize)]
+pub struct TextStats {
+    /// Text runs and CDATA sections, whitespace-only ones excluded.
+    text_nodes: u64,
+    text_bytes: u64,
+    /// Whitespace-separated words.
+    tokens: u64,
+    longest: Vec<TextNode>,
+}
+
+/// How much text the file (or the element at `offset`) holds, and the
+/// longest text nodes, to find elements carrying megabytes of inline data.
+/// Emits `stats-progress` (0-100).
+#[tauri::command]
+pub async fn text_stats(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    offset: Option<u64>,
+    op_id: Option<OpId>,
+) -> Result<TextStats, XmlReaderError> {
+    let handle = files.get(file_id)?;
+    ops::run(window, op_id, "stats", move |op| {
+        text_stats_internal(&handle, offset, |pct| {
+            op.progress(pct);
+        })
+        .map_err(|e| XmlReaderError::in_file(&handle, e))
+    })
+    .await
+}
+
+fn text_stats_internal(handle: &FileHandle, offset: Option<u64>, mut progress: impl FnMut(u64)) -> Result<TextStats> {
+    let start = offset.unwrap_or(0);
+    let mut reader = match offset {
+        Some(offset) => crate::format::element_reader(handle, offset)?,
+        None => {
+            let mut reader = XmlReader::scan(handle.reader()?);
+            reader.check_end_names(false);
+            reader
+        }
+    };
+    let span = (handle.len() - start).max(1);
+    let mut buf = Vec::new();
+    let mut last_pct = 0;
+
+    let mut stats = TextStats {
+        text_nodes: 0,
+        text_bytes: 0,
+        tokens: 0,
+        longest: Vec::new(),
+    };
+    // Start offset of each open element
+    let mut stack: Vec<u64> = Vec::new();
+    // The longest nodes so far, smallest on top
+    let mut longest: BinaryHeap<Reverse<TextCandidate>> = BinaryHeap::new();
+
+    loop {
+        buf.clear();
+        let pos_before = reader.position();
+        let event = reader.read_event_into(&mut buf)?;
+        let text = match &event {
+            Event::Start(_) => {
+                stack.push(pos_before);
+                None
+            }
+            Event::End(_) => {
+                stack.pop();
+                None
+            }
+            Event::Text(e) if !e.iter().all(u8::is_ascii_whitespace) => Some((&e[..], false)),
+            Event::CData(e) => Some((&e[..], true)),
+            Event::Eof if offset.is_some() => {
+                return Err(anyhow::anyhow!("Element is not closed before the end of the file"))
+            }
+            Event::Eof => break,
+            _ => None,
+        };
+
+        if let Some((text, cdata)) = text {
+            let bytes = text.len() as u64;
+            stats.text_nodes += 1;
+            stats.text_bytes += bytes;
+            stats.tokens += text.split(u8::is_ascii_whitespace).filter(|word| !word.is_empty()).count() as u64;
+            if longest.len() < TEXT_STATS_LONGEST || longest.peek().is_some_and(|Reverse(min)| bytes > min.0) {
+                longest.push(Reverse((bytes, pos_before, stack.last().copied(), cdata)));
+                if longest.len() > TEXT_STATS_LONGEST {
+                    longest.pop();
+                }
+            }
+        }
+
+        // The element at `offset` is done
+        if offset.is_some() && stack.is_empty() && matches!(event, Event::End(_) | Event::Empty(_)) {
+            break;
+        }
+
+        let pct = (reader.position() - start) * 100 / span;
+        if pct > last_pct {
+            last_pct = pct;
+            progress(pct);
+        }
+    }
+    progress(100);
+
+    for Reverse((bytes, offset, parent_offset, cdata)) in longest.into_sorted_vec() {
+        stats.longest.push(TextNode {
+            offset,
+            bytes,
+            parent: parent_offset.map(|parent| element_name(handle, parent)).transpose()?,
+            parent_offset,
+            cdata,
+        });
+    }
+    Ok(stats)
+}
@@ -0,0 +1,1381 @@
+use crate::error::XmlReaderError;
+use crate::export;
+use crate::files::{FileHandle, FileId, FileRegistry};
+use crate::io::{self, XmlReader};
+use crate::ops::{self, OpId};
+use anyhow::{Context, Result};
+use crate::query::run_query_internal;
+use quick_xml::events::Event;
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Seek, SeekFrom};
+use tauri::{State, WebviewWindow};
+
+/// Errors reported by `check_wellformed` before it stops.
+const MAX_WELLFORMED_ERRORS: usize = 1000;
+/// Bytes shown on each side of an error.
+const CONTEXT_BYTES: u64 = 60;
+
+#[derive(serde::Serialize)]
+pub struct WellFormedError {
+    offset: u64,
+    /// 1-based.
+    line: u64,
+    /// 1-based, in characters.
+    column: u64,
+    message: String,
+    context: String,
+}
+
+#[derive(serde::Serialize)]
+pub struct WellFormedness {
+    errors: Vec<WellFormedError>,
+    /// Stopped after `MAX_WELLFORMED_ERRORS`.
+    truncated: bool,
+    elements: u64,
+}
+
+/// Parse the whole file and report every well-formedness error found, in file
+/// order: mismatched or unclosed tags, malformed or duplicate attributes,
+/// characters XML doesn't allow, invalid UTF-8, undefined or broken entity
+/// references, and content outside the root element. After a mismatched end
+/// tag the check resyncs on the nearest open element of that name, so one
+/// mistake doesn't cascade. Emits `validate-progress` (0-100).
+#[tauri::command]
+pub async fn check_wellformed(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    op_id: Option<OpId>,
+) -> Result<WellFormedness, XmlReaderError> {
+    let handle = files.get(file_id)?;
+    ops::run(window, op_id, "validate", move |op| {
+        check_wellformed_internal(&handle, |pct| {
+            op.progress(pct);
+        })
+        .map_err(|e| XmlReaderError::in_file(&handle, e))
+    })
+    .await
+}
+
+fn check_wellformed_internal(handle: &FileHandle, mut progress: impl FnMut(u64)) -> Result<WellFormedness> {
+    let mut head = Vec::with_capacity(1024);
+    handle.reader()?.take(1024).read_to_end(&mut head)?;
+    let utf8 = io::detect_encoding(&head) == encoding_rs::UTF_8;
+
+    let file = handle.reader()?;
+    let file_len = file.len().max(1);
+    let mut reader = XmlReader::scan(file);
+    reader.check_end_names(false);
+
+    // (offset, message)
+    let mut errors: Vec<(u64, String)> = Vec::new();
+    // Open elements as (name, offset)
+    let mut stack: Vec<(Vec<u8>, u64)> = Vec::new();
+    let mut entities: HashSet<String> = HashSet::new();
+    let mut seen_root = false;
+    let mut elements = 0u64;
+    let mut buf = Vec::new();
+    let mut last_error_pos = None;
+    let mut last_pct = 0;
+
+    while errors.len() < MAX_WELLFORMED_ERRORS {
+        buf.clear();
+        let pos_before = reader.position();
+        let event = match reader.read_event_into(&mut buf) {
+            Ok(event) => event,
+            Err(e) => {
+                let at = reader.position();
+                errors.push((at, e.to_string()));
+                // Some errors leave the parser where it was
+                if last_error_pos == Some(at) {
+                    break;
+                }
+                last_error_pos = Some(at);
+                continue;
+            }
+        };
+        match &event {
+            Event::Start(e) | Event::Empty(e) => {
+                elements += 1;
+                if stack.is_empty() {
+                    if seen_root {
+                        let name = String::from_utf8_lossy(e.name().as_ref()).into_owned();
+                        errors.push((pos_before, format!("Second root element <{}>", name)));
+                    }
+                    seen_root = true;
+                }
+                for attr in e.attributes() {
+                    match attr {
+                        Ok(attr) => {
+                            // The value's position in the file isn't kept by the
+                            // parser; find it in the raw tag
+                            let at = pos_before + 1 + find(e, &attr.value).unwrap_or(0) as u64;
+                            check_chars(&attr.value, at, utf8, &entities, true, &mut errors);
+                        }
+                        Err(err) => {
+                            errors.push((pos_before, format!("Malformed attribute: {}", err)));
+                            break;
+                        }
+                    }
+                }
+                if matches!(event, Event::Start(_)) {
+                    stack.push((e.name().as_ref().to_vec(), pos_before));
+                }
+            }
+            Event::End(e) => {
+                let name = e.name();
+                match stack.iter().rposition(|(open, _)| open.as_slice() == name.as_ref()) {
+                    Some(i) => {
+                        for (open, at) in stack.drain(i + 1..) {
+                            errors.push((at, format!("<{}> is not closed", String::from_utf8_lossy(&open))));
+                        }
+                        stack.pop();
+                    }
+                    None => {
+                        let expected = stack.last().map_or("no open element".to_string(), |(open, _)| {
+                            format!("</{}>", String::from_utf8_lossy(open))
+                        });
+                        errors.push((pos_before, format!("Unexpected </{}>, expected {}", String::from_utf8_lossy(name.as_ref()), expected)));
+                    }
+                }
+            }
+            Event::Text(e) => {
+                if stack.is_empty() && !e.iter().all(u8::is_ascii_whitespace) {
+                    errors.push((pos_before, "Text outside the root element".to_string()));
+                } else {
+                    check_chars(e, pos_before, utf8, &entities, false, &mut errors);
+                }
+            }
+            Event::CData(e) => {
+                if stack.is_empty() {
+                    errors.push((pos_before, "CDATA outside the root element".to_string()));
+                }
+                check_chars(e, pos_before + 9, utf8, &entities, false, &mut errors);
+            }
+            Event::DocType(e) => entities.extend(declared_entities(e)),
+            Event::Eof => break,
+            _ => {}
+        }
+
+        let pct = reader.position() * 100 / file_len;
+        if pct > last_pct {
+            last_pct = pct;
+            progress(pct);
+        }
+    }
+
+    let truncated = errors.len() >= MAX_WELLFORMED_ERRORS;
+    if !truncated {
+        for (open, at) in stack.drain(..) {
+            errors.push((at, format!("<{}> is never closed", String::from_utf8_lossy(&open))));
+        }
+        if !seen_root {
+            errors.push((0, "No root element".to_string()));
+        }
+    }
+    errors.sort_by_key(|(at, _)| *at);
+    errors.truncate(MAX_WELLFORMED_ERRORS);
+
+    let offsets: Vec<u64> = errors.iter().map(|(at, _)| *at).collect();
+    let positions = line_columns(handle, &offsets)?;
+    let mut source = handle.reader()?;
+    let errors = errors
+        .into_iter()
+        .zip(positions)
+        .map(|((offset, message), (line, column))| {
+            Ok(WellFormedError {
+                offset,
+                line,
+                column,
+                message,
+                context: context_at(&mut source, offset)?,
+            })
+        })
+        .collect::<Result<_>>()?;
+    progress(100);
+    Ok(WellFormedness {
+        errors,
+        truncated,
+        elements,
+    })
+}
+
+/// Offending elements listed per failed rule; the rest are only counted.
+const SCHEMATRON_MAX_OFFSETS: usize = 1000;
+
+#[derive(serde::Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleKind {
+    Assert,
+    Report,
+}
+
+/// One `assert` / `report` of a Schematron schema.
+struct Check {
+    pattern: usize,
+    rule: usize,
+    context: String,
+    kind: RuleKind,
+    id: Option<String>,
+    test: String,
+    message: String,
+}
+
+#[derive(serde::Serialize)]
+pub struct RuleFailure {
+    /// The check's `id`, or `pattern[n]/rule[@context]/assert[m]` style
+    /// position when it has none.
+    rule_id: String,
+    kind: RuleKind,
+    context: String,
+    test: String,
+    message: String,
+    /// Elements failing an assert or matching a report, in file order.
+    offsets: Vec<u64>,
+    count: u64,
+}
+
+#[derive(serde::Serialize)]
+pub struct SchematronResult {
+    checks: usize,
+    failures: Vec<RuleFailure>,
+}
+
+/// Check the file against the asserts and reports of the ISO Schematron
+/// schema at `rules_path`. Rule contexts and tests use the XPath subset of
+/// `run_query` (a context without a leading `/` matches anywhere); on top of
+/// that a test can be a reference check, `@ref = //Element/@guid`, true when
+/// the attribute's value is one of the values the path selects. As in
+/// Schematron, an element is only checked by the first rule of a pattern
+/// whose context it matches. `let`, `value-of` and phases are not supported.
+/// Emits `validate-progress` (0-100).
+#[tauri::command]
+pub async fn validate_schematron(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    rules_path: String,
+    op_id: Option<OpId>,
+) -> Result<SchematronResult, XmlReaderError> {
+    let handle = files.get(file_id)?;
+    ops::run(window, op_id, "validate", move |op| {
+        validate_schematron_internal(&handle, &rules_path, |pct| {
+            op.progress(pct);
+        })
+        .map_err(|e| XmlReaderError::in_file(&handle, e))
+    })
+    .await
+}
+
+fn validate_schematron_internal(handle: &FileHandle, rules_path: &str, mut progress: impl FnMut(u64)) -> Result<SchematronResult> {
+    let schema = std::fs::read_to_string(rules_path).with_context(|| format!("Could not read {}", rules_path))?;
+    let checks = parse_schematron(&schema)?;
+
+    // Elements claimed by an earlier rule of the same pattern, and the
+    // matches of each context, which a pattern's rules share
+    let mut claimed: HashMap<usize, HashSet<u64>> = HashMap::new();
+    let mut contexts: HashMap<String, Vec<u64>> = HashMap::new();
+    let mut failures = Vec::new();
+
+    for (i, check) in checks.iter().enumerate() {
+        let context = if check.context.starts_with('/') { check.context.clone() } else { format!("//{}", check.context) };
+        if !contexts.contains_key(&context) {
+            contexts.insert(context.clone(), query_offsets(handle, &context)?);
+        }
+        let in_context = &contexts[&context];
+        let taken = claimed.entry(check.pattern).or_default();
+        // A rule's elements stay claimed for the rest of the pattern
+        let candidates: Vec<u64> = in_context.iter().copied().filter(|at| !taken.contains(at)).collect();
+
+        let holds = test_holds(handle, &context, &check.test, &candidates)?;
+        let offsets: Vec<u64> = candidates
+            .iter()
+            .zip(holds)
+            .filter(|(_, holds)| *holds == (check.kind == RuleKind::Report))
+            .map(|(at, _)| *at)
+            .collect();
+        if let Some(next) = checks.get(i + 1) {
+            if next.rule != check.rule {
+                taken.extend(candidates);
+            }
+        }
+
+        if !offsets.is_empty() {
+            let kind = match check.kind {
+                RuleKind::Assert => "assert",
+                RuleKind::Report => "report",
+            };
+            failures.push(RuleFailure {
+                rule_id: check.id.clone().unwrap_or_else(|| format!("pattern[{}]/rule[@context='{}']/{}", check.pattern + 1, check.context, kind)),
+                kind: check.kind,
+                context: check.context.clone(),
+                test: check.test.clone(),
+                message: check.message.clone(),
+                count: offsets.len() as u64,
+                offsets: offsets.into_iter().take(SCHEMATRON_MAX_OFFSETS).collect(),
+            });
+        }
+        progress((i as u64 + 1) * 100 / checks.len() as u64);
+    }
+
+    Ok(SchematronResult {
+        checks: checks.len(),
+        failures,
+    })
+}
+
+/// Start offsets of every element `expr` selects.
+fn query_offsets(handle: &FileHandle, expr: &str) -> Result<Vec<u64>> {
+    Ok(run_query_internal(handle, expr, 0, usize::MAX)?.matches.into_iter().map(|m| m.offset).collect())
+}
+
+/// Whether `test` holds for each of the `candidates` (sorted offsets of
+/// elements matching `context`).
+fn test_holds(handle: &FileHandle, context: &str, test: &str, candidates: &[u64]) -> Result<Vec<bool>> {
+    let test = test.trim();
+    // `@attr = /path/@other`: the attribute's value is one of the path's values
+    if let Some((attr, path)) = test.split_once('=').filter(|(l, r)| l.trim().starts_with('@') && r.trim().starts_with('/')) {
+        let values: HashSet<String> = run_query_internal(handle, path.trim(), 0, usize::MAX)?
+            .matches
+            .into_iter()
+            .map(|m| m.value)
+            .collect();
+        let own: HashMap<u64, String> = run_query_internal(handle, &format!("{}/{}", context, attr.trim()), 0, usize::MAX)?
+            .matches
+            .into_iter()
+            .map(|m| (m.offset, m.value))
+            .collect();
+        return Ok(candidates.iter().map(|at| own.get(at).is_some_and(|v| values.contains(v))).collect());
+    }
+    let passing: HashSet<u64> = query_offsets(handle, &format!("{}[{}]", context, test))?.into_iter().collect();
+    Ok(candidates.iter().map(|at| passing.contains(at)).collect())
+}
+
+/// The asserts and reports of a Schematron schema, in document order.
+fn parse_schematron(schema: &str) -> Result<Vec<Check>> {
+    let mut reader = quick_xml::Reader::from_str(schema);
+    let mut checks = Vec::new();
+    let mut pattern = None;
+    let mut context: Option<String> = None;
+    let mut patterns = 0;
+    let mut rules = 0;
+    // Check being read, until its end tag
+    let mut current: Option<Check> = None;
+
+    loop {
+        let event = reader.read_event()?;
+        let local = match &event {
+            Event::Start(e) | Event::Empty(e) => e.local_name().as_ref().to_vec(),
+            Event::End(e) => e.local_name().as_ref().to_vec(),
+            _ => Vec::new(),
+        };
+        match &event {
+            Event::Start(e) | Event::Empty(e) => {
+                let attr = |name: &str| -> Result<Option<String>> {
+                    match e.try_get_attribute(name)? {
+                        Some(a) => Ok(Some(a.unescape_value()?.into_owned())),
+                        None => Ok(None),
+                    }
+                };
+                match local.as_slice() {
+                    b"pattern" => {
+                        pattern = Some(patterns);
+                        patterns += 1;
+                    }
+                    b"rule" => {
+                        context = Some(attr("context")?.ok_or_else(|| anyhow::anyhow!("<rule> without a context"))?);
+                        rules += 1;
+                    }
+                    b"assert" | b"report" => {
+                        let (Some(pattern), Some(context)) = (pattern, &context) else {
+                            return Err(anyhow::anyhow!("<assert> and <report> must be inside a <rule>"));
+                        };
+                        let check = Check {
+                            pattern,
+                            rule: rules,
+                            context: context.clone(),
+                            kind: if local == b"assert" { RuleKind::Assert } else { RuleKind::Report },
+                            id: attr("id")?,
+                            test: attr("test")?.ok_or_else(|| anyhow::anyhow!("<assert> or <report> without a test"))?,
+                            message: String::new(),
+                        };
+                        if matches!(event, Event::Empty(_)) {
+                            checks.push(check);
+                        } else {
+                            current = Some(check);
+                        }
+                    }
+                    b"let" => {
+                        return Err(XmlReaderError::Unsupported {
+                            message: "Schematron <let> variables are not supported".to_string(),
+                        }
+                        .into())
+                    }
+                    _ => {}
+                }
+            }
+            Event::Text(t) => {
+                if let Some(check) = &mut current {
+                    check.message.push_str(&t.unescape()?);
+                }
+            }
+            Event::End(_) => match local.as_slice() {
+                b"assert" | b"report" => {
+                    if let Some(mut check) = current.take() {
+                        check.message = check.message.split_whitespace().collect::<Vec<_>>().join(" ");
+                        checks.push(check);
+                    }
+                }
+                b"rule" => context = None,
+                b"pattern" => pattern = None,
+                _ => {}
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    if checks.is_empty() {
+        return Err(anyhow::anyhow!("The schema has no asserts or reports"));
+    }
+    Ok(checks)
+}
+
+/// ID attributes checked when none are given.
+const DEFAULT_ID_ATTRIBUTES: [&str; 4] = ["id", "guid", "xmi:id", "ea_guid"];
+/// Duplicated values listed by `find_duplicate_ids`.
+const MAX_DUPLICATES: usize = 10_000;
+
+#[derive(serde::Serialize)]
+pub struct Occurrence {
+    offset: u64,
+    attribute: String,
+}
+
+#[derive(serde::Serialize)]
+pub struct DuplicateId {
+    value: String,
+    occurrences: Vec<Occurrence>,
+}
+
+#[derive(serde::Serialize)]
+pub struct DuplicateIds {
+    /// In order of first occurrence.
+    duplicates: Vec<DuplicateId>,
+    /// ID values seen, duplicates included.
+    values: u64,
+    /// More than `MAX_DUPLICATES` values are duplicated.
+    truncated: bool,
+}
+
+/// Report every value of the `attrs` attributes (ASCII case-insensitive;
+/// `id`, `guid`, `xmi:id` and `ea_guid` when empty) that appears more than
+/// once anywhere in the file, whichever of the attributes holds it, with all
+/// its occurrences. Emits `validate-progress` (0-100).
+#[tauri::command]
+pub async fn find_duplicate_ids(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    attrs: Vec<String>,
+    op_id: Option<OpId>,
+) -> Result<DuplicateIds, XmlReaderError> {
+    let handle = files.get(file_id)?;
+    ops::run(window, op_id, "validate", move |op| {
+        find_duplicate_ids_internal(&handle, &attrs, |pct| {
+            op.progress(pct);
+        })
+        .map_err(|e| XmlReaderError::in_file(&handle, e))
+    })
+    .await
+}
+
+fn find_duplicate_ids_internal(handle: &FileHandle, attrs: &[String], progress: impl FnMut(u64)) -> Result<DuplicateIds> {
+    // (element offset, attribute name)
+    type Seen = (u64, Box<[u8]>);
+    // First occurrence of every value, and every occurrence of the duplicated ones
+    let mut first: HashMap<Box<[u8]>, Seen> = HashMap::new();
+    let mut duplicated: HashMap<Box<[u8]>, Vec<Seen>> = HashMap::new();
+    let mut values = 0u64;
+
+    scan_attributes(handle, &attribute_names(attrs, &DEFAULT_ID_ATTRIBUTES), progress, |offset, key, value| {
+        values += 1;
+        match first.get(value) {
+            None => {
+                first.insert(value.into(), (offset, key.into()));
+            }
+            Some(earlier) => duplicated
+                .entry(value.into())
+                .or_insert_with(|| vec![earlier.clone()])
+                .push((offset, key.into())),
+        }
+    })?;
+
+    let truncated = duplicated.len() > MAX_DUPLICATES;
+    let mut duplicates: Vec<DuplicateId> = duplicated
+        .into_iter()
+        .map(|(value, occurrences)| DuplicateId {
+            value: String::from_utf8_lossy(&value).into_owned(),
+            occurrences: occurrences
+                .into_iter()
+                .map(|(offset, key)| Occurrence {
+                    offset,
+                    attribute: String::from_utf8_lossy(&key).into_owned(),
+                })
+                .collect(),
+        })
+        .collect();
+    duplicates.sort_by_key(|d| d.occurrences[0].offset);
+    duplicates.truncate(MAX_DUPLICATES);
+    Ok(DuplicateIds {
+        duplicates,
+        values,
+        truncated,
+    })
+}
+
+/// Reference attributes checked when none are given.
+const DEFAULT_REF_ATTRIBUTES: [&str; 4] = ["idref", "idrefs", "guidref", "xmi:idref"];
+
+#[derive(serde::Serialize, Clone)]
+pub struct BrokenReference {
+    offset: u64,
+    attribute: String,
+    value: String,
+}
+
+#[derive(serde::Serialize)]
+pub struct ReferenceSummary {
+    ids: u64,
+    references: u64,
+    broken: u64,
+}
+
+/// Collect every value of the `id_attrs` attributes, then emit a
+/// `broken-reference` event for each `ref_attrs` reference that matches none
+/// of them, as it is found. Attribute names compare ASCII case-insensitively;
+/// empty lists mean the usual `id`/`guid` and `idref`/`guidref` names. A
+/// value holding several whitespace-separated IDs (IDREFS) is checked per ID,
+/// and a leading `#` is ignored. Emits `validate-progress` (0-100).
+#[tauri::command]
+pub async fn check_references(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    ref_attrs: Vec<String>,
+    id_attrs: Vec<String>,
+    op_id: Option<OpId>,
+) -> Result<ReferenceSummary, XmlReaderError> {
+    let handle = files.get(file_id)?;
+    ops::run(window, op_id, "validate", move |op| {
+        check_references_internal(
+            &handle,
+            &ref_attrs,
+            &id_attrs,
+            |pct| {
+                op.progress(pct);
+            },
+            |broken| {
+                op.emit("broken-reference", broken);
+            },
+        )
+        .map_err(|e| XmlReaderError::in_file(&handle, e))
+    })
+    .await
+}
+
+fn check_references_internal(
+    handle: &FileHandle,
+    ref_attrs: &[String],
+    id_attrs: &[String],
+    mut progress: impl FnMut(u64),
+    mut on_broken: impl FnMut(BrokenReference),
+) -> Result<ReferenceSummary> {
+    let mut ids: HashSet<Box<[u8]>> = HashSet::new();
+    scan_attributes(handle, &attribute_names(id_attrs, &DEFAULT_ID_ATTRIBUTES), |pct| progress(pct / 2), |_, _, value| {
+        ids.insert(value.into());
+    })?;
+
+    let mut references = 0u64;
+    let mut broken = 0u64;
+    scan_attributes(handle, &attribute_names(ref_attrs, &DEFAULT_REF_ATTRIBUTES), |pct| progress(50 + pct / 2), |offset, key, value| {
+        for id in value.split(u8::is_ascii_whitespace).filter(|id| !id.is_empty()) {
+            references += 1;
+            let id = id.strip_prefix(b"#").unwrap_or(id);
+            if !ids.contains(id) {
+                broken += 1;
+                on_broken(BrokenReference {
+                    offset,
+                    attribute: String::from_utf8_lossy(key).into_owned(),
+                    value: String::from_utf8_lossy(id).into_owned(),
+                });
+            }
+        }
+    })?;
+    progress(100);
+
+    Ok(ReferenceSummary {
+        ids: ids.len() as u64,
+        references,
+        broken,
+    })
+}
+
+/// Invalid sequences listed by `audit_encoding`; the rest are only counted.
+const MAX_ENCODING_ISSUES: usize = 1000;
+
+#[derive(serde::Serialize)]
+pub struct EncodingIssue {
+    offset: u64,
+    /// 1-based.
+    line: u64,
+    /// The invalid bytes, as hex.
+    bytes: String,
+}
+
+#[derive(serde::Serialize)]
+pub struct EncodingAudit {
+    /// Encoding from the BOM or declaration, UTF-8 by default.
+    encoding: String,
+    issues: Vec<EncodingIssue>,
+    total: u64,
+}
+
+/// Decode the whole file with its encoding (BOM, then declaration, then
+/// UTF-8) and report every byte sequence that encoding doesn't allow, which
+/// the viewer otherwise shows as replacement characters. Emits
+/// `validate-progress` (0-100).
+#[tauri::command]
+pub async fn audit_encoding(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    op_id: Option<OpId>,
+) -> Result<EncodingAudit, XmlReaderError> {
+    let handle = files.get(file_id)?;
+    ops::run(window, op_id, "validate", move |op| {
+        audit_encoding_internal(&handle, |pct| {
+            op.progress(pct);
+        })
+        .map_err(|e| XmlReaderError::in_file(&handle, e))
+    })
+    .await
+}
+
+fn audit_encoding_internal(handle: &FileHandle, mut progress: impl FnMut(u64)) -> Result<EncodingAudit> {
+    let mut source = handle.reader()?;
+    let file_len = source.len().max(1);
+    let mut head = Vec::with_capacity(1024);
+    (&mut source).take(1024).read_to_end(&mut head)?;
+    let encoding = io::detect_encoding(&head);
+    source.seek(SeekFrom::Start(0))?;
+
+    // The BOM is removed, not reported
+    let mut decoder = encoding.new_decoder_with_bom_removal();
+    let mut input = vec![0; 1024 * 1024];
+    // End of the previous chunk, for sequences cut by a chunk boundary
+    let mut carry = Vec::new();
+    let mut output = vec![0; 3 * input.len() + 16];
+    let (mut offset, mut line) = (0u64, 1u64);
+    let mut issues = Vec::new();
+    let mut total = 0u64;
+    let mut last_pct = 0;
+
+    loop {
+        let n = source.read(&mut input)?;
+        let last = n == 0;
+        let mut chunk = &input[..n];
+        loop {
+            let (result, read, written) = decoder.decode_to_utf8_without_replacement(chunk, &mut output, last);
+            line += output[..written].iter().filter(|&&b| b == b'\n').count() as u64;
+            match result {
+                encoding_rs::DecoderResult::InputEmpty => {
+                    offset += read as u64;
+                    break;
+                }
+                encoding_rs::DecoderResult::OutputFull => {}
+                encoding_rs::DecoderResult::Malformed(bad, after) => {
+                    total += 1;
+                    if issues.len() < MAX_ENCODING_ISSUES {
+                        // The bad bytes may have started in the previous chunk
+                        let end = read - after as usize;
+                        let mut bytes = carry[carry.len() - (bad as usize).saturating_sub(end).min(carry.len())..].to_vec();
+                        bytes.extend_from_slice(&chunk[end.saturating_sub(bad as usize)..end]);
+                        issues.push(EncodingIssue {
+                            offset: offset + end as u64 - bad as u64,
+                            line,
+                            bytes: bytes.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(" "),
+                        });
+                    }
+                }
+            }
+            offset += read as u64;
+            chunk = &chunk[read..];
+        }
+        if last {
+            break;
+        }
+        carry = input[n.saturating_sub(8)..n].to_vec();
+        let pct = offset * 100 / file_len;
+        if pct > last_pct {
+            last_pct = pct;
+            progress(pct);
+        }
+    }
+    progress(100);
+
+    Ok(EncodingAudit {
+        encoding: encoding.name().to_string(),
+        issues,
+        total,
+    })
+}
+
+/// Mismatches listed by `find_tag_mismatches`; the rest are only counted.
+const MAX_TAG_MISMATCHES: usize = 1000;
+
+#[derive(serde::Serialize)]
+pub struct TagMismatch {
+    /// `None` for an end tag with no open element left to close.
+    open_offset: Option<u64>,
+    open_name: Option<String>,
+    /// `None` for an element never closed before the end of the file.
+    close_offset: Option<u64>,
+    close_name: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct TagMismatches {
+    mismatches: Vec<TagMismatch>,
+    total: u64,
+}
+
+/// Check every end tag against the element it closes, which the other scans
+/// don't, and pair each open tag with the end tag that closes it wrongly. An
+/// end tag matching an element further up closes it along with those inside,
+/// which are reported as never closed; an end tag matching nothing open is
+/// reported on its own. Elements still open at the end of the file (a
+/// truncated document) come last. Emits `validate-progress` (0-100).
+#[tauri::command]
+pub async fn find_tag_mismatches(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    op_id: Option<OpId>,
+) -> Result<TagMismatches, XmlReaderError> {
+    let handle = files.get(file_id)?;
+    ops::run(window, op_id, "validate", move |op| {
+        find_tag_mismatches_internal(&handle, |pct| {
+            op.progress(pct);
+        })
+        .map_err(|e| XmlReaderError::in_file(&handle, e))
+    })
+    .await
+}
+
+fn find_tag_mismatches_internal(handle: &FileHandle, mut progress: impl FnMut(u64)) -> Result<TagMismatches> {
+    let file = handle.reader()?;
+    let file_len = file.len().max(1);
+    let mut reader = XmlReader::scan(file);
+    // Checked here instead, so the scan can carry on past a mismatch
+    reader.check_end_names(false);
+    let mut buf = Vec::new();
+    let mut last_pct = 0;
+    // Open elements as (name, offset)
+    let mut stack: Vec<(Vec<u8>, u64)> = Vec::new();
+    let mut mismatches = Vec::new();
+    let mut total = 0u64;
+
+    let mut report = |open: Option<(Vec<u8>, u64)>, close: Option<(&[u8], u64)>| {
+        total += 1;
+        if mismatches.len() < MAX_TAG_MISMATCHES {
+            let (open_name, open_offset) = open.map(|(name, at)| (String::from_utf8_lossy(&name).into_owned(), at)).unzip();
+            let (close_name, close_offset) = close.map(|(name, at)| (String::from_utf8_lossy(name).into_owned(), at)).unzip();
+            mismatches.push(TagMismatch {
+                open_offset,
+                open_name,
+                close_offset,
+                close_name,
+            });
+        }
+    };
+
+    loop {
+        buf.clear();
+        let pos_before = reader.position();
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(e) => stack.push((e.name().as_ref().to_vec(), pos_before)),
+            Event::End(e) => {
+                let name = e.name();
+                let name = name.as_ref();
+                if stack.last().is_some_and(|(open, _)| open.as_slice() == name) {
+                    stack.pop();
+                } else {
+                    match stack.iter().rposition(|(open, _)| open.as_slice() == name) {
+                        Some(i) => {
+                            let mut unclosed = stack.drain(i + 1..).rev();
+                            report(unclosed.next(), Some((name, pos_before)));
+                            for open in unclosed {
+                                report(Some(open), None);
+                            }
+                            stack.pop();
+                        }
+                        None => report(stack.last().cloned(), Some((name, pos_before))),
+                    }
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+
+        let pct = reader.position() * 100 / file_len;
+        if pct > last_pct {
+            last_pct = pct;
+            progress(pct);
+        }
+    }
+    for open in stack.into_iter().rev() {
+        report(Some(open), None);
+    }
+    progress(100);
+
+    Ok(TagMismatches { mismatches, total })
+}
+
+/// Problems listed by `check_namespaces`; the rest are only counted.
+const MAX_NAMESPACE_ISSUES: usize = 1000;
+/// Bound to every document without a declaration.
+const XML_NAMESPACE: &str = "http://www.w3.org/XML/1998/namespace";
+
+#[derive(serde::Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum NamespaceIssue {
+    /// An element or attribute name uses a prefix no ancestor declares.
+    UndeclaredPrefix { offset: u64, prefix: String, name: String },
+    /// Nothing in the declaration's scope uses it, not even a QName value
+    /// such as `xsi:type="p:Name"`.
+    UnusedDeclaration { offset: u64, prefix: String, uri: String },
+    /// The default namespace is redeclared to another URI inside an element
+    /// that already has one.
+    ConflictingDefault { offset: u64, uri: String, inherited: String },
+}
+
+#[derive(serde::Serialize)]
+pub struct NamespaceUsage {
+    uri: String,
+    /// Prefixes bound to it anywhere, `""` for the default namespace.
+    prefixes: Vec<String>,
+    elements: u64,
+    attributes: u64,
+}
+
+#[derive(serde::Serialize)]
+pub struct NamespaceReport {
+    issues: Vec<NamespaceIssue>,
+    total_issues: u64,
+    /// Most used first.
+    namespaces: Vec<NamespaceUsage>,
+}
+
+/// Check namespace use across the whole file: prefixes used without a
+/// declaration in scope, declarations nothing in their scope uses, and
+/// default namespaces redeclared to a different URI, plus every namespace in
+/// use with how many elements and attributes are in it. Emits
+/// `validate-progress` (0-100).
+#[tauri::command]
+pub async fn check_namespaces(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    op_id: Option<OpId>,
+) -> Result<NamespaceReport, XmlReaderError> {
+    let handle = files.get(file_id)?;
+    ops::run(window, op_id, "validate", move |op| {
+        check_namespaces_internal(&handle, |pct| {
+            op.progress(pct);
+        })
+        .map_err(|e| XmlReaderError::in_file(&handle, e))
+    })
+    .await
+}
+
+fn check_namespaces_internal(handle: &FileHandle, mut progress: impl FnMut(u64)) -> Result<NamespaceReport> {
+    struct Decl {
+        offset: u64,
+        prefix: String,
+        uri: String,
+        used: bool,
+    }
+
+    let file = handle.reader()?;
+    let file_len = file.len().max(1);
+    let mut reader = XmlReader::scan(file);
+    reader.check_end_names(false);
+    let mut buf = Vec::new();
+    let mut last_pct = 0;
+    // Declarations of each open element
+    let mut scopes: Vec<Vec<Decl>> = Vec::new();
+    let mut issues = Vec::new();
+    let mut total_issues = 0u64;
+    let mut usage: HashMap<String, NamespaceUsage> = HashMap::new();
+
+    let mut report = |issue: NamespaceIssue| {
+        total_issues += 1;
+        if issues.len() < MAX_NAMESPACE_ISSUES {
+            issues.push(issue);
+        }
+    };
+    let close = |scope: Vec<Decl>, report: &mut dyn FnMut(NamespaceIssue)| {
+        for decl in scope.into_iter().filter(|d| !d.used) {
+            report(NamespaceIssue::UnusedDeclaration {
+                offset: decl.offset,
+                prefix: decl.prefix,
+                uri: decl.uri,
+            });
+        }
+    };
+    // Mark the innermost declaration of `prefix` used and return its URI
+    fn resolve<'s>(scopes: &'s mut [Vec<Decl>], prefix: &str) -> Option<&'s str> {
+        let decl = scopes.iter_mut().rev().flat_map(|s| s.iter_mut()).find(|d| d.prefix == prefix)?;
+        decl.used = true;
+        Some(&decl.uri)
+    }
+
+    loop {
+        buf.clear();
+        let pos_before = reader.position();
+        let event = reader.read_event_into(&mut buf)?;
+        let empty = matches!(event, Event::Empty(_));
+        match event {
+            Event::Start(e) | Event::Empty(e) => {
+                let mut scope = Vec::new();
+                for (key, uri) in export::namespace_decls(&e) {
+                    let prefix = key.strip_prefix("xmlns").unwrap_or_default().trim_start_matches(':').to_string();
+                    if prefix.is_empty() {
+                        if let Some(inherited) = scopes.iter().rev().flat_map(|s| s.iter()).find(|d| d.prefix.is_empty()) {
+                            if inherited.uri != uri && !inherited.uri.is_empty() && !uri.is_empty() {
+                                report(NamespaceIssue::ConflictingDefault {
+                                    offset: pos_before,
+                                    uri: uri.clone(),
+                                    inherited: inherited.uri.clone(),
+                                });
+                            }
+                        }
+                    }
+                    usage.entry(uri.clone()).or_insert_with(|| NamespaceUsage {
+                        uri: uri.clone(),
+                        prefixes: Vec::new(),
+                        elements: 0,
+                        attributes: 0,
+                    });
+                    let entry = usage.get_mut(&uri).unwrap();
+                    if !entry.prefixes.contains(&prefix) {
+                        entry.prefixes.push(prefix.clone());
+                    }
+                    scope.push(Decl {
+                        offset: pos_before,
+                        // `xmlns=""` undeclares the default namespace, there is nothing to use
+                        used: uri.is_empty(),
+                        prefix,
+                        uri,
+                    });
+                }
+                scopes.push(scope);
+
+                let name = String::from_utf8_lossy(e.name().as_ref()).into_owned();
+                let (prefix, _) = name.split_once(':').unwrap_or(("", &name));
+                match resolve(&mut scopes, prefix) {
+                    Some(uri) => {
+                        if let Some(entry) = usage.get_mut(uri) {
+                            entry.elements += 1;
+                        }
+                    }
+                    None if prefix.is_empty() || prefix == "xml" => {}
+                    None => report(NamespaceIssue::UndeclaredPrefix {
+                        offset: pos_before,
+                        prefix: prefix.to_string(),
+                        name: name.clone(),
+                    }),
+                }
+
+                for attr in e.attributes().with_checks(false).flatten() {
+                    let key = String::from_utf8_lossy(attr.key.as_ref()).into_owned();
+                    if key == "xmlns" || key.starts_with("xmlns:") {
+                        continue;
+                    }
+                    if let Some((prefix, _)) = key.split_once(':') {
+                        match resolve(&mut scopes, prefix) {
+                            Some(uri) => {
+                                if let Some(entry) = usage.get_mut(uri) {
+                                    entry.attributes += 1;
+                                }
+                            }
+                            None if prefix == "xml" => {
+                                usage
+                                    .entry(XML_NAMESPACE.to_string())
+                                    .or_insert_with(|| NamespaceUsage {
+                                        uri: XML_NAMESPACE.to_string(),
+                                        prefixes: vec!["xml".to_string()],
+                                        elements: 0,
+                                        attributes: 0,
+                                    })
+                                    .attributes += 1;
+                            }
+                            None => report(NamespaceIssue::UndeclaredPrefix {
+                                offset: pos_before,
+                                prefix: prefix.to_string(),
+                                name: key.clone(),
+                            }),
+                        }
+                    }
+                    // QName values keep their prefix's declaration in use
+                    let value = String::from_utf8_lossy(&attr.value);
+                    if let Some((prefix, local)) = value.split_once(':') {
+                        if !local.is_empty() && !local.contains(':') && !prefix.contains(char::is_whitespace) {
+                            resolve(&mut scopes, prefix);
+                        }
+                    }
+                }
+
+                if empty {
+                    close(scopes.pop().unwrap_or_default(), &mut report);
+                }
+            }
+            Event::End(_) => {
+                if let Some(scope) = scopes.pop() {
+                    close(scope, &mut report);
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+
+        let pct = reader.position() * 100 / file_len;
+        if pct > last_pct {
+            last_pct = pct;
+            progress(pct);
+        }
+    }
+    while let Some(scope) = scopes.pop() {
+        close(scope, &mut report);
+    }
+    progress(100);
+
+    issues.sort_by_key(|issue| match issue {
+        NamespaceIssue::UndeclaredPrefix { offset, .. }
+        | NamespaceIssue::UnusedDeclaration { offset, .. }
+        | NamespaceIssue::ConflictingDefault { offset, .. } => *offset,
+    });
+    let mut namespaces: Vec<NamespaceUsage> = usage.into_values().filter(|u| !u.uri.is_empty()).collect();
+    namespaces.sort_by(|a, b| (b.elements + b.attributes).cmp(&(a.elements + a.attributes)).then(a.uri.cmp(&b.uri)));
+    Ok(NamespaceReport {
+        issues,
+        total_issues,
+        namespaces,
+    })
+}
+
+/// Regions listed by `scan_for_errors`; the rest are only counted.
+const MAX_ERROR_REGIONS: usize = 10_000;
+
+#[derive(serde::Serialize)]
+pub struct ErrorRegion {
+    /// From the start of the markup that failed to parse...
+    start: u64,
+    /// ...to the `<` where parsing picked up again, or the end of the file.
+    end: u64,
+    /// 1-based line of `start`.
+    line: u64,
+    /// The first error in the region.
+    message: String,
+}
+
+#[derive(serde::Serialize)]
+pub struct ErrorMap {
+    regions: Vec<ErrorRegion>,
+    total: u64,
+    /// Bytes inside the regions, to show how much of the file is still usable.
+    unparseable_bytes: u64,
+    file_len: u64,
+}
+
+/// Parse the whole file, and after every parse error skip to the next `<`
+/// and carry on, so a partly corrupted file yields a map of the regions that
+/// couldn't be parsed instead of one error. Errors that follow each other
+/// with no event parsed in between make one region. Mismatched end tags
+/// aren't errors here (see `find_tag_mismatches`). Emits `validate-progress`
+/// (0-100).
+#[tauri::command]
+pub async fn scan_for_errors(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    op_id: Option<OpId>,
+) -> Result<ErrorMap, XmlReaderError> {
+    let handle = files.get(file_id)?;
+    ops::run(window, op_id, "validate", move |op| {
+        scan_for_errors_internal(&handle, |pct| {
+            op.progress(pct);
+        })
+        .map_err(|e| XmlReaderError::in_file(&handle, e))
+    })
+    .await
+}
+
+fn scan_for_errors_internal(handle: &FileHandle, mut progress: impl FnMut(u64)) -> Result<ErrorMap> {
+    let file = handle.reader()?;
+    let file_len = file.len();
+    let mut reader = XmlReader::scan(file);
+    reader.check_end_names(false);
+    let mut buf = Vec::new();
+    let mut last_pct = 0;
+    // (start, end, message)
+    let mut regions: Vec<(u64, u64, String)> = Vec::new();
+    let mut total = 0u64;
+    let mut unparseable_bytes = 0u64;
+    // The region still being extended by consecutive errors
+    let mut open: Option<(u64, String)> = None;
+
+    loop {
+        buf.clear();
+        let pos_before = reader.position();
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::Eof) => break,
+            Ok(_) => {
+                if let Some((start, message)) = open.take() {
+                    total += 1;
+                    unparseable_bytes += pos_before - start;
+                    if regions.len() < MAX_ERROR_REGIONS {
+                        regions.push((start, pos_before, message));
+                    }
+                }
+            }
+            Err(e) => {
+                if e.downcast_ref::<io::LimitError>().is_some() {
+                    return Err(e);
+                }
+                open.get_or_insert((pos_before, e.to_string()));
+                let resume = reader.position().max(pos_before + 1);
+                let mut source = handle.reader()?;
+                let Some(next) = next_tag(&mut source, resume)? else {
+                    break;
+                };
+                source.seek(SeekFrom::Start(next))?;
+                reader = XmlReader::scan(source);
+                reader.check_end_names(false);
+            }
+        }
+
+        let pct = reader.position() * 100 / file_len.max(1);
+        if pct > last_pct {
+            last_pct = pct;
+            progress(pct);
+        }
+    }
+    if let Some((start, message)) = open {
+        total += 1;
+        unparseable_bytes += file_len - start;
+        if regions.len() < MAX_ERROR_REGIONS {
+            regions.push((start, file_len, message));
+        }
+    }
+
+    let offsets: Vec<u64> = regions.iter().map(|(start, _, _)| *start).collect();
+    let lines = line_columns(handle, &offsets)?;
+    progress(100);
+    Ok(ErrorMap {
+        regions: regions
+            .into_iter()
+            .zip(lines)
+            .map(|((start, end, message), (line, _))| ErrorRegion { start, end, line, message })
+            .collect(),
+        total,
+        unparseable_bytes,
+        file_len,
+    })
+}
+
+/// Offset of the first `<` at or after `from`.
+fn next_tag(source: &mut io::Source, from: u64) -> Result<Option<u64>> {
+    let mut chunk = vec![0; 64 * 1024];
+    let mut pos = from;
+    source.seek(SeekFrom::Start(from))?;
+    loop {
+        let n = source.read(&mut chunk)?;
+        if n == 0 {
+            return Ok(None);
+        }
+        if let Some(i) = chunk[..n].iter().position(|&b| b == b'<') {
+            return Ok(Some(pos + i as u64));
+        }
+        pos += n as u64;
+    }
+}
+
+/// Lowercased attribute names to scan for: `attrs`, or `defaults` if empty.
+fn attribute_names(attrs: &[String], defaults: &[&str]) -> Vec<Vec<u8>> {
+    if attrs.is_empty() {
+        defaults.iter().map(|a| a.as_bytes().to_vec()).collect()
+    } else {
+        attrs.iter().map(|a| a.to_ascii_lowercase().into_bytes()).collect()
+    }
+}
+
+/// Call `found(element offset, attribute name, raw value)` for every attribute
+/// named (ASCII case-insensitively) in `names`, in file order.
+fn scan_attributes(
+    handle: &FileHandle,
+    names: &[Vec<u8>],
+    mut progress: impl FnMut(u64),
+    mut found: impl FnMut(u64, &[u8], &[u8]),
+) -> Result<()> {
+    let file = handle.reader()?;
+    let file_len = file.len().max(1);
+    let mut reader = XmlReader::scan(file);
+    reader.check_end_names(false);
+    let mut buf = Vec::new();
+    let mut last_pct = 0;
+
+    loop {
+        buf.clear();
+        let pos_before = reader.position();
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(e) | Event::Empty(e) => {
+                for attr in e.attributes().with_checks(false).flatten() {
+                    let key = attr.key.as_ref();
+                    if names.iter().any(|n| n.eq_ignore_ascii_case(key)) {
+                        found(pos_before, key, &attr.value);
+                    }
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+
+        let pct = reader.position() * 100 / file_len;
+        if pct > last_pct {
+            last_pct = pct;
+            progress(pct);
+        }
+    }
+    Ok(())
+}
+
+/// Report characters XML doesn't allow and bad entity references in text or an
+/// attribute value found at file offset `at`.
+fn check_chars(bytes: &[u8], at: u64, utf8: bool, entities: &HashSet<String>, attribute: bool, errors: &mut Vec<(u64, String)>) {
+    if utf8 {
+        if let Err(e) = std::str::from_utf8(bytes) {
+            errors.push((at + e.valid_up_to() as u64, "Invalid UTF-8".to_string()));
+        }
+    }
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b if b < 0x20 && !matches!(b, b'\t' | b'\n' | b'\r') => {
+                errors.push((at + i as u64, format!("Character U+{:04X} is not allowed in XML", b)));
+            }
+            b'<' if attribute => errors.push((at + i as u64, "'<' in an attribute value".to_string())),
+            b'&' => {
+                let name_len = bytes[i + 1..]
+                    .iter()
+                    .position(|&b| !(b.is_ascii_alphanumeric() || matches!(b, b'#' | b'_' | b'-' | b'.' | b':') || b >= 0x80))
+                    .unwrap_or(bytes.len() - i - 1);
+                match (bytes.get(i + 1 + name_len) == Some(&b';') && name_len > 0).then(|| &bytes[i + 1..i + 1 + name_len]) {
+                    Some(name) => {
+                        if let Some(message) = check_reference(name, entities) {
+                            errors.push((at + i as u64, message));
+                        }
+                    }
+                    None => errors.push((at + i as u64, "'&' does not start an entity reference".to_string())),
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+}
+
+/// Why `&name;` is not a valid reference, if it isn't.
+fn check_reference(name: &[u8], entities: &HashSet<String>) -> Option<String> {
+    let name = String::from_utf8_lossy(name);
+    if let Some(number) = name.strip_prefix('#') {
+        let code = match number.strip_prefix('x') {
+            Some(hex) => u32::from_str_radix(hex, 16).ok(),
+            None => number.parse().ok(),
+        };
+        return match code.and_then(char::from_u32) {
+            Some(c) if is_xml_char(c) => None,
+            _ => Some(format!("&{}; is not a valid character reference", name)),
+        };
+    }
+    match name.as_ref() {
+        "amp" | "lt" | "gt" | "quot" | "apos" => None,
+        n if entities.contains(n) => None,
+        n => Some(format!("Entity &{}; is not defined", n)),
+    }
+}
+
+fn is_xml_char(c: char) -> bool {
+    matches!(c, '\t' | '\n' | '\r' | '\u{20}'..='\u{D7FF}' | '\u{E000}'..='\u{FFFD}' | '\u{10000}'..='\u{10FFFF}')
+}
+
+/// General entities declared in a DOCTYPE's internal subset.
+fn declared_entities(doctype: &[u8]) -> Vec<String> {
+    let text = String::from_utf8_lossy(doctype);
+    text.split("<!ENTITY")
+        .skip(1)
+        .filter_map(|decl| decl.split_whitespace().next())
+        // `<!ENTITY % name ...>` declares a parameter entity
+        .filter(|name| *name != "%")
+        .map(str::to_string)
+        .collect()
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() {
+        return None;
+    }
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// 1-based (line, column) of each of the sorted `offsets`, in one pass.
+/// Columns count characters, not bytes.
+fn line_columns(handle: &FileHandle, offsets: &[u64]) -> Result<Vec<(u64, u64)>> {
+    let mut positions = Vec::with_capacity(offsets.len());
+    let Some(&last) = offsets.last() else {
+        return Ok(positions);
+    };
+    let mut source = handle.reader()?;
+    let mut chunk = vec![0; 1024 * 1024];
+    let (mut pos, mut line, mut column) = (0u64, 1u64, 1u64);
+    let mut next = offsets.iter().peekable();
+    while pos <= last {
+        let n = source.read(&mut chunk)?;
+        for &b in &chunk[..n] {
+            while next.next_if(|&&at| at == pos).is_some() {
+                positions.push((line, column));
+            }
+            if b == b'\n' {
+                line += 1;
+                column = 1;
+            } else if b & 0xC0 != 0x80 {
+                column += 1;
+            }
+            pos += 1;
+        }
+        if n == 0 {
+            break;
+        }
+    }
+    // Offsets at the very end of the file
+    positions.resize(offsets.len(), (line, column));
+    Ok(positions)
+}
+
+fn context_at(source: &mut io::Source, offset: u64) -> Result<String> {
+    let start = offset.saturating_sub(CONTEXT_BYTES);
+    let mut context = Vec::new();
+    source.seek(SeekFrom::Start(start))?;
+    source.take(offset - start + CONTEXT_BYTES).read_to_end(&mut context)?;
+    Ok(String::from_utf8_lossy(&context).into_owned())
+}
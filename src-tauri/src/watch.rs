@@ -0,0 +1,255 @@
+use crate::error::XmlReaderError;
+use crate::files::{FileHandle, FileId, FileRegistry};
+use crate::io::{self, XmlReader};
+use anyhow::Result;
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use quick_xml::events::Event as XmlEvent;
+use std::collections::HashMap;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, State};
+
+/// How often a followed file is checked for appended data.
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(serde::Serialize, Clone)]
+struct FileChange {
+    file_id: FileId,
+    len: u64,
+}
+
+fn watchers() -> &'static Mutex<HashMap<FileId, RecommendedWatcher>> {
+    static WATCHERS: OnceLock<Mutex<HashMap<FileId, RecommendedWatcher>>> = OnceLock::new();
+    WATCHERS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+#[tauri::command]
+pub async fn watch_file(
+    app: AppHandle,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+) -> Result<(), XmlReaderError> {
+    let handle = files.get(file_id)?;
+    watch_file_internal(app, handle).map_err(XmlReaderError::from)
+}
+
+#[tauri::command]
+pub async fn unwatch_file(file_id: FileId) -> Result<(), XmlReaderError> {
+    // Dropping the watcher stops it
+    watchers().lock().unwrap().remove(&file_id);
+    Ok(())
+}
+
+/// Stop watching and following a file that is being closed.
+pub fn stop(file_id: FileId) {
+    watchers().lock().unwrap().remove(&file_id);
+    if let Some(stop) = followers().lock().unwrap().remove(&file_id) {
+        stop.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Emit `file-changed` / `file-truncated` with the new length whenever the file
+/// is modified, after refreshing the handle so later reads see the new
+/// content. The parent directory is watched rather than the file itself, so
+/// generators that replace the file (write temp + rename) are still seen.
+fn watch_file_internal(app: AppHandle, handle: Arc<FileHandle>) -> Result<()> {
+    if io::is_remote(&handle.path) {
+        return Err(anyhow::anyhow!("Remote files cannot be watched"));
+    }
+
+    let target = std::fs::canonicalize(&handle.path)?;
+    let dir = target
+        .parent()
+        .map(Path::to_path_buf)
+        .ok_or_else(|| anyhow::anyhow!("Cannot watch {}", handle.path))?;
+    let mut last_len = std::fs::metadata(&target)?.len();
+    let file_id = handle.id;
+
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<Event>| {
+        let Ok(event) = res else { return };
+        if !event.paths.iter().any(|p| same_file(p, &target)) {
+            return;
+        }
+        // Deleted or mid-replace; the follow-up create event reports the new length
+        let Ok(meta) = std::fs::metadata(&target) else { return };
+
+        let len = meta.len();
+        io::invalidate(&handle.path);
+        if handle.refresh().is_err() {
+            return;
+        }
+        let change = FileChange { file_id: handle.id, len };
+        if len < last_len {
+            let _ = app.emit_to(handle.window(), "file-truncated", change);
+        } else {
+            let _ = app.emit_to(handle.window(), "file-changed", change);
+        }
+        last_len = len;
+    })?;
+    watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+
+    watchers().lock().unwrap().insert(file_id, watcher);
+    Ok(())
+}
+
+fn same_file(a: &Path, b: &PathBuf) -> bool {
+    a == b || std::fs::canonicalize(a).is_ok_and(|a| &a == b)
+}
+
+// ── Tail-follow ───────────────────────────────────────────────────────────
+
+#[derive(serde::Serialize, Clone)]
+struct FollowedElement {
+    file_id: FileId,
+    offset: u64,
+    end: u64,
+    text: String,
+}
+
+fn followers() -> &'static Mutex<HashMap<FileId, Arc<AtomicBool>>> {
+    static FOLLOWERS: OnceLock<Mutex<HashMap<FileId, Arc<AtomicBool>>>> = OnceLock::new();
+    FOLLOWERS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+#[tauri::command]
+pub async fn follow_file(
+    app: AppHandle,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+) -> Result<(), XmlReaderError> {
+    let handle = files.get(file_id)?;
+    follow_file_internal(app, handle).map_err(XmlReaderError::from)
+}
+
+#[tauri::command]
+pub async fn unfollow_file(file_id: FileId) -> Result<(), XmlReaderError> {
+    if let Some(stop) = followers().lock().unwrap().remove(&file_id) {
+        stop.store(true, Ordering::SeqCst);
+    }
+    Ok(())
+}
+
+/// Poll `path` for growth and emit a `follow-element` event for every top-level
+/// element (child of the root) completed in the appended data. Elements that
+/// already existed when following started are not reported.
+fn follow_file_internal(app: AppHandle, handle: Arc<FileHandle>) -> Result<()> {
+    if io::is_remote(&handle.path) {
+        return Err(anyhow::anyhow!("Remote files cannot be followed"));
+    }
+
+    // Find where the existing content stops being complete
+    let mut len = handle.reader()?.len();
+    let (mut resume, mut depth) = scan_top_level(&handle, 0, 0, |_, _| {})?;
+
+    let stop = Arc::new(AtomicBool::new(false));
+    if let Some(previous) = followers().lock().unwrap().insert(handle.id, stop.clone()) {
+        previous.store(true, Ordering::SeqCst);
+    }
+
+    std::thread::spawn(move || {
+        while !stop.load(Ordering::SeqCst) {
+            std::thread::sleep(FOLLOW_INTERVAL);
+
+            let Ok(new_len) = handle.reader().map(|f| f.len()) else { continue };
+            if new_len == len {
+                continue;
+            }
+            if new_len < len {
+                // Rewritten from scratch: start over and report everything again
+                let _ = app.emit_to(handle.window(), "file-truncated", FileChange { file_id: handle.id, len: new_len });
+                resume = 0;
+                depth = 0;
+            }
+            len = new_len;
+
+            let mut completed = Vec::new();
+            let Ok(next) = scan_top_level(&handle, resume, depth, |start, end| completed.push((start, end))) else {
+                continue;
+            };
+            (resume, depth) = next;
+
+            let Ok(mut file) = handle.reader() else { continue };
+            for (start, end) in completed {
+                let mut buf = vec![0u8; (end - start) as usize];
+                if file.seek(SeekFrom::Start(start)).and_then(|_| file.read_exact(&mut buf)).is_err() {
+                    break;
+                }
+                let element = FollowedElement {
+                    file_id: handle.id,
+                    offset: start,
+                    end,
+                    text: String::from_utf8_lossy(&buf).to_string(),
+                };
+                let _ = app.emit_to(handle.window(), "follow-element", element);
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Parse from `start` (known to be at `depth`, 0 = before the root, 1 = inside
+/// it) and call `on_complete(start, end)` for every finished child of the root.
+/// Returns the position/depth to resume from next time: the end of the last
+/// complete top-level construct, so a half-written element is parsed again.
+fn scan_top_level(
+    handle: &FileHandle,
+    start: u64,
+    depth: u32,
+    mut on_complete: impl FnMut(u64, u64),
+) -> Result<(u64, u32)> {
+    let mut file = handle.reader()?;
+    file.seek(SeekFrom::Start(start))?;
+    let mut reader = XmlReader::new(file, 8 * 1024);
+    reader.check_end_names(false);
+
+    let mut buf = Vec::new();
+    let mut depth = depth;
+    let mut resume = (start, depth);
+    let mut child_start = start;
+
+    loop {
+        let pos_before = reader.position();
+        let event = reader.read_event_into(&mut buf);
+        let pos_after = reader.position();
+
+        match event {
+            Ok(XmlEvent::Start(_)) => {
+                if depth == 1 {
+                    child_start = pos_before;
+                }
+                depth += 1;
+                if depth == 1 {
+                    resume = (pos_after, depth);
+                }
+            }
+            Ok(XmlEvent::End(_)) => {
+                depth = depth.saturating_sub(1);
+                if depth == 1 {
+                    on_complete(child_start, pos_after);
+                }
+                if depth <= 1 {
+                    resume = (pos_after, depth);
+                }
+            }
+            Ok(XmlEvent::Empty(_)) => {
+                if depth == 1 {
+                    on_complete(pos_before, pos_after);
+                }
+                if depth <= 1 {
+                    resume = (pos_after, depth);
+                }
+            }
+            Ok(XmlEvent::Eof) | Err(_) => break,
+            Ok(_) => {
+                if depth == 0 {
+                    resume = (pos_after, depth);
+                }
+            }
+        }
+        buf.clear();
+    }
+    Ok(resume)
+}
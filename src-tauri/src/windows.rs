@@ -0,0 +1,87 @@
+use crate::error::XmlReaderError;
+use crate::files::FileRegistry;
+use crate::{cli, journal, ops, watch};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use tauri::{AppHandle, Manager, WebviewUrl, WebviewWindow, WebviewWindowBuilder};
+
+static NEXT_WINDOW: AtomicU64 = AtomicU64::new(1);
+
+/// Files windows were opened for, by window label, until the window takes
+/// its file with `take_window_file`.
+static PENDING: Mutex<Vec<(String, String)>> = Mutex::new(Vec::new());
+
+/// Open another window. Each window has its own open file, searches and
+/// operations, and gets only its own events. With `path`, the new window
+/// opens that file once it has loaded. Returns the new window's label.
+#[tauri::command]
+pub async fn new_window(app: AppHandle, path: Option<String>) -> Result<String, XmlReaderError> {
+    let label = next_label();
+    // Registered first: the page may ask for it before `build` returns
+    if let Some(path) = path {
+        PENDING.lock().unwrap().push((label.clone(), path));
+    }
+    if let Err(e) = build(&app, &label) {
+        PENDING.lock().unwrap().retain(|(pending, _)| *pending != label);
+        return Err(e.into());
+    }
+    Ok(label)
+}
+
+fn next_label() -> String {
+    format!("window-{}", NEXT_WINDOW.fetch_add(1, Ordering::SeqCst))
+}
+
+fn build(app: &AppHandle, label: &str) -> anyhow::Result<()> {
+    let title = format!("xml-reader v{}", app.package_info().version);
+    WebviewWindowBuilder::new(app, label, WebviewUrl::App("index.html".into()))
+        .title(title)
+        .inner_size(800.0, 600.0)
+        .build()
+        .map_err(|e| anyhow::anyhow!("Cannot open a new window: {}", e))?;
+    Ok(())
+}
+
+/// A second launch of the app, which the single-instance plugin exits after
+/// handing over its arguments: each file it was given opens in a new window
+/// of this instance, rather than in a second process competing for the same
+/// files. Without files the main window is brought to the front.
+pub fn forwarded(app: &AppHandle, args: Vec<String>, cwd: String) {
+    let requests = cli::requests(args.get(1..).unwrap_or_default(), Path::new(&cwd));
+    if requests.is_empty() {
+        if let Some(main) = app.get_webview_window("main") {
+            let _ = main.unminimize();
+            let _ = main.set_focus();
+        }
+        return;
+    }
+    for request in requests {
+        let label = next_label();
+        // Queued first, like `new_window`
+        cli::open(app, &label, request);
+        if build(app, &label).is_err() {
+            cli::forget(&label);
+        }
+    }
+}
+
+/// The file the calling window was opened for by `new_window`, once.
+#[tauri::command]
+pub async fn take_window_file(window: WebviewWindow) -> Result<Option<String>, XmlReaderError> {
+    let mut pending = PENDING.lock().unwrap();
+    let index = pending.iter().position(|(label, _)| label == window.label());
+    Ok(index.map(|i| pending.remove(i).1))
+}
+
+/// Clean up after window `label` was closed: cancel its operations and
+/// close its files.
+pub fn closed(app: &AppHandle, label: &str) {
+    ops::cancel_window(label);
+    for file_id in app.state::<FileRegistry>().close_window(label) {
+        watch::stop(file_id);
+    }
+    journal::record(app);
+    PENDING.lock().unwrap().retain(|(pending, _)| pending != label);
+    cli::forget(label);
+}
@@ -0,0 +1,197 @@
+use crate::error::XmlReaderError;
+use crate::files::FileRegistry;
+use crate::xml_ops::{self, FilesOpened};
+use anyhow::Result;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tauri::{State, WebviewWindow};
+
+/// A place in one of a workspace's files.
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+pub struct Bookmark {
+    path: String,
+    offset: u64,
+    label: Option<String>,
+}
+
+/// A named set of related files opened together, such as a model split
+/// across several XMI exports, with bookmarks spanning all of them.
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+pub struct Workspace {
+    name: String,
+    files: Vec<String>,
+    bookmarks: Vec<Bookmark>,
+    /// Milliseconds since the Unix epoch.
+    created: i64,
+}
+
+/// Workspaces, persisted as JSON in the app data directory. Kept in Tauri
+/// managed state.
+pub struct Workspaces {
+    store: Option<PathBuf>,
+    entries: Mutex<Vec<Workspace>>,
+}
+
+impl Workspaces {
+    /// Load the workspaces from `store`; a missing or unreadable file starts
+    /// empty.
+    pub fn load(store: Option<PathBuf>) -> Workspaces {
+        let entries = store
+            .as_ref()
+            .and_then(|path| std::fs::read(path).ok())
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default();
+        Workspaces {
+            store,
+            entries: Mutex::new(entries),
+        }
+    }
+
+    fn save(&self, entries: &[Workspace]) -> Result<()> {
+        let Some(store) = &self.store else {
+            return Ok(());
+        };
+        if let Some(dir) = store.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        // Write then rename, so a crash never leaves half-written workspaces
+        let tmp = store.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(entries)?)?;
+        std::fs::rename(&tmp, store)?;
+        Ok(())
+    }
+
+    fn get(&self, name: &str) -> Result<Workspace> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .iter()
+            .find(|w| w.name == name)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("No workspace named {}", name))
+    }
+
+    /// Apply `change` to workspace `name` and persist the result.
+    fn update(&self, name: &str, change: impl FnOnce(&mut Workspace)) -> Result<Workspace> {
+        let mut entries = self.entries.lock().unwrap();
+        let workspace = entries
+            .iter_mut()
+            .find(|w| w.name == name)
+            .ok_or_else(|| anyhow::anyhow!("No workspace named {}", name))?;
+        change(workspace);
+        let updated = workspace.clone();
+        self.save(&entries)?;
+        Ok(updated)
+    }
+}
+
+#[tauri::command]
+pub async fn create_workspace(workspaces: State<'_, Workspaces>, name: String) -> Result<Workspace, XmlReaderError> {
+    let mut entries = workspaces.entries.lock().unwrap();
+    if entries.iter().any(|w| w.name == name) {
+        return Err(anyhow::anyhow!("A workspace named {} already exists", name).into());
+    }
+    let workspace = Workspace {
+        name,
+        files: Vec::new(),
+        bookmarks: Vec::new(),
+        created: chrono::Utc::now().timestamp_millis(),
+    };
+    entries.push(workspace.clone());
+    workspaces.save(&entries)?;
+    Ok(workspace)
+}
+
+/// Delete a workspace. Its files are left alone.
+#[tauri::command]
+pub async fn delete_workspace(workspaces: State<'_, Workspaces>, name: String) -> Result<(), XmlReaderError> {
+    let mut entries = workspaces.entries.lock().unwrap();
+    entries.retain(|w| w.name != name);
+    workspaces.save(&entries).map_err(XmlReaderError::from)
+}
+
+/// Names of all workspaces, oldest first.
+#[tauri::command]
+pub async fn list_workspaces(workspaces: State<'_, Workspaces>) -> Result<Vec<String>, XmlReaderError> {
+    Ok(workspaces.entries.lock().unwrap().iter().map(|w| w.name.clone()).collect())
+}
+
+/// The files and bookmarks of workspace `name`.
+#[tauri::command]
+pub async fn list_workspace(workspaces: State<'_, Workspaces>, name: String) -> Result<Workspace, XmlReaderError> {
+    workspaces.get(&name).map_err(XmlReaderError::from)
+}
+
+/// Add `path` to workspace `name`, after the files already in it. Adding a
+/// file twice does nothing.
+#[tauri::command]
+pub async fn add_file(workspaces: State<'_, Workspaces>, name: String, path: String) -> Result<Workspace, XmlReaderError> {
+    workspaces
+        .update(&name, |workspace| {
+            if !workspace.files.contains(&path) {
+                workspace.files.push(path);
+            }
+        })
+        .map_err(XmlReaderError::from)
+}
+
+/// Take `path` out of workspace `name`, with its bookmarks.
+#[tauri::command]
+pub async fn remove_file(
+    workspaces: State<'_, Workspaces>,
+    name: String,
+    path: String,
+) -> Result<Workspace, XmlReaderError> {
+    workspaces
+        .update(&name, |workspace| {
+            workspace.files.retain(|f| *f != path);
+            workspace.bookmarks.retain(|b| b.path != path);
+        })
+        .map_err(XmlReaderError::from)
+}
+
+/// Bookmark `offset` in `path`, which is added to the workspace if it isn't
+/// in it yet.
+#[tauri::command]
+pub async fn add_bookmark(
+    workspaces: State<'_, Workspaces>,
+    name: String,
+    path: String,
+    offset: u64,
+    label: Option<String>,
+) -> Result<Workspace, XmlReaderError> {
+    workspaces
+        .update(&name, |workspace| {
+            if !workspace.files.contains(&path) {
+                workspace.files.push(path.clone());
+            }
+            workspace.bookmarks.retain(|b| b.path != path || b.offset != offset);
+            workspace.bookmarks.push(Bookmark { path, offset, label });
+        })
+        .map_err(XmlReaderError::from)
+}
+
+#[tauri::command]
+pub async fn remove_bookmark(
+    workspaces: State<'_, Workspaces>,
+    name: String,
+    path: String,
+    offset: u64,
+) -> Result<Workspace, XmlReaderError> {
+    workspaces
+        .update(&name, |workspace| workspace.bookmarks.retain(|b| b.path != path || b.offset != offset))
+        .map_err(XmlReaderError::from)
+}
+
+/// Open every file of workspace `name` in the calling window, like
+/// `open_files`: files that fail to open (moved, deleted) are reported
+/// instead of failing the rest.
+#[tauri::command]
+pub async fn open_workspace(
+    window: WebviewWindow,
+    workspaces: State<'_, Workspaces>,
+    files: State<'_, FileRegistry>,
+    name: String,
+) -> Result<FilesOpened, XmlReaderError> {
+    let workspace = workspaces.get(&name)?;
+    Ok(xml_ops::open_paths(&window, &files, workspace.files))
+}
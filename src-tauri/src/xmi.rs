@@ -0,0 +1,547 @@
+use crate::error::XmlReaderError;
+use crate::files::{FileHandle, FileId, FileRegistry};
+use crate::format::element_reader;
+use crate::io::XmlReader;
+use crate::ops::{self, OpId};
+use anyhow::Result;
+use quick_xml::events::{BytesStart, Event};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use tauri::{State, WebviewWindow};
+
+/// An element of the model: anything outside `xmi:Extension` with an
+/// `xmi:id` (XMI 2) or `xmi.id` (XMI 1) attribute.
+struct Entry {
+    guid: String,
+    name: Option<String>,
+    /// `xmi:type`, or the tag name when there is none (XMI 1).
+    kind: String,
+    offset: u64,
+    /// The nearest enclosing model element.
+    parent: Option<usize>,
+    package: bool,
+}
+
+/// Roles in which UML relationships refer to the elements they connect.
+const REF_ROLES: [&str; 6] = ["type", "client", "supplier", "general", "contract", "memberEnd"];
+
+/// The ID index of an XMI export (Enterprise Architect or another UML tool):
+/// every model element by guid, with its place in the model, so it can be
+/// browsed by packages rather than by raw XML nesting. Built by one scan on
+/// first use and kept on the file's handle until the file changes.
+pub struct XmiIndex {
+    entries: Vec<Entry>,
+    by_guid: HashMap<String, usize>,
+    /// Enterprise Architect's extra data on an element (stereotype, notes,
+    /// author...): offsets of the `<element xmi:idref>` in its extension.
+    extensions: HashMap<String, u64>,
+    /// Guids an element refers to by role (`type`, `client`, `supplier`,
+    /// `general`, `contract`, `memberEnd`), as attributes or child elements.
+    refs: HashMap<usize, Vec<(String, String)>>,
+    /// Enterprise Architect's `<connector>` list, which gives the source and
+    /// target of every relationship explicitly.
+    ea_connectors: Vec<Connector>,
+}
+
+impl XmiIndex {
+    fn get(&self, guid: &str) -> Result<&Entry> {
+        self.by_guid
+            .get(guid)
+            .map(|&i| &self.entries[i])
+            .ok_or_else(|| anyhow::anyhow!("No model element with id {}", guid))
+    }
+
+    /// The package `entry` belongs to, skipping owners that aren't packages
+    /// (a class owning a nested class).
+    fn package_of(&self, entry: &Entry) -> Option<&Entry> {
+        let mut parent = entry.parent;
+        while let Some(i) = parent {
+            if self.entries[i].package {
+                return Some(&self.entries[i]);
+            }
+            parent = self.entries[i].parent;
+        }
+        None
+    }
+
+    fn element(&self, entry: &Entry) -> XmiElement {
+        XmiElement {
+            guid: entry.guid.clone(),
+            name: entry.name.clone(),
+            kind: entry.kind.clone(),
+            offset: entry.offset,
+            parent: entry.parent.map(|i| self.entries[i].guid.clone()),
+        }
+    }
+
+    fn name_of(&self, guid: &str) -> Option<String> {
+        self.by_guid.get(guid).and_then(|&i| self.entries[i].name.clone())
+    }
+
+    fn first_ref(&self, entry: usize, role: &str) -> Option<&str> {
+        let refs = self.refs.get(&entry)?;
+        refs.iter().find(|(r, _)| r == role).map(|(_, guid)| guid.as_str())
+    }
+
+    /// Every relationship in the model: EA's connector list, then the UML
+    /// relationships it doesn't cover (or all of them, for other tools).
+    fn connectors(&self) -> Vec<Connector> {
+        let mut connectors = self.ea_connectors.clone();
+        let listed: HashSet<&str> = self.ea_connectors.iter().map(|c| c.guid.as_str()).collect();
+        for (i, entry) in self.entries.iter().enumerate() {
+            if listed.contains(entry.guid.as_str()) {
+                continue;
+            }
+            let owner = entry.parent.map(|p| self.entries[p].guid.as_str());
+            let ends = match local_name(&entry.kind) {
+                "Generalization" => owner.zip(self.first_ref(i, "general")),
+                "InterfaceRealization" => owner.zip(self.first_ref(i, "contract")),
+                // Associations have no direction; their ends are taken in
+                // `memberEnd` order
+                "Association" | "AssociationClass" => {
+                    let ends = self.refs.get(&i).into_iter().flatten().filter(|(role, _)| role == "memberEnd");
+                    let mut types = ends.filter_map(|(_, end)| self.first_ref(*self.by_guid.get(end)?, "type"));
+                    types.next().zip(types.next())
+                }
+                _ => self.first_ref(i, "client").zip(self.first_ref(i, "supplier")),
+            };
+            let Some((source, target)) = ends else {
+                continue;
+            };
+            connectors.push(Connector {
+                guid: entry.guid.clone(),
+                kind: local_name(&entry.kind).to_string(),
+                name: entry.name.clone(),
+                source: source.to_string(),
+                source_name: None,
+                target: target.to_string(),
+                target_name: None,
+                offset: entry.offset,
+            });
+        }
+        for connector in &mut connectors {
+            connector.source_name = self.name_of(&connector.source);
+            connector.target_name = self.name_of(&connector.target);
+        }
+        connectors
+    }
+
+    /// The elements directly owned by `guid`, in file order.
+    fn children(&self, guid: &str) -> Result<impl Iterator<Item = &Entry>> {
+        self.get(guid)?;
+        let owner = self.by_guid[guid];
+        Ok(self.entries.iter().filter(move |e| e.parent == Some(owner)))
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct XmiElement {
+    guid: String,
+    name: Option<String>,
+    /// e.g. `uml:Class`.
+    #[serde(rename = "type")]
+    kind: String,
+    offset: u64,
+    /// Guid of the owning element, `None` at the top of the model.
+    parent: Option<String>,
+}
+
+/// A relationship between two model elements.
+#[derive(serde::Serialize, Clone)]
+pub struct Connector {
+    guid: String,
+    /// e.g. `Association`, `Generalization`, `Dependency`.
+    #[serde(rename = "type")]
+    kind: String,
+    name: Option<String>,
+    source: String,
+    source_name: Option<String>,
+    target: String,
+    target_name: Option<String>,
+    offset: u64,
+}
+
+/// Which connectors `list_connectors` returns; fields left out match all.
+#[derive(serde::Deserialize, Default)]
+#[serde(default)]
+pub struct ConnectorFilter {
+    /// Connector type, ASCII case-insensitive.
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    /// Guid of an element at either end.
+    element: Option<String>,
+    /// Text in the connector's name or an end's name, case-insensitive.
+    text: Option<String>,
+}
+
+impl ConnectorFilter {
+    fn matches(&self, connector: &Connector) -> bool {
+        let text = self.text.as_ref().map(|t| t.to_lowercase());
+        let names = [&connector.name, &connector.source_name, &connector.target_name];
+        self.kind.as_ref().is_none_or(|kind| kind.eq_ignore_ascii_case(&connector.kind))
+            && self.element.as_ref().is_none_or(|e| *e == connector.source || *e == connector.target)
+            && text.is_none_or(|t| names.iter().any(|n| n.as_ref().is_some_and(|n| n.to_lowercase().contains(&t))))
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct ConnectorPage {
+    connectors: Vec<Connector>,
+    /// Connectors matching the filter, on all pages.
+    total: u64,
+}
+
+#[derive(serde::Serialize)]
+pub struct ExtensionPart {
+    /// Path from the extension element, e.g. `properties` or `tags/tag`.
+    tag: String,
+    attributes: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ElementDetails {
+    element: XmiElement,
+    package: Option<XmiElement>,
+    /// All attributes of the element, in file order.
+    attributes: serde_json::Map<String, serde_json::Value>,
+    /// Owned elements: attributes, operations, nested classifiers...
+    children: Vec<XmiElement>,
+    /// Enterprise Architect's extension data on the element, empty for
+    /// other tools.
+    extension: Vec<ExtensionPart>,
+}
+
+fn local_name(name: &str) -> &str {
+    name.rsplit_once(':').map_or(name, |(_, local)| local)
+}
+
+fn attr(e: &BytesStart, names: &[&[u8]]) -> Option<String> {
+    e.attributes()
+        .with_checks(false)
+        .flatten()
+        .find(|a| names.contains(&a.key.as_ref()))
+        .map(|a| {
+            a.unescape_value()
+                .map(|v| v.into_owned())
+                .unwrap_or_else(|_| String::from_utf8_lossy(&a.value).into_owned())
+        })
+}
+
+fn attributes(e: &BytesStart) -> serde_json::Map<String, serde_json::Value> {
+    e.attributes()
+        .with_checks(false)
+        .flatten()
+        .map(|a| {
+            let value = a
+                .unescape_value()
+                .map(|v| v.into_owned())
+                .unwrap_or_else(|_| String::from_utf8_lossy(&a.value).into_owned());
+            (String::from_utf8_lossy(a.key.as_ref()).into_owned(), value.into())
+        })
+        .collect()
+}
+
+/// The `REF_ROLES` attributes of an element; `memberEnd="a b"` holds two.
+fn relationship_refs(e: &BytesStart) -> Vec<(String, String)> {
+    let mut refs = Vec::new();
+    for a in e.attributes().with_checks(false).flatten() {
+        let role = String::from_utf8_lossy(a.key.as_ref()).into_owned();
+        if REF_ROLES.contains(&role.as_str()) {
+            for guid in String::from_utf8_lossy(&a.value).split_whitespace() {
+                refs.push((role.clone(), guid.to_string()));
+            }
+        }
+    }
+    refs
+}
+
+/// The file's XMI index, built now if this is the first command to need it.
+pub fn index(handle: &FileHandle, progress: impl FnMut(u64)) -> Result<Arc<XmiIndex>> {
+    if let Some(index) = handle.xmi.lock().unwrap().clone() {
+        return Ok(index);
+    }
+    let index = Arc::new(handle.consistent(|| build_index(handle, progress))?);
+    *handle.xmi.lock().unwrap() = Some(index.clone());
+    Ok(index)
+}
+
+fn build_index(handle: &FileHandle, mut progress: impl FnMut(u64)) -> Result<XmiIndex> {
+    let file = handle.reader()?;
+    let file_len = file.len().max(1);
+    let mut reader = XmlReader::scan(file);
+    reader.check_end_names(false);
+    let mut buf = Vec::new();
+    let mut last_pct = 0;
+
+    let mut index = XmiIndex {
+        entries: Vec::new(),
+        by_guid: HashMap::new(),
+        extensions: HashMap::new(),
+        refs: HashMap::new(),
+        ea_connectors: Vec::new(),
+    };
+    // Per open element: the model element owning its content
+    let mut owners: Vec<Option<usize>> = Vec::new();
+    // Depth of the `xmi:Extension` being skipped
+    let mut extension: Option<usize> = None;
+    // The EA `<connector>` being read
+    let mut connector: Option<Connector> = None;
+    let mut root = true;
+
+    loop {
+        buf.clear();
+        let pos_before = reader.position();
+        let event = reader.read_event_into(&mut buf)?;
+        let (e, empty) = match &event {
+            Event::Start(e) => (e, false),
+            Event::Empty(e) => (e, true),
+            Event::End(_) => {
+                owners.pop();
+                if extension.is_some_and(|depth| owners.len() == depth + 2) {
+                    index.ea_connectors.extend(connector.take());
+                }
+                if extension.is_some_and(|depth| depth >= owners.len()) {
+                    extension = None;
+                }
+                continue;
+            }
+            Event::Eof => break,
+            _ => continue,
+        };
+        let tag = String::from_utf8_lossy(e.name().as_ref()).into_owned();
+        if root {
+            root = false;
+            if local_name(&tag) != "XMI" && attr(e, &[b"xmi:version", b"xmi.version"]).is_none() {
+                return Err(anyhow::anyhow!("Not an XMI file: the root element is <{}>", tag));
+            }
+        }
+
+        let owner = owners.last().copied().flatten();
+        let mut entry = None;
+        if let Some(depth) = extension {
+            // EA's `<elements><element xmi:idref="...">`
+            if tag == "element" && owners.len() == depth + 2 {
+                if let Some(guid) = attr(e, &[b"xmi:idref", b"xmi.idref"]) {
+                    index.extensions.insert(guid, pos_before);
+                }
+            }
+            // EA's `<connectors><connector xmi:idref="...">`
+            if tag == "connector" && owners.len() == depth + 2 && !empty {
+                if let Some(guid) = attr(e, &[b"xmi:idref", b"xmi.idref"]) {
+                    connector = Some(Connector {
+                        guid,
+                        kind: String::new(),
+                        name: attr(e, &[b"name"]),
+                        source: String::new(),
+                        source_name: None,
+                        target: String::new(),
+                        target_name: None,
+                        offset: pos_before,
+                    });
+                }
+            }
+            if let Some(connector) = connector.as_mut().filter(|_| owners.len() == depth + 3) {
+                match tag.as_str() {
+                    "source" => connector.source = attr(e, &[b"xmi:idref"]).unwrap_or_default(),
+                    "target" => connector.target = attr(e, &[b"xmi:idref"]).unwrap_or_default(),
+                    "properties" => {
+                        connector.kind = attr(e, &[b"ea_type"]).unwrap_or_default();
+                        connector.name = connector.name.take().or_else(|| attr(e, &[b"name"]));
+                    }
+                    _ => {}
+                }
+            }
+        } else if local_name(&tag) == "Extension" || tag == "XMI.extensions" {
+            if !empty {
+                extension = Some(owners.len());
+            }
+        } else if let Some(guid) = attr(e, &[b"xmi:id", b"xmi.id"]) {
+            let kind = attr(e, &[b"xmi:type", b"xmi.type"]).unwrap_or_else(|| tag.clone());
+            let package = matches!(local_name(&kind), "Package" | "Model");
+            index.by_guid.insert(guid.clone(), index.entries.len());
+            entry = Some(index.entries.len());
+            index.entries.push(Entry {
+                guid,
+                name: attr(e, &[b"name"]),
+                kind,
+                offset: pos_before,
+                parent: owner,
+                package,
+            });
+            let refs = relationship_refs(e);
+            if !refs.is_empty() {
+                index.refs.insert(index.entries.len() - 1, refs);
+            }
+        } else if let Some(owner) = owner.filter(|_| REF_ROLES.contains(&local_name(&tag))) {
+            // `<type xmi:idref="..."/>` and the like
+            if let Some(guid) = attr(e, &[b"xmi:idref", b"xmi.idref"]) {
+                index.refs.entry(owner).or_default().push((local_name(&tag).to_string(), guid));
+            }
+        }
+        if !empty {
+            owners.push(entry.or(owner));
+        }
+
+        let pct = reader.position() * 100 / file_len;
+        if pct > last_pct {
+            last_pct = pct;
+            progress(pct);
+        }
+    }
+    if root {
+        return Err(anyhow::anyhow!("Not an XMI file: no root element"));
+    }
+    Ok(index)
+}
+
+/// Every package of an XMI model (and the model, if it has an id), in file
+/// order. `parent` is the enclosing package, so the frontend can build the
+/// package tree. The first `xmi` command on a file indexes it, emitting
+/// `xmi-progress` (0-100).
+#[tauri::command]
+pub async fn list_packages(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    op_id: Option<OpId>,
+) -> Result<Vec<XmiElement>, XmlReaderError> {
+    let handle = files.get(file_id)?;
+    ops::run(window, op_id, "xmi", move |op| {
+        let index = index(&handle, |pct| op.progress(pct)).map_err(|e| XmlReaderError::in_file(&handle, e))?;
+        Ok(index
+            .entries
+            .iter()
+            .filter(|e| e.package)
+            .map(|e| XmiElement {
+                parent: index.package_of(e).map(|p| p.guid.clone()),
+                ..index.element(e)
+            })
+            .collect())
+    })
+    .await
+}
+
+/// The elements directly in package `package_guid` (classes, use cases,
+/// diagrams...), in file order. Sub-packages are left to `list_packages`.
+#[tauri::command]
+pub async fn list_elements(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    package_guid: String,
+    op_id: Option<OpId>,
+) -> Result<Vec<XmiElement>, XmlReaderError> {
+    let handle = files.get(file_id)?;
+    ops::run(window, op_id, "xmi", move |op| {
+        let index = index(&handle, |pct| op.progress(pct)).map_err(|e| XmlReaderError::in_file(&handle, e))?;
+        let children = index.children(&package_guid)?;
+        Ok(children.filter(|e| !e.package).map(|e| index.element(e)).collect())
+    })
+    .await
+}
+
+/// Everything known about model element `guid`: its attributes, owning
+/// package, owned elements, and Enterprise Architect's extension data.
+#[tauri::command]
+pub async fn get_element_details(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    guid: String,
+    op_id: Option<OpId>,
+) -> Result<ElementDetails, XmlReaderError> {
+    let handle = files.get(file_id)?;
+    ops::run(window, op_id, "xmi", move |op| {
+        let index = index(&handle, |pct| op.progress(pct)).map_err(|e| XmlReaderError::in_file(&handle, e))?;
+        handle
+            .consistent(|| element_details(&handle, &index, &guid))
+            .map_err(|e| XmlReaderError::in_file(&handle, e))
+    })
+    .await
+}
+
+fn element_details(handle: &FileHandle, index: &XmiIndex, guid: &str) -> Result<ElementDetails> {
+    let entry = index.get(guid)?;
+    let mut reader = element_reader(handle, entry.offset)?;
+    let mut buf = Vec::new();
+    let attributes = match reader.read_event_into(&mut buf)? {
+        Event::Start(e) | Event::Empty(e) => attributes(&e),
+        _ => return Err(anyhow::anyhow!("No element starts at offset {}", entry.offset)),
+    };
+    let extension = match index.extensions.get(guid) {
+        Some(&offset) => extension_parts(handle, offset)?,
+        None => Vec::new(),
+    };
+    Ok(ElementDetails {
+        element: index.element(entry),
+        package: index.package_of(entry).map(|p| index.element(p)),
+        attributes,
+        children: index.children(guid)?.map(|e| index.element(e)).collect(),
+        extension,
+    })
+}
+
+/// Every element inside the extension element at `offset`, with its path.
+fn extension_parts(handle: &FileHandle, offset: u64) -> Result<Vec<ExtensionPart>> {
+    let mut reader = element_reader(handle, offset)?;
+    let mut buf = Vec::new();
+    if !matches!(reader.read_event_into(&mut buf)?, Event::Start(_)) {
+        return Ok(Vec::new());
+    }
+    let mut parts = Vec::new();
+    let mut path: Vec<String> = Vec::new();
+    loop {
+        buf.clear();
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(e) => {
+                path.push(String::from_utf8_lossy(e.name().as_ref()).into_owned());
+                parts.push(ExtensionPart {
+                    tag: path.join("/"),
+                    attributes: attributes(&e),
+                });
+            }
+            Event::Empty(e) => {
+                let name = String::from_utf8_lossy(e.name().as_ref()).into_owned();
+                let tag = path.iter().chain([&name]).cloned().collect::<Vec<_>>().join("/");
+                parts.push(ExtensionPart {
+                    tag,
+                    attributes: attributes(&e),
+                });
+            }
+            // The extension element's own end tag
+            Event::End(_) if path.pop().is_none() => return Ok(parts),
+            Event::Eof => return Ok(parts),
+            _ => {}
+        }
+    }
+}
+
+/// Connectors `list_connectors` returns at most per page.
+const CONNECTORS_MAX_LIMIT: usize = 10_000;
+
+/// The relationships of an XMI model as a table: type, name, and source and
+/// target by guid and name, filtered, then `skip..skip + limit` of them.
+/// Enterprise Architect exports are read from its `<connector>` list; UML
+/// associations, generalizations, dependencies and realizations are read
+/// from the model itself.
+#[tauri::command]
+pub async fn list_connectors(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    filter: ConnectorFilter,
+    skip: usize,
+    limit: usize,
+    op_id: Option<OpId>,
+) -> Result<ConnectorPage, XmlReaderError> {
+    let handle = files.get(file_id)?;
+    ops::run(window, op_id, "xmi", move |op| {
+        let index = index(&handle, |pct| op.progress(pct)).map_err(|e| XmlReaderError::in_file(&handle, e))?;
+        let matching: Vec<Connector> = index.connectors().into_iter().filter(|c| filter.matches(c)).collect();
+        Ok(ConnectorPage {
+            total: matching.len() as u64,
+            connectors: matching.into_iter().skip(skip).take(limit.min(CONNECTORS_MAX_LIMIT)).collect(),
+        })
+    })
+    .await
+}
@@ -1,50 +1,635 @@
+use crate::error::XmlReaderError;
+use crate::files::{FileHandle, FileId, FileRegistry, OpenElements, ParsingMode};
+use crate::io::{self, Source, XmlReader};
+use crate::ops::{self, OpId};
+use crate::{journal, preview, query, recent, session, settings, watch};
 use anyhow::Result;
 use quick_xml::events::{BytesStart, Event};
-use std::fs::File;
-use std::io::{BufReader, Read, Seek, SeekFrom};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Temp file holding the XML piped in via `xml-reader -`, once fully spooled.
+static STDIN_FILE: Mutex<Option<String>> = Mutex::new(None);
+
+/// Run a command's file work on Tauri's blocking thread pool rather than on
+/// an async runtime worker, so a long scan never holds up quick commands
+/// like `read_chunk` arriving meanwhile.
+pub async fn blocking<T, E>(work: impl FnOnce() -> Result<T, E> + Send + 'static) -> Result<T, E>
+where
+    T: Send + 'static,
+    E: From<anyhow::Error> + Send + 'static,
+{
+    match tauri::async_runtime::spawn_blocking(work).await {
+        Ok(result) => result,
+        Err(e) => Err(anyhow::anyhow!("Worker thread failed: {}", e).into()),
+    }
+}
+
+#[tauri::command]
+pub async fn set_mmap_mode(enabled: bool) -> Result<(), XmlReaderError> {
+    io::set_mmap_enabled(enabled);
+    Ok(())
+}
+
+/// Set how many bytes of an element first/last child, parent and
+/// offset navigation read before giving up on its end tag; 0 for no limit.
+/// Bigger elements come back `truncated`, to be read with `read_element_range`.
+#[tauri::command]
+pub async fn set_element_scan_limit(bytes: u64) -> Result<(), XmlReaderError> {
+    settings::update(|settings| settings.element_scan_limit = bytes).map_err(XmlReaderError::from)
+}
+
+#[tauri::command]
+pub async fn set_reader_limits(limits: io::ReaderLimits) -> Result<(), XmlReaderError> {
+    settings::update(|settings| settings.limits = limits).map_err(XmlReaderError::from)
+}
+
+/// Set reader buffer, remote cache and memory budget sizes. Readers already
+/// running keep their buffers; the cache shrinks on its next insert.
+#[tauri::command]
+pub async fn set_memory_settings(memory: io::MemorySettings) -> Result<(), XmlReaderError> {
+    settings::update(|settings| settings.memory = memory).map_err(XmlReaderError::from)
+}
+
+#[tauri::command]
+pub async fn get_memory_settings() -> Result<io::MemorySettings, XmlReaderError> {
+    Ok(settings::current().memory)
+}
+
+/// Switch a file between lenient and strict parsing for search, navigation
+/// and XPath queries. Files open in the `parsing_mode` setting.
+#[tauri::command]
+pub async fn set_parsing_mode(
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    mode: ParsingMode,
+) -> Result<(), XmlReaderError> {
+    files.get(file_id)?.set_parsing_mode(mode);
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn set_s3_credentials(config: io::S3Config) -> Result<(), XmlReaderError> {
+    io::set_s3_config(config);
+    Ok(())
+}
+
+/// Spool stdin to a temp file, reporting `stdin-progress` (bytes read) and
+/// finally `stdin-ready` with the temp path to the main window. Runs on its
+/// own thread at startup.
+pub fn ingest_stdin(app: AppHandle) {
+    let mut last_progress = 0u64;
+    let result = io::spool_stdin(|total| {
+        if total >= last_progress + 4 * 1024 * 1024 {
+            let _ = app.emit_to("main", "stdin-progress", total);
+            last_progress = total;
+        }
+    });
+
+    match result {
+        Ok(path) => {
+            let path = path.to_string_lossy().to_string();
+            *STDIN_FILE.lock().unwrap() = Some(path.clone());
+            let _ = app.emit_to("main", "stdin-ready", path);
+        }
+        Err(e) => {
+            let _ = app.emit_to("main", "stdin-error", e.to_string());
+        }
+    }
+}
+
+/// Path of the spooled stdin file, for a frontend that subscribed after
+/// `stdin-ready` was already emitted. Only the main window opens it.
+#[tauri::command]
+pub async fn get_stdin_file(window: WebviewWindow) -> Result<Option<String>, XmlReaderError> {
+    if window.label() != "main" {
+        return Ok(None);
+    }
+    Ok(STDIN_FILE.lock().unwrap().clone())
+}
+
+#[derive(serde::Serialize, Clone)]
+pub struct OpenedFile {
+    file_id: FileId,
+    path: String,
+    len: u64,
+}
+
+#[derive(serde::Serialize, Clone)]
+pub struct OpenFailure {
+    path: String,
+    error: String,
+}
+
+/// Payload of `files_opened`, sent once a batch of dropped files is open.
+#[derive(serde::Serialize, Clone)]
+pub struct FilesOpened {
+    opened: Vec<OpenedFile>,
+    failed: Vec<OpenFailure>,
+}
 
-static SEARCH_CANCELLED: AtomicBool = AtomicBool::new(false);
+/// Start per-file background work for a handle newly opened in `window`.
+fn opened(window: &WebviewWindow, handle: Arc<FileHandle>) -> OpenedFile {
+    handle.set_window(window.label());
+    preview::spawn(window.app_handle().clone(), handle.clone());
+    session::restore(window.app_handle(), handle.clone());
+    journal::record(window.app_handle());
+    OpenedFile {
+        file_id: handle.id,
+        path: handle.path.clone(),
+        len: handle.len(),
+    }
+}
+
+#[tauri::command]
+pub async fn open_file(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    path: String,
+) -> Result<OpenedFile, XmlReaderError> {
+    let handle = files.open(&path)?;
+    recent::touch(window.app_handle(), &path);
+    Ok(opened(&window, handle))
+}
+
+/// Open several files at once (drag and drop). Files that fail to open are
+/// reported alongside the others instead of failing the whole batch.
+#[tauri::command]
+pub async fn open_files(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    paths: Vec<String>,
+) -> Result<FilesOpened, XmlReaderError> {
+    let result = open_paths(&window, &files, paths);
+    let _ = window.emit_to(window.label(), "files_opened", result.clone());
+    Ok(result)
+}
+
+/// Open `paths` in `window`, collecting the failures instead of stopping at
+/// the first.
+pub fn open_paths(window: &WebviewWindow, files: &FileRegistry, paths: Vec<String>) -> FilesOpened {
+    let mut result = FilesOpened {
+        opened: Vec::new(),
+        failed: Vec::new(),
+    };
+    for path in paths {
+        match open_path(window, files, path) {
+            Ok((_, file)) => result.opened.push(file),
+            Err(failure) => result.failed.push(failure),
+        }
+    }
+    result
+}
+
+/// Open `path` in `window` as `open_file` does.
+pub fn open_path(
+    window: &WebviewWindow,
+    files: &FileRegistry,
+    path: String,
+) -> Result<(Arc<FileHandle>, OpenedFile), OpenFailure> {
+    match files.open(&path) {
+        Ok(handle) => {
+            recent::touch(window.app_handle(), &path);
+            Ok((handle.clone(), opened(window, handle)))
+        }
+        Err(e) => Err(OpenFailure {
+            path,
+            error: e.to_string(),
+        }),
+    }
+}
+
+/// Open pasted XML by writing it to a temp file owned by the registry, so it
+/// can be browsed like any file without saving it first.
+#[tauri::command]
+pub async fn open_from_text(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    content: String,
+) -> Result<OpenedFile, XmlReaderError> {
+    let path = io::write_temp("pasted", content.as_bytes())?;
+    let path = path.to_string_lossy();
+    let handle = files.open_temp(&path, &path)?;
+    Ok(opened(&window, handle))
+}
+
+/// Fallback for files locked by another process: read a copy taken now. The
+/// copy doesn't follow later changes to the original.
+#[tauri::command]
+pub async fn open_snapshot(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    path: String,
+) -> Result<OpenedFile, XmlReaderError> {
+    let copy = io::snapshot(&path)?;
+    let handle = files
+        .open_temp(&path, &copy.to_string_lossy())
+        ?;
+    Ok(opened(&window, handle))
+}
 
+/// Close a file: stop its watcher and follower and release its handle. Temp
+/// files backing it (pasted text, decoded binary XML) are deleted.
 #[tauri::command]
-pub async fn open_file(path: String) -> Result<u64, String> {
-    let file = File::open(&path).map_err(|e| e.to_string())?;
-    let len = file.metadata().map_err(|e| e.to_string())?.len();
-    Ok(len)
+pub async fn close_file(app: AppHandle, files: State<'_, FileRegistry>, file_id: FileId) -> Result<(), XmlReaderError> {
+    watch::stop(file_id);
+    files.close(file_id)?;
+    journal::record(&app);
+    Ok(())
+}
+
+pub struct Chunk {
+    bytes: Vec<u8>,
+    // Actual byte range returned, after snapping both edges to char boundaries
+    offset: u64,
+    end: u64,
 }
 
+/// Sent as raw bytes rather than JSON, since it's read on every scroll: the
+/// actual `offset` and `end` as little-endian u64s, then the chunk's bytes
+/// as they are in the file (UTF-8, possibly invalid).
+///
+/// A read starting where the previous one ended counts as scrolling: the
+/// next `PREFETCH_CHUNKS` chunks of the same size are then read on a
+/// background thread, so they're ready even from a slow network drive.
 #[tauri::command]
-pub async fn read_chunk(path: String, offset: u64, size: u32) -> Result<String, String> {
-    read_chunk_internal(&path, offset, size).map_err(|e| e.to_string())
+pub async fn read_chunk(
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    offset: u64,
+    size: u32,
+    align: Option<String>,
+) -> Result<tauri::ipc::Response, XmlReaderError> {
+    let handle = files.get(file_id)?;
+    let _foreground = ops::foreground();
+    let key = (offset, size, align.unwrap_or_else(|| "char".to_string()));
+    let (prefetched, sequential) = {
+        let mut read_ahead = handle.read_ahead();
+        let found = read_ahead.chunks.iter().position(|(k, _)| *k == key);
+        let prefetched = found.and_then(|i| read_ahead.chunks.remove(i)).map(|(_, body)| body);
+        (prefetched, read_ahead.next == Some(offset))
+    };
+    let body = match prefetched {
+        Some(body) => body,
+        None => {
+            let chunk = read_chunk_internal(&mut handle.source(), offset, size, &key.2)
+                .map_err(|e| XmlReaderError::in_file(&handle, e))?;
+            chunk_body(&chunk)
+        }
+    };
+    let end = body_end(&body);
+    handle.read_ahead().next = Some(end);
+    if sequential {
+        prefetch(handle, end, size, key.2);
+    }
+    Ok(tauri::ipc::Response::new(body))
 }
 
-fn read_chunk_internal(path: &str, offset: u64, size: u32) -> Result<String> {
-    let mut file = File::open(path)?;
+fn chunk_body(chunk: &Chunk) -> Vec<u8> {
+    let mut body = Vec::with_capacity(16 + chunk.bytes.len());
+    body.extend_from_slice(&chunk.offset.to_le_bytes());
+    body.extend_from_slice(&chunk.end.to_le_bytes());
+    body.extend_from_slice(&chunk.bytes);
+    body
+}
+
+fn body_end(body: &[u8]) -> u64 {
+    u64::from_le_bytes(body[8..16].try_into().unwrap())
+}
+
+/// Chunks read ahead of a sequential `read_chunk`.
+const PREFETCH_CHUNKS: usize = 2;
+/// Prefetched chunks kept per file, oldest dropped first.
+const PREFETCH_MAX_CACHED: usize = 4;
+
+/// Read the chunks following `offset` into the file's read-ahead cache,
+/// through a reader of its own so the view's reads never wait on it.
+fn prefetch(handle: Arc<FileHandle>, mut offset: u64, size: u32, align: String) {
+    tauri::async_runtime::spawn_blocking(move || {
+        let generation = handle.read_ahead().generation;
+        for _ in 0..PREFETCH_CHUNKS {
+            if offset >= handle.len() {
+                break;
+            }
+            let key = (offset, size, align.clone());
+            {
+                let mut read_ahead = handle.read_ahead();
+                if read_ahead.generation != generation || read_ahead.pending.contains(&key) {
+                    break;
+                }
+                if let Some((_, body)) = read_ahead.chunks.iter().find(|(k, _)| *k == key) {
+                    offset = body_end(body);
+                    continue;
+                }
+                read_ahead.pending.push(key.clone());
+            }
+            let chunk = handle.reader().and_then(|mut file| read_chunk_internal(&mut file, offset, size, &align));
+            let mut read_ahead = handle.read_ahead();
+            read_ahead.pending.retain(|k| *k != key);
+            let Ok(chunk) = chunk else { break };
+            if read_ahead.generation != generation {
+                break;
+            }
+            if read_ahead.chunks.len() >= PREFETCH_MAX_CACHED {
+                read_ahead.chunks.pop_front();
+            }
+            offset = chunk.end;
+            read_ahead.chunks.push_back((key, chunk_body(&chunk)));
+        }
+    });
+}
+
+/// How far back `align: "tag" | "line"` looks for the start of the tag/line.
+const ALIGN_SCAN_BACK: u64 = 4096;
+
+/// `align` is `"char"` (only UTF-8 boundaries), `"line"` (whole lines) or
+/// `"tag"` (never start or end inside a tag, for syntax highlighting).
+fn read_chunk_internal(file: &mut Source, offset: u64, size: u32, align: &str) -> Result<Chunk> {
+    if !matches!(align, "char" | "line" | "tag") {
+        return Err(anyhow::anyhow!("Unknown align mode: {}", align));
+    }
+
+    let len = file.len();
+    let mut offset = offset.min(len);
+    let end = offset.saturating_add(size as u64).min(len);
+
+    if align != "char" {
+        offset = aligned_start(file, offset, align)?;
+    }
+
+    // Read up to 3 bytes before the window and 1 byte past it, so both edges
+    // can be moved back onto the first byte of the character they fall in.
+    let read_start = offset.saturating_sub(3);
+    let read_end = (end + 1).min(len);
+    file.seek(SeekFrom::Start(read_start))?;
+    let mut buffer = vec![0; (read_end - read_start) as usize];
+    file.read_exact(&mut buffer)?;
+
+    let start_idx = char_boundary_before(&buffer, (offset - read_start) as usize);
+    let mut end_idx = char_boundary_before(&buffer, (end - read_start) as usize).max(start_idx);
+    if align != "char" && end < len {
+        end_idx = start_idx + aligned_len(&buffer[start_idx..end_idx], align);
+    }
+
+    buffer.truncate(end_idx);
+    buffer.drain(..start_idx);
+    Ok(Chunk {
+        bytes: buffer,
+        offset: read_start + start_idx as u64,
+        end: read_start + end_idx as u64,
+    })
+}
+
+/// Move `offset` back to the start of the line, or to the `<` of the tag it
+/// falls inside. Stays put if nothing is found within `ALIGN_SCAN_BACK`.
+fn aligned_start(file: &mut Source, offset: u64, align: &str) -> Result<u64> {
+    let back_start = offset.saturating_sub(ALIGN_SCAN_BACK);
+    let mut buf = vec![0u8; (offset - back_start) as usize];
+    file.seek(SeekFrom::Start(back_start))?;
+    file.read_exact(&mut buf)?;
+
+    let start = match align {
+        "line" => match buf.iter().rposition(|&b| b == b'\n') {
+            Some(i) => back_start + i as u64 + 1,
+            None if back_start == 0 => 0,
+            None => offset,
+        },
+        _ => match unclosed_tag_start(&buf) {
+            Some(i) => back_start + i as u64,
+            None => offset,
+        },
+    };
+    Ok(start)
+}
+
+/// Length of `bytes` to keep so the chunk ends on a whole line, or before a
+/// tag that is cut off. Never trims the chunk down to nothing.
+fn aligned_len(bytes: &[u8], align: &str) -> usize {
+    let keep = match align {
+        "line" => bytes.iter().rposition(|&b| b == b'\n').map(|i| i + 1),
+        _ => unclosed_tag_start(bytes),
+    };
+    match keep {
+        Some(n) if n > 0 => n,
+        _ => bytes.len(),
+    }
+}
+
+/// Index of the last `<` in `bytes` if no `>` follows it.
+fn unclosed_tag_start(bytes: &[u8]) -> Option<usize> {
+    let lt = bytes.iter().rposition(|&b| b == b'<')?;
+    match bytes.iter().rposition(|&b| b == b'>') {
+        Some(gt) if gt > lt => None,
+        _ => Some(lt),
+    }
+}
+
+/// Move `idx` back to the lead byte of the UTF-8 sequence it points into.
+/// Gives up after 3 continuation bytes (the longest valid tail), so invalid
+/// input never moves an edge by more than that.
+fn char_boundary_before(buf: &[u8], idx: usize) -> usize {
+    let mut i = idx;
+    while i > 0 && i < buf.len() && idx - i < 3 && (buf[i] & 0xC0) == 0x80 {
+        i -= 1;
+    }
+    i
+}
+
+/// Upper bound for a single `read_hex` response.
+const MAX_HEX_BYTES: u32 = 64 * 1024;
+
+#[derive(serde::Serialize)]
+pub struct HexView {
+    offset: u64,
+    bytes: Vec<u8>,
+    // `xxd`-style dump: offset, 16 bytes in hex, then the printable ASCII
+    text: String,
+}
+
+/// Raw bytes of `offset..offset + size` (clamped to the file and to
+/// `MAX_HEX_BYTES`) with a hex+ASCII rendering, for looking at BOMs, stray
+/// control characters and broken encodings around a parse error.
+#[tauri::command]
+pub async fn read_hex(
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    offset: u64,
+    size: u32,
+) -> Result<HexView, XmlReaderError> {
+    let handle = files.get(file_id)?;
+    let _foreground = ops::foreground();
+    read_hex_internal(&handle, offset, size).map_err(|e| XmlReaderError::in_file(&handle, e))
+}
+
+fn read_hex_internal(handle: &FileHandle, offset: u64, size: u32) -> Result<HexView> {
+    let mut file = handle.source();
+    let len = file.len();
+    let offset = offset.min(len);
+    let end = offset.saturating_add(size.min(MAX_HEX_BYTES) as u64).min(len);
+    let mut bytes = vec![0; (end - offset) as usize];
     file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut bytes)?;
+
+    let mut text = String::new();
+    for (i, row) in bytes.chunks(16).enumerate() {
+        text.push_str(&format!("{:08x}  ", offset + i as u64 * 16));
+        for col in 0..16 {
+            match row.get(col) {
+                Some(b) => text.push_str(&format!("{:02x} ", b)),
+                None => text.push_str("   "),
+            }
+            if col == 7 {
+                text.push(' ');
+            }
+        }
+        text.push_str(" |");
+        text.extend(row.iter().map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' }));
+        text.push_str("|\n");
+    }
 
-    let mut buffer = vec![0; size as usize];
-    let n = file.read(&mut buffer)?;
+    Ok(HexView { offset, bytes, text })
+}
+
+/// Upper bound for a single `read_lines` response, so a machine-generated file
+/// that is one giant line can't be pulled into memory in one go.
+const MAX_LINES_BYTES: usize = 4 * 1024 * 1024;
 
-    let s = String::from_utf8_lossy(&buffer[..n]).to_string();
-    Ok(s)
+#[derive(serde::Serialize)]
+pub struct LineChunk {
+    text: String,
+    start_line: u64,
+    line_count: u64,
+    offset: u64,
+    end: u64,
+    truncated: bool,
 }
 
+#[tauri::command]
+pub async fn read_lines(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    start_line: u64,
+    line_count: u64,
+    op_id: Option<OpId>,
+) -> Result<LineChunk, XmlReaderError> {
+    let handle = files.get(file_id)?;
+    ops::run(window, op_id, "lines", move |op| {
+        read_lines_internal(&handle, start_line, line_count, |pct| op.progress(pct))
+            .map_err(|e| XmlReaderError::in_file(&handle, e))
+    })
+    .await
+}
+
+/// Read `line_count` whole lines starting at 1-based `start_line`.
+/// `progress` (0-100) follows the count up to `start_line`, the slow part
+/// deep into a big file.
+fn read_lines_internal(
+    handle: &FileHandle,
+    start_line: u64,
+    line_count: u64,
+    mut progress: impl FnMut(u64),
+) -> Result<LineChunk> {
+    let file = handle.reader()?;
+    let mut reader = BufReader::with_capacity(io::scan_buffer(), file);
+    let mut line = 1u64;
+    let mut pos = 0u64;
+    let mut last_pct = 0;
+
+    // Skip to the first requested line
+    while line < start_line {
+        let pct = line * 100 / start_line;
+        if pct > last_pct {
+            ops::check()?;
+            last_pct = pct;
+            progress(pct);
+        }
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            break;
+        }
+        let n = match buf.iter().position(|&b| b == b'\n') {
+            Some(i) => {
+                line += 1;
+                i + 1
+            }
+            None => buf.len(),
+        };
+        reader.consume(n);
+        pos += n as u64;
+    }
+
+    let offset = pos;
+    let mut out = Vec::new();
+    let mut lines_read = 0u64;
+    let mut truncated = false;
+
+    while lines_read < line_count {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            // Last line without a trailing newline
+            if out.last().is_some_and(|&b| b != b'\n') {
+                lines_read += 1;
+            }
+            break;
+        }
+        let newline = buf.iter().position(|&b| b == b'\n');
+        let wanted = newline.map_or(buf.len(), |i| i + 1);
+        let take = wanted.min(MAX_LINES_BYTES - out.len());
+        out.extend_from_slice(&buf[..take]);
+        reader.consume(take);
+        pos += take as u64;
+
+        if newline.is_some() && take == wanted {
+            lines_read += 1;
+        }
+        if out.len() >= MAX_LINES_BYTES {
+            truncated = true;
+            break;
+        }
+    }
+
+    Ok(LineChunk {
+        text: String::from_utf8_lossy(&out).to_string(),
+        start_line: line,
+        line_count: lines_read,
+        offset,
+        end: pos,
+        truncated,
+    })
+}
 
 #[tauri::command]
-pub async fn resolve_xpath(path: String, offset: u64, tag_name: String) -> Result<String, String> {
-    let parent_path = reconstruct_xpath(&path, offset).map_err(|e| e.to_string())?;
-    Ok(format!("{}/{}", parent_path, tag_name))
+pub async fn resolve_xpath(
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    offset: u64,
+    tag_name: String,
+) -> Result<String, XmlReaderError> {
+    let handle = files.get(file_id)?;
+    blocking(move || {
+        let parent_path = reconstruct_xpath(&handle, offset).map_err(|e| XmlReaderError::in_file(&handle, e))?;
+        Ok(format!("{}/{}", parent_path, tag_name))
+    })
+    .await
 }
 
 #[tauri::command]
-pub async fn get_first_child(path: String) -> Result<SearchResult, String> {
-    get_first_child_internal(&path).map_err(|e| e.to_string())
+pub async fn get_first_child(files: State<'_, FileRegistry>, file_id: FileId) -> Result<SearchResult, XmlReaderError> {
+    let handle = files.get(file_id)?;
+    blocking(move || {
+        handle.consistent(|| get_first_child_internal(&handle)).map_err(|e| XmlReaderError::in_file(&handle, e))
+    })
+    .await
 }
 
-fn get_first_child_internal(path: &str) -> Result<SearchResult> {
-    let file = File::open(path)?;
-    let file_len = file.metadata()?.len();
-    let mut reader = quick_xml::Reader::from_reader(BufReader::new(file));
+fn get_first_child_internal(handle: &FileHandle) -> Result<SearchResult> {
+    let file = handle.reader()?;
+    let file_len = file.len();
+    let mut reader = XmlReader::new(file, 8 * 1024);
+    reader.set_strict(handle.strict());
 
     let mut buf = Vec::new();
     let mut root_found = false;
@@ -52,7 +637,7 @@ fn get_first_child_internal(path: &str) -> Result<SearchResult> {
 
     // Loop until we find the first child element (the one after <root>)
     loop {
-        let pos_before = reader.buffer_position();
+        let pos_before = reader.position();
         match reader.read_event_into(&mut buf) {
             Ok(Event::Start(ref e)) => {
                 if !root_found {
@@ -63,11 +648,11 @@ fn get_first_child_internal(path: &str) -> Result<SearchResult> {
 
                 let name = String::from_utf8_lossy(e.name().as_ref()).to_string();
                 // Found first child!
-                let approx_start = pos_before as u64;
-                let approx_end = find_element_end_pos(&mut reader, &mut buf, &name, file_len, 0)?;
+                let approx_start = pos_before;
+                let (approx_end, truncated) = find_element_end_pos(&mut reader, &mut buf, &name, file_len, element_scan_limit())?;
                 let xpath = format!("/{}/{} (first)", root_name, name);
 
-                return extract_and_build_result(path, file_len, approx_start, approx_end, &xpath, vec![]);
+                return extract_and_build_result(handle, file_len, approx_start, approx_end, &xpath, vec![], truncated);
             }
             Ok(Event::Empty(ref e)) => {
                 if !root_found {
@@ -77,13 +662,13 @@ fn get_first_child_internal(path: &str) -> Result<SearchResult> {
 
                 // First child is empty self-closing tag
                 let name = String::from_utf8_lossy(e.name().as_ref()).to_string();
-                let approx_start = pos_before as u64;
-                let approx_end = reader.buffer_position() as u64;
+                let approx_start = pos_before;
+                let approx_end = reader.position();
                 let xpath = format!("/{}/{} (first)", root_name, name);
-                return extract_and_build_result(path, file_len, approx_start, approx_end, &xpath, vec![]);
+                return extract_and_build_result(handle, file_len, approx_start, approx_end, &xpath, vec![], false);
             }
             Ok(Event::Eof) => break,
-            Err(e) => return Err(anyhow::anyhow!("Error: {:?}", e)),
+            Err(e) => return Err(e),
             _ => (),
         }
         buf.clear();
@@ -93,18 +678,25 @@ fn get_first_child_internal(path: &str) -> Result<SearchResult> {
 }
 
 #[tauri::command]
-pub async fn get_last_child(path: String) -> Result<SearchResult, String> {
-    get_last_child_internal(&path).map_err(|e| e.to_string())
+pub async fn get_last_child(files: State<'_, FileRegistry>, file_id: FileId) -> Result<SearchResult, XmlReaderError> {
+    let handle = files.get(file_id)?;
+    blocking(move || {
+        handle.consistent(|| get_last_child_internal(&handle)).map_err(|e| XmlReaderError::in_file(&handle, e))
+    })
+    .await
 }
 
-fn get_last_child_internal(path: &str) -> Result<SearchResult> {
-    let mut file = File::open(path)?;
-    let len = file.metadata()?.len();
+fn get_last_child_internal(handle: &FileHandle) -> Result<SearchResult> {
+    let mut file = handle.reader()?;
+    let len = file.len();
 
     if len == 0 {
         return Err(anyhow::anyhow!("File is empty"));
     }
 
+    // The prolog may hold a DOCTYPE whose internal subset has `<` and `>` of
+    // its own, so the backward scan stops at the root
+    let root_start = preview::root_offset(handle)?;
     let mut depth: i32 = 0;
     let mut last_tag_end: Option<u64> = None;
     let mut root_name = String::new();
@@ -125,23 +717,22 @@ fn get_last_child_internal(path: &str) -> Result<SearchResult> {
         file.seek(SeekFrom::Start(current_pos))?;
         file.read_exact(&mut buf[..read_size])?;
 
-        for i in (0..read_size).rev() {
-            if buf[i] != b'<' {
-                continue;
-            }
-
+        for i in memchr::memrchr_iter(b'<', &buf[..read_size]) {
             let abs_start = current_pos + i as u64;
+            if abs_start < root_start {
+                return Err(anyhow::anyhow!("Last child not found"));
+            }
             let remaining = &buf[i..read_size];
 
             // Resolve the full tag bytes up to the closing '>'.
-            let parsed = if let Some(gt) = remaining.iter().position(|&b| b == b'>') {
+            let parsed = if let Some(gt) = memchr::memchr(b'>', remaining) {
                 classify_tag(&remaining[..gt + 1])
             } else {
                 // Tag spans chunk boundary — one small forward read (very rare)
                 let to_read = std::cmp::min(1024u64, len - abs_start) as usize;
                 file.seek(SeekFrom::Start(abs_start))?;
                 let n = file.read(&mut tag_buf[..to_read])?;
-                if let Some(gt) = tag_buf[..n].iter().position(|&b| b == b'>') {
+                if let Some(gt) = memchr::memchr(b'>', &tag_buf[..n]) {
                     classify_tag(&tag_buf[..gt + 1])
                 } else {
                     None
@@ -173,7 +764,7 @@ fn get_last_child_internal(path: &str) -> Result<SearchResult> {
                     if depth == 1 {
                         if let Some(end) = last_tag_end {
                             let xpath = format!("/{}/{} (last)", root_name, tag_name);
-                            return extract_and_build_result(path, len, abs_start, end, &xpath, vec![]);
+                            return extract_and_build_result(handle, len, abs_start, end, &xpath, vec![], false);
                         }
                     }
                     // If we hit depth 0 (<Root>), we are done searching children.
@@ -186,7 +777,7 @@ fn get_last_child_internal(path: &str) -> Result<SearchResult> {
                     if depth == 1 {
                         let abs_end = abs_start + tag_len as u64;
                         let xpath = format!("/{}/{} (last)", root_name, tag_name);
-                        return extract_and_build_result(path, len, abs_start, abs_end, &xpath, vec![]);
+                        return extract_and_build_result(handle, len, abs_start, abs_end, &xpath, vec![], false);
                     }
                 }
             }
@@ -240,35 +831,46 @@ fn extract_tag_name_from_bytes(bytes: &[u8]) -> String {
     String::from_utf8_lossy(&bytes[..end]).to_string()
 }
 
+/// Stop the searches running in the calling window; they return not found.
 #[tauri::command]
-pub async fn cancel_search() -> Result<(), String> {
-    SEARCH_CANCELLED.store(true, Ordering::SeqCst);
+pub async fn cancel_search(window: WebviewWindow) -> Result<(), XmlReaderError> {
+    ops::cancel_kind(window.label(), "search");
     Ok(())
 }
 
-use tauri::{AppHandle, Emitter};
+use tauri::{AppHandle, Emitter, Manager, State, WebviewWindow};
 
 #[tauri::command]
 pub async fn search_node(
-    app: AppHandle,
-    path: String,
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
     query: String,
     search_type: String,
     start_offset: u64,
-) -> Result<SearchResult, String> {
-    SEARCH_CANCELLED.store(false, Ordering::SeqCst);
-    search_node_internal(&app, &path, &query, &search_type, start_offset).map_err(|e| e.to_string())
+    op_id: Option<OpId>,
+) -> Result<SearchResult, XmlReaderError> {
+    let handle = files.get(file_id)?;
+    ops::run(window, op_id, "search", move |op| {
+        let result =
+            handle.consistent(|| search_node_internal(&handle, &query, &search_type, start_offset, |pct| op.progress(pct)));
+        match result {
+            Err(e) if matches!(e.downcast_ref(), Some(XmlReaderError::Cancelled)) => Ok(SearchResult::not_found()),
+            result => result.map_err(|e| XmlReaderError::in_file(&handle, e)),
+        }
+    })
+    .await
 }
 
 fn search_node_internal(
-    app: &AppHandle,
-    path: &str,
+    handle: &FileHandle,
     query: &str,
     search_type: &str,
     start_offset: u64,
+    mut progress: impl FnMut(u64),
 ) -> Result<SearchResult> {
-    let mut file = File::open(path)?;
-    let file_len = file.metadata()?.len();
+    let mut file = handle.reader()?;
+    let file_len = file.len();
     
     // Seek to start_offset if > 0
     if start_offset > 0 {
@@ -276,177 +878,215 @@ fn search_node_internal(
     }
 
     // Increase buffer size to 1MB for better performance on large files
-    let mut reader = quick_xml::Reader::from_reader(BufReader::with_capacity(1024 * 1024, file));
+    let mut reader = XmlReader::scan(file);
     reader.check_end_names(false);
+    reader.set_strict(handle.strict());
 
     let mut buf = Vec::new();
-    let mut stack: Vec<(String, u64)> = Vec::new();
-    // If we sought, we don't know the parents. Push a placeholder or empty?
-    // Let's just keep stack empty. The found element will be at top level relative to search.
-    if start_offset > 0 {
-        // We know the stack is invalid, but we will reconstruct it exactly when needed
-    }
+    // A resumed search picks up the ancestors open at `start_offset`
+    let mut stack = if start_offset > 0 {
+        NameStack::from_open_elements(&open_elements_at(handle, start_offset)?)
+    } else {
+        NameStack::default()
+    };
 
     let query_bytes = query.to_lowercase().into_bytes();
-    let type_bytes = search_type.to_lowercase().into_bytes();
+    let target = SearchTarget::new(search_type);
     
     let mut last_progress = 0u64;
     let total_len = file_len as f64;
+    let mut last_checkpoint = start_offset;
 
     loop {
-        if SEARCH_CANCELLED.load(Ordering::SeqCst) {
-            return Ok(SearchResult {
-                found: false,
-                xpath: String::new(),
-                element_text: String::new(),
-                context_before: String::new(),
-                context_after: String::new(),
-                offset: 0,
-                line_number: 0,
-                ancestors: vec![],
-            });
-        }
-
-        // buffer_position() is relative to where we started reading
-        let pos_before = start_offset + reader.buffer_position() as u64;
+        let pos_before = reader.position();
         
         // Report progress every ~1% or continuously if small
         if pos_before > last_progress + (file_len / 100).max(1024 * 1024) {
             let pct = (pos_before as f64 / total_len * 100.0) as u64;
-            let _ = app.emit("search-progress", pct);
+            progress(pct);
             last_progress = pos_before;
         }
+        if pos_before >= last_checkpoint + CHECKPOINT_INTERVAL {
+            handle.add_checkpoint(pos_before, &stack.to_open_elements());
+            last_checkpoint = pos_before;
+        }
 
         match reader.read_event_into(&mut buf) {
             Ok(Event::Start(ref e)) => {
-                let name = String::from_utf8_lossy(e.name().as_ref()).to_string();
-                
                 // Check match
-                if element_matches_bytes(e, &query_bytes, &type_bytes) {
-                    let approx_start = pos_before as u64;
-                    let approx_end = start_offset + reader.buffer_position() as u64;
+                if element_matches_bytes(e, &query_bytes, &target) {
+                    let approx_start = pos_before;
+                    let approx_end = reader.position();
 
-                    let mut current_path: Vec<String> = stack.iter().map(|(n, _)| n.clone()).collect();
-                    current_path.push(name.clone());
-                    let xpath = format!("/{}", current_path.join("/"));
+                    let open = stack.to_open_elements();
+                    let xpath = xpath_of(&open, e.name().as_ref());
                     
                     // Emit 100% progress on find
-                    let _ = app.emit("search-progress", 100u64);
+                    progress(100);
 
-                    let ancestors: Vec<AncestorInfo> = stack.iter().map(|(n, off)| AncestorInfo {
-                        name: n.clone(),
-                        offset: *off,
+                    let ancestors: Vec<AncestorInfo> = open.into_iter().map(|(name, offset)| AncestorInfo {
+                        name,
+                        offset,
                         line_number: 0 // Expensive to calc, lazy load if needed
                     }).collect();
 
                     return extract_and_build_result(
-                        path,
+                        handle,
                         file_len,
                         approx_start,
                         approx_end,
                         &xpath,
                         ancestors,
+                        false,
                     );
                 }
 
-                stack.push((name, pos_before));
+                stack.push(e.name().as_ref(), pos_before);
             }
             Ok(Event::End(_)) => {
                 stack.pop();
             }
-            Ok(Event::Empty(ref e)) => {
-                // Self-closing matches too
-                let name = String::from_utf8_lossy(e.name().as_ref()).to_string();
-                if element_matches_bytes(e, &query_bytes, &type_bytes) {
-                    let approx_start = pos_before as u64;
-                    let approx_end = start_offset + reader.buffer_position() as u64;
+            // Self-closing matches too
+            Ok(Event::Empty(ref e)) if element_matches_bytes(e, &query_bytes, &target) => {
+                let approx_start = pos_before;
+                let approx_end = reader.position();
 
-                    let mut current_path: Vec<String> = stack.iter().map(|(n, _)| n.clone()).collect();
-                    current_path.push(name.clone());
-                    let xpath = format!("/{}", current_path.join("/"));
-                    
-                    // Emit 100% progress on find
-                    let _ = app.emit("search-progress", 100u64);
-
-                    let ancestors: Vec<AncestorInfo> = stack.iter().map(|(n, off)| AncestorInfo {
-                        name: n.clone(),
-                        offset: *off,
-                        line_number: 0
-                    }).collect();
-
-                    return extract_and_build_result(
-                        path,
-                        file_len,
-                        approx_start,
-                        approx_end,
-                        &xpath,
-                        ancestors,
-                    );
-                }
+                let open = stack.to_open_elements();
+                let xpath = xpath_of(&open, e.name().as_ref());
+                
+                // Emit 100% progress on find
+                progress(100);
+
+                let ancestors: Vec<AncestorInfo> = open.into_iter().map(|(name, offset)| AncestorInfo {
+                    name,
+                    offset,
+                    line_number: 0
+                }).collect();
+
+                return extract_and_build_result(
+                    handle,
+                    file_len,
+                    approx_start,
+                    approx_end,
+                    &xpath,
+                    ancestors,
+                    false,
+                );
             }
             Ok(Event::Eof) => break,
-            Err(e) => {
-                return Err(anyhow::anyhow!(
-                    "Error at position {}: {:?}",
-                    reader.buffer_position(),
-                    e
-                ))
-            }
+            Err(e) => return Err(e),
             _ => (),
         }
         buf.clear();
     }
     
     // Emit 100% progress on end
-    let _ = app.emit("search-progress", 100u64);
+    progress(100);
 
-    Ok(SearchResult {
-        found: false,
-        xpath: String::new(),
-        element_text: String::new(),
-        context_before: String::new(),
-        context_after: String::new(),
-        offset: 0,
-        line_number: 0,
-        ancestors: vec![],
-    })
+    Ok(SearchResult::not_found())
+}
+
+/// Names and start offsets of the elements open during a scan, the names
+/// packed into one buffer so a start tag costs no allocation of its own.
+/// Turned into `OpenElements` only for results and checkpoints.
+#[derive(Default)]
+struct NameStack {
+    names: Vec<u8>,
+    // Where each name starts in `names`, and the element's offset
+    open: Vec<(usize, u64)>,
+}
+
+impl NameStack {
+    fn from_open_elements(elements: &OpenElements) -> NameStack {
+        let mut stack = NameStack::default();
+        for (name, offset) in elements {
+            stack.push(name.as_bytes(), *offset);
+        }
+        stack
+    }
+
+    fn push(&mut self, name: &[u8], offset: u64) {
+        self.open.push((self.names.len(), offset));
+        self.names.extend_from_slice(name);
+    }
+
+    /// Close the innermost element; false if none was open.
+    fn pop(&mut self) -> bool {
+        match self.open.pop() {
+            Some((start, _)) => {
+                self.names.truncate(start);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn to_open_elements(&self) -> OpenElements {
+        let ends = self.open.iter().skip(1).map(|&(start, _)| start).chain([self.names.len()]);
+        self.open
+            .iter()
+            .zip(ends)
+            .map(|(&(start, offset), end)| (String::from_utf8_lossy(&self.names[start..end]).to_string(), offset))
+            .collect()
+    }
+}
+
+/// `/a/b/name` for an element named `name` inside `open`.
+fn xpath_of(open: &OpenElements, name: &[u8]) -> String {
+    let mut xpath = String::new();
+    for (ancestor, _) in open {
+        xpath.push('/');
+        xpath.push_str(ancestor);
+    }
+    xpath.push('/');
+    xpath.push_str(&String::from_utf8_lossy(name));
+    xpath
 }
 
-/// Check if an element matches the query by tag name, guid, id, or name attribute.
+/// What a search of some `search_type` compares its query with, worked out
+/// once per search: `"tag"` looks at tag names, `"any"` at tag names and the
+/// `search_attributes` setting, anything else at the attribute of that name.
+pub struct SearchTarget {
+    tags: bool,
+    attributes: Vec<Vec<u8>>,
+}
+
+impl SearchTarget {
+    pub fn new(search_type: &str) -> SearchTarget {
+        let search_type = search_type.to_lowercase();
+        let attributes = if search_type == "any" {
+            settings::current().search_attributes.iter().map(|a| a.as_bytes().to_vec()).collect()
+        } else {
+            vec![search_type.as_bytes().to_vec()]
+        };
+        SearchTarget {
+            tags: search_type.is_empty() || search_type == "tag" || search_type == "any",
+            attributes,
+        }
+    }
+
+    /// Whether tag names are searched.
+    pub fn tags(&self) -> bool {
+        self.tags
+    }
+
+    /// Whether the attribute named `key` is searched.
+    pub fn attribute(&self, key: &[u8]) -> bool {
+        self.attributes.iter().any(|target| key_matches(key, target))
+    }
+}
+
+/// Check if an element matches the query by tag name or a searched attribute.
 /// Uses raw bytes comparison to avoid allocations.
-fn element_matches_bytes(e: &BytesStart, query_bytes: &[u8], type_bytes: &[u8]) -> bool {
-    // If type is "tag" or "any", check tag name
-    if (type_bytes.is_empty() || type_bytes == b"tag" || type_bytes == b"any") && contains_ignore_case(e.name().as_ref(), query_bytes) {
+pub fn element_matches_bytes(e: &BytesStart, query_bytes: &[u8], target: &SearchTarget) -> bool {
+    if target.tags() && contains_ignore_case(e.name().as_ref(), query_bytes) {
         return true;
     }
     
     // Check specific attributes
     for attr in e.attributes().flatten() {
-        let key = attr.key.as_ref();
-        
-        let mut check_this_attr = false;
-
-        // "any" matches everything in our list
-        if type_bytes == b"any" {
-            if key_matches(key, b"guid") || 
-               key_matches(key, b"id") || 
-               key_matches(key, b"name") ||
-               key_matches(key, b"eaid") ||
-               key_matches(key, b"value") ||
-               key_matches(key, b"guidref") {
-                check_this_attr = true;
-            }
-        } else {
-            // specific match
-            if key_matches(key, type_bytes) {
-                check_this_attr = true;
-            }
-        }
-        
-        if check_this_attr {
-            if contains_ignore_case(&attr.value, query_bytes) {
-                return true;
-            }
+        if target.attribute(attr.key.as_ref()) && contains_ignore_case(&attr.value, query_bytes) {
+            return true;
         }
     }
     false
@@ -489,25 +1129,29 @@ fn iter_eq_ignore_case(a: &[u8], b: &[u8]) -> bool {
         .all(|(x, y)| x.eq_ignore_ascii_case(y))
 }
 
-/// After consuming a Start event, continue parsing until the matching End event.
-/// Returns the approximate byte position after the closing tag.
+/// The `element_scan_limit` setting.
+fn element_scan_limit() -> Option<u64> {
+    Some(settings::current().element_scan_limit).filter(|&limit| limit > 0)
+}
+
+/// End of the element whose start tag `reader` just read, and whether the
+/// scan stopped at `scan_limit` bytes (`None`: no limit) without finding it.
 fn find_element_end_pos(
-    reader: &mut quick_xml::Reader<BufReader<File>>,
+    reader: &mut XmlReader,
     buf: &mut Vec<u8>,
     tag_name: &str,
     file_len: u64,
-    start_offset: u64,
-) -> Result<u64> {
+    scan_limit: Option<u64>,
+) -> Result<(u64, bool)> {
     let mut depth = 1u32;
-    let initial_pos = reader.buffer_position();
-    let scan_limit = 10 * 1024 * 1024; // 10MB limit
+    let initial_pos = reader.position();
 
     loop {
         buf.clear();
-        let current_pos = reader.buffer_position();
-        if current_pos - initial_pos > scan_limit {
+        let current_pos = reader.position();
+        if scan_limit.is_some_and(|limit| current_pos - initial_pos > limit) {
             // Stop scanning if element is too large
-            return Ok(start_offset + current_pos as u64);
+            return Ok((current_pos, true));
         }
 
         match reader.read_event_into(buf) {
@@ -524,12 +1168,12 @@ fn find_element_end_pos(
                 if name == tag_name {
                     depth -= 1;
                     if depth == 0 {
-                        return Ok(start_offset + reader.buffer_position() as u64);
+                        return Ok((reader.position(), false));
                     }
                 }
             }
-            Ok(Event::Eof) => return Ok(file_len),
-            Err(_) => return Ok(start_offset + reader.buffer_position() as u64),
+            Ok(Event::Eof) => return Ok((file_len, false)),
+            Err(_) => return Ok((reader.position(), false)),
             _ => (),
         }
     }
@@ -552,11 +1196,30 @@ pub struct SearchResult {
     offset: u64,
     line_number: u64,
     ancestors: Vec<AncestorInfo>,
+    /// The element runs past the scan limit, so `element_text` stops short;
+    /// `read_element_range` reads all of it.
+    truncated: bool,
 }
 
-fn count_lines_up_to(path: &str, offset: u64) -> Result<u64> {
-    let file = File::open(path)?;
-    let mut reader = std::io::BufReader::with_capacity(1024 * 1024, file); // 1MB buffer
+impl SearchResult {
+    fn not_found() -> SearchResult {
+        SearchResult {
+            found: false,
+            xpath: String::new(),
+            element_text: String::new(),
+            context_before: String::new(),
+            context_after: String::new(),
+            offset: 0,
+            line_number: 0,
+            ancestors: vec![],
+            truncated: false,
+        }
+    }
+}
+
+fn count_lines_up_to(handle: &FileHandle, offset: u64) -> Result<u64> {
+    let file = handle.reader()?;
+    let mut reader = std::io::BufReader::with_capacity(io::scan_buffer(), file);
     let mut count = 1; // 1-based line number
     let mut total_read = 0;
     
@@ -577,6 +1240,7 @@ fn count_lines_up_to(path: &str, offset: u64) -> Result<u64> {
         // Count newlines in this chunk
         count += buffer[..n].iter().filter(|&&b| b == b'\n').count() as u64;
         total_read += n as u64;
+        ops::check()?;
     }
     
     Ok(count)
@@ -585,18 +1249,20 @@ fn count_lines_up_to(path: &str, offset: u64) -> Result<u64> {
 /// Given approximate start/end positions from quick-xml, find the exact element
 /// boundaries in the file and extract the text + surrounding context.
 fn extract_and_build_result(
-    path: &str,
+    handle: &FileHandle,
     file_len: u64,
     approx_start: u64,
     approx_end: u64,
     xpath: &str,
     ancestors: Vec<AncestorInfo>,
+    truncated: bool,
 ) -> Result<SearchResult> {
-    let mut file = File::open(path)?;
+    let mut file = handle.source();
 
     // --- Find exact start: scan backward for '<' ---
     // We start scanning back a bit from approx_start to be safe
-    let scan_back = 2048u64;
+    let settings = settings::current();
+    let scan_back = settings.scan_back;
     let scan_start = if approx_start > scan_back { approx_start - scan_back } else { 0 };
     file.seek(SeekFrom::Start(scan_start))?;
     
@@ -625,11 +1291,8 @@ fn extract_and_build_result(
             let mut buf_fwd = vec![0u8; fwd_search_len];
             let n_fwd = file.read(&mut buf_fwd)?;
 
-            for i in 0..n_fwd {
-                if buf_fwd[i] == b'<' {
-                    exact_start = approx_start + i as u64;
-                    break;
-                }
+            if let Some(i) = memchr::memchr(b'<', &buf_fwd[..n_fwd]) {
+                exact_start = approx_start + i as u64;
             }
         }
     }
@@ -643,22 +1306,19 @@ fn extract_and_build_result(
     let n2 = file.read(&mut fwd_buf)?;
 
     let mut exact_end = approx_end;
-    for i in 0..n2 {
-        if fwd_buf[i] == b'>' {
-            exact_end = fwd_start + i as u64 + 1; // +1 to include the '>'
-            break;
-        }
+    if let Some(i) = memchr::memchr(b'>', &fwd_buf[..n2]) {
+        exact_end = fwd_start + i as u64 + 1; // +1 to include the '>'
     }
 
     // --- Read Element Text ---
     let len = exact_end - exact_start;
     file.seek(SeekFrom::Start(exact_start))?;
-    let mut element_buf = vec![0u8; len as usize];
+    let mut element_buf = vec![0u8; usize::try_from(len)?];
     file.read(&mut element_buf)?;
     let element_text = String::from_utf8_lossy(&element_buf).to_string();
 
     // --- Read Context Before ---
-    let context_len = 2000u64;
+    let context_len = settings.context_len;
     let context_start = if exact_start > context_len { exact_start - context_len } else { 0 };
     file.seek(SeekFrom::Start(context_start))?;
     let context_read_len = (exact_start - context_start) as usize;
@@ -674,7 +1334,8 @@ fn extract_and_build_result(
     let context_after = String::from_utf8_lossy(&context_after_buf).to_string();
 
     // --- Count Lines ---
-    let line_number = count_lines_up_to(path, exact_start).unwrap_or(0);
+    drop(file);
+    let line_number = count_lines_up_to(handle, exact_start).unwrap_or(0);
 
     Ok(SearchResult {
         found: true,
@@ -685,77 +1346,261 @@ fn extract_and_build_result(
         offset: exact_start,
         line_number,
         ancestors,
+        truncated,
     })
 }
 
-fn reconstruct_xpath(path: &str, target_offset: u64) -> Result<String> {
-    let file = File::open(path)?;
-    let mut reader = quick_xml::Reader::from_reader(BufReader::with_capacity(1024 * 1024, file));
+fn reconstruct_xpath(handle: &FileHandle, target_offset: u64) -> Result<String> {
+    let stack = open_elements_at(handle, target_offset)?;
+    Ok(format!("/{}", stack.iter().map(|(n, _)| n.as_str()).collect::<Vec<_>>().join("/")))
+}
+
+/// A scan recording its open elements leaves a checkpoint this often, so the
+/// next lookup near (or after) the same spot only parses the gap.
+const CHECKPOINT_INTERVAL: u64 = 8 * 1024 * 1024;
+
+/// The elements open at `target_offset`, parsed forward from the nearest
+/// checkpoint before it (byte 0 if there is none), leaving new checkpoints
+/// along the way.
+pub fn open_elements_at(handle: &FileHandle, target_offset: u64) -> Result<OpenElements> {
+    let (start, open) = handle.checkpoint_before(target_offset).unwrap_or_default();
+    let mut stack = NameStack::from_open_elements(&open);
+    let mut file = handle.reader()?;
+    file.seek(SeekFrom::Start(start))?;
+    let mut reader = XmlReader::scan(file);
     reader.check_end_names(false);
+    reader.set_strict(handle.strict());
 
     let mut buf = Vec::new();
-    let mut stack: Vec<String> = Vec::new();
+    let mut last_checkpoint = start;
 
     loop {
         // Must check position BEFORE reading event
-        // Note: buffer_position is relative to start of file (since we started from 0)
-        let pos = reader.buffer_position() as u64;
-        if pos >= target_offset {
+        let pos_before = reader.position();
+        if pos_before >= target_offset {
             break;
         }
+        if pos_before >= last_checkpoint + CHECKPOINT_INTERVAL {
+            handle.add_checkpoint(pos_before, &stack.to_open_elements());
+            last_checkpoint = pos_before;
+        }
 
         match reader.read_event_into(&mut buf) {
             Ok(Event::Start(ref e)) => {
-                let name = String::from_utf8_lossy(e.name().as_ref()).to_string();
-                stack.push(name);
+                stack.push(e.name().as_ref(), pos_before);
             }
             Ok(Event::End(_)) => {
                 stack.pop();
             }
             Ok(Event::Eof) => break,
-            Err(_) => break, // Ignore errors, just do best effor
+            Err(e) if handle.strict() => return Err(e),
+            Err(_) => break, // Ignore errors, just do best effort
             _ => {}
         }
         buf.clear();
     }
-    Ok(format!("/{}", stack.join("/")))
+    Ok(stack.to_open_elements())
 }
 
+/// Record checkpoints over the whole file now, rather than as navigation
+/// and searches happen to pass them, so any XPath or parent lookup after
+/// it only parses up to `CHECKPOINT_INTERVAL` bytes.
 #[tauri::command]
-pub async fn find_parent(path: String, child_offset: u64, ancestor_depth: u32) -> Result<SearchResult, String> {
-    find_parent_internal(&path, child_offset, ancestor_depth).map_err(|e| e.to_string())
+pub async fn build_index(
+    window: WebviewWindow,
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    op_id: Option<OpId>,
+) -> Result<(), XmlReaderError> {
+    let handle = files.get(file_id)?;
+    ops::run(window, op_id, "index", move |op| {
+        build_index_internal(&handle, &|pct| op.progress(pct)).map_err(|e| XmlReaderError::in_file(&handle, e))
+    })
+    .await
 }
 
-fn find_parent_internal(path: &str, child_offset: u64, ancestor_depth: u32) -> Result<SearchResult> {
-    let file = File::open(path)?;
-    let file_len = file.metadata()?.len();
-    let mut reader = quick_xml::Reader::from_reader(BufReader::with_capacity(1024 * 1024, file));
+/// Files are split into at most this many ranges per core...
+const INDEX_RANGES_PER_THREAD: u64 = 4;
+/// ...of at least this many bytes; smaller files aren't worth splitting.
+const INDEX_MIN_RANGE: u64 = 16 * 1024 * 1024;
+/// How far past a range boundary to look for a tag to start parsing at.
+const INDEX_RESYNC_SCAN: usize = 64 * 1024;
+
+/// What parsing one range of the file found, relative to the elements open
+/// where it starts (which only the ranges before it can tell).
+struct RangeScan {
+    start: u64,
+    /// The first event boundary at or past the range end, where the next
+    /// range must have started for the two to fit together.
+    end: u64,
+    /// Elements closed in the range that were opened before it.
+    closed: usize,
+    /// Elements opened in the range and still open at `end`.
+    open: OpenElements,
+    /// Offsets to checkpoint at, with `closed` and `open` as they were there.
+    checkpoints: Vec<(u64, usize, OpenElements)>,
+}
+
+/// Build the checkpoints of a whole file, parsing ranges of it on all cores.
+/// Each range starts at the first tag after its boundary; the ranges are
+/// then stitched in order, the open elements at the end of one being the
+/// base of the next. A boundary that falls in a comment, CDATA section or
+/// the like shows up as a range that didn't end where the next one began;
+/// that one is parsed again from where the previous one did end. Strict mode
+/// always parses in one pass, as its tag matching can't start mid-file.
+fn build_index_internal(handle: &FileHandle, progress: &(dyn Fn(u64) + Sync)) -> Result<()> {
+    use rayon::prelude::*;
+
+    let len = handle.len();
+    let ranges = (len / INDEX_MIN_RANGE).min(rayon::current_num_threads() as u64 * INDEX_RANGES_PER_THREAD);
+    if handle.strict() || ranges < 2 {
+        open_elements_at(handle, len)?;
+        progress(100);
+        return Ok(());
+    }
+
+    let mut starts = vec![0];
+    for i in 1..ranges {
+        if let Some(start) = resync(handle, len * i / ranges)? {
+            if start > *starts.last().unwrap() {
+                starts.push(start);
+            }
+        }
+    }
+    let stops: Vec<u64> = starts.iter().skip(1).copied().chain([len]).collect();
+
+    let done = AtomicU64::new(0);
+    let last_pct = AtomicU64::new(0);
+    let cancelled = ops::current();
+    let scans: Vec<Result<RangeScan>> = starts
+        .par_iter()
+        .zip(stops.par_iter())
+        .map(|(&start, &stop)| {
+            ops::enter(cancelled.clone(), || {
+                let scan = scan_range(handle, start, stop);
+                let pct = (done.fetch_add(stop - start, Ordering::Relaxed) + stop - start) * 100 / len.max(1);
+                // Ranges finish out of order; only ever report forward
+                if last_pct.fetch_max(pct, Ordering::Relaxed) < pct {
+                    progress(pct.min(99));
+                }
+                scan
+            })
+        })
+        .collect();
+    ops::check()?;
+
+    let mut open: OpenElements = Vec::new();
+    let mut expected_start = 0;
+    for (scan, stop) in scans.into_iter().zip(stops) {
+        let scan = match scan {
+            Ok(scan) if scan.start == expected_start => scan,
+            // Started off a real tag boundary (or hit an error): redo the
+            // range from where the previous one really ended
+            _ => match scan_range(handle, expected_start, stop) {
+                Ok(scan) => scan,
+                Err(_) => break,
+            },
+        };
+        if scan.start > 0 {
+            handle.add_checkpoint(scan.start, &open);
+        }
+        for (at, closed, local) in &scan.checkpoints {
+            let mut stack = open[..open.len().saturating_sub(*closed)].to_vec();
+            stack.extend(local.iter().cloned());
+            handle.add_checkpoint(*at, &stack);
+        }
+        open.truncate(open.len().saturating_sub(scan.closed));
+        open.extend(scan.open);
+        expected_start = scan.end;
+    }
+    ops::check()?;
+    if expected_start < len {
+        // A parse error; parse up to it from the last checkpoint stitched
+        open_elements_at(handle, len)?;
+    }
+    progress(100);
+    Ok(())
+}
+
+/// The first `<` at or after `offset` that looks like a start or end tag.
+fn resync(handle: &FileHandle, offset: u64) -> Result<Option<u64>> {
+    let mut file = handle.reader()?;
+    file.seek(SeekFrom::Start(offset))?;
+    let mut buf = vec![0; INDEX_RESYNC_SCAN];
+    let n = file.read(&mut buf)?;
+    let found = memchr::memchr_iter(b'<', &buf[..n]).find(|&i| {
+        buf.get(i + 1)
+            .is_some_and(|&b| b == b'/' || b == b'_' || b == b':' || b.is_ascii_alphabetic() || b >= 0x80)
+    });
+    Ok(found.map(|i| offset + i as u64))
+}
+
+/// Parse `start..stop` with no idea which elements are open at `start`.
+fn scan_range(handle: &FileHandle, start: u64, stop: u64) -> Result<RangeScan> {
+    let mut file = handle.reader()?;
+    file.seek(SeekFrom::Start(start))?;
+    let mut reader = XmlReader::scan(file);
     reader.check_end_names(false);
 
     let mut buf = Vec::new();
-    // Stack of (tag_name, byte_position_before_start_event)
-    let mut stack: Vec<(String, u64)> = Vec::new();
-
+    let mut open = NameStack::default();
+    let mut scan = RangeScan {
+        start,
+        end: start,
+        closed: 0,
+        open: Vec::new(),
+        checkpoints: Vec::new(),
+    };
+    let mut last_checkpoint = start;
     loop {
-        let pos_before = reader.buffer_position() as u64;
-        if pos_before >= child_offset {
+        let pos_before = reader.position();
+        if pos_before >= stop {
             break;
         }
-
-        match reader.read_event_into(&mut buf) {
-            Ok(Event::Start(ref e)) => {
-                let name = String::from_utf8_lossy(e.name().as_ref()).to_string();
-                stack.push((name, pos_before));
-            }
-            Ok(Event::End(_)) => {
-                stack.pop();
+        if pos_before >= last_checkpoint + CHECKPOINT_INTERVAL {
+            scan.checkpoints.push((pos_before, scan.closed, open.to_open_elements()));
+            last_checkpoint = pos_before;
+        }
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(ref e) => open.push(e.name().as_ref(), pos_before),
+            Event::End(_) => {
+                // Nothing opened in the range left to close: it closes one from before
+                scan.closed += usize::from(!open.pop());
             }
-            Ok(Event::Eof) => break,
-            Err(_) => break,
+            Event::Eof => break,
             _ => {}
         }
         buf.clear();
     }
+    scan.end = reader.position();
+    scan.open = open.to_open_elements();
+    Ok(scan)
+}
+
+#[tauri::command]
+pub async fn find_parent(
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    child_offset: u64,
+    ancestor_depth: u32,
+) -> Result<SearchResult, XmlReaderError> {
+    let handle = files.get(file_id)?;
+    blocking(move || {
+        handle
+            .consistent(|| find_parent_internal(&handle, child_offset, ancestor_depth))
+            .map_err(|e| XmlReaderError::in_file(&handle, e))
+    })
+    .await
+}
+
+fn find_parent_internal(handle: &FileHandle, child_offset: u64, ancestor_depth: u32) -> Result<SearchResult> {
+    let file_len = handle.len();
+    // Strict mode has to parse the way there to report errors on it
+    let backward = if handle.strict() { None } else { open_elements_backward(handle, child_offset)? };
+    let stack = match backward {
+        Some(stack) => stack,
+        None => open_elements_at(handle, child_offset)?,
+    };
 
     // The stack now contains ancestors of the element at child_offset.
     let depth = ancestor_depth as usize;
@@ -769,10 +1614,11 @@ fn find_parent_internal(path: &str, child_offset: u64, ancestor_depth: u32) -> R
     let xpath = format!("/{}", stack[..=depth].iter().map(|(n, _)| n.as_str()).collect::<Vec<_>>().join("/"));
 
     // Find the end of the ancestor element by seeking to its start and parsing
-    let mut file3 = File::open(path)?;
+    let mut file3 = handle.reader()?;
     file3.seek(SeekFrom::Start(ancestor_start))?;
-    let mut reader3 = quick_xml::Reader::from_reader(BufReader::with_capacity(1024 * 1024, file3));
+    let mut reader3 = XmlReader::scan(file3);
     reader3.check_end_names(false);
+    reader3.set_strict(handle.strict());
 
     let mut buf3 = Vec::new();
     // Read the first Start event (the ancestor itself)
@@ -785,31 +1631,191 @@ fn find_parent_internal(path: &str, child_offset: u64, ancestor_depth: u32) -> R
                 }
             }
             Ok(Event::Eof) => return Err(anyhow::anyhow!("Unexpected EOF while seeking ancestor start")),
-            Err(e) => return Err(anyhow::anyhow!("Parse error: {:?}", e)),
+            Err(e) => return Err(e),
             _ => {}
         }
         buf3.clear();
     }
 
     // Now find the matching end tag
-    let approx_end = find_element_end_pos(&mut reader3, &mut buf3, &ancestor_name, file_len, ancestor_start)?;
+    let (approx_end, truncated) = find_element_end_pos(&mut reader3, &mut buf3, &ancestor_name, file_len, element_scan_limit())?;
+
+    extract_and_build_result(handle, file_len, ancestor_start, approx_end, &xpath, vec![], truncated)
+}
+
+/// The elements open at `target_offset`, found by scanning back for tags
+/// with the depth tracking of `get_last_child_internal`: back to the nearest
+/// checkpoint if there is one, else to the root. Much cheaper than parsing
+/// forward, but blind to markup that hides tags, so `None` (parse instead)
+/// whenever the stretch holds comments, CDATA, PIs, quoted `>`, or tags
+/// that don't pair up.
+fn open_elements_backward(handle: &FileHandle, target_offset: u64) -> Result<Option<OpenElements>> {
+    let (scan_start, mut stack) = match handle.checkpoint_before(target_offset) {
+        Some(checkpoint) => checkpoint,
+        None => (preview::root_offset(handle)?, Vec::new()),
+    };
+    let mut file = handle.reader()?;
+    let len = file.len();
+    let target_offset = target_offset.min(len);
+
+    // Ancestors opened after `scan_start`, nearest first
+    let mut found: OpenElements = Vec::new();
+    // Names of elements closed before `target_offset`, waiting for their start
+    let mut closed: Vec<String> = Vec::new();
+
+    let chunk_size: usize = 64 * 1024;
+    let mut current_pos = target_offset;
+    let mut buf = vec![0u8; chunk_size];
+    let mut tag_buf = vec![0u8; 1024];
+
+    while current_pos > scan_start {
+        let read_size = std::cmp::min(current_pos - scan_start, chunk_size as u64) as usize;
+        current_pos -= read_size as u64;
+
+        file.seek(SeekFrom::Start(current_pos))?;
+        file.read_exact(&mut buf[..read_size])?;
+
+        for i in memchr::memrchr_iter(b'<', &buf[..read_size]) {
+            let abs_start = current_pos + i as u64;
+            let remaining = &buf[i..read_size];
+            let tag = match memchr::memchr(b'>', remaining) {
+                Some(gt) => &remaining[..gt + 1],
+                None => {
+                    // Tag spans the chunk boundary
+                    let to_read = std::cmp::min(1024u64, len - abs_start) as usize;
+                    file.seek(SeekFrom::Start(abs_start))?;
+                    let n = file.read(&mut tag_buf[..to_read])?;
+                    match memchr::memchr(b'>', &tag_buf[..n]) {
+                        Some(gt) => &tag_buf[..gt + 1],
+                        None => return Ok(None),
+                    }
+                }
+            };
+            let quotes_open = |q: u8| tag.iter().filter(|&&b| b == q).count() % 2 == 1;
+            if tag.len() < 3 || tag[1] == b'!' || tag[1] == b'?' || quotes_open(b'"') || quotes_open(b'\'') {
+                return Ok(None);
+            }
+
+            match classify_tag(tag) {
+                Some((name, TagKind::Close, _)) => closed.push(name),
+                Some((name, TagKind::Open, _)) => match closed.pop() {
+                    Some(expected) if expected != name => return Ok(None),
+                    Some(_) => {}
+                    None => found.push((name, abs_start)),
+                },
+                Some((_, TagKind::Empty, _)) => {}
+                None => return Ok(None),
+            }
+        }
+    }
+
+    // What is still closed must be the innermost elements open at the checkpoint
+    while let Some(name) = closed.pop() {
+        match stack.pop() {
+            Some((open, _)) if open == name => {}
+            _ => return Ok(None),
+        }
+    }
+    stack.extend(found.into_iter().rev());
+    Ok(Some(stack))
+}
 
-    extract_and_build_result(path, file_len, ancestor_start, approx_end, &xpath, vec![])
+#[derive(serde::Serialize)]
+pub struct ElementRange {
+    text: String,
+    // Byte range of `text`, snapped to char boundaries
+    offset: u64,
+    end: u64,
+    /// End of the whole element; the next window starts at `end` until
+    /// `end` reaches it.
+    element_end: u64,
 }
 
+/// One window of at most `window` bytes (but always at least one character)
+/// of an element too big to show whole, starting at `start`. The first call passes the element's own
+/// offset and no `end`, and gets the element's end found without any scan
+/// limit; later calls pass it back as `end`.
 #[tauri::command]
-pub async fn read_element_at_offset(path: String, offset: u64) -> Result<SearchResult, String> {
-    read_element_at_offset_internal(&path, offset).map_err(|e| e.to_string())
+pub async fn read_element_range(
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    start: u64,
+    end: Option<u64>,
+    window: u32,
+) -> Result<ElementRange, XmlReaderError> {
+    let handle = files.get(file_id)?;
+    blocking(move || {
+        handle
+            .consistent(|| read_element_range_internal(&handle, start, end, window))
+            .map_err(|e| XmlReaderError::in_file(&handle, e))
+    })
+    .await
 }
 
-fn read_element_at_offset_internal(path: &str, offset: u64) -> Result<SearchResult> {
-    let file = File::open(path)?;
-    let file_len = file.metadata()?.len();
-    let mut reader = quick_xml::Reader::from_reader(BufReader::new(file));
-    
+fn read_element_range_internal(handle: &FileHandle, start: u64, end: Option<u64>, window: u32) -> Result<ElementRange> {
+    if window == 0 {
+        return Err(anyhow::anyhow!("The window must be at least 1 byte"));
+    }
+    let element_end = match end {
+        Some(end) => end.min(handle.len()),
+        None => {
+            let mut file = handle.reader()?;
+            let file_len = file.len();
+            file.seek(SeekFrom::Start(start))?;
+            let mut reader = XmlReader::scan(file);
+            reader.check_end_names(false);
+            reader.set_strict(handle.strict());
+            let mut buf = Vec::new();
+            match reader.read_event_into(&mut buf)? {
+                Event::Start(ref e) => {
+                    let name = String::from_utf8_lossy(e.name().as_ref()).to_string();
+                    find_element_end_pos(&mut reader, &mut buf, &name, file_len, None)?.0
+                }
+                Event::Empty(_) => reader.position(),
+                _ => return Err(anyhow::anyhow!("No start tag found at offset {}", start)),
+            }
+        }
+    };
+    if start >= element_end {
+        return Err(anyhow::anyhow!("Offset {} is past the end of the element ({})", start, element_end));
+    }
+
+    // A window smaller than the character at `start` would end where it
+    // began and the caller would never get past it; 4 bytes hold any one
+    let size = (element_end - start).min(window.max(4) as u64) as u32;
+    let chunk = read_chunk_internal(&mut handle.source(), start, size, "char")?;
+    Ok(ElementRange {
+        text: String::from_utf8_lossy(&chunk.bytes).to_string(),
+        offset: chunk.offset,
+        end: chunk.end,
+        element_end,
+    })
+}
+
+#[tauri::command]
+pub async fn read_element_at_offset(
+    files: State<'_, FileRegistry>,
+    file_id: FileId,
+    offset: u64,
+) -> Result<SearchResult, XmlReaderError> {
+    let handle = files.get(file_id)?;
+    blocking(move || {
+        handle
+            .consistent(|| read_element_at_offset_internal(&handle, offset))
+            .map_err(|e| XmlReaderError::in_file(&handle, e))
+    })
+    .await
+}
+
+fn read_element_at_offset_internal(handle: &FileHandle, offset: u64) -> Result<SearchResult> {
+    let mut file = handle.reader()?;
+    let file_len = file.len();
+
     // Seek to offset
-    reader.get_mut().seek(SeekFrom::Start(offset))?;
+    file.seek(SeekFrom::Start(offset))?;
+    let mut reader = XmlReader::new(file, 8 * 1024);
     reader.check_end_names(false);
+    reader.set_strict(handle.strict());
     
     let mut buf = Vec::new();
     
@@ -820,24 +1826,324 @@ fn read_element_at_offset_internal(path: &str, offset: u64) -> Result<SearchResu
             let approx_start = offset;
             
             // Find end of element
-            // Note: find_element_end_pos expects start_offset to be the base of reader positions
-            // Here reader base is at 'offset'
-            let approx_end = find_element_end_pos(&mut reader, &mut buf, &name, file_len, offset)?;
+            let (approx_end, truncated) = find_element_end_pos(&mut reader, &mut buf, &name, file_len, element_scan_limit())?;
             
             // We don't reconstruct full xpath here (frontend handles it)
             // But we can put the tag name as context or empty
             let xpath = format!(".../{}", name);
             
-            extract_and_build_result(path, file_len, approx_start, approx_end, &xpath, vec![])
+            extract_and_build_result(handle, file_len, approx_start, approx_end, &xpath, vec![], truncated)
         },
         Ok(Event::Empty(ref e)) => {
             let name = String::from_utf8_lossy(e.name().as_ref()).to_string();
             let approx_start = offset;
-            let approx_end = offset + reader.buffer_position() as u64;
+            let approx_end = reader.position();
              let xpath = format!(".../{}", name);
             
-            extract_and_build_result(path, file_len, approx_start, approx_end, &xpath, vec![])
+            extract_and_build_result(handle, file_len, approx_start, approx_end, &xpath, vec![], false)
         },
         _ => Err(anyhow::anyhow!("No start tag found at offset {}", offset).into())
     }
 }
+
+/// One command of an `execute_batch`, with the arguments of the command of
+/// the same name.
+#[derive(serde::Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum CommandSpec {
+    ResolveXpath {
+        file_id: FileId,
+        offset: u64,
+        tag_name: String,
+    },
+    ReadElementAtOffset {
+        file_id: FileId,
+        offset: u64,
+    },
+    ReadElementRange {
+        file_id: FileId,
+        start: u64,
+        end: Option<u64>,
+        window: u32,
+    },
+    FindParent {
+        file_id: FileId,
+        child_offset: u64,
+        ancestor_depth: u32,
+    },
+    GetFirstChild {
+        file_id: FileId,
+    },
+    GetLastChild {
+        file_id: FileId,
+    },
+    ReadHex {
+        file_id: FileId,
+        offset: u64,
+        size: u32,
+    },
+    RunQuery {
+        file_id: FileId,
+        expr: String,
+        skip: usize,
+        limit: usize,
+    },
+}
+
+impl CommandSpec {
+    fn file_id(&self) -> FileId {
+        match self {
+            CommandSpec::ResolveXpath { file_id, .. }
+            | CommandSpec::ReadElementAtOffset { file_id, .. }
+            | CommandSpec::ReadElementRange { file_id, .. }
+            | CommandSpec::FindParent { file_id, .. }
+            | CommandSpec::GetFirstChild { file_id }
+            | CommandSpec::GetLastChild { file_id }
+            | CommandSpec::ReadHex { file_id, .. }
+            | CommandSpec::RunQuery { file_id, .. } => *file_id,
+        }
+    }
+}
+
+/// What one command of a batch returned, as `{ "ok": result }` or
+/// `{ "error": { kind, ... } }`.
+#[derive(serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchResult {
+    Ok(serde_json::Value),
+    Error(XmlReaderError),
+}
+
+/// Run several navigation commands in one round trip, in order, for UI
+/// actions that need a few answers at once (an element, its XPath, its
+/// parent). A failing command doesn't stop the others; results come back in
+/// the order of `commands`.
+#[tauri::command]
+pub async fn execute_batch(
+    files: State<'_, FileRegistry>,
+    commands: Vec<CommandSpec>,
+) -> Result<Vec<BatchResult>, XmlReaderError> {
+    let commands: Vec<_> = commands.into_iter().map(|spec| (files.get(spec.file_id()), spec)).collect();
+    blocking(move || {
+        Ok(commands
+            .into_iter()
+            .map(|(handle, spec)| {
+                let result = handle.map_err(XmlReaderError::from).and_then(|handle| {
+                    handle
+                        .consistent(|| run_batch_command(&handle, spec))
+                        .map_err(|e| XmlReaderError::in_file(&handle, e))
+                });
+                match result {
+                    Ok(value) => BatchResult::Ok(value),
+                    Err(e) => BatchResult::Error(e),
+                }
+            })
+            .collect())
+    })
+    .await
+}
+
+fn run_batch_command(handle: &FileHandle, spec: CommandSpec) -> Result<serde_json::Value> {
+    let value = match spec {
+        CommandSpec::ResolveXpath { offset, tag_name, .. } => {
+            serde_json::to_value(format!("{}/{}", reconstruct_xpath(handle, offset)?, tag_name))?
+        }
+        CommandSpec::ReadElementAtOffset { offset, .. } => serde_json::to_value(read_element_at_offset_internal(handle, offset)?)?,
+        CommandSpec::ReadElementRange { start, end, window, .. } => {
+            serde_json::to_value(read_element_range_internal(handle, start, end, window)?)?
+        }
+        CommandSpec::FindParent {
+            child_offset,
+            ancestor_depth,
+            ..
+        } => serde_json::to_value(find_parent_internal(handle, child_offset, ancestor_depth)?)?,
+        CommandSpec::GetFirstChild { .. } => serde_json::to_value(get_first_child_internal(handle)?)?,
+        CommandSpec::GetLastChild { .. } => serde_json::to_value(get_last_child_internal(handle)?)?,
+        CommandSpec::ReadHex { offset, size, .. } => serde_json::to_value(read_hex_internal(handle, offset, size)?)?,
+        CommandSpec::RunQuery { expr, skip, limit, .. } => {
+            serde_json::to_value(query::run_query_internal(handle, &expr, skip, limit.min(query::QUERY_MAX_LIMIT))?)?
+        }
+    };
+    Ok(value)
+}
+
+/// One timed step of `profile_operations`.
+#[derive(serde::Serialize)]
+pub struct ProfileStep {
+    name: &'static str,
+    millis: f64,
+    /// What the step found or how fast it went, e.g. `"412.3 MB/s"`.
+    detail: String,
+}
+
+#[derive(serde::Serialize)]
+pub struct ProfileReport {
+    version: String,
+    path: String,
+    len: u64,
+    mmap: bool,
+    steps: Vec<ProfileStep>,
+}
+
+/// Chunk reads `profile_operations` times, spread evenly over the file.
+const PROFILE_CHUNK_READS: u64 = 100;
+const PROFILE_CHUNK_SIZE: u32 = 64 * 1024;
+
+/// Time the main operations on `path` from a cold start: opening it, the
+/// forward scan that indexes ancestors (checkpoints), a search that reads
+/// the whole file, XPath reconstruction and random chunk reads. The file is
+/// opened separately from any handle the app already has, so earlier work
+/// doesn't skew the numbers. Meant to be attached to performance reports.
+#[tauri::command]
+pub async fn profile_operations(window: WebviewWindow, path: String, op_id: Option<OpId>) -> Result<ProfileReport, XmlReaderError> {
+    let version = window.package_info().version.to_string();
+    ops::run(window, op_id, "profile", move |op| {
+        let registry = FileRegistry::default();
+        let start = Instant::now();
+        let handle = registry.open(&path)?;
+        profile_operations_internal(&handle, start, version, |pct| op.progress(pct))
+            .map_err(|e| XmlReaderError::in_file(&handle, e))
+    })
+    .await
+}
+
+fn profile_operations_internal(
+    handle: &FileHandle,
+    opened_at: Instant,
+    version: String,
+    mut progress: impl FnMut(u64),
+) -> Result<ProfileReport> {
+    let len = handle.len();
+    let mb_per_sec = |elapsed: Duration| {
+        format!("{:.1} MB/s", len as f64 / (1024.0 * 1024.0) / elapsed.as_secs_f64().max(1e-9))
+    };
+    let mut steps = vec![ProfileStep {
+        name: "open",
+        millis: millis(opened_at.elapsed()),
+        detail: format!("{} bytes", len),
+    }];
+
+    let start = Instant::now();
+    let root = preview::root_offset(handle)?;
+    steps.push(ProfileStep {
+        name: "root element",
+        millis: millis(start.elapsed()),
+        detail: format!("at offset {}", root),
+    });
+    progress(5);
+
+    let start = Instant::now();
+    build_index_internal(handle, &|_| {})?;
+    let elapsed = start.elapsed();
+    steps.push(ProfileStep {
+        name: "index",
+        millis: millis(elapsed),
+        detail: mb_per_sec(elapsed),
+    });
+    progress(35);
+
+    // A NUL byte is in no tag name or attribute value, so this reads the whole file
+    let start = Instant::now();
+    search_node_internal(handle, "\0", "any", 0, |pct| progress(35 + pct * 55 / 100))?;
+    let elapsed = start.elapsed();
+    steps.push(ProfileStep {
+        name: "first search",
+        millis: millis(elapsed),
+        detail: mb_per_sec(elapsed),
+    });
+
+    // Cold, like on a file that was just opened: with the checkpoints of the
+    // index it would only parse from the nearest one
+    handle.clear_checkpoints();
+    let start = Instant::now();
+    let xpath = reconstruct_xpath(handle, len / 2)?;
+    steps.push(ProfileStep {
+        name: "xpath reconstruction",
+        millis: millis(start.elapsed()),
+        detail: format!("{} at offset {}", xpath, len / 2),
+    });
+    progress(95);
+
+    let start = Instant::now();
+    let mut slowest = Duration::ZERO;
+    for i in 0..PROFILE_CHUNK_READS {
+        ops::check()?;
+        let read_start = Instant::now();
+        read_chunk_internal(&mut handle.source(), len / PROFILE_CHUNK_READS * i, PROFILE_CHUNK_SIZE, "tag")?;
+        slowest = slowest.max(read_start.elapsed());
+    }
+    steps.push(ProfileStep {
+        name: "chunk reads",
+        millis: millis(start.elapsed()),
+        detail: format!(
+            "{} reads of {} bytes, slowest {:.2} ms",
+            PROFILE_CHUNK_READS,
+            PROFILE_CHUNK_SIZE,
+            millis(slowest)
+        ),
+    });
+    progress(100);
+
+    Ok(ProfileReport {
+        version,
+        path: handle.path.clone(),
+        len,
+        mmap: io::mmap_enabled(),
+        steps,
+    })
+}
+
+fn millis(elapsed: Duration) -> f64 {
+    elapsed.as_secs_f64() * 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::io::Write;
+
+    #[test]
+    fn read_chunk_past_4gb() {
+        let path = std::env::temp_dir().join(format!("xml-reader-chunk-{}.xml", std::process::id()));
+        let offset = (1u64 << 32) + 1000;
+        {
+            let mut file = File::create(&path).unwrap();
+            file.set_len(offset).unwrap();
+            file.seek(SeekFrom::Start(offset)).unwrap();
+            file.write_all("<a>\n<b>é</b>\n</a>\n".as_bytes()).unwrap();
+        }
+        let mut source = Source::open(path.to_str().unwrap()).unwrap();
+        let chunks = [
+            // Ends inside `é`
+            read_chunk_internal(&mut source, offset + 5, 3, "char").unwrap(),
+            read_chunk_internal(&mut source, offset + 6, 100, "line").unwrap(),
+            read_chunk_internal(&mut source, offset + 6, 6, "tag").unwrap(),
+        ];
+        let _ = std::fs::remove_file(&path);
+
+        let ranges: Vec<_> = chunks.iter().map(|c| (c.offset - offset, c.end - offset)).collect();
+        assert_eq!(ranges, [(5, 7), (4, 19), (4, 9)]);
+        assert_eq!(chunks[0].bytes, b"b>");
+        assert_eq!(chunks[1].bytes, "<b>é</b>\n</a>\n".as_bytes());
+        assert_eq!(chunks[2].bytes, "<b>é".as_bytes());
+    }
+
+    #[test]
+    fn resumed_search_knows_ancestors() {
+        let path = std::env::temp_dir().join(format!("xml-reader-resume-{}.xml", std::process::id()));
+        std::fs::write(&path, "<Root><List><Item id=\"1\"><Name/></Item><Item id=\"2\"/></List></Root>").unwrap();
+        let handle = crate::files::FileRegistry::default().open(path.to_str().unwrap()).unwrap();
+        let first = search_node_internal(&handle, "item", "tag", 0, |_| {}).unwrap();
+        // The next search starts just past the previous hit, like the frontend's
+        let next = search_node_internal(&handle, "item", "tag", first.offset + 1, |_| {}).unwrap();
+        drop(handle);
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(first.offset, 12);
+        assert_eq!(next.offset, 39);
+        assert_eq!(next.xpath, "/Root/List/Item");
+        let ancestors: Vec<_> = next.ancestors.iter().map(|a| (a.name.as_str(), a.offset)).collect();
+        assert_eq!(ancestors, [("Root", 0), ("List", 6)]);
+    }
+}
@@ -1,5 +1,5 @@
 import { invoke } from "@tauri-apps/api/core";
-import { listen } from "@tauri-apps/api/event";
+import { getCurrentWebviewWindow } from "@tauri-apps/api/webviewWindow";
 
 export interface RecentFile {
   path: string;
@@ -28,6 +28,37 @@ export interface AncestorInfo {
   line_number: number;
 }
 
+interface OpenedFile {
+  file_id: number;
+  path: string;
+  len: number;
+}
+
+interface OpenRequest {
+  path: string;
+  offset: number | null;
+  line: number | null;
+}
+
+interface Chunk {
+  text: string;
+  offset: number;
+  end: number;
+}
+
+const chunkDecoder = new TextDecoder();
+
+/** `read_chunk` answers with raw bytes: offset and end as little-endian u64s, then the text. */
+async function readChunk(args: { fileId: number | null; offset: number; size: number }): Promise<Chunk> {
+  const body = await invoke<ArrayBuffer>("read_chunk", args);
+  const view = new DataView(body);
+  return {
+    offset: Number(view.getBigUint64(0, true)),
+    end: Number(view.getBigUint64(8, true)),
+    text: chunkDecoder.decode(new Uint8Array(body, 16)),
+  };
+}
+
 function loadSearchMemory(): Record<string, SearchMemoryEntry> {
   try {
     const raw = localStorage.getItem(SEARCH_MEMORY_KEY);
@@ -69,6 +100,7 @@ function saveRecentFilesToStorage(files: RecentFile[]) {
 
 export class AppState {
   currentFile = $state<string | null>(null);
+  fileId = $state<number | null>(null);
   fileSize = $state<number>(0);
   viewOffset = $state<number>(0);
   isSearching = $state<boolean>(false);
@@ -117,9 +149,68 @@ export class AppState {
   }
 
   async setupListeners() {
-    await listen<number>("search-progress", (event) => {
+    // Each window gets only its own events
+    const win = getCurrentWebviewWindow();
+    await win.listen<number>("search-progress", (event) => {
       this.searchProgress = event.payload;
     });
+
+    // Piped input (`xml-reader -`) is opened as soon as it is fully spooled
+    await win.listen<string>("stdin-ready", (event) => {
+      this.openFile(event.payload);
+    });
+    const stdinFile = await invoke<string | null>("get_stdin_file");
+    if (stdinFile) await this.openFile(stdinFile);
+
+    // A window opened with `Open in New Window`
+    const windowFile = await invoke<string | null>("take_window_file");
+    if (windowFile) await this.openFile(windowFile);
+
+    // The last run crashed: offer its files, bookmarks and staged edits back
+    if (win.label === "main") {
+      const previous = await invoke<unknown[] | null>("get_previous_session");
+      if (previous?.length) {
+        if (confirm(`Restore the previous session (${previous.length} files)?`)) {
+          const session = await invoke<{ restored: { file: OpenedFile }[] }>("restore_previous_session");
+          const last = session.restored.at(-1);
+          if (last) await this.showFile(last.file);
+        } else {
+          await invoke("discard_previous_session");
+        }
+      }
+    }
+
+    // `xml-reader big.xml:123456` and "Open with…", at startup or later
+    await win.listen("open-request", () => this.takeOpenRequests());
+    await this.takeOpenRequests();
+  }
+
+  private async takeOpenRequests() {
+    const requests = await invoke<OpenRequest[]>("take_open_requests");
+    const request = requests.at(-1);
+    if (!request) return;
+    await this.openFile(request.path);
+    let offset = request.offset;
+    if (offset == null && request.line != null) {
+      const lines = await invoke<{ offset: number }>("read_lines", {
+        fileId: this.fileId,
+        startLine: request.line,
+        lineCount: 1,
+      });
+      offset = lines.offset;
+    }
+    if (offset != null) {
+      this.viewOffset = offset;
+      await this.loadThreeSections(offset);
+    }
+  }
+
+  async newWindow(path?: string) {
+    try {
+      await invoke<string>("new_window", { path: path ?? null });
+    } catch (e) {
+      console.error("Failed to open window:", e);
+    }
   }
 
   private addToRecentFiles(path: string) {
@@ -139,8 +230,12 @@ export class AppState {
     saveRecentFilesToStorage(this.recentFiles);
   }
 
-  closeFile() {
+  async closeFile() {
+    if (this.fileId !== null) {
+      await this.releaseFile(this.fileId);
+    }
     this.currentFile = null;
+    this.fileId = null;
     this.fileSize = 0;
     this.viewOffset = 0;
     this.contentWindow = "";
@@ -219,7 +314,7 @@ export class AppState {
       // Use cached offset
       try {
         const result: any = await invoke("read_element_at_offset", {
-            path: this.currentFile,
+            fileId: this.fileId,
             offset: ancestor.offset,
         });
         
@@ -282,7 +377,7 @@ export class AppState {
     
     try {
       const result: any = await invoke("find_parent", {
-        path: this.currentFile,
+        fileId: this.fileId,
         childOffset: this.lastMatchOffset,
         ancestorDepth: depth,
       });
@@ -300,7 +395,30 @@ export class AppState {
 
   async openFile(path: string) {
     try {
-      this.fileSize = await invoke("open_file", { path });
+      const opened = await invoke<OpenedFile>("open_file", { path });
+      await this.showFile(opened);
+    } catch (e) {
+      console.error("Failed to open file:", e);
+    }
+  }
+
+  // Let the backend drop a file's handle, watcher and temp files
+  private async releaseFile(fileId: number) {
+    try {
+      await invoke("close_file", { fileId });
+    } catch (e) {
+      console.error("Failed to close file:", e);
+    }
+  }
+
+  private async showFile(opened: OpenedFile) {
+    const path = opened.path;
+    try {
+      if (this.fileId !== null && this.fileId !== opened.file_id) {
+        await this.releaseFile(this.fileId);
+      }
+      this.fileId = opened.file_id;
+      this.fileSize = opened.len;
       this.currentFile = path;
       this.viewOffset = 0;
       this.lastMatchOffset = null;
@@ -312,7 +430,7 @@ export class AppState {
       this.loadSearchPrefsForFile(path);
       await this.loadChunk();
     } catch (e) {
-      console.error("Failed to open file:", e);
+      console.error("Failed to show file:", e);
     }
   }
 
@@ -320,12 +438,12 @@ export class AppState {
     if (!this.currentFile) return;
     try {
       const chunkSize = 5000;
-      const text = await invoke<string>("read_chunk", {
-        path: this.currentFile,
+      const chunk = await readChunk({
+        fileId: this.fileId,
         offset: this.viewOffset,
         size: chunkSize,
       });
-      this.contentWindow = text;
+      this.contentWindow = chunk.text;
     } catch (e) {
       console.error("Failed to read chunk:", e);
     }
@@ -351,7 +469,7 @@ export class AppState {
       }
 
       const result: any = await invoke("search_node", {
-        path: this.currentFile,
+        fileId: this.fileId,
         query: query,
         searchType: this.searchType,
         startOffset: start,
@@ -365,7 +483,7 @@ export class AppState {
           const tagName = result.xpath.replace(/^\//, "");
 
           invoke<string>("resolve_xpath", {
-            path: this.currentFile,
+            fileId: this.fileId,
             offset: result.offset,
             tagName
           })
@@ -418,27 +536,29 @@ export class AppState {
       const beforeStart = Math.max(0, startOffset - beforeSize);
       const actualBeforeSize = startOffset - beforeStart;
       if (actualBeforeSize > 0) {
-        this.contentBefore = await invoke<string>("read_chunk", {
-          path: this.currentFile,
+        const before = await readChunk({
+          fileId: this.fileId,
           offset: beforeStart,
           size: actualBeforeSize,
         });
+        this.contentBefore = before.text;
       } else {
         this.contentBefore = "";
       }
 
-      this.contentActive = await invoke<string>("read_chunk", {
-        path: this.currentFile,
+      const active = await readChunk({
+        fileId: this.fileId,
         offset: startOffset,
         size: activeSize,
       });
+      this.contentActive = active.text;
 
-      const afterStart = startOffset + activeSize;
-      this.contentAfter = await invoke<string>("read_chunk", {
-        path: this.currentFile,
-        offset: afterStart,
+      const after = await readChunk({
+        fileId: this.fileId,
+        offset: active.end,
         size: afterSize,
       });
+      this.contentAfter = after.text;
 
       this.contentWindow =
         this.contentBefore + this.contentActive + this.contentAfter;